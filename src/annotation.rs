@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// The file at the workspace root the annotations of its files are kept
+/// in, next to `.neonano.toml`.
+const ANNOTATIONS_FILE_NAME: &str = ".neonano-annotations.toml";
+
+/// Notes attached to the lines of a buffer, by row. They move along as
+/// lines are inserted or deleted above them.
+#[derive(Clone, Default)]
+pub struct Annotations {
+    notes: BTreeMap<usize, String>,
+}
+
+impl Annotations {
+    pub fn get(&self, row: usize) -> Option<&str> {
+        self.notes.get(&row).map(String::as_str)
+    }
+
+    pub fn set(&mut self, row: usize, note: String) {
+        self.notes.insert(row, note);
+    }
+
+    /// Removes the note of `row`. Returns whether there was one.
+    pub fn remove(&mut self, row: usize) -> bool {
+        self.notes.remove(&row).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.notes.iter().map(|(row, note)| (*row, note.as_str()))
+    }
+
+    /// Moves the notes after the rows `start..=old_end`, which were
+    /// replaced by `start..=new_end`. Notes of rows that were joined into
+    /// `start` go to it unless it has one of its own.
+    pub fn edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+        if old_end == new_end {
+            return;
+        }
+        let notes = std::mem::take(&mut self.notes);
+        for (row, note) in notes {
+            let row = if row > old_end {
+                row + new_end - old_end
+            } else if row > start && row > new_end {
+                start
+            } else {
+                row
+            };
+            self.notes.entry(row).or_insert(note);
+        }
+    }
+}
+
+/// A note as it is written to the annotations file: the path is relative to
+/// the workspace root and lines count from 1.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Annotation {
+    pub path: String,
+    pub line: usize,
+    pub note: String,
+}
+
+/// The annotations of all files of a workspace, read from
+/// `.neonano-annotations.toml` at its root:
+///
+/// ```toml
+/// [[annotation]]
+/// path = "src/main.rs"
+/// line = 12
+/// note = "Why is this cloned?"
+/// ```
+pub struct Project {
+    path: PathBuf,
+    annotations: Vec<Annotation>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct AnnotationsFile {
+    #[serde(rename = "annotation")]
+    annotations: Vec<Annotation>,
+}
+
+impl Project {
+    /// The annotations of the workspace at `root`, none if it has no
+    /// annotations file yet.
+    pub fn load(root: &Path) -> Result<Self> {
+        let path = root.join(ANNOTATIONS_FILE_NAME);
+        let annotations = if path.exists() {
+            toml::from_str::<AnnotationsFile>(&fs::read_to_string(&path)?)?.annotations
+        } else {
+            Vec::new()
+        };
+        Ok(Self { path, annotations })
+    }
+
+    /// Every annotation, by path and line.
+    pub fn all(&self) -> Vec<&Annotation> {
+        let mut all: Vec<&Annotation> = self.annotations.iter().collect();
+        all.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+        all
+    }
+
+    /// The annotations of the file at `path`, relative to the root.
+    pub fn of(&self, path: &str) -> Annotations {
+        let mut annotations = Annotations::default();
+        for annotation in self.annotations.iter().filter(|entry| entry.path == path) {
+            annotations.set(annotation.line.saturating_sub(1), annotation.note.clone());
+        }
+        annotations
+    }
+
+    /// Replaces the annotations of the file at `path` with `annotations`.
+    pub fn set(&mut self, path: &str, annotations: &Annotations) {
+        self.annotations.retain(|entry| entry.path != path);
+        self.annotations
+            .extend(annotations.iter().map(|(row, note)| Annotation {
+                path: path.to_string(),
+                line: row + 1,
+                note: note.to_string(),
+            }));
+    }
+
+    /// Writes the annotations file, or removes it once nothing is
+    /// annotated any more.
+    pub fn save(&self) -> Result<()> {
+        if self.annotations.is_empty() {
+            if self.path.exists() {
+                fs::remove_file(&self.path)?;
+            }
+            return Ok(());
+        }
+        let file = AnnotationsFile {
+            annotations: self.all().into_iter().cloned().collect(),
+        };
+        fs::write(&self.path, toml::to_string(&file)?)?;
+        Ok(())
+    }
+}
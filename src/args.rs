@@ -0,0 +1,32 @@
+/// The command line: the files to open and how.
+#[derive(Default)]
+pub struct Args {
+    pub files: Vec<String>,
+    /// `-F`: follow the file as it grows, like `tail -f`.
+    pub follow: bool,
+    /// `-R`: open the files read-only.
+    pub read_only: bool,
+    /// The first option not understood.
+    pub unknown: Option<String>,
+}
+
+impl Args {
+    /// Parses the arguments after the program name. A `--` ends the
+    /// options, so files starting with `-` can be opened.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Self {
+        let mut parsed = Self::default();
+        let mut options = true;
+        for arg in args {
+            match arg.as_str() {
+                "--" if options => options = false,
+                "-F" if options => parsed.follow = true,
+                "-R" if options => parsed.read_only = true,
+                _ if options && arg.len() > 1 && arg.starts_with('-') => {
+                    parsed.unknown.get_or_insert(arg);
+                }
+                _ => parsed.files.push(arg),
+            }
+        }
+        parsed
+    }
+}
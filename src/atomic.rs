@@ -0,0 +1,259 @@
+#[cfg(target_os = "linux")]
+use std::ffi::{CStr, CString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::{self as unix_fs, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// How many names `write` tries for the file it writes into, past those
+/// of earlier writes that were cut short.
+const TEMPORARY_ATTEMPTS: u32 = 100;
+
+/// Writes the file at `path` with `write` so that it ends up either whole
+/// or as it was: into a file next to it, which is synced to disk and
+/// renamed over it with the mode, owner and extended attributes of the
+/// file it replaces. Symbolic links are followed. Files with more than one
+/// hard link, which renaming would split up, and files in directories that
+/// cannot be written to are overwritten in place instead. Returns notes on
+/// what could not be done that way, to tell the user.
+pub fn write<F>(path: &Path, write: F) -> io::Result<Vec<String>>
+where
+    F: Fn(&mut dyn Write) -> io::Result<()>,
+{
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let metadata = fs::metadata(&target).ok();
+    if metadata
+        .as_ref()
+        .is_some_and(|metadata| metadata.nlink() > 1)
+    {
+        write_in_place(&target, &write)?;
+        return Ok(Vec::new());
+    }
+    let (temporary, file) = match create_temporary(&target) {
+        Ok(created) => created,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            write_in_place(&target, &write)?;
+            return Ok(vec![format!("written in place, not atomically: {e}")]);
+        }
+        Err(e) => return Err(e),
+    };
+    let mut notes = Vec::new();
+    let written = write_to(file, &write).and_then(|()| {
+        if let Some(metadata) = &metadata {
+            fs::set_permissions(&temporary, metadata.permissions())?;
+            // Only root may give files away; others keep them.
+            let _ = unix_fs::chown(&temporary, Some(metadata.uid()), Some(metadata.gid()));
+            notes = copy_attributes(&target, &temporary);
+        }
+        fs::rename(&temporary, &target)
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temporary);
+        return Err(e);
+    }
+    // The rename itself is only durable once the directory is synced.
+    if let Some(directory) = target.parent() {
+        let _ = File::open(directory).and_then(|directory| directory.sync_all());
+    }
+    Ok(notes)
+}
+
+fn write_in_place<F>(path: &Path, write: &F) -> io::Result<()>
+where
+    F: Fn(&mut dyn Write) -> io::Result<()>,
+{
+    write_to(File::create(path)?, write)
+}
+
+fn write_to<F>(file: File, write: &F) -> io::Result<()>
+where
+    F: Fn(&mut dyn Write) -> io::Result<()>,
+{
+    let mut writer = BufWriter::new(file);
+    write(&mut writer)?;
+    let file = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    file.sync_all()
+}
+
+/// Creates a file to write `path` into next to it, passing over names
+/// taken by files left behind, e.g. by a crash of a process of the same id.
+fn create_temporary(path: &Path) -> io::Result<(PathBuf, File)> {
+    let mut attempt = 0;
+    loop {
+        let temporary = temporary_path(path, attempt);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temporary)
+        {
+            Ok(file) => return Ok((temporary, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < TEMPORARY_ATTEMPTS => {
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// E.g. `.main.rs.1234.0.tmp` for `main.rs`, hidden next to it, where 0
+/// counts the names tried before.
+fn temporary_path(path: &Path, attempt: u32) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.{}.{attempt}.tmp", process::id()))
+}
+
+/// Copies the extended attributes of `from` to `to`, returning what could
+/// not be copied.
+#[cfg(target_os = "linux")]
+fn copy_attributes(from: &Path, to: &Path) -> Vec<String> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let (Ok(from), Ok(to)) = (
+        CString::new(from.as_os_str().as_bytes()),
+        CString::new(to.as_os_str().as_bytes()),
+    ) else {
+        return Vec::new();
+    };
+    let names = match attribute_names(&from) {
+        Ok(names) => names,
+        Err(e) => return vec![format!("could not list the attributes: {e}")],
+    };
+    names
+        .split(|&byte| byte == 0)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let name = CString::new(name).ok()?;
+            attribute(&from, &name)
+                .and_then(|value| set_attribute(&to, &name, &value))
+                .err()
+                .map(|e| {
+                    format!(
+                        "could not copy the attribute {}: {e}",
+                        name.to_string_lossy()
+                    )
+                })
+        })
+        .collect()
+}
+
+/// The names of the extended attributes of `path`, each ended by a NUL.
+#[cfg(target_os = "linux")]
+fn attribute_names(path: &CStr) -> io::Result<Vec<u8>> {
+    // SAFETY: `path` is NUL-terminated; a null buffer of size 0 only asks
+    // for the size of the list.
+    let size = unsafe { libc::listxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+    let size = usize::try_from(size).map_err(|_| io::Error::last_os_error())?;
+    let mut names = vec![0_u8; size];
+    // SAFETY: `names` is writable for the length passed along with it.
+    let size = unsafe { libc::listxattr(path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    let size = usize::try_from(size).map_err(|_| io::Error::last_os_error())?;
+    names.truncate(size);
+    Ok(names)
+}
+
+/// The value of the extended attribute `name` of `path`.
+#[cfg(target_os = "linux")]
+fn attribute(path: &CStr, name: &CStr) -> io::Result<Vec<u8>> {
+    // SAFETY: both are NUL-terminated; a null buffer of size 0 only asks
+    // for the size of the value.
+    let size = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+    let size = usize::try_from(size).map_err(|_| io::Error::last_os_error())?;
+    let mut value = vec![0_u8; size];
+    // SAFETY: `value` is writable for the length passed along with it.
+    let size = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_mut_ptr().cast(),
+            value.len(),
+        )
+    };
+    let size = usize::try_from(size).map_err(|_| io::Error::last_os_error())?;
+    value.truncate(size);
+    Ok(value)
+}
+
+#[cfg(target_os = "linux")]
+fn set_attribute(path: &CStr, name: &CStr, value: &[u8]) -> io::Result<()> {
+    // SAFETY: both are NUL-terminated and `value` is readable for the
+    // length passed along with it.
+    let result = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_attributes(_from: &Path, _to: &Path) -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, contents: &str) -> PathBuf {
+        let path = Path::new("target/snap").join(name);
+        fs::create_dir_all("target/snap").unwrap();
+        let _ = fs::remove_file(&path);
+        if !contents.is_empty() {
+            fs::write(&path, contents).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn failure_keeps_original() {
+        let path = file("atomic-failed.txt", "original\n");
+        let result = write(&path, |file| {
+            file.write_all(b"half")?;
+            Err(io::Error::other("disk full"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original\n");
+        assert!(!temporary_path(&fs::canonicalize(&path).unwrap(), 0).exists());
+    }
+
+    #[test]
+    fn stale_temporary_passed_over() {
+        let path = file("atomic-stale.txt", "old\n");
+        let stale = temporary_path(&fs::canonicalize(&path).unwrap(), 0);
+        fs::write(&stale, "left behind\n").unwrap();
+        let notes = write(&path, |file| file.write_all(b"new\n")).unwrap();
+        assert!(notes.is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(&stale).unwrap(), "left behind\n");
+        fs::remove_file(stale).unwrap();
+    }
+
+    #[test]
+    fn new_file() {
+        let path = file("atomic-new.txt", "");
+        write(&path, |file| file.write_all(b"new\n")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn hard_links_kept() {
+        let path = file("atomic-linked.txt", "one\n");
+        let link = file("atomic-link.txt", "");
+        fs::hard_link(&path, &link).unwrap();
+        write(&path, |file| file.write_all(b"two\n")).unwrap();
+        assert_eq!(fs::read_to_string(&link).unwrap(), "two\n");
+    }
+}
@@ -0,0 +1,9 @@
+use crate::{Document, Position};
+
+/// An open document put aside while another one is edited, with where its
+/// cursor and view were.
+pub struct Buffer {
+    pub document: Document,
+    pub cursor_position: Position,
+    pub offset: Position,
+}
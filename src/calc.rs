@@ -0,0 +1,244 @@
+use anyhow::{anyhow, Result};
+
+/// Binary operators from the loosest to the tightest binding, as in C.
+const LEVELS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// How a number is written.
+#[derive(Clone, Copy, Default)]
+pub enum Radix {
+    #[default]
+    Decimal,
+    Hex,
+    Binary,
+    Octal,
+}
+
+impl Radix {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "dec" => Some(Self::Decimal),
+            "hex" => Some(Self::Hex),
+            "bin" => Some(Self::Binary),
+            "oct" => Some(Self::Octal),
+            _ => None,
+        }
+    }
+
+    /// `value` as a literal, e.g. `0x1f` or `-0b101`.
+    pub fn format(self, value: i128) -> String {
+        let sign = if value < 0 { "-" } else { "" };
+        let magnitude = value.unsigned_abs();
+        match self {
+            Self::Decimal => value.to_string(),
+            Self::Hex => format!("{sign}0x{magnitude:x}"),
+            Self::Binary => format!("{sign}0b{magnitude:b}"),
+            Self::Octal => format!("{sign}0o{magnitude:o}"),
+        }
+    }
+}
+
+/// `value` in every radix, e.g. `93 = 0x5d = 0b1011101 = 0o135`.
+pub fn describe(value: i128) -> String {
+    [Radix::Decimal, Radix::Hex, Radix::Binary, Radix::Octal]
+        .map(|radix| radix.format(value))
+        .join(" = ")
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Number(i128),
+    Operator(&'a str),
+}
+
+/// Evaluates an integer expression such as `0x1F * 3` of literals in any
+/// radix, parentheses and the operators of C. A trailing `in hex`,
+/// `in bin`, `in oct` or `in dec` picks the radix of the result.
+pub fn evaluate(input: &str) -> Result<(i128, Radix)> {
+    let (expression, radix) = match input.trim_end().rsplit_once(" in ") {
+        Some((expression, name)) => {
+            let radix = Radix::parse(name.trim()).ok_or_else(|| {
+                anyhow!(
+                    "Unknown radix: {}, expected hex, bin, oct or dec",
+                    name.trim()
+                )
+            })?;
+            (expression, radix)
+        }
+        None => (input, Radix::default()),
+    };
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser { tokens, next: 0 };
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.next) {
+        None => Ok((value, radix)),
+        Some(Token::Number(_)) => Err(anyhow!("Expected an operator")),
+        Some(Token::Operator(operator)) => Err(anyhow!("Unexpected {operator}")),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(number(&rest[..len])?));
+            len
+        } else if let Some(operator) = ["<<", ">>"].into_iter().find(|op| rest.starts_with(op)) {
+            tokens.push(Token::Operator(operator));
+            operator.len()
+        } else if "+-*/%&|^~()".contains(c) {
+            tokens.push(Token::Operator(&rest[..1]));
+            1
+        } else {
+            return Err(anyhow!("Unexpected {c}"));
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Parses a literal like `42`, `0x2a`, `0b101010`, `0o52` or `1_000`.
+fn number(literal: &str) -> Result<i128> {
+    let digits = literal.replace('_', "");
+    let lower = digits.to_ascii_lowercase();
+    let (radix, digits) = match lower.get(..2) {
+        Some("0x") => (16, &lower[2..]),
+        Some("0b") => (2, &lower[2..]),
+        Some("0o") => (8, &lower[2..]),
+        _ => (10, lower.as_str()),
+    };
+    i128::from_str_radix(digits, radix).map_err(|_| anyhow!("Invalid number: {literal}"))
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn take_operator(&mut self, operators: &[&str]) -> Option<&'a str> {
+        match self.tokens.get(self.next).copied() {
+            Some(Token::Operator(operator)) if operators.contains(&operator) => {
+                self.next += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    /// An expression of the operators of `LEVELS[level]` and tighter ones.
+    fn binary(&mut self, level: usize) -> Result<i128> {
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut value = self.binary(level + 1)?;
+        while let Some(operator) = self.take_operator(operators) {
+            let right = self.binary(level + 1)?;
+            value = apply(operator, value, right)?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i128> {
+        if let Some(operator) = self.take_operator(&["-", "+", "~"]) {
+            let value = self.unary()?;
+            return match operator {
+                "-" => value.checked_neg().ok_or_else(|| anyhow!("Overflow")),
+                "~" => Ok(!value),
+                _ => Ok(value),
+            };
+        }
+        match self.tokens.get(self.next).copied() {
+            Some(Token::Number(value)) => {
+                self.next += 1;
+                Ok(value)
+            }
+            Some(Token::Operator("(")) => {
+                self.next += 1;
+                let value = self.binary(0)?;
+                self.take_operator(&[")"])
+                    .ok_or_else(|| anyhow!("Expected )"))?;
+                Ok(value)
+            }
+            Some(Token::Operator(operator)) => Err(anyhow!("Unexpected {operator}")),
+            None => Err(anyhow!("Expected a number")),
+        }
+    }
+}
+
+fn apply(operator: &str, left: i128, right: i128) -> Result<i128> {
+    if matches!(operator, "/" | "%") && right == 0 {
+        return Err(anyhow!("Division by zero"));
+    }
+    let shift = || u32::try_from(right).ok();
+    match operator {
+        "|" => Some(left | right),
+        "^" => Some(left ^ right),
+        "&" => Some(left & right),
+        "<<" => shift().and_then(|shift| left.checked_shl(shift)),
+        ">>" => shift().and_then(|shift| left.checked_shr(shift)),
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" => left.checked_div(right),
+        _ => left.checked_rem(right),
+    }
+    .ok_or_else(|| anyhow!("Overflow"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The result of `input` as it is shown, or the error.
+    fn result(input: &str) -> String {
+        match evaluate(input) {
+            Ok((value, radix)) => radix.format(value),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(result("1 + 2 * 3"), "7");
+        assert_eq!(result("(1 + 2) * 3"), "9");
+        assert_eq!(result("1 | 2 ^ 3 & 6"), "1");
+        assert_eq!(result("1 << 2 + 1"), "8");
+        assert_eq!(result("-2 * -3 - ~0"), "7");
+        assert_eq!(result("7 % 4 / 2"), "1");
+    }
+
+    #[test]
+    fn radixes() {
+        assert_eq!(result("0x1F + 0b1 + 0o7 + 1_000"), "1039");
+        assert_eq!(result("255 in hex"), "0xff");
+        assert_eq!(result("-5 in bin"), "-0b101");
+        assert_eq!(result("8 in oct"), "0o10");
+        assert_eq!(describe(93), "93 = 0x5d = 0b1011101 = 0o135");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(result("1 / 0"), "Division by zero");
+        assert_eq!(
+            result("1 in roman"),
+            "Unknown radix: roman, expected hex, bin, oct or dec"
+        );
+        assert_eq!(result("(1 + 2"), "Expected )");
+        assert_eq!(result("1 2"), "Expected an operator");
+        assert_eq!(result("1 +"), "Expected a number");
+        assert_eq!(result("1 $ 2"), "Unexpected $");
+        assert_eq!(result("0xg"), "Invalid number: 0xg");
+        assert_eq!(result("1 << 200"), "Overflow");
+    }
+}
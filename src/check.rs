@@ -0,0 +1,160 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range};
+
+/// A problem the `on_save` command reported.
+pub struct Problem {
+    pub path: PathBuf,
+    /// From 0, like the columns.
+    pub line: usize,
+    pub column: usize,
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let position = Position::new(self.line as u32, self.column as u32);
+        Diagnostic {
+            range: Range::new(position, position),
+            severity: Some(self.severity),
+            source: Some("on_save".to_string()),
+            message: self.message.clone(),
+            ..Diagnostic::default()
+        }
+    }
+}
+
+/// The `on_save` command running in the background.
+pub struct Check {
+    pub command: String,
+    receiver: Receiver<Result<Vec<Problem>>>,
+}
+
+impl Check {
+    /// Runs `command` by the shell in `root`.
+    pub fn spawn(command: &str, root: &Path) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let (shell_command, root) = (command.to_string(), root.to_path_buf());
+        thread::spawn(move || {
+            let _ = sender.send(run(&shell_command, &root));
+        });
+        Self {
+            command: command.to_string(),
+            receiver,
+        }
+    }
+
+    /// The problems, once the command has finished.
+    pub fn poll(&self) -> Option<Result<Vec<Problem>>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(anyhow!("the check stopped"))),
+        }
+    }
+}
+
+fn run(command: &str, root: &Path) -> Result<Vec<Problem>> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .output()?;
+    // Compilers write their diagnostics to stderr, linters to stdout.
+    let text = String::from_utf8_lossy(&output.stderr) + String::from_utf8_lossy(&output.stdout);
+    Ok(parse(&text, root))
+}
+
+/// The problems of the output of a compiler or linter: lines of
+/// `path:line[:column]: [severity:] message`, as most of them write with
+/// `--message-format=short` or the like, and the `--> path:line:column`
+/// lines rustc puts below `error: message`.
+fn parse(output: &str, root: &Path) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut heading: Option<(DiagnosticSeverity, String)> = None;
+    for line in output.lines() {
+        let trimmed = line.trim_start();
+        if let Some(location) = trimmed.strip_prefix("--> ") {
+            if let (Some((severity, message)), Some((path, line, column, _))) =
+                (heading.take(), location_of(location))
+            {
+                problems.push(Problem {
+                    path: root.join(path),
+                    line,
+                    column,
+                    severity,
+                    message,
+                });
+            }
+            continue;
+        }
+        if let Some((severity, message)) = severity_of(trimmed) {
+            heading = Some((severity, message.to_string()));
+            continue;
+        }
+        let Some((path, line, column, rest)) = location_of(line) else {
+            continue;
+        };
+        let path = root.join(path);
+        if !path.is_file() {
+            continue;
+        }
+        let (severity, message) = severity_of(rest).unwrap_or((DiagnosticSeverity::ERROR, rest));
+        problems.push(Problem {
+            path,
+            line,
+            column,
+            severity,
+            message: message.to_string(),
+        });
+    }
+    problems
+}
+
+/// `path:line[:column][: rest]`, with the line and column from 0.
+fn location_of(text: &str) -> Option<(&str, usize, usize, &str)> {
+    let (path, rest) = text.split_once(':')?;
+    if path.is_empty() || path.contains(' ') {
+        return None;
+    }
+    let (line, rest) = rest.split_once(':').unwrap_or((rest, ""));
+    let line: usize = line.trim().parse().ok()?;
+    let (column, rest) = match rest.split_once(':') {
+        Some((column, after)) => match column.trim().parse::<usize>() {
+            Ok(column) => (column, after),
+            // `path:line: message`, whose message may hold colons.
+            Err(_) => (1, rest),
+        },
+        None => match rest.trim().parse::<usize>() {
+            Ok(column) => (column, ""),
+            Err(_) => (1, rest),
+        },
+    };
+    Some((
+        path,
+        line.saturating_sub(1),
+        column.saturating_sub(1),
+        rest.trim(),
+    ))
+}
+
+/// The severity a message starts with, e.g. `error[E0425]: `, and the
+/// message after it.
+fn severity_of(text: &str) -> Option<(DiagnosticSeverity, &str)> {
+    let (word, message) = text.split_once(':')?;
+    let word = word.split('[').next()?.trim();
+    let severity = match word {
+        "error" | "fatal error" => DiagnosticSeverity::ERROR,
+        "warning" => DiagnosticSeverity::WARNING,
+        "note" | "info" => DiagnosticSeverity::INFORMATION,
+        "help" | "hint" => DiagnosticSeverity::HINT,
+        _ => return None,
+    };
+    Some((severity, message.trim()))
+}
@@ -0,0 +1,106 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+
+/// Clipboard programs tried in order, with their arguments for copying.
+const COPY_COMMANDS: [(&str, &[&str]); 5] = [
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+    ("pbcopy", &[]),
+    ("clip.exe", &[]),
+];
+/// Clipboard programs tried in order, with their arguments for pasting.
+const PASTE_COMMANDS: [(&str, &[&str]); 5] = [
+    ("wl-paste", &["--no-newline"]),
+    ("xclip", &["-selection", "clipboard", "-o"]),
+    ("xsel", &["--clipboard", "--output"]),
+    ("pbpaste", &[]),
+    (
+        "powershell.exe",
+        &["-NoProfile", "-Command", "Get-Clipboard"],
+    ),
+];
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Copies `text` with the first clipboard program that works, falling back
+/// to the OSC 52 escape sequence, which most terminals (also over ssh)
+/// forward to the system clipboard. Returns what was used.
+pub fn copy(text: &str) -> Result<&'static str> {
+    for (program, args) in COPY_COMMANDS {
+        if run(program, args, text).is_ok() {
+            return Ok(program);
+        }
+    }
+    print!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    io::stdout().flush()?;
+    Ok("OSC 52")
+}
+
+/// The text of the clipboard, by the first clipboard program that works.
+/// Terminals do not answer OSC 52 queries reliably, so there is no fallback.
+pub fn paste() -> Result<String> {
+    for (program, args) in PASTE_COMMANDS {
+        if let Ok(text) = read(program, args) {
+            // `clip.exe` and `Get-Clipboard` deal in CRLF.
+            return Ok(text.replace("\r\n", "\n"));
+        }
+    }
+    Err(anyhow!("No clipboard program to paste with"))
+}
+
+/// The clipboard programs, in the order they are tried.
+pub fn programs() -> Vec<&'static str> {
+    COPY_COMMANDS.iter().map(|(program, _)| *program).collect()
+}
+
+fn run(program: &str, args: &[&str], input: &str) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    if child.wait()?.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{program} failed"))
+    }
+}
+
+fn read(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout)?)
+    } else {
+        Err(anyhow!("{program} failed"))
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,489 @@
+use anyhow::{anyhow, Result};
+
+use crate::document::LineEnding;
+use crate::lsp::Goto;
+use crate::sort::Order;
+use crate::textobject::Object;
+use crate::theme::Style;
+use crate::SearchDirection;
+
+/// The names of the commands, which the command prompt completes.
+const NAMES: &[&str] = &[
+    "annotate",
+    "annotations",
+    "buffers",
+    "calc",
+    "calc-insert",
+    "checkhealth",
+    "close-buffer",
+    "compare-clipboard",
+    "copy-path",
+    "copy-reference",
+    "copy-relative-path",
+    "count",
+    "diff",
+    "doc-comment",
+    "enable-highlighting",
+    "export-theme",
+    "find-file",
+    "fold",
+    "follow",
+    "goto-definition",
+    "goto-implementation",
+    "goto-mark",
+    "goto-type-definition",
+    "grep",
+    "hex",
+    "highlight",
+    "history",
+    "line-ending",
+    "mark",
+    "marks",
+    "matching-bracket",
+    "narrow",
+    "next",
+    "next-buffer",
+    "next-diagnostic",
+    "nohighlight",
+    "open",
+    "previous",
+    "previous-buffer",
+    "previous-diagnostic",
+    "problems",
+    "read-only",
+    "redo",
+    "reindent",
+    "reindent-file",
+    "reload-runtime",
+    "remove-annotation",
+    "remove-duplicate-lines",
+    "rename",
+    "replace",
+    "reverse-lines",
+    "save-as",
+    "save-copy",
+    "select",
+    "set",
+    "show-annotation",
+    "sort-lines",
+    "spell-suggest",
+    "stats",
+    "structural-replace",
+    "symbols",
+    "theme",
+    "todos",
+    "toggle-bom",
+    "toggle-comment",
+    "toggle-folds",
+    "undo",
+    "undo-file-operation",
+    "unfold",
+    "widen",
+];
+
+/// Commands entered at the command prompt (Ctrl-P).
+pub enum Command {
+    CopyPath,
+    CopyRelativePath,
+    CopyReference,
+    Goto(Goto),
+    History,
+    /// Shows what works and what does not, and how to fix it.
+    CheckHealth,
+    Diagnostic(SearchDirection),
+    /// Replaces the nodes matching a tree-sitter query, asked for.
+    StructuralReplace,
+    /// Inserts a doc comment skeleton for the function at the cursor.
+    DocComment,
+    /// Selects the object around the cursor, or its body if `inner`.
+    Select {
+        object: Object,
+        inner: bool,
+    },
+    /// Jumps to the start of the next or previous object.
+    Object(Object, SearchDirection),
+    /// Jumps to the bracket paired with the one at the cursor.
+    MatchingBracket,
+    /// Indents the cursor line as its syntax calls for.
+    Reindent,
+    /// Indents every line as its syntax calls for.
+    ReindentFile,
+    /// Folds the syntax node around the cursor line.
+    Fold,
+    /// Opens the fold at the cursor line.
+    Unfold,
+    /// Opens every fold, or folds every top-level node.
+    ToggleFolds,
+    /// Sets an option of the buffer, or shows its value.
+    Set {
+        option: String,
+        value: Option<String>,
+    },
+    /// Evaluates an integer expression, showing the result or, if
+    /// `insert`, typing it at the cursor.
+    Calculate {
+        expression: String,
+        insert: bool,
+    },
+    /// Offers the words of the dictionary to replace the misspelled one at
+    /// the cursor with.
+    SpellSuggest,
+    /// Stops highlighting the matches of the last search.
+    NoHighlight,
+    /// Draws with the named theme, or lists the themes.
+    Theme(Option<String>),
+    /// Draws a group of the theme in the style, or shows its style.
+    Highlight {
+        group: String,
+        style: Option<Style>,
+    },
+    /// Writes the current colors to the named theme file.
+    ExportTheme(String),
+    /// Searches all files of the workspace for the text.
+    Grep(String),
+    /// Replaces every occurrence of `from` in the workspace.
+    Replace {
+        from: String,
+        to: String,
+    },
+    /// Puts back the files the latest `replace` overwrote.
+    UndoFileOperation,
+    /// Attaches the note, or one asked for, to the cursor line.
+    Annotate(Option<String>),
+    /// Removes the note of the cursor line.
+    RemoveAnnotation,
+    /// Shows the note of the cursor line.
+    ShowAnnotation,
+    /// Lists the annotations of every file of the workspace.
+    Annotations,
+    /// Names the cursor position to jump back to.
+    Mark(String),
+    /// Jumps to the mark of the buffer, or else of another one.
+    GotoMark(String),
+    /// Lists the marks of every open buffer.
+    Marks,
+    /// Lists the problems the `on_save` command reported.
+    Problems,
+    /// Shows the selection, or the buffer, and the clipboard side by side.
+    CompareClipboard,
+    /// Shows how the buffer differs from the file on disk.
+    Diff,
+    /// Shows the lines, words, characters and bytes of the selection, or of
+    /// the buffer.
+    Count,
+    /// Comments or uncomments the cursor line.
+    ToggleComment,
+    /// Lists the indexed files whose path fuzzily matches the query.
+    FindFile(String),
+    /// Lists the indexed functions and classes whose name fuzzily matches
+    /// the query.
+    Symbols(String),
+    /// Lists the TODO, FIXME and XXX comments of the workspace.
+    Todos,
+    /// Shows how many files and lines of each language the workspace has.
+    Statistics,
+    /// Highlights a file that is too big to be highlighted by default.
+    EnableHighlighting,
+    /// Compiles the query overrides and loads the theme again.
+    ReloadRuntime,
+    /// Takes back the last edits.
+    Undo,
+    /// Makes the last undone edits again.
+    Redo,
+    /// Shows only the lines containing a query, asked for, to edit them.
+    Narrow,
+    /// Writes the edited lines of a narrowed view back and shows all lines.
+    Widen,
+    /// Opens the file, or one asked for, in a buffer of its own.
+    Open(Option<String>),
+    /// Saves the buffer to the file, or one asked for, which it is of from
+    /// then on.
+    SaveAs(Option<String>),
+    /// Writes the buffer to the file, or one asked for, as a copy.
+    SaveCopy(Option<String>),
+    /// Renames the file of the buffer on disk.
+    Rename(Option<String>),
+    /// Shows the next or previous open buffer.
+    Buffer(SearchDirection),
+    /// Closes the current buffer.
+    CloseBuffer,
+    /// Lists the open buffers to show one of them.
+    Buffers,
+    /// Appends the lines written to the file as they come, or stops.
+    Follow,
+    /// Turns editing the buffer off, or on again.
+    ReadOnly,
+    /// Writes a byte order mark on save, or stops writing it.
+    ToggleBom,
+    /// Ends the lines with LF or CRLF on save, or shows which they end with.
+    LineEnding(Option<LineEnding>),
+    /// Edits the bytes of the buffer as a hex dump, or the text again.
+    Hex,
+    /// Sorts the lines of the selection, or of the buffer.
+    SortLines(Order),
+    /// Reverses the lines of the selection, or of the buffer.
+    ReverseLines,
+    /// Removes the lines of the selection, or of the buffer, that came
+    /// before.
+    RemoveDuplicateLines,
+}
+
+impl Command {
+    /// Whether the command changes the buffer, which a read-only one
+    /// refuses.
+    pub fn edits(&self) -> bool {
+        matches!(
+            self,
+            Self::History
+                | Self::StructuralReplace
+                | Self::DocComment
+                | Self::Reindent
+                | Self::ReindentFile
+                | Self::Calculate { insert: true, .. }
+                | Self::SpellSuggest
+                | Self::ToggleComment
+                | Self::Undo
+                | Self::Redo
+                | Self::ToggleBom
+                | Self::LineEnding(Some(_))
+                | Self::SortLines(_)
+                | Self::ReverseLines
+                | Self::RemoveDuplicateLines
+                | Self::Narrow
+        )
+    }
+
+    /// The names of the commands starting with the command typed so far,
+    /// while no argument follows it.
+    pub fn complete(input: &str) -> Vec<String> {
+        let input = input.trim_start();
+        if input.contains(' ') {
+            return Vec::new();
+        }
+        NAMES
+            .iter()
+            .filter(|name| name.starts_with(input))
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    pub fn parse(input: &str) -> Result<Self> {
+        if let Some(expression) = input.trim_start().strip_prefix('=') {
+            let (expression, insert) = match expression.strip_prefix('>') {
+                Some(expression) => (expression, true),
+                None => (expression, false),
+            };
+            return calculation(expression, insert);
+        }
+        let mut words = input.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        match name {
+            "calc" | "calc-insert" => {
+                return calculation(&input.trim_start()[name.len()..], name == "calc-insert");
+            }
+            "grep" => {
+                let query = input.trim_start()[name.len()..].trim();
+                if query.is_empty() {
+                    return Err(anyhow!("Usage: grep <text>"));
+                }
+                return Ok(Self::Grep(query.to_string()));
+            }
+            "open" | "save-as" | "save-copy" | "rename" => {
+                let file_name = input.trim_start()[name.len()..].trim();
+                let file_name = (!file_name.is_empty()).then(|| file_name.to_string());
+                return Ok(match name {
+                    "open" => Self::Open(file_name),
+                    "save-as" => Self::SaveAs(file_name),
+                    "save-copy" => Self::SaveCopy(file_name),
+                    _ => Self::Rename(file_name),
+                });
+            }
+            "find-file" | "symbols" => {
+                let query = input.trim_start()[name.len()..].trim().to_string();
+                return Ok(if name == "find-file" {
+                    Self::FindFile(query)
+                } else {
+                    Self::Symbols(query)
+                });
+            }
+            "set" => {
+                // Values may hold spaces, e.g. `set comment=# %s`.
+                let assignment = input.trim_start()[name.len()..].trim();
+                let (option, value) = match assignment.split_once('=') {
+                    Some((option, value)) => (option, Some(value.to_string())),
+                    None => (assignment, None),
+                };
+                if option.is_empty() || option.contains(char::is_whitespace) {
+                    return Err(anyhow!("Usage: set <option>[=<value>]"));
+                }
+                return Ok(Self::Set {
+                    option: option.to_string(),
+                    value,
+                });
+            }
+            "sort-lines" => {
+                return Order::parse(words)
+                    .map(Self::SortLines)
+                    .ok_or_else(|| anyhow!("Usage: sort-lines [reverse] [numeric] [nocase]"));
+            }
+            "line-ending" => {
+                let (name, None) = (words.next(), words.next()) else {
+                    return Err(anyhow!("Usage: line-ending [lf|crlf]"));
+                };
+                let Some(name) = name else {
+                    return Ok(Self::LineEnding(None));
+                };
+                let line_ending = LineEnding::named(name)
+                    .ok_or_else(|| anyhow!("Usage: line-ending [lf|crlf]"))?;
+                return Ok(Self::LineEnding(Some(line_ending)));
+            }
+            "theme" => {
+                let (name, None) = (words.next(), words.next()) else {
+                    return Err(anyhow!("Usage: theme [<name>]"));
+                };
+                return Ok(Self::Theme(name.map(str::to_string)));
+            }
+            "highlight" | "hi" => {
+                let Some(group) = words.next() else {
+                    return Err(anyhow!(
+                        "Usage: highlight <group> [fg=#rrggbb] [bg=#rrggbb] [bold] [italic] [underline] [strikethrough]"
+                    ));
+                };
+                let attributes: Vec<&str> = words.collect();
+                let style = if attributes.is_empty() {
+                    None
+                } else {
+                    Some(Style::parse(&attributes).map_err(|e| anyhow!(e))?)
+                };
+                return Ok(Self::Highlight {
+                    group: group.to_string(),
+                    style,
+                });
+            }
+            "export-theme" => {
+                let (Some(name), None) = (words.next(), words.next()) else {
+                    return Err(anyhow!("Usage: export-theme <name>"));
+                };
+                return Ok(Self::ExportTheme(name.to_string()));
+            }
+            "select" => {
+                let words: Vec<&str> = words.collect();
+                let (inner, object) = match words[..] {
+                    [object] => (false, object),
+                    ["inner", object] => (true, object),
+                    _ => return Err(anyhow!("Usage: select [inner] <object>")),
+                };
+                return Ok(Self::Select {
+                    object: parse_object(object)?,
+                    inner,
+                });
+            }
+            "next" | "previous" => {
+                let (Some(object), None) = (words.next(), words.next()) else {
+                    return Err(anyhow!("Usage: {name} <object>"));
+                };
+                let direction = if name == "next" {
+                    SearchDirection::Forward
+                } else {
+                    SearchDirection::Backward
+                };
+                return Ok(Self::Object(parse_object(object)?, direction));
+            }
+            "annotate" => {
+                let note = input.trim_start()[name.len()..].trim();
+                return Ok(Self::Annotate((!note.is_empty()).then(|| note.to_string())));
+            }
+            "mark" | "m" | "goto-mark" => {
+                let (Some(mark), None) = (words.next(), words.next()) else {
+                    return Err(anyhow!("Usage: {name} <name>"));
+                };
+                return Ok(if name == "goto-mark" {
+                    Self::GotoMark(mark.to_string())
+                } else {
+                    Self::Mark(mark.to_string())
+                });
+            }
+            "replace" => {
+                let (Some(from), Some(to), None) = (words.next(), words.next(), words.next())
+                else {
+                    return Err(anyhow!("Usage: replace <from> <to>"));
+                };
+                return Ok(Self::Replace {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                });
+            }
+            _ => (),
+        }
+        let command = match name {
+            "copy-path" => Self::CopyPath,
+            "copy-relative-path" => Self::CopyRelativePath,
+            "copy-reference" => Self::CopyReference,
+            "goto-definition" => Self::Goto(Goto::Definition),
+            "goto-type-definition" => Self::Goto(Goto::TypeDefinition),
+            "goto-implementation" => Self::Goto(Goto::Implementation),
+            "history" => Self::History,
+            "checkhealth" | "health" => Self::CheckHealth,
+            "next-diagnostic" => Self::Diagnostic(SearchDirection::Forward),
+            "previous-diagnostic" => Self::Diagnostic(SearchDirection::Backward),
+            "matching-bracket" => Self::MatchingBracket,
+            "reindent" => Self::Reindent,
+            "reindent-file" => Self::ReindentFile,
+            "fold" => Self::Fold,
+            "unfold" => Self::Unfold,
+            "toggle-folds" => Self::ToggleFolds,
+            "nohighlight" | "noh" => Self::NoHighlight,
+            "spell-suggest" => Self::SpellSuggest,
+            "doc-comment" => Self::DocComment,
+            "structural-replace" => Self::StructuralReplace,
+            "undo-file-operation" => Self::UndoFileOperation,
+            "remove-annotation" => Self::RemoveAnnotation,
+            "show-annotation" => Self::ShowAnnotation,
+            "annotations" => Self::Annotations,
+            "marks" => Self::Marks,
+            "problems" => Self::Problems,
+            "compare-clipboard" => Self::CompareClipboard,
+            "diff" => Self::Diff,
+            "count" => Self::Count,
+            "toggle-comment" => Self::ToggleComment,
+            "todos" => Self::Todos,
+            "stats" => Self::Statistics,
+            "enable-highlighting" => Self::EnableHighlighting,
+            "reload-runtime" => Self::ReloadRuntime,
+            "undo" => Self::Undo,
+            "redo" => Self::Redo,
+            "narrow" => Self::Narrow,
+            "widen" => Self::Widen,
+            "next-buffer" => Self::Buffer(SearchDirection::Forward),
+            "previous-buffer" => Self::Buffer(SearchDirection::Backward),
+            "close-buffer" => Self::CloseBuffer,
+            "buffers" => Self::Buffers,
+            "toggle-bom" => Self::ToggleBom,
+            "follow" => Self::Follow,
+            "read-only" => Self::ReadOnly,
+            "hex" => Self::Hex,
+            "reverse-lines" => Self::ReverseLines,
+            "remove-duplicate-lines" => Self::RemoveDuplicateLines,
+            _ => return Err(anyhow!("Unknown command: {name}")),
+        };
+        if words.next().is_some() {
+            return Err(anyhow!("{name} takes no arguments"));
+        }
+        Ok(command)
+    }
+}
+
+fn calculation(expression: &str, insert: bool) -> Result<Command> {
+    if expression.trim().is_empty() {
+        return Err(anyhow!("Usage: = <expression> [in hex|bin|oct|dec]"));
+    }
+    Ok(Command::Calculate {
+        expression: expression.to_string(),
+        insert,
+    })
+}
+
+fn parse_object(name: &str) -> Result<Object> {
+    Object::parse(name)
+        .ok_or_else(|| anyhow!("Unknown object: {name}, expected function, class or parameter"))
+}
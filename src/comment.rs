@@ -0,0 +1,51 @@
+/// `lines` commented by `open` and `close`, e.g. `//` and nothing or `/*`
+/// and `*/`, at the indentation they share; uncommented instead if every
+/// line with text is commented already. Lines without text are left as
+/// they are.
+pub fn toggle(lines: &[&str], open: &str, close: &str) -> Vec<String> {
+    let texts = || lines.iter().filter(|line| !line.trim().is_empty());
+    if texts().next().is_some() && texts().all(|line| uncommented(line, open, close).is_some()) {
+        return lines
+            .iter()
+            .map(|line| uncommented(line, open, close).unwrap_or_else(|| line.to_string()))
+            .collect();
+    }
+    let indent = texts()
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or_default();
+    lines
+        .iter()
+        .map(|line| {
+            if line.trim().is_empty() {
+                return line.to_string();
+            }
+            // Tabs and spaces may be mixed, so cut at a character boundary.
+            let indent = (0..=indent)
+                .rev()
+                .find(|indent| line.is_char_boundary(*indent))
+                .unwrap_or_default();
+            let (indentation, text) = line.split_at(indent);
+            match close {
+                "" => format!("{indentation}{open} {text}"),
+                close => format!("{indentation}{open} {text} {close}"),
+            }
+        })
+        .collect()
+}
+
+/// `line` without the comment around its text, if it is commented.
+fn uncommented(line: &str, open: &str, close: &str) -> Option<String> {
+    let text = line.trim_start();
+    let indentation = &line[..line.len() - text.len()];
+    let inner = text.strip_prefix(open)?;
+    let inner = match close {
+        "" => inner,
+        close => {
+            let inner = inner.trim_end().strip_suffix(close)?;
+            inner.strip_suffix(' ').unwrap_or(inner)
+        }
+    };
+    let inner = inner.strip_prefix(' ').unwrap_or(inner);
+    Some(format!("{indentation}{inner}"))
+}
@@ -0,0 +1,536 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use lsp_types::{CompletionItem, CompletionItemKind, Documentation, InsertTextFormat};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::floating_item::FloatingItem;
+use crate::markdown::{self, StyledLine};
+use crate::theme::Style;
+use crate::{Document, Position};
+
+const MENU_HEIGHT: usize = 10;
+const DOCUMENTATION_WIDTH: usize = 60;
+const DOCUMENTATION_HEIGHT: usize = 12;
+
+/// What the user is completing: the cursor and the row it is on.
+pub struct Context {
+    pub position: Position,
+    graphemes: Vec<String>,
+}
+
+impl Context {
+    fn new(document: &Document, position: &Position) -> Self {
+        let graphemes = document
+            .row(position.y)
+            .map(|row| row.as_str().graphemes(true).map(String::from).collect())
+            .unwrap_or_default();
+        Self {
+            position: position.clone(),
+            graphemes,
+        }
+    }
+
+    fn before_cursor(&self) -> &[String] {
+        &self.graphemes[..self.position.x.min(self.graphemes.len())]
+    }
+
+    /// Column where the identifier under the cursor starts.
+    pub fn word_start(&self) -> usize {
+        let before = self.before_cursor();
+        before.len()
+            - before
+                .iter()
+                .rev()
+                .take_while(|g| is_word(g.as_str()))
+                .count()
+    }
+
+    /// Column where the whitespace-delimited token under the cursor starts.
+    pub fn token_start(&self) -> usize {
+        let before = self.before_cursor();
+        before.len()
+            - before
+                .iter()
+                .rev()
+                .take_while(|g| !g.trim().is_empty() && !matches!(g.as_str(), "\"" | "'" | "("))
+                .count()
+    }
+
+    pub fn text(&self, start: usize) -> String {
+        self.before_cursor()[start.min(self.position.x)..].concat()
+    }
+}
+
+pub struct Candidate {
+    pub label: String,
+    pub insert_text: String,
+    pub detail: Option<String>,
+    /// Column the completed text starts at; everything from there up to
+    /// the cursor is replaced and used as the fuzzy query.
+    pub start: usize,
+    /// `insert_text` is in the LSP snippet syntax.
+    pub snippet: bool,
+    icon: char,
+    /// The language server item, kept to resolve on demand.
+    item: Option<CompletionItem>,
+    resolved: bool,
+    documentation: Option<String>,
+}
+
+impl Candidate {
+    pub fn new(label: String, start: usize) -> Self {
+        Self {
+            insert_text: label.clone(),
+            label,
+            detail: None,
+            start,
+            snippet: false,
+            icon: ' ',
+            item: None,
+            resolved: true,
+            documentation: None,
+        }
+    }
+
+    /// Markdown shown beside the menu while this candidate is selected.
+    fn documentation(&self) -> Option<String> {
+        let detail = self
+            .item
+            .as_ref()
+            .and_then(|item| item.detail.as_ref())
+            .map(|detail| format!("```\n{detail}\n```"));
+        match (detail, &self.documentation) {
+            (Some(detail), Some(documentation)) => Some(format!("{detail}\n{documentation}")),
+            (detail, documentation) => detail.or_else(|| documentation.clone()),
+        }
+    }
+}
+
+/// A provider of completion candidates. Register new sources in
+/// `Completion::default`; their order is also their priority when
+/// deduplicating.
+pub trait CompletionSource {
+    /// Shown in front of every candidate of this source.
+    fn icon(&self) -> char;
+    fn candidates(&mut self, document: &mut Document, context: &Context) -> Vec<Candidate>;
+}
+
+pub struct LspSource;
+
+impl CompletionSource for LspSource {
+    fn icon(&self) -> char {
+        'λ'
+    }
+
+    fn candidates(&mut self, document: &mut Document, context: &Context) -> Vec<Candidate> {
+        let start = context.word_start();
+        document
+            .lsp_completion(context.position.x as u32, context.position.y as u32)
+            .into_iter()
+            .map(|item| Candidate {
+                insert_text: item
+                    .insert_text
+                    .clone()
+                    .unwrap_or_else(|| item.label.clone()),
+                snippet: item.insert_text_format == Some(InsertTextFormat::SNIPPET),
+                detail: item
+                    .detail
+                    .clone()
+                    .or_else(|| item.kind.and_then(kind_name)),
+                label: item.label.clone(),
+                start,
+                icon: ' ',
+                documentation: item.documentation.clone().map(documentation_text),
+                resolved: false,
+                item: Some(item),
+            })
+            .collect()
+    }
+}
+
+/// Identifiers that already appear in the buffer.
+pub struct BufferWordsSource;
+
+impl CompletionSource for BufferWordsSource {
+    fn icon(&self) -> char {
+        'w'
+    }
+
+    fn candidates(&mut self, document: &mut Document, context: &Context) -> Vec<Candidate> {
+        let start = context.word_start();
+        let prefix = context.text(start);
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        let mut seen = HashSet::new();
+        let mut candidates = Vec::new();
+        for y in 0..document.len() {
+            let Some(row) = document.row(y) else {
+                continue;
+            };
+            for word in row
+                .as_str()
+                .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            {
+                if word.graphemes(true).count() < 2 || word == prefix {
+                    continue;
+                }
+                if seen.insert(word.to_string()) {
+                    candidates.push(Candidate::new(word.to_string(), start));
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// Entries of the directory named by the path-like token under the cursor.
+pub struct PathSource;
+
+impl CompletionSource for PathSource {
+    fn icon(&self) -> char {
+        '/'
+    }
+
+    fn candidates(&mut self, _document: &mut Document, context: &Context) -> Vec<Candidate> {
+        let token_start = context.token_start();
+        let token = context.text(token_start);
+        if !token.contains('/') {
+            return Vec::new();
+        }
+        let (dir, _) = token.rsplit_once('/').unwrap_or_default();
+        let dir_path = match dir.strip_prefix('~') {
+            Some(rest) => PathBuf::from(env::var("HOME").unwrap_or_default() + rest),
+            None if dir.is_empty() => PathBuf::from("/"),
+            None => PathBuf::from(dir),
+        };
+        let Ok(entries) = fs::read_dir(dir_path) else {
+            return Vec::new();
+        };
+        let start = token_start + dir.graphemes(true).count() + 1;
+        entries
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let mut name = entry.file_name().to_string_lossy().into_owned();
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    name.push('/');
+                }
+                Candidate::new(name, start)
+            })
+            .collect()
+    }
+}
+
+/// The snippets of the file type, by the word they complete.
+pub struct SnippetSource;
+
+impl CompletionSource for SnippetSource {
+    fn icon(&self) -> char {
+        '$'
+    }
+
+    fn candidates(&mut self, document: &mut Document, context: &Context) -> Vec<Candidate> {
+        let start = context.word_start();
+        document
+            .settings()
+            .snippets
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(label, body)| Candidate {
+                insert_text: body,
+                snippet: true,
+                detail: Some("snippet".to_string()),
+                ..Candidate::new(label, start)
+            })
+            .collect()
+    }
+}
+
+pub struct Completion {
+    sources: Vec<Box<dyn CompletionSource>>,
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Self {
+            sources: vec![
+                Box::new(LspSource),
+                Box::new(SnippetSource),
+                Box::new(BufferWordsSource),
+                Box::new(PathSource),
+            ],
+        }
+    }
+}
+
+impl Completion {
+    /// Asks every source for candidates at `position`, dropping duplicates
+    /// of what a higher priority source already offered.
+    pub fn complete(&mut self, document: &mut Document, position: &Position) -> Option<Menu> {
+        let context = Context::new(document, position);
+        let mut seen = HashSet::new();
+        let mut candidates = Vec::new();
+        for source in &mut self.sources {
+            let icon = source.icon();
+            for mut candidate in source.candidates(document, &context) {
+                if seen.insert(candidate.insert_text.clone()) {
+                    candidate.icon = icon;
+                    candidates.push(candidate);
+                }
+            }
+        }
+        let mut menu = Menu {
+            candidates,
+            matches: Vec::new(),
+            selected: 0,
+        };
+        menu.refilter(&context);
+        (!menu.is_empty()).then_some(menu)
+    }
+}
+
+/// The completion popup: candidates ranked against what has been typed.
+pub struct Menu {
+    candidates: Vec<Candidate>,
+    matches: Vec<usize>,
+    selected: usize,
+}
+
+impl Menu {
+    /// Re-ranks the candidates after the text before the cursor changed.
+    pub fn update(&mut self, document: &Document, position: &Position) {
+        self.refilter(&Context::new(document, position));
+    }
+
+    fn refilter(&mut self, context: &Context) {
+        let mut scored: Vec<(i64, usize)> = self
+            .candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.start <= context.position.x)
+            .filter_map(|(index, candidate)| {
+                fuzzy_score(&context.text(candidate.start), &candidate.label)
+                    .map(|score| (score, index))
+            })
+            .collect();
+        // Stable, so equal scores keep the source priority order.
+        scored.sort_by_key(|&(score, _)| Reverse(score));
+        self.matches = scored.into_iter().map(|(_, index)| index).collect();
+        self.selected = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + 1) % self.matches.len();
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = self
+                .selected
+                .checked_sub(1)
+                .unwrap_or(self.matches.len() - 1);
+        }
+    }
+
+    /// Asks the language server for the details of the selected candidate
+    /// the first time it is selected.
+    pub fn resolve_selected(&mut self, document: &mut Document) {
+        let Some(candidate) = self
+            .matches
+            .get(self.selected)
+            .and_then(|index| self.candidates.get_mut(*index))
+        else {
+            return;
+        };
+        if candidate.resolved {
+            return;
+        }
+        candidate.resolved = true;
+        let Some(item) = candidate
+            .item
+            .as_ref()
+            .and_then(|item| document.lsp_resolve_completion(item))
+        else {
+            return;
+        };
+        if let Some(documentation) = item.documentation.clone() {
+            candidate.documentation = Some(documentation_text(documentation));
+        }
+        candidate.item = Some(item);
+    }
+
+    /// The documentation of the selected candidate beside `menu`, the placed
+    /// popup, on a screen `screen_width` wide: right of it if it fits, else
+    /// left of it.
+    pub fn documentation_floating(
+        &self,
+        menu: &FloatingItem,
+        screen_width: usize,
+    ) -> Option<FloatingItem> {
+        let text = self.selected()?.documentation()?;
+        let lines: Vec<StyledLine> = markdown::render(&text, DOCUMENTATION_WIDTH)
+            .into_iter()
+            .take(DOCUMENTATION_HEIGHT)
+            .collect();
+        let width = lines
+            .iter()
+            .map(StyledLine::width)
+            .max()
+            .unwrap_or(0)
+            .min(DOCUMENTATION_WIDTH);
+        if width == 0 {
+            return None;
+        }
+        let (text, styles): (Vec<String>, Vec<Vec<Style>>) = lines
+            .into_iter()
+            .map(|line| (line.text, line.styles))
+            .unzip();
+        let right = menu.get_pos().x + menu.get_width() + 1;
+        let x = if right + width <= screen_width {
+            right
+        } else {
+            menu.get_pos().x.saturating_sub(width + 1)
+        };
+        let pos = Position {
+            x,
+            y: menu.get_pos().y,
+        };
+        Some(FloatingItem::new(pos, width, text.len(), text).with_styles(styles))
+    }
+
+    pub fn selected(&self) -> Option<&Candidate> {
+        self.matches
+            .get(self.selected)
+            .and_then(|index| self.candidates.get(*index))
+    }
+
+    /// The popup, anchored at the document position `anchor`.
+    pub fn floating(&self, anchor: Position) -> FloatingItem {
+        let first = self.selected.saturating_sub(MENU_HEIGHT - 1);
+        let lines: Vec<String> = self
+            .matches
+            .iter()
+            .skip(first)
+            .take(MENU_HEIGHT)
+            .filter_map(|index| self.candidates.get(*index))
+            .map(|candidate| match &candidate.detail {
+                Some(detail) => format!("{} {}  {detail}", candidate.icon, candidate.label),
+                None => format!("{} {}", candidate.icon, candidate.label),
+            })
+            .collect();
+        let width = lines
+            .iter()
+            .map(|line| line.graphemes(true).count())
+            .max()
+            .unwrap_or(0);
+        FloatingItem::new(Position::default(), width, lines.len(), lines)
+            .with_selected(self.selected - first)
+            .with_anchor(anchor)
+    }
+}
+
+/// Scores `candidate` as a case-insensitive subsequence match of `query`,
+/// rewarding consecutive runs, word starts and an exact prefix. `None` if
+/// it does not match at all.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate_chars: Vec<char> = candidate.chars().collect();
+    let mut score: i64 = 0;
+    let mut index = 0;
+    let mut previous_match: Option<usize> = None;
+    for query_char in query.chars() {
+        let found = candidate_chars[index..]
+            .iter()
+            .position(|c| c.to_lowercase().eq(query_char.to_lowercase()))?;
+        let position = index + found;
+        score += 1;
+        if previous_match.is_some_and(|previous| previous + 1 == position) {
+            score += 5;
+        }
+        let at_word_start = position == 0
+            || candidate_chars
+                .get(position - 1)
+                .is_some_and(|c| !c.is_alphanumeric())
+            || (candidate_chars[position].is_uppercase()
+                && candidate_chars[position - 1].is_lowercase());
+        if at_word_start {
+            score += 3;
+        }
+        previous_match = Some(position);
+        index = position + 1;
+    }
+    if candidate.starts_with(query) {
+        score += 10;
+    }
+    Some(score * 100 - candidate_chars.len() as i64)
+}
+
+fn documentation_text(documentation: Documentation) -> String {
+    match documentation {
+        Documentation::String(text) => text,
+        Documentation::MarkupContent(content) => content.value,
+    }
+}
+
+fn is_word(grapheme: &str) -> bool {
+    grapheme.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn kind_name(kind: CompletionItemKind) -> Option<String> {
+    let name = match kind {
+        CompletionItemKind::METHOD => "method",
+        CompletionItemKind::FUNCTION => "function",
+        CompletionItemKind::CONSTRUCTOR => "constructor",
+        CompletionItemKind::FIELD => "field",
+        CompletionItemKind::VARIABLE => "variable",
+        CompletionItemKind::CLASS => "class",
+        CompletionItemKind::INTERFACE => "interface",
+        CompletionItemKind::MODULE => "module",
+        CompletionItemKind::PROPERTY => "property",
+        CompletionItemKind::ENUM => "enum",
+        CompletionItemKind::KEYWORD => "keyword",
+        CompletionItemKind::SNIPPET => "snippet",
+        CompletionItemKind::CONSTANT => "constant",
+        CompletionItemKind::STRUCT => "struct",
+        CompletionItemKind::TYPE_PARAMETER => "type parameter",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_score_ranking() {
+        let score = |candidate| fuzzy_score("get", candidate).unwrap();
+        // An exact prefix beats a run at a word start, which beats letters
+        // at word starts, which beat letters scattered over the word; the
+        // shorter candidate wins among equals.
+        assert!(score("get") > score("get_value"));
+        assert!(score("get_value") > score("try_get"));
+        assert!(score("try_get") > score("g_e_t"));
+        assert!(score("g_e_t") > score("gadget"));
+    }
+
+    #[test]
+    fn fuzzy_score_not_only_prefixes() {
+        assert!(fuzzy_score("gv", "get_value").is_some());
+        assert!(fuzzy_score("GV", "getValue").is_some());
+        assert!(fuzzy_score("val", "get_value").is_some());
+        assert!(fuzzy_score("vg", "get_value").is_none());
+        assert!(fuzzy_score("getx", "get_value").is_none());
+        // Camel humps count as word starts.
+        assert!(fuzzy_score("gv", "getValue") > fuzzy_score("gv", "gravy"));
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::filetype::Settings;
+use crate::save::{OnFailure, Step};
+use crate::theme::{Background, Colors, DEFAULT_THEME};
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(rename = "language_server")]
+    language_servers: Vec<LanguageServerConfig>,
+    #[serde(rename = "grammar")]
+    grammars: Vec<GrammarConfig>,
+    /// Show a diff and ask before applying formatting or code actions.
+    preview_edits: bool,
+    /// Paragraphs of hovers are reflowed to this many columns.
+    hover_max_width: usize,
+    /// Name of the theme in the `themes` directory.
+    theme: String,
+    /// `light` or `dark`; asked from the terminal if not set.
+    background: Option<Background>,
+    /// Color brackets by how deep they nest.
+    rainbow_brackets: bool,
+    /// `auto`, `truecolor`, `256`, `16` or `monochrome`.
+    colors: Colors,
+    /// Draw background colors; without them, what they mark is shown by
+    /// attributes.
+    background_colors: bool,
+    /// Underline misspelled words of comments and strings.
+    spell_check: bool,
+    /// Continue lists and quotes of Markdown on `Enter`.
+    continue_lists: bool,
+    /// Settings by language, e.g. `[filetype.python]`, over those of the
+    /// registry.
+    #[serde(rename = "filetype")]
+    file_types: HashMap<String, Settings>,
+    /// A command run by the shell in the workspace root after every save,
+    /// whose `path:line:column: message` lines become diagnostics.
+    on_save: Option<String>,
+    /// Patterns, as in `.gitignore`, of files and directories searches of
+    /// the workspace skip on top of those of the `.gitignore` files.
+    ignore: Vec<String>,
+    /// The hunspell `.dic` file to check with; `en_US.dic` of the
+    /// `dictionaries` directory or of the system if not set.
+    dictionary: Option<PathBuf>,
+    /// Give the line of the message bar to the text while there is no
+    /// message or prompt.
+    hide_message_bar: bool,
+    /// Show the words of the buffer, or of the selection, in the status
+    /// bar.
+    word_count: bool,
+    /// The language of the interface, e.g. `ja`; from `LANG` and the like
+    /// if not set.
+    locale: Option<String>,
+    /// Index the files, symbols and TODOs of the workspace in the
+    /// background.
+    index: bool,
+    /// What the buffer goes through before it is saved, in order, unless
+    /// the file type has its own.
+    save_steps: Option<Vec<Step>>,
+    /// Whether a failing save step aborts the save.
+    save_failure: Option<OnFailure>,
+    /// Files of more bytes than this are opened without highlighting and
+    /// language server.
+    big_file_size: u64,
+    /// As are files with a line of more bytes than this, e.g. minified
+    /// code.
+    big_file_line_length: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            language_servers: Vec::new(),
+            grammars: Vec::new(),
+            preview_edits: true,
+            hover_max_width: 80,
+            theme: DEFAULT_THEME.to_string(),
+            background: None,
+            rainbow_brackets: false,
+            colors: Colors::default(),
+            background_colors: true,
+            spell_check: false,
+            continue_lists: true,
+            ignore: Vec::new(),
+            on_save: None,
+            file_types: HashMap::new(),
+            dictionary: None,
+            hide_message_bar: false,
+            word_count: false,
+            locale: None,
+            index: true,
+            save_steps: None,
+            save_failure: None,
+            big_file_size: 8 * 1024 * 1024,
+            big_file_line_length: 10_000,
+        }
+    }
+}
+
+/// A `[[language_server]]` entry of the config file.
+///
+/// ```toml
+/// [[language_server]]
+/// extensions = ["ts", "tsx"]
+/// command = "typescript-language-server"
+/// args = ["--stdio"]
+/// language_id = "typescript"
+///
+/// [language_server.initialization_options]
+/// preferences = { quotePreference = "single" }
+///
+/// [language_server.settings]
+/// typescript = { format = { semicolons = "remove" } }
+/// ```
+#[derive(Clone, Deserialize)]
+pub struct LanguageServerConfig {
+    pub extensions: Vec<String>,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub language_id: String,
+    /// Sent as `initializationOptions` of the `initialize` request.
+    pub initialization_options: Option<Value>,
+    /// Returned for `workspace/configuration` requests by section.
+    pub settings: Option<Value>,
+}
+
+/// A `[[grammar]]` entry of the config file: a tree-sitter grammar loaded
+/// at runtime, highlighted with `queries/<name>/highlights.scm`.
+///
+/// ```toml
+/// [[grammar]]
+/// name = "toml"
+/// extensions = ["toml"]
+/// library = "/usr/local/lib/tree-sitter/toml.so"
+/// ```
+#[derive(Clone, Deserialize)]
+pub struct GrammarConfig {
+    pub name: String,
+    pub extensions: Vec<String>,
+    /// `grammars/<name>.so` of the config directory by default.
+    pub library: Option<PathBuf>,
+}
+
+impl Config {
+    /// Loads `config.toml` from the config directory.
+    /// A missing file is not an error and yields the default config.
+    pub fn load() -> Result<Self> {
+        match config_dir() {
+            Some(dir) => Self::load_from(&dir.join(CONFIG_FILE_NAME)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Layers `project` on top of `self`; project entries win.
+    pub fn merge(&mut self, project: Self) {
+        let mut language_servers = project.language_servers;
+        language_servers.append(&mut self.language_servers);
+        self.language_servers = language_servers;
+        let mut grammars = project.grammars;
+        grammars.append(&mut self.grammars);
+        self.grammars = grammars;
+        self.ignore.extend(project.ignore);
+        self.file_types.extend(project.file_types);
+        if project.on_save.is_some() {
+            self.on_save = project.on_save;
+        }
+        if project.save_steps.is_some() {
+            self.save_steps = project.save_steps;
+        }
+        if project.save_failure.is_some() {
+            self.save_failure = project.save_failure;
+        }
+    }
+
+    pub fn preview_edits(&self) -> bool {
+        self.preview_edits
+    }
+
+    pub fn hover_max_width(&self) -> usize {
+        self.hover_max_width
+    }
+
+    pub fn theme(&self) -> &str {
+        &self.theme
+    }
+
+    pub fn background(&self) -> Option<Background> {
+        self.background
+    }
+
+    pub fn rainbow_brackets(&self) -> bool {
+        self.rainbow_brackets
+    }
+
+    pub fn colors(&self) -> Colors {
+        self.colors
+    }
+
+    pub fn background_colors(&self) -> bool {
+        self.background_colors
+    }
+
+    pub fn spell_check(&self) -> bool {
+        self.spell_check
+    }
+
+    pub fn continue_lists(&self) -> bool {
+        self.continue_lists
+    }
+
+    pub fn on_save(&self) -> Option<&str> {
+        self.on_save.as_deref()
+    }
+
+    pub fn file_type_settings(&self, language: &str) -> Option<&Settings> {
+        self.file_types.get(language)
+    }
+
+    pub fn ignore(&self) -> &[String] {
+        &self.ignore
+    }
+
+    pub fn dictionary(&self) -> Option<&Path> {
+        self.dictionary.as_deref()
+    }
+
+    pub fn hide_message_bar(&self) -> bool {
+        self.hide_message_bar
+    }
+
+    pub fn word_count(&self) -> bool {
+        self.word_count
+    }
+
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
+    pub fn index(&self) -> bool {
+        self.index
+    }
+
+    pub fn save_steps(&self) -> &[Step] {
+        self.save_steps.as_deref().unwrap_or_default()
+    }
+
+    pub fn save_failure(&self) -> OnFailure {
+        self.save_failure.unwrap_or_default()
+    }
+
+    pub fn big_file_size(&self) -> u64 {
+        self.big_file_size
+    }
+
+    pub fn big_file_line_length(&self) -> usize {
+        self.big_file_line_length
+    }
+
+    pub fn language_servers(&self) -> &[LanguageServerConfig] {
+        &self.language_servers
+    }
+
+    pub fn grammars(&self) -> &[GrammarConfig] {
+        &self.grammars
+    }
+
+    pub fn grammar(&self, file_name: &str) -> Option<&GrammarConfig> {
+        let extension = Path::new(file_name).extension()?.to_str()?;
+        self.grammars
+            .iter()
+            .find(|grammar| grammar.extensions.iter().any(|ext| ext == extension))
+    }
+
+    pub fn language_server(&self, file_name: &str) -> Option<&LanguageServerConfig> {
+        let extension = Path::new(file_name).extension()?.to_str()?;
+        self.language_servers
+            .iter()
+            .find(|server| server.extensions.iter().any(|ext| ext == extension))
+    }
+}
+
+/// `$XDG_CONFIG_HOME/neonano`, falling back to `~/.config/neonano`.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("neonano"));
+    }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("neonano"))
+}
+
+/// `$XDG_DATA_HOME/neonano`, falling back to `~/.local/share/neonano`.
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("neonano"));
+    }
+    env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(".local")
+            .join("share")
+            .join("neonano")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = r#"
+hover_max_width = 60
+ignore = ["target"]
+on_save = "make check"
+
+[[language_server]]
+extensions = ["ts", "tsx"]
+command = "typescript-language-server"
+args = ["--stdio"]
+language_id = "typescript"
+
+[language_server.settings]
+typescript = { format = { semicolons = "remove" } }
+"#;
+
+    #[test]
+    fn parsed() {
+        let config: Config = toml::from_str(USER).unwrap();
+        assert_eq!(config.hover_max_width(), 60);
+        // What is not set keeps its default.
+        assert!(config.preview_edits());
+        let server = config.language_server("src/app.tsx").unwrap();
+        assert_eq!(server.command, "typescript-language-server");
+        assert_eq!(server.args, ["--stdio"]);
+        assert_eq!(server.language_id, "typescript");
+        assert!(server.initialization_options.is_none());
+        assert_eq!(
+            server.settings.as_ref().unwrap()["typescript"]["format"]["semicolons"],
+            "remove"
+        );
+        assert!(config.language_server("main.rs").is_none());
+        assert!(config.language_server("Makefile").is_none());
+    }
+
+    #[test]
+    fn missing_file_default() {
+        let config = Config::load_from(Path::new("target/snap/missing/config.toml")).unwrap();
+        assert!(config.language_servers().is_empty());
+        assert_eq!(config.theme(), DEFAULT_THEME);
+    }
+
+    #[test]
+    fn project_merged() {
+        let mut config: Config = toml::from_str(USER).unwrap();
+        let project: Config = toml::from_str(
+            r#"
+ignore = ["dist"]
+hover_max_width = 100
+
+[[language_server]]
+extensions = ["ts"]
+command = "deno"
+args = ["lsp"]
+language_id = "typescript"
+"#,
+        )
+        .unwrap();
+        config.merge(project);
+        // The project's server comes first, the user's still serves `.tsx`.
+        assert_eq!(config.language_server("main.ts").unwrap().command, "deno");
+        assert_eq!(
+            config.language_server("app.tsx").unwrap().command,
+            "typescript-language-server"
+        );
+        assert_eq!(config.ignore(), ["target", "dist"]);
+        // Unset in the project, so the user's stays.
+        assert_eq!(config.on_save(), Some("make check"));
+        // Only some settings can be set by a project.
+        assert_eq!(config.hover_max_width(), 60);
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How much text there is: lines, words as `wc` counts them, characters as
+/// graphemes and bytes, line breaks included.
+#[derive(Clone, Copy, Default)]
+pub struct Counts {
+    pub lines: usize,
+    pub words: usize,
+    pub graphemes: usize,
+    pub bytes: usize,
+}
+
+impl Counts {
+    pub fn of(text: &str) -> Self {
+        Self {
+            lines: text.lines().count(),
+            words: text.split_whitespace().count(),
+            graphemes: text.graphemes(true).count(),
+            bytes: text.len(),
+        }
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} lines, {} words, {} characters, {} bytes",
+            self.lines, self.words, self.graphemes, self.bytes
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multibyte() {
+        let counts = Counts::of("naïve café\n日本語 テキスト\n👍🏽 e\u{301}\n");
+        assert_eq!(counts.lines, 3);
+        assert_eq!(counts.words, 6);
+        assert_eq!(counts.graphemes, 10 + 1 + 8 + 1 + 3 + 1);
+        assert_eq!(counts.bytes, 12 + 1 + 22 + 1 + 12 + 1);
+        assert_eq!(
+            counts.to_string(),
+            "3 lines, 6 words, 24 characters, 49 bytes"
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let counts = Counts::of("");
+        assert_eq!(
+            counts.lines + counts.words + counts.graphemes + counts.bytes,
+            0
+        );
+        let counts = Counts::of(" \t\n");
+        assert_eq!((counts.lines, counts.words, counts.graphemes), (1, 0, 3));
+    }
+}
@@ -0,0 +1,448 @@
+use termion::color;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::floating_item::FloatingItem;
+use crate::theme::{self, Style};
+use crate::Position;
+
+const CONTEXT: usize = 2;
+/// Starts the lines of the right side of a side by side comparison.
+const SEPARATOR: char = '│';
+
+/// One line of a line-based diff between an old and a new text.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Line diff of `old` against `new` using Myers' O(ND) algorithm, so large
+/// inputs with few changes stay cheap.
+pub fn lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Change<'a>> {
+    diff(old, new)
+}
+
+/// Word diff of two lines: runs of word characters, runs of whitespace and
+/// single punctuation characters are compared as a whole.
+pub fn words<'a>(old: &'a str, new: &'a str) -> Vec<Change<'a>> {
+    diff(&tokens(old), &tokens(new))
+}
+
+fn tokens(line: &str) -> Vec<&str> {
+    let class = |grapheme: &str| {
+        let c = grapheme.chars().next().unwrap_or_default();
+        if c.is_alphanumeric() || c == '_' {
+            1
+        } else if c.is_whitespace() {
+            2
+        } else {
+            0
+        }
+    };
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut previous = None;
+    for (index, grapheme) in line.grapheme_indices(true) {
+        let current = class(grapheme);
+        if index > start && (current == 0 || previous != Some(current)) {
+            tokens.push(&line[start..index]);
+            start = index;
+        }
+        previous = Some(current);
+    }
+    if start < line.len() {
+        tokens.push(&line[start..]);
+    }
+    tokens
+}
+
+fn diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Change<'a>> {
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut changes: Vec<Change<'a>> = old[..prefix].iter().map(|l| Change::Same(l)).collect();
+    changes.extend(myers(old_middle, new_middle));
+    changes.extend(old[old.len() - suffix..].iter().map(|l| Change::Same(l)));
+    changes
+}
+
+fn myers<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Change<'a>> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = n + m;
+    if max == 0 {
+        return Vec::new();
+    }
+    let offset = max;
+    let mut v = vec![0_isize; 2 * max as usize + 2];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    // Walk the trace backwards to recover the edit script.
+    let mut changes = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let index = (k + offset) as usize;
+        let previous_k = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = v[(previous_k + offset) as usize];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            changes.push(Change::Same(old[x as usize]));
+        }
+        if d > 0 {
+            if x == previous_x {
+                y -= 1;
+                changes.push(Change::Added(new[y as usize]));
+            } else {
+                x -= 1;
+                changes.push(Change::Removed(old[x as usize]));
+            }
+        }
+    }
+    changes.reverse();
+    changes
+}
+
+/// Renders `changes` as unified diff lines, keeping `context` unchanged
+/// lines around every change and separating hunks with `@@` headers.
+pub fn unified(changes: &[Change], context: usize) -> Vec<String> {
+    let changed: Vec<usize> = changes
+        .iter()
+        .enumerate()
+        .filter(|(_, change)| !matches!(change, Change::Same(_)))
+        .map(|(index, _)| index)
+        .collect();
+    let mut result = Vec::new();
+    let mut old_line = 1;
+    let mut new_line = 1;
+    let mut last_shown: Option<usize> = None;
+    for (index, change) in changes.iter().enumerate() {
+        let near_change = changed
+            .iter()
+            .any(|c| index + context >= *c && index <= c + context);
+        if near_change {
+            if last_shown.is_none_or(|last| last + 1 != index) {
+                result.push(format!("@@ -{old_line} +{new_line} @@"));
+            }
+            result.push(match change {
+                Change::Same(line) => format!(" {line}"),
+                Change::Removed(line) => format!("-{line}"),
+                Change::Added(line) => format!("+{line}"),
+            });
+            last_shown = Some(index);
+        }
+        match change {
+            Change::Same(_) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            Change::Removed(_) => old_line += 1,
+            Change::Added(_) => new_line += 1,
+        }
+    }
+    result
+}
+
+/// A unified diff of `old` against `new` as a floating item at `pos` of at
+/// most `width` x `height`. Removed and added lines that pair up within a hunk
+/// additionally have the words that differ between them highlighted.
+pub fn floating(old: &str, new: &str, pos: Position, width: usize, height: usize) -> FloatingItem {
+    floating_lines(&unified_text(old, new), 0, pos, width, height)
+}
+
+/// The unified diff lines of `old` against `new`, see `unified`.
+pub fn unified_text(old: &str, new: &str) -> Vec<String> {
+    let old_lines: Vec<&str> = old.split('\n').collect();
+    let new_lines: Vec<&str> = new.split('\n').collect();
+    unified(&lines(&old_lines, &new_lines), CONTEXT)
+}
+
+/// The unified diff `lines` from `first` on as a floating item, like
+/// `floating`, to scroll through a long one.
+pub fn floating_lines(
+    lines: &[String],
+    first: usize,
+    pos: Position,
+    width: usize,
+    height: usize,
+) -> FloatingItem {
+    let rendered: Vec<String> = lines
+        .iter()
+        .skip(first)
+        .take(height)
+        .map(|line| line.graphemes(true).take(width).collect())
+        .collect();
+    let ui = &theme::current().ui;
+    let colors = rendered
+        .iter()
+        .map(|line| match line.chars().next() {
+            Some('+') => ui.diff_added.fg,
+            Some('-') => ui.diff_removed.fg,
+            Some('@') => ui.diff_hunk.fg,
+            _ => None,
+        })
+        .collect();
+    let styles = word_styles(&rendered);
+    FloatingItem::new(pos, width, rendered.len(), rendered)
+        .with_line_colors(colors)
+        .with_styles(styles)
+}
+
+/// One side of a side by side comparison: a `-`, `+` or ` ` prefixed line
+/// of its text, or an empty line where the other side has lines of its own.
+/// The lines of the right side start with a separator.
+#[derive(Default)]
+pub struct Pane {
+    lines: Vec<String>,
+    colors: Vec<Option<color::Rgb>>,
+    styles: Vec<Vec<Style>>,
+}
+
+impl Pane {
+    fn push(&mut self, line: String, color: Option<color::Rgb>, styles: Vec<Style>) {
+        self.lines.push(line);
+        self.colors.push(color);
+        self.styles.push(styles);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The lines from `first` on as a floating item at `pos` of at most
+    /// `width` x `height`.
+    pub fn floating(
+        &self,
+        first: usize,
+        pos: Position,
+        width: usize,
+        height: usize,
+    ) -> FloatingItem {
+        let end = (first + height).min(self.len());
+        let first = first.min(end);
+        let lines = self.lines[first..end].to_vec();
+        FloatingItem::new(pos, width, lines.len(), lines)
+            .with_line_colors(self.colors[first..end].to_vec())
+            .with_styles(self.styles[first..end].to_vec())
+    }
+}
+
+/// `old` and `new` side by side under their titles, with the lines that
+/// differ facing each other and the words that changed between them marked.
+pub fn panes(old: &str, new: &str, old_title: &str, new_title: &str) -> (Pane, Pane) {
+    let old_lines: Vec<&str> = old.split('\n').collect();
+    let new_lines: Vec<&str> = new.split('\n').collect();
+    let changes = lines(&old_lines, &new_lines);
+    let ui = &theme::current().ui;
+    let (mut left, mut right) = (Pane::default(), Pane::default());
+    let mut push_right = |line: &str, color, mut styles: Vec<Style>| {
+        if !styles.is_empty() {
+            styles.insert(0, Style::default());
+        }
+        right.push(format!("{SEPARATOR}{line}"), color, styles);
+    };
+    left.push(old_title.to_string(), ui.diff_hunk.fg, Vec::new());
+    push_right(new_title, ui.diff_hunk.fg, Vec::new());
+    let mut index = 0;
+    while index < changes.len() {
+        if let Change::Same(line) = changes[index] {
+            left.push(format!(" {line}"), None, Vec::new());
+            push_right(&format!(" {line}"), None, Vec::new());
+            index += 1;
+            continue;
+        }
+        let removed: Vec<&str> = changes[index..]
+            .iter()
+            .map_while(|change| match change {
+                Change::Removed(line) => Some(*line),
+                _ => None,
+            })
+            .collect();
+        let added: Vec<&str> = changes[index + removed.len()..]
+            .iter()
+            .map_while(|change| match change {
+                Change::Added(line) => Some(*line),
+                _ => None,
+            })
+            .collect();
+        for row in 0..removed.len().max(added.len()) {
+            let (old_styles, new_styles) = match (removed.get(row), added.get(row)) {
+                (Some(old), Some(new)) => changed_words(old, new),
+                _ => (Vec::new(), Vec::new()),
+            };
+            match removed.get(row) {
+                Some(line) => left.push(format!("-{line}"), ui.diff_removed.fg, old_styles),
+                None => left.push(String::new(), None, Vec::new()),
+            }
+            match added.get(row) {
+                Some(line) => push_right(&format!("+{line}"), ui.diff_added.fg, new_styles),
+                None => push_right("", None, Vec::new()),
+            }
+        }
+        index += removed.len() + added.len();
+    }
+    (left, right)
+}
+
+/// Pairs every run of `-` lines with the `+` lines that follow it and marks
+/// the graphemes of the words that changed.
+fn word_styles(lines: &[String]) -> Vec<Vec<Style>> {
+    let mut styles = vec![Vec::new(); lines.len()];
+    let mut index = 0;
+    while index < lines.len() {
+        let removed = lines[index..]
+            .iter()
+            .take_while(|line| line.starts_with('-'))
+            .count();
+        let added = lines[index + removed..]
+            .iter()
+            .take_while(|line| line.starts_with('+'))
+            .count();
+        for pair in 0..removed.min(added) {
+            let old_index = index + pair;
+            let new_index = index + removed + pair;
+            let (old_styles, new_styles) =
+                changed_words(&lines[old_index][1..], &lines[new_index][1..]);
+            styles[old_index] = old_styles;
+            styles[new_index] = new_styles;
+        }
+        index += (removed + added).max(1);
+    }
+    styles
+}
+
+fn changed_words(old: &str, new: &str) -> (Vec<Style>, Vec<Style>) {
+    let ui = &theme::current().ui;
+    let removed = ui.diff_removed_word;
+    let added = ui.diff_added_word;
+    // The first grapheme is the `-` or `+` prefix.
+    let mut old_styles = vec![Style::default()];
+    let mut new_styles = vec![Style::default()];
+    for change in words(old, new) {
+        match change {
+            Change::Same(word) => {
+                let count = word.graphemes(true).count();
+                old_styles.extend(std::iter::repeat_n(Style::default(), count));
+                new_styles.extend(std::iter::repeat_n(Style::default(), count));
+            }
+            Change::Removed(word) => {
+                old_styles.extend(std::iter::repeat_n(removed, word.graphemes(true).count()));
+            }
+            Change::Added(word) => {
+                new_styles.extend(std::iter::repeat_n(added, word.graphemes(true).count()));
+            }
+        }
+    }
+    (old_styles, new_styles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Change::{Added, Removed, Same};
+
+    #[test]
+    fn line_changes() {
+        let changes = lines(&["a", "b", "c", "d"], &["a", "x", "c", "d", "e"]);
+        assert_eq!(
+            changes,
+            [
+                Same("a"),
+                Removed("b"),
+                Added("x"),
+                Same("c"),
+                Same("d"),
+                Added("e")
+            ]
+        );
+        assert_eq!(lines(&[], &[]), []);
+        assert_eq!(lines(&["a"], &[]), [Removed("a")]);
+    }
+
+    #[test]
+    fn word_changes() {
+        let changes = words("let x = foo(1);", "let y = foo(1);");
+        assert_eq!(
+            changes,
+            [
+                Same("let"),
+                Same(" "),
+                Removed("x"),
+                Added("y"),
+                Same(" "),
+                Same("="),
+                Same(" "),
+                Same("foo"),
+                Same("("),
+                Same("1"),
+                Same(")"),
+                Same(";"),
+            ]
+        );
+    }
+
+    #[test]
+    fn hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9";
+        let new = "1\ntwo\n3\n4\n5\n6\n7\n8\nnine";
+        assert_eq!(
+            unified_text(old, new),
+            [
+                "@@ -1 +1 @@",
+                " 1",
+                "-2",
+                "+two",
+                " 3",
+                " 4",
+                "@@ -7 +7 @@",
+                " 7",
+                " 8",
+                "-9",
+                "+nine",
+            ]
+        );
+        assert!(unified_text(old, old).is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use tree_sitter::{Language, Node, Parser, Point};
+
+const PLACEHOLDER: &str = "Description.";
+
+/// A doc comment to insert as a snippet before the line `row`.
+pub struct Skeleton {
+    pub row: usize,
+    pub snippet: String,
+}
+
+/// The doc comment skeleton of the function whose signature is on line
+/// `row` of `lines`: `///` comments with argument and return sections for
+/// Rust, a Google style docstring for Python. `None` for other languages,
+/// other lines and functions that are documented already.
+pub fn skeleton(language_id: &str, lang: Language, lines: &[&str], row: usize) -> Option<Skeleton> {
+    let source = lines.join("\n");
+    let mut parser = Parser::new();
+    parser.set_language(lang).ok()?;
+    let tree = parser.parse(&source, None)?;
+    let line = lines.get(row)?;
+    let indent = line.len() - line.trim_start().len();
+    let node = tree
+        .root_node()
+        .descendant_for_point_range(Point::new(row, indent), Point::new(row, line.len()))?;
+    let kinds: &[&str] = match language_id {
+        "rust" => &["function_item", "function_signature_item"],
+        "python" => &["function_definition"],
+        _ => return None,
+    };
+    let function = ancestors(node).find(|node| {
+        let signature_end = node
+            .child_by_field_name("body")
+            .map_or(node.end_position().row, |body| body.start_position().row);
+        kinds.contains(&node.kind()) && (node.start_position().row..=signature_end).contains(&row)
+    })?;
+    let text = |node: Node| node.utf8_text(source.as_bytes()).unwrap_or_default();
+    match language_id {
+        "rust" => rust(function, lines, text),
+        _ => python(function, lines, text),
+    }
+}
+
+fn ancestors(node: Node) -> impl Iterator<Item = Node> {
+    std::iter::successors(Some(node), Node::parent)
+}
+
+/// Above the function and its attributes.
+fn rust<'a>(
+    function: Node<'a>,
+    lines: &[&str],
+    text: impl Fn(Node<'a>) -> &'a str,
+) -> Option<Skeleton> {
+    let mut first = function;
+    while let Some(previous) = first
+        .prev_named_sibling()
+        .filter(|previous| previous.kind() == "attribute_item")
+    {
+        first = previous;
+    }
+    let row = first.start_position().row;
+    if row > 0 && lines[row - 1].trim_start().starts_with("///") {
+        return None;
+    }
+    let indent = indent(lines, first.start_position());
+    let parameters: Vec<&str> = children(function.child_by_field_name("parameters"))
+        .filter(|parameter| parameter.kind() == "parameter")
+        .filter_map(|parameter| parameter.child_by_field_name("pattern"))
+        .map(&text)
+        .collect();
+    let returns = function
+        .child_by_field_name("return_type")
+        .is_some_and(|returned| text(returned) != "()");
+
+    let mut stop = 0;
+    let mut next = || {
+        stop += 1;
+        stop
+    };
+    let mut lines = vec![format!("${{{}:Summary.}}", next())];
+    if !parameters.is_empty() {
+        lines.extend([String::new(), "# Arguments".to_string(), String::new()]);
+        for parameter in parameters {
+            let stop = next();
+            lines.push(format!(
+                "* `{}` - ${{{stop}:{PLACEHOLDER}}}",
+                escape(parameter)
+            ));
+        }
+    }
+    if returns {
+        let stop = next();
+        lines.extend([String::new(), "# Returns".to_string(), String::new()]);
+        lines.push(format!("${{{stop}:{PLACEHOLDER}}}"));
+    }
+    let snippet = lines
+        .iter()
+        .map(|line| {
+            format!(
+                "{indent}///{}{line}\n",
+                if line.is_empty() { "" } else { " " }
+            )
+        })
+        .collect();
+    Some(Skeleton { row, snippet })
+}
+
+/// As the first statement of the body.
+fn python<'a>(
+    function: Node<'a>,
+    lines: &[&str],
+    text: impl Fn(Node<'a>) -> &'a str,
+) -> Option<Skeleton> {
+    let body = function.child_by_field_name("body")?;
+    let row = body.start_position().row;
+    // The body has to start on its own line.
+    if row == function.start_position().row || row >= lines.len() {
+        return None;
+    }
+    let documented = body
+        .named_child(0)
+        .filter(|statement| statement.kind() == "expression_statement")
+        .and_then(|statement| statement.named_child(0))
+        .is_some_and(|expression| expression.kind() == "string");
+    if documented {
+        return None;
+    }
+    let indent = indent(lines, body.start_position());
+    let parameters: Vec<&str> = children(function.child_by_field_name("parameters"))
+        .filter_map(|parameter| match parameter.kind() {
+            "identifier" | "list_splat_pattern" | "dictionary_splat_pattern" => Some(parameter),
+            "typed_parameter" => parameter.named_child(0),
+            "default_parameter" | "typed_default_parameter" => {
+                parameter.child_by_field_name("name")
+            }
+            _ => None,
+        })
+        .map(&text)
+        .filter(|name| !matches!(*name, "self" | "cls"))
+        .collect();
+    let returns = function
+        .child_by_field_name("return_type")
+        .is_some_and(|returned| text(returned) != "None");
+
+    let mut stop = 0;
+    let mut next = || {
+        stop += 1;
+        stop
+    };
+    let summary = format!("\"\"\"${{{}:Summary.}}", next());
+    if parameters.is_empty() && !returns {
+        return Some(Skeleton {
+            row,
+            snippet: format!("{indent}{summary}\"\"\"\n"),
+        });
+    }
+    let mut lines = vec![summary];
+    if !parameters.is_empty() {
+        lines.extend([String::new(), "Args:".to_string()]);
+        for parameter in parameters {
+            let stop = next();
+            lines.push(format!(
+                "    {}: ${{{stop}:{PLACEHOLDER}}}",
+                escape(parameter)
+            ));
+        }
+    }
+    if returns {
+        let stop = next();
+        lines.extend([String::new(), "Returns:".to_string()]);
+        lines.push(format!("    ${{{stop}:{PLACEHOLDER}}}"));
+    }
+    lines.push("\"\"\"".to_string());
+    let snippet = lines
+        .iter()
+        .map(|line| {
+            if line.is_empty() {
+                "\n".to_string()
+            } else {
+                format!("{indent}{line}\n")
+            }
+        })
+        .collect();
+    Some(Skeleton { row, snippet })
+}
+
+/// The whitespace before `point` on its line.
+fn indent<'a>(lines: &[&'a str], point: Point) -> &'a str {
+    lines
+        .get(point.row)
+        .and_then(|line| line.get(..point.column))
+        .unwrap_or_default()
+}
+
+fn children(node: Option<Node>) -> impl Iterator<Item = Node> {
+    let count = node.map_or(0, |node| node.named_child_count());
+    (0..count).filter_map(move |index| node?.named_child(index))
+}
+
+/// Keeps `$`, `}` and `\` of the source from being read as snippet syntax.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('$', "\\$")
+        .replace('}', "\\}")
+}
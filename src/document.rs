@@ -1,78 +1,520 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::env::current_dir;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
 
-use anyhow::Result;
-use lsp_types::HoverContents;
-use unicode_segmentation::UnicodeSegmentation;
+use anyhow::{anyhow, Result};
+use lsp_types::{
+    CompletionItem, Diagnostic, Hover, HoverContents, Location, MarkedString, MarkupKind, TextEdit,
+};
+use tree_sitter::{InputEdit, Language, Point};
 
+use crate::annotation::Annotations;
+use crate::atomic;
+use crate::comment;
+use crate::config::Config;
+use crate::count::Counts;
+use crate::doc_comment::{self, Skeleton};
 use crate::editor::SearchDirection;
+use crate::filetype::Settings;
 use crate::floating_item::FloatingItem;
-use crate::highlighting::Highlight;
-use crate::lsp::LspConnector;
+use crate::fold::Folds;
+use crate::hex::Hex;
+use crate::highlighting::{self, Background, Highlight};
+use crate::indent;
+use crate::lines::Lines;
+use crate::list::{self, Continuation};
+use crate::lsp::{Goto, LspConnector};
+use crate::mark::Marks;
+use crate::markdown;
+use crate::modeline::Modeline;
+use crate::progress::Cancellation;
+use crate::stream::Stream;
+use crate::structural;
+use crate::textobject::{self, Object};
+use crate::undo::{Edit, History};
 use crate::Row;
 use crate::{FileType, Position};
 
+const BOM: &str = "\u{feff}";
+/// Files are read in chunks of this size to report progress.
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+/// The columns a tab is drawn in unless the settings give another width.
+const TAB_WIDTH: usize = 2;
+/// How many lines at the start of a file tell its file type.
+const SAMPLE_LINES: usize = 100;
+
+/// How the lines of a file end.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// The ending of most lines of `contents`.
+    fn detect(contents: &str) -> Self {
+        let lines = contents.matches('\n').count();
+        if contents.matches("\r\n").count() * 2 > lines {
+            Self::CrLf
+        } else {
+            Self::Lf
+        }
+    }
+
+    pub fn named(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "lf" => Some(Self::Lf),
+            "crlf" => Some(Self::CrLf),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        }
+    }
+
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::CrLf => b"\r\n",
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Document {
-    rows: Vec<Row>,
+    rows: Lines,
     pub file_name: Option<String>,
     dirty: bool,
     file_type: FileType,
     floatings: Vec<FloatingItem>,
     lsp: Option<LspConnector>,
-    highlighter: Option<Highlight>,
+    highlighter: Option<Background>,
+    /// Why the queries of the file type do not compile, while the basic
+    /// highlighter stands in for them.
+    highlight_error: Option<String>,
+    config: Rc<Config>,
+    lsp_stale: bool,
+    /// Whether the file started with a UTF-8 byte order mark, which is
+    /// hidden while editing and written back on save.
+    bom: bool,
+    /// What the lines of the file end with, which is written back on save.
+    line_ending: LineEnding,
+    /// Whether the last line of the file had no line break, which it is
+    /// saved without again.
+    unterminated: bool,
+    /// Modification time of the file when it was last read or written.
+    disk_modified: Option<SystemTime>,
+    folds: Folds,
+    /// The notes of the annotated lines.
+    annotations: Annotations,
+    /// The named positions to jump back to.
+    marks: Marks,
+    /// The diagnostics of the last run of the `on_save` command.
+    checked: Vec<Diagnostic>,
+    /// The settings of the modelines of the file and of `set`, over those
+    /// of the file type.
+    overrides: Settings,
+    /// Whether the file is too big, or its lines too long, to be parsed,
+    /// so it is drawn without highlighting and has no language server.
+    plain: bool,
+    /// The edits to undo and redo.
+    history: History,
+    /// The rest of a big file while it is still being read.
+    stream: Option<Stream>,
+    /// Why the rest of the file could not be read, which keeps the part
+    /// that was from being saved over it.
+    stream_error: Option<String>,
+    /// How far the file was read while it is followed.
+    follow: Option<Follow>,
+    /// Whether editing is turned off, e.g. to look at a file safely.
+    read_only: bool,
+    /// Whether the file has NUL bytes or is not UTF-8, so its rows only
+    /// show it, with replacement characters, and are never saved over it.
+    binary: bool,
+    /// The bytes while the buffer is edited as a hex dump.
+    hex: Option<Hex>,
+}
+
+/// How far a followed file was read: lines written after `read` bytes are
+/// appended, the first one to the last row unless it ended there.
+#[derive(Clone, Copy)]
+struct Follow {
+    read: u64,
+    line_start: bool,
 }
 
 impl Document {
-    pub fn open(file_name: &str) -> Result<Self> {
-        let contents = fs::read_to_string(file_name)?;
-        let file_type = FileType::from(file_name).unwrap_or_default();
-        let hl_opt = file_type.highlighting_options();
-        let highlighter = match Highlight::new(
-            hl_opt.get_lang().unwrap(),
-            hl_opt.get_hl_query().unwrap(),
-            hl_opt.get_inj_query().unwrap(),
-        ) {
-            Ok(highlighter) => Some(highlighter),
-            Err(_) => None,
+    pub fn open(file_name: &str, config: Rc<Config>) -> Result<Self> {
+        Self::open_with_progress(file_name, config, &mut |_, _| true)
+    }
+
+    /// Opens `file_name`, calling `report` with the bytes read so far and
+    /// the size of the file after every chunk. Reading stops with an error
+    /// when `report` returns `false`. Of a file bigger than `big_file_size`
+    /// only the first lines are read; the rest streams in, see
+    /// `poll_stream`.
+    pub fn open_with_progress(
+        file_name: &str,
+        config: Rc<Config>,
+        report: &mut dyn FnMut(u64, u64) -> bool,
+    ) -> Result<Self> {
+        let mut file = fs::File::open(file_name)?;
+        let size = file.metadata()?.len();
+        let streamed = size > config.big_file_size();
+        let mut bytes = Vec::with_capacity(if streamed {
+            READ_CHUNK_SIZE
+        } else {
+            size as usize
+        });
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        let mut ended = false;
+        loop {
+            let read = file.read(&mut chunk)?;
+            if read == 0 {
+                ended = true;
+                break;
+            }
+            bytes.extend_from_slice(&chunk[..read]);
+            if !report(bytes.len() as u64, size) {
+                return Err(anyhow!("Cancelled"));
+            }
+            if streamed && chunk[..read].contains(&b'\n') {
+                break;
+            }
+        }
+        let read = bytes.len() as u64;
+        let stream = (streamed && !ended).then(|| {
+            let end = bytes
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(0, |index| index + 1);
+            let rest = bytes.split_off(end);
+            Stream::new(file, rest, read, size, is_binary(&bytes))
+        });
+        let (contents, binary) = decode(bytes);
+        let disk_modified = modified(file_name);
+        let (contents, bom) = match contents.strip_prefix(BOM) {
+            Some(rest) => (rest, true),
+            None => (contents.as_str(), false),
         };
-        let lsp = match LspConnector::new(
-            file_type.lsp_name().unwrap_or_default(),
-            file_type.lsp_args().unwrap_or_default(),
-            file_type.name(),
-            current_dir()
-                .unwrap_or(PathBuf::new())
-                .join(
-                    PathBuf::from(file_name)
-                        .canonicalize()
-                        .unwrap_or(PathBuf::new()),
-                )
-                .into_os_string()
-                .into_string()
-                .unwrap_or(String::from("Unknown File")),
-        ) {
-            Ok(lsp) => Some(lsp),
-            Err(_) => None,
+        let line_ending = LineEnding::detect(contents);
+        let unterminated = if stream.is_some() {
+            last_byte(file_name)?.1.is_some_and(|last| last != b'\n')
+        } else {
+            !contents.is_empty() && !contents.ends_with('\n')
         };
-        let mut rows: Vec<Row> = Vec::new();
-        for value in contents.lines() {
-            rows.push(Row::from(value));
-        }
+        let lines: Vec<&str> = contents.lines().collect();
+        let modeline = Modeline::parse(&lines);
+        let file_type = modeline
+            .file_type
+            .as_deref()
+            .and_then(|name| FileType::named(name, &config))
+            .or_else(|| FileType::detect(file_name, &sample(&lines), &config))
+            .unwrap_or_default();
+        let plain = binary
+            || size > config.big_file_size()
+            || lines
+                .iter()
+                .any(|line| line.len() > config.big_file_line_length());
+        let (highlighter, highlight_error) = if plain {
+            (None, None)
+        } else {
+            highlighter(&file_type, &config)
+        };
+        let rows: Lines = lines.into_iter().map(Row::from).collect();
         let mut res = Self {
             rows,
             file_name: Some(file_name.to_owned()),
             dirty: false,
             file_type,
             floatings: vec![],
-            lsp,
+            lsp: None,
             highlighter,
+            highlight_error,
+            config,
+            lsp_stale: false,
+            bom,
+            line_ending,
+            unterminated,
+            disk_modified,
+            folds: Folds::default(),
+            annotations: Annotations::default(),
+            marks: Marks::default(),
+            checked: Vec::new(),
+            overrides: modeline.settings,
+            plain,
+            history: History::default(),
+            stream,
+            stream_error: None,
+            follow: None,
+            read_only: false,
+            binary,
+            hex: None,
         };
-        res.highlight();
+        res.reset_highlight();
         Ok(res)
     }
 
+    /// Spawns the language server of the file type, if it has one.
+    /// Kept separate from `open` so it only happens in trusted workspaces.
+    pub fn start_lsp(&mut self) -> Result<()> {
+        let (Some(file_name), Some(lsp_name)) = (&self.file_name, self.file_type.lsp_name()) else {
+            return Ok(());
+        };
+        if self.plain {
+            return Ok(());
+        }
+        let lsp = LspConnector::new(
+            lsp_name,
+            self.file_type.lsp_args(),
+            self.file_type.language_id(),
+            current_dir()
+                .unwrap_or_default()
+                .join(PathBuf::from(file_name).canonicalize().unwrap_or_default())
+                .into_os_string()
+                .into_string()
+                .unwrap_or(String::from("Unknown File")),
+        )
+        .map_err(|e| anyhow!("Could not start {lsp_name}: {e}"))?;
+        let text = self.text();
+        let server = self.config.language_server(file_name);
+        let lsp = self.lsp.insert(lsp);
+        lsp.configure(
+            server.and_then(|server| server.initialization_options.clone()),
+            server.and_then(|server| server.settings.clone()),
+        );
+        if let Err(e) = lsp.init(text) {
+            self.lsp = None;
+            return Err(anyhow!("{lsp_name} did not start: {e}"));
+        }
+        Ok(())
+    }
+
+    /// Sends the current text to the language server if it changed since
+    /// the last sync.
+    fn sync_lsp(&mut self) {
+        if !self.lsp_stale {
+            return;
+        }
+        let text = self.text();
+        if let Some(lsp) = self.lsp.as_mut() {
+            lsp.did_change(text);
+        }
+        self.lsp_stale = false;
+    }
+
+    pub fn lsp_completion(&mut self, x: u32, y: u32) -> Vec<CompletionItem> {
+        self.sync_lsp();
+        match self.lsp.as_mut() {
+            Some(lsp) if lsp.is_initialized() => lsp.completion(y, x),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The diagnostics of the language server, or, while it has none,
+    /// those of the `on_save` command.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self.lsp.as_ref().map(LspConnector::diagnostics) {
+            Some(diagnostics) if !diagnostics.is_empty() => diagnostics,
+            _ => &self.checked,
+        }
+    }
+
+    /// Replaces the diagnostics of the `on_save` command.
+    pub fn set_checked(&mut self, mut diagnostics: Vec<Diagnostic>) {
+        diagnostics.sort_by_key(|diagnostic| {
+            (
+                diagnostic.range.start.line,
+                diagnostic.range.start.character,
+            )
+        });
+        self.checked = diagnostics;
+    }
+
+    pub fn lsp_resolve_completion(&mut self, item: &CompletionItem) -> Option<CompletionItem> {
+        match self.lsp.as_mut() {
+            Some(lsp) if lsp.is_initialized() => lsp.resolve_completion(item),
+            _ => None,
+        }
+    }
+
+    /// Formats with the indentation of the file's settings. `None` if
+    /// cancelled before the language server answered.
+    pub fn lsp_formatting(&mut self, cancellation: &Cancellation) -> Option<Vec<TextEdit>> {
+        let settings = self.settings();
+        let width = settings.indent_width.unwrap_or(self.tab_width());
+        let tab_size = u32::try_from(width).unwrap_or(u32::MAX);
+        let insert_spaces = settings.expand_tabs != Some(false);
+        self.sync_lsp();
+        match self.lsp.as_mut() {
+            Some(lsp) if lsp.is_initialized() => {
+                lsp.formatting(tab_size, insert_spaces, cancellation)
+            }
+            _ => Some(Vec::new()),
+        }
+    }
+
+    pub fn lsp_organize_imports(&mut self, cancellation: &Cancellation) -> Option<Vec<TextEdit>> {
+        self.sync_lsp();
+        let lines = self.len() as u32;
+        match self.lsp.as_mut() {
+            Some(lsp) if lsp.is_initialized() => lsp.organize_imports(lines, cancellation),
+            _ => Some(Vec::new()),
+        }
+    }
+
+    pub fn lsp_code_actions(&mut self, x: u32, y: u32) -> Vec<(String, Vec<TextEdit>)> {
+        self.sync_lsp();
+        match self.lsp.as_mut() {
+            Some(lsp) if lsp.is_initialized() => lsp.code_actions(y, x),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn lsp_goto(&mut self, target: Goto, x: u32, y: u32) -> Vec<Location> {
+        self.sync_lsp();
+        match self.lsp.as_mut() {
+            Some(lsp) if lsp.is_initialized() => lsp.goto(target, y, x),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The text as it would be after applying the language server `edits`.
+    pub fn text_with_edits(&self, edits: &[TextEdit]) -> String {
+        let mut text = self.text();
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        let offset = |position: &lsp_types::Position| {
+            let Some(&start) = line_starts.get(position.line as usize) else {
+                return text.len();
+            };
+            let line = &text[start..];
+            let line = &line[..line.find('\n').unwrap_or(line.len())];
+            // Columns count UTF-16 code units, two for characters such as
+            // emoji outside the basic plane.
+            let mut units = 0;
+            start
+                + line
+                    .char_indices()
+                    .find(|(_, c)| {
+                        let reached = units >= position.character as usize;
+                        units += c.len_utf16();
+                        reached
+                    })
+                    .map_or(line.len(), |(index, _)| index)
+        };
+        // Apply back to front so earlier offsets stay valid. Edits at the
+        // same position must end up in their original order.
+        let mut ranges: Vec<(usize, usize, &str)> = edits
+            .iter()
+            .rev()
+            .map(|edit| {
+                (
+                    offset(&edit.range.start),
+                    offset(&edit.range.end),
+                    edit.new_text.as_str(),
+                )
+            })
+            .collect();
+        ranges.sort_by_key(|&(start, _, _)| Reverse(start));
+        for (start, end, new_text) in ranges {
+            text.replace_range(start..end.max(start), new_text);
+        }
+        text
+    }
+
+    pub fn apply_edits(&mut self, edits: &[TextEdit]) {
+        let text = self.text_with_edits(edits);
+        self.set_text(&text);
+    }
+
+    /// Replaces the whole buffer, e.g. with a local history snapshot.
+    pub fn set_text(&mut self, text: &str) {
+        self.history.record(Edit {
+            at: Position::default(),
+            removed: self.text(),
+            inserted: text.to_string(),
+        });
+        self.rows = text.split('\n').map(Row::from).collect();
+        self.dirty = true;
+        self.lsp_stale = true;
+        self.folds.clear();
+        self.reset_highlight();
+    }
+
+    /// The counts of the buffer as it is saved, without a byte order mark.
+    /// Cheap, as the chunks of rows keep theirs until they change.
+    pub fn counts(&self) -> Counts {
+        let breaks = if self.unterminated {
+            self.len().saturating_sub(1)
+        } else {
+            self.len()
+        };
+        let mut counts = self.rows.counts();
+        counts.graphemes += breaks;
+        counts.bytes += breaks * self.line_ending.as_bytes().len();
+        counts
+    }
+
+    pub fn text(&self) -> String {
+        self.rows
+            .iter()
+            .map(Row::as_str)
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    /// The text from `start` up to `end`.
+    pub fn text_in(&self, start: &Position, end: &Position) -> String {
+        let mut lines = Vec::new();
+        for (y, row) in self.rows.iter().enumerate().take(end.y + 1).skip(start.y) {
+            let from = if y == start.y {
+                row.byte_index(start.x)
+            } else {
+                0
+            };
+            let to = if y == end.y {
+                row.byte_index(end.x)
+            } else {
+                row.as_str().len()
+            };
+            lines.push(&row.as_str()[from..to.max(from)]);
+        }
+        lines.join("\n")
+    }
+
+    /// Handles pending language server messages. Returns whether any arrived.
+    pub fn poll_lsp(&mut self) -> bool {
+        self.lsp.as_mut().is_some_and(LspConnector::poll)
+    }
+
+    pub fn lsp_progress(&self) -> Option<String> {
+        self.lsp.as_ref().and_then(LspConnector::progress)
+    }
+
+    /// Asks the language server to cancel its latest cancellable progress.
+    /// Returns whether there was one.
+    pub fn cancel_lsp_progress(&mut self) -> bool {
+        self.lsp.as_mut().is_some_and(LspConnector::cancel_progress)
+    }
+
+    pub fn has_lsp(&self) -> bool {
+        self.file_type.lsp_name().is_some()
+    }
+
     pub fn row(&self, index: usize) -> Option<&Row> {
         self.rows.get(index)
     }
@@ -93,73 +535,549 @@ impl Document {
         self.floatings.get(index)
     }
 
-    fn insert_newline(&mut self, at: &Position) {
-        if at.y == self.len() {
-            self.rows.push(Row::default());
+    pub fn insert(&mut self, at: &Position, c: char) {
+        if at.y > self.len() {
             return;
         }
-
-        let current_row = &mut self.rows[at.y];
-        let new_row = current_row.split(at.x);
-        self.rows.insert(at.y + 1, new_row);
-        self.highlight();
+        if c == '\n' && at.y == self.len() {
+            // Appending already starts a line.
+            self.edit(at, at, "");
+            return;
+        }
+        self.edit(at, at, c.encode_utf8(&mut [0; 4]));
     }
 
-    pub fn insert(&mut self, at: &Position, c: char) {
-        if at.y > self.len() {
+    pub fn delete(&mut self, at: &Position) {
+        let len = self.len();
+        let Some(row) = self.rows.get(at.y) else {
+            return;
+        };
+        let end = if at.x < row.len() {
+            Position {
+                x: at.x + 1,
+                y: at.y,
+            }
+        } else if at.y + 1 < len {
+            Position { x: 0, y: at.y + 1 }
+        } else {
             return;
+        };
+        self.edit(at, &end, "");
+    }
+
+    /// Replaces the graphemes `start..end` of row `y` with `text`, which may
+    /// span several lines. Returns the position right after the new text.
+    pub fn replace(&mut self, y: usize, start: usize, end: usize, text: &str) -> Position {
+        if y > self.len() {
+            return Position { x: start, y };
         }
+        self.edit(
+            &Position { x: start, y },
+            &Position {
+                x: end.max(start),
+                y,
+            },
+            text,
+        )
+    }
 
-        self.dirty = true;
+    /// Replaces the text from `start` up to `end` with `text` and records
+    /// it for undoing. A `start` on the line after the last one appends a
+    /// line. Returns the position right after the new text.
+    fn edit(&mut self, start: &Position, end: &Position, text: &str) -> Position {
+        if start.y == self.len() && start.y > 0 {
+            // Appending is inserting a line break after the last line.
+            let y = start.y - 1;
+            let at = Position {
+                x: self.rows[y].len(),
+                y,
+            };
+            return self.edit(&at, &at, &format!("\n{text}"));
+        }
+        self.history.record(Edit {
+            at: start.clone(),
+            removed: self.text_in(start, end),
+            inserted: text.to_string(),
+        });
+        self.splice(start, end, text)
+    }
 
-        if c == '\n' {
-            self.insert_newline(at);
-            return;
+    /// Replaces the text from `start` up to `end` with `text`, which may
+    /// span several lines, without recording it. Returns the position
+    /// right after the new text.
+    fn splice(&mut self, start: &Position, end: &Position, text: &str) -> Position {
+        self.dirty = true;
+        self.lsp_stale = true;
+        let appended = self.rows.is_empty();
+        if appended {
+            self.rows.push(Row::default());
+        }
+        let last = self.len() - 1;
+        let start = Position {
+            x: start.x,
+            y: start.y.min(last),
+        };
+        let end = if end.y > last {
+            Position {
+                x: self.rows[last].len(),
+                y: last,
+            }
+        } else {
+            end.clone()
+        };
+        let start_point = self.point(&start);
+        let old_end = self.point(&end);
+        let new_end = match text.rsplit_once('\n') {
+            Some((before, last)) => {
+                Point::new(start.y + before.matches('\n').count() + 1, last.len())
+            }
+            None => Point::new(start.y, start_point.1.column + text.len()),
+        };
+        let rest = self
+            .rows
+            .update(end.y, |row| row.split(end.x))
+            .unwrap_or_default();
+        self.rows.remove(start.y + 1..end.y + 1);
+        let mut lines = text.split('\n');
+        let first = Row::from(lines.next().unwrap_or_default());
+        let x = self
+            .rows
+            .update(start.y, |row| {
+                row.split(start.x);
+                row.append(&first);
+                row.len()
+            })
+            .unwrap_or_default();
+        let mut position = Position { x, y: start.y };
+        for line in lines {
+            position.y += 1;
+            let row = Row::from(line);
+            position.x = row.len();
+            self.rows.insert(position.y, row);
         }
-        if at.y == self.len() {
-            let mut row = Row::default();
-            row.insert(0, c);
-            self.rows.push(row);
+        self.rows.update(position.y, |row| row.append(&rest));
+        if appended {
+            self.reset_highlight();
         } else {
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.insert(at.x, c);
+            self.edit_tree(
+                start_point,
+                old_end,
+                (start_point.0 + text.len(), new_end),
+                text,
+            );
         }
-        self.highlight();
+        position
     }
 
-    pub fn delete(&mut self, at: &Position) {
-        let len = self.len();
-        if at.y >= len {
-            return;
+    /// Takes back the last step of edits. Returns where they started.
+    pub fn undo(&mut self) -> Option<Position> {
+        let step = self.history.undo()?;
+        for edit in step.edits.iter().rev() {
+            self.splice(&edit.at, &edit.inserted_end(), &edit.removed);
         }
+        let start = step.start();
+        self.history.undone(step);
+        self.dirty = !self.history.is_saved();
+        Some(start)
+    }
 
-        self.dirty = true;
+    /// Makes the last step undone again. Returns where its edits ended.
+    pub fn redo(&mut self) -> Option<Position> {
+        let step = self.history.redo()?;
+        for edit in &step.edits {
+            self.splice(&edit.at, &edit.removed_end(), &edit.inserted);
+        }
+        let end = step.end();
+        self.history.redone(step);
+        self.dirty = !self.history.is_saved();
+        Some(end)
+    }
 
-        if at.x == self.rows.get_mut(at.y).unwrap().len() && at.y < len - 1 {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.append(&next_row);
-        } else {
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.delete(at.x);
+    /// Ends the step of edits in progress, e.g. after a key press.
+    pub fn end_undo_step(&mut self) {
+        self.history.end_step();
+    }
+
+    /// Writes the buffer to its file. Returns notes on what the file could
+    /// not keep, such as extended attributes.
+    pub fn save(&mut self) -> Result<Vec<String>> {
+        self.finish_stream()?;
+        let Some(file_name) = &self.file_name else {
+            return Ok(Vec::new());
+        };
+        let notes = self.write_file(Path::new(file_name))?;
+        self.disk_modified = modified(file_name);
+        self.dirty = false;
+        self.history.mark_saved();
+        Ok(notes)
+    }
+
+    /// Saves the buffer to `file_name`, which it is of from then on once
+    /// it was written.
+    pub fn save_as(&mut self, file_name: String) -> Result<Vec<String>> {
+        self.finish_stream()?;
+        let notes = self.write_file(Path::new(&file_name))?;
+        self.set_file_name(file_name);
+        self.dirty = false;
+        self.history.mark_saved();
+        Ok(notes)
+    }
+
+    /// Writes the buffer to `file_name` as it is saved, leaving the buffer
+    /// of its own file.
+    pub fn save_copy(&mut self, file_name: &str) -> Result<Vec<String>> {
+        self.finish_stream()?;
+        self.write_file(Path::new(file_name))
+    }
+
+    fn write_file(&self, path: &Path) -> Result<Vec<String>> {
+        if self.binary && self.hex.is_none() {
+            return Err(anyhow!("Binary files are not saved"));
+        }
+        let notes = atomic::write(path, |file| match &self.hex {
+            Some(hex) => file.write_all(hex.bytes()),
+            None => self.write_text(file),
+        })?;
+        Ok(notes)
+    }
+
+    /// Renames the file of the buffer on disk to `file_name`, which the
+    /// buffer is of from then on. A file cannot be renamed onto another
+    /// file system, so there it is copied and the old one removed.
+    pub fn rename(&mut self, file_name: String) -> Result<()> {
+        let old = self
+            .file_name
+            .as_deref()
+            .ok_or_else(|| anyhow!("The buffer has no file"))?;
+        match fs::rename(old, &file_name) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                fs::copy(old, &file_name)?;
+                fs::remove_file(old)?;
+            }
+            result => result?,
+        }
+        self.set_file_name(file_name);
+        Ok(())
+    }
+
+    /// Writes the rows as they are saved: with the byte order mark and
+    /// line ending of the file.
+    fn write_text(&self, file: &mut dyn Write) -> io::Result<()> {
+        if self.bom {
+            file.write_all(BOM.as_bytes())?;
         }
-        self.highlight();
+        for (y, row) in self.rows.iter().enumerate() {
+            file.write_all(row.as_bytes())?;
+            if !self.unterminated || y + 1 < self.rows.len() {
+                file.write_all(self.line_ending.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Names the file to save to, e.g. a new one, and switches to the file
+    /// type of the name.
+    pub fn set_file_name(&mut self, file_name: String) {
+        let lines: Vec<&str> = self.rows.iter().map(Row::as_str).collect();
+        let file_type =
+            FileType::detect(&file_name, &sample(&lines), &self.config).unwrap_or_default();
+        // A file saved over on purpose is no change by someone else.
+        self.disk_modified = modified(&file_name);
+        self.file_name = Some(file_name);
+        if file_type.name() != self.file_type.name() {
+            self.set_file_type(file_type);
+        }
+    }
+
+    /// A document without a file of the `lines` of this one, highlighted
+    /// the same way, for editing them narrowed down.
+    pub fn narrowed(&self, lines: &[usize]) -> Self {
+        let file_type = self.detect_file_type();
+        let (highlighter, highlight_error) = if self.plain {
+            (None, None)
+        } else {
+            highlighter(&file_type, &self.config)
+        };
+        let mut document = Self {
+            rows: lines
+                .iter()
+                .filter_map(|&y| self.rows.get(y))
+                .map(|row| Row::from(row.as_str()))
+                .collect(),
+            file_type,
+            highlighter,
+            highlight_error,
+            config: Rc::clone(&self.config),
+            overrides: self.overrides.clone(),
+            plain: self.plain,
+            ..Self::default()
+        };
+        document.reset_highlight();
+        document
     }
 
-    pub fn save(&mut self) -> Result<()> {
+    /// The file type the modeline names, or else the one told by the file
+    /// name and the first lines, as when the file is opened.
+    pub fn detect_file_type(&self) -> FileType {
+        let lines: Vec<&str> = self.rows.iter().map(Row::as_str).collect();
+        let file_name = self.file_name.as_deref().unwrap_or_default();
+        Modeline::parse(&lines)
+            .file_type
+            .as_deref()
+            .and_then(|name| FileType::named(name, &self.config))
+            .or_else(|| FileType::detect(file_name, &sample(&lines), &self.config))
+            .unwrap_or_default()
+    }
+
+    /// Highlights the buffer as `file_type` from now on and stops the
+    /// language server of the previous file type; `start_lsp` starts the
+    /// one of the new type.
+    pub fn set_file_type(&mut self, file_type: FileType) {
+        self.lsp = None;
+        (self.highlighter, self.highlight_error) = if self.plain {
+            (None, None)
+        } else {
+            highlighter(&file_type, &self.config)
+        };
+        self.file_type = file_type;
+        self.rows.clear_highlights();
+        self.reset_highlight();
+    }
+
+    /// Whether the file was modified by someone else since it was last
+    /// read or written.
+    pub fn changed_on_disk(&self) -> bool {
+        let Some(file_name) = &self.file_name else {
+            return false;
+        };
+        self.disk_modified.is_some() && modified(file_name) != self.disk_modified
+    }
+
+    /// Stops reporting the current version on disk as a change.
+    pub fn ignore_disk_changes(&mut self) {
         if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name).unwrap_or(FileType::default());
-            for row in &mut self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+            self.disk_modified = modified(file_name);
+        }
+    }
+
+    /// The contents of the file on disk, in the form of `text()`.
+    pub fn disk_text(&self) -> Result<String> {
+        let file_name = self.file_name.as_deref().unwrap_or_default();
+        let contents = fs::read_to_string(file_name)?;
+        let contents = contents.strip_prefix(BOM).unwrap_or(&contents);
+        Ok(contents.lines().collect::<Vec<&str>>().join("\n"))
+    }
+
+    /// Replaces the buffer with the contents of the file on disk.
+    pub fn reload(&mut self) -> Result<()> {
+        let file_name = self.file_name.as_deref().unwrap_or_default();
+        let bytes = fs::read(file_name)?;
+        if self.hex.is_some() {
+            self.hex = Some(Hex::new(bytes.clone()));
+        }
+        let (contents, binary) = decode(bytes);
+        self.stream = None;
+        self.stream_error = None;
+        self.disk_modified = modified(file_name);
+        self.bom = contents.starts_with(BOM);
+        let contents = contents.strip_prefix(BOM).unwrap_or(&contents);
+        self.line_ending = LineEnding::detect(contents);
+        self.unterminated = !contents.is_empty() && !contents.ends_with('\n');
+        self.rows = contents.lines().map(Row::from).collect();
+        self.plain |= binary;
+        self.binary = binary;
+        self.dirty = false;
+        self.history = History::default();
+        self.lsp_stale = true;
+        self.folds.clear();
+        self.reset_highlight();
+        Ok(())
+    }
+
+    /// Appends the lines written to the file from now on, see
+    /// `poll_follow`.
+    pub fn follow(&mut self) -> Result<()> {
+        self.finish_stream()?;
+        let file_name = self
+            .file_name
+            .as_deref()
+            .ok_or_else(|| anyhow!("No file to follow"))?;
+        self.follow = Some(follow_from(file_name)?);
+        Ok(())
+    }
+
+    pub fn unfollow(&mut self) {
+        self.follow = None;
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.follow.is_some()
+    }
+
+    /// Whether editing is off: turned off, or for the text of a binary
+    /// file, which only its hex dump edits.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only || (self.binary && self.hex.is_none())
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    pub fn hex(&self) -> Option<&Hex> {
+        self.hex.as_ref()
+    }
+
+    pub fn is_hex(&self) -> bool {
+        self.hex.is_some()
+    }
+
+    /// Edits the bytes of the buffer as a hex dump: those of the file for
+    /// a binary one, else the text as it would be saved.
+    pub fn start_hex(&mut self) -> Result<()> {
+        self.finish_stream()?;
+        self.follow = None;
+        let bytes = match &self.file_name {
+            Some(file_name) if self.binary => fs::read(file_name)?,
+            _ => {
+                let mut bytes = Vec::new();
+                self.write_text(&mut bytes)?;
+                bytes
+            }
+        };
+        self.hex = Some(Hex::new(bytes));
+        Ok(())
+    }
+
+    /// Overwrites the byte at the hex cursor with `c`, see
+    /// `Hex::type_char`. Returns whether it did.
+    pub fn type_hex(&mut self, c: char) -> bool {
+        let typed = self.hex.as_mut().is_some_and(|hex| hex.type_char(c));
+        self.dirty |= typed;
+        typed
+    }
+
+    /// Moves the hex cursor by `change`.
+    pub fn move_hex(&mut self, change: impl FnOnce(&mut Hex)) {
+        if let Some(hex) = self.hex.as_mut() {
+            change(hex);
+        }
+    }
+
+    /// Shows the bytes as text again, as one edit that can be undone if
+    /// they changed. Bytes that are no text stay in the hex dump until
+    /// they are saved, as their text would lose them.
+    pub fn end_hex(&mut self) -> Result<()> {
+        let Some(hex) = self.hex.take() else {
+            return Ok(());
+        };
+        if is_binary(hex.bytes()) {
+            if self.dirty {
+                self.hex = Some(hex);
+                return Err(anyhow!("The bytes are no text; save them first"));
+            }
+            let (contents, _) = decode(hex.into_bytes());
+            self.rows = contents.lines().map(Row::from).collect();
+            self.binary = true;
+            self.plain = true;
+            self.history = History::default();
+            self.reset_highlight();
+            return Ok(());
+        }
+        let (contents, _) = decode(hex.into_bytes());
+        self.bom = contents.starts_with(BOM);
+        let contents = contents.strip_prefix(BOM).unwrap_or(&contents);
+        self.line_ending = LineEnding::detect(contents);
+        self.unterminated = !contents.is_empty() && !contents.ends_with('\n');
+        let text = contents.lines().collect::<Vec<&str>>().join("\n");
+        if self.binary {
+            self.binary = false;
+            self.rows = text.lines().map(Row::from).collect();
+            self.history = History::default();
+            self.lsp_stale = true;
+            self.reset_highlight();
+        } else if text != self.text() {
+            let saved = !self.dirty;
+            self.set_text(&text);
+            if saved {
+                self.dirty = false;
+                self.history.mark_saved();
             }
-            self.highlight();
-            self.dirty = false;
         }
         Ok(())
     }
 
+    /// Appends the lines written to the followed file since the last call,
+    /// or reads it again if it was truncated, e.g. by log rotation.
+    /// Returns whether the buffer changed.
+    pub fn poll_follow(&mut self) -> Result<bool> {
+        let (Some(follow), Some(file_name)) = (self.follow, self.file_name.clone()) else {
+            return Ok(false);
+        };
+        let size = fs::metadata(&file_name)?.len();
+        if size < follow.read {
+            let follow = follow_from(&file_name)?;
+            self.reload()?;
+            self.follow = Some(follow);
+            return Ok(true);
+        }
+        let mut file = fs::File::open(&file_name)?;
+        file.seek(SeekFrom::Start(follow.read))?;
+        let mut bytes = Vec::new();
+        file.take(size - follow.read).read_to_end(&mut bytes)?;
+        // A line still being written is read once it is complete.
+        let Some(end) = bytes.iter().rposition(|&byte| byte == b'\n') else {
+            return Ok(false);
+        };
+        bytes.truncate(end + 1);
+        let text = String::from_utf8(bytes)?;
+        let mut lines = text.lines();
+        if !follow.line_start {
+            if let Some(rest) = lines.next() {
+                let last = self.len().saturating_sub(1);
+                self.rows.update(last, |row| row.append(&Row::from(rest)));
+            }
+        }
+        for line in lines {
+            self.rows.push(Row::from(line));
+        }
+        self.follow = Some(Follow {
+            read: follow.read + text.len() as u64,
+            line_start: true,
+        });
+        self.unterminated = false;
+        self.disk_modified = modified(&file_name);
+        self.lsp_stale = true;
+        self.reset_highlight();
+        Ok(true)
+    }
+
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    /// Switches between keeping and dropping the byte order mark on save.
+    pub fn toggle_bom(&mut self) {
+        self.bom = !self.bom;
+        self.dirty = true;
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Ends the lines with `line_ending` from the next save on.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if line_ending != self.line_ending {
+            self.line_ending = line_ending;
+            self.dirty = true;
+        }
+    }
+
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
@@ -201,83 +1119,654 @@ impl Document {
         None
     }
 
-    pub fn file_type(&self) -> String {
-        self.file_type.name()
+    /// The doc comment skeleton for the function whose signature is on line
+    /// `y`, if the language has a generator.
+    pub fn doc_comment(&self, y: usize) -> Option<Skeleton> {
+        let lang = self.file_type.highlighting_options().get_lang()?;
+        let lines: Vec<&str> = self.rows.iter().map(Row::as_str).collect();
+        doc_comment::skeleton(self.file_type.language_id(), lang, &lines, y)
+    }
+
+    /// The list or quote marker at the start of line `y`, its length and
+    /// how the next line continues it.
+    pub fn list_marker(&self, y: usize) -> Option<(usize, Continuation)> {
+        list::marker(self.file_type.language_id(), self.rows.get(y)?.as_str())
+    }
+
+    /// The edits of a structural replace, see `structural::replace`.
+    pub fn structural_replace(&self, query: &str, template: &str) -> Result<Vec<TextEdit>> {
+        let lang = self
+            .file_type
+            .highlighting_options()
+            .get_lang()
+            .ok_or_else(|| anyhow!("{} has no grammar", self.file_type.name()))?;
+        let lines: Vec<&str> = self.rows.iter().map(Row::as_str).collect();
+        structural::replace(lang, &lines, query, template)
+    }
+
+    /// The innermost `object` around `at`, or its body if `inner`.
+    pub fn select_object(
+        &self,
+        object: Object,
+        at: &Position,
+        inner: bool,
+    ) -> Result<Option<(Position, Position)>> {
+        let (lang, query) = self.textobject_query()?;
+        let lines: Vec<&str> = self.rows.iter().map(Row::as_str).collect();
+        let span = textobject::select(lang, &query, &lines, object, self.point(at).1, inner)?;
+        Ok(span.map(|(start, end)| (self.position(start), self.position(end))))
+    }
+
+    /// The start of the next or previous `object` from `at`.
+    pub fn next_object(
+        &self,
+        object: Object,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Result<Option<Position>> {
+        let (lang, query) = self.textobject_query()?;
+        let lines: Vec<&str> = self.rows.iter().map(Row::as_str).collect();
+        let start = textobject::next(lang, &query, &lines, object, self.point(at).1, direction)?;
+        Ok(start.map(|start| self.position(start)))
     }
 
-    pub fn highlight(&mut self) {
-        let chars: Vec<Vec<u8>> = self
+    fn textobject_query(&self) -> Result<(Language, Cow<'static, str>)> {
+        let options = self.file_type.highlighting_options();
+        options
+            .get_lang()
+            .zip(options.get_textobject_query())
+            .ok_or_else(|| anyhow!("{} has no text objects", self.file_type.name()))
+    }
+
+    /// Replaces the indentation of the lines `rows` with the one the syntax
+    /// around them calls for.
+    pub fn reindent(&mut self, rows: Range<usize>) {
+        let rows = rows.start..rows.end.min(self.len());
+        for (y, indentation) in rows.clone().zip(self.indentation(rows)) {
+            let line = self.rows[y].as_str();
+            let current = &line[..line.len() - line.trim_start().len()];
+            if current != indentation {
+                let end = self.rows[y].grapheme_index(current.len());
+                self.replace(y, 0, end, &indentation);
+            }
+        }
+    }
+
+    /// The text of the lines `rows`.
+    pub fn lines(&self, rows: Range<usize>) -> Vec<String> {
+        self.rows
+            .iter()
+            .skip(rows.start)
+            .take(rows.len())
+            .map(|row| row.as_str().to_string())
+            .collect()
+    }
+
+    /// Replaces the lines `rows` with `lines`, as one edit to undo.
+    pub fn replace_lines(&mut self, rows: Range<usize>, lines: &[String]) {
+        let rows = rows.start..rows.end.min(self.len());
+        if rows.is_empty() || self.lines(rows.clone()) == lines {
+            return;
+        }
+        let end = Position {
+            x: self.rows[rows.end - 1].len(),
+            y: rows.end - 1,
+        };
+        self.edit(
+            &Position {
+                x: 0,
+                y: rows.start,
+            },
+            &end,
+            &lines.join("\n"),
+        );
+    }
+
+    /// Comments the lines `rows`, or uncomments them if they are all
+    /// commented, by the comment of the settings. Returns whether the
+    /// settings have a comment.
+    pub fn toggle_comment(&mut self, rows: Range<usize>) -> bool {
+        let settings = self.settings();
+        let Some((open, close)) = settings.comment_tokens() else {
+            return false;
+        };
+        let rows = rows.start..rows.end.min(self.len());
+        let lines: Vec<&str> = self
             .rows
             .iter()
-            .map(|r| {
-                let mut res = r.as_bytes().to_vec();
-                res.push(b'\r');
-                res.push(b'\n');
-                res
-            })
+            .skip(rows.start)
+            .take(rows.len())
+            .map(Row::as_str)
             .collect();
-        let chars = chars.into_iter().flatten().collect::<Vec<u8>>();
-        let chars: &[u8] = chars.as_slice();
+        let toggled = comment::toggle(&lines, open, close);
+        for (y, line) in rows.zip(toggled) {
+            if self.rows[y].as_str() != line {
+                let end = self.rows[y].len();
+                self.replace(y, 0, end, &line);
+            }
+        }
+        true
+    }
+
+    /// Removes the spaces and tabs at the ends of the lines. Returns
+    /// whether there were any.
+    pub fn trim_trailing_whitespace(&mut self) -> bool {
+        let mut trimmed = false;
+        for y in 0..self.len() {
+            let line = self.rows[y].as_str();
+            let kept = line.trim_end_matches([' ', '\t']).len();
+            if kept < line.len() {
+                let start = self.rows[y].grapheme_index(kept);
+                let end = self.rows[y].len();
+                self.replace(y, start, end, "");
+                trimmed = true;
+            }
+        }
+        trimmed
+    }
+
+    /// Drops the empty lines at the end, which would be saved as newlines
+    /// after the one ending the last line, and ends the last line if it was
+    /// not. Returns whether anything changed.
+    pub fn trim_final_newlines(&mut self) -> bool {
+        let mut trimmed = mem::take(&mut self.unterminated);
+        while self.len() > 1 && self.rows[self.len() - 1].is_empty() {
+            let y = self.len() - 2;
+            self.delete(&Position {
+                x: self.rows[y].len(),
+                y,
+            });
+            trimmed = true;
+        }
+        trimmed
+    }
 
-        let hl_opt = self.file_type.highlighting_options();
-        if !hl_opt.get_hl_query().is_some() || !hl_opt.get_inj_query().is_some() {
+    /// The indentation of each of the lines `rows`: as deep as the syntax
+    /// nodes around them are nested, or, in files without a grammar, that
+    /// of the closest line above with text.
+    fn indentation(&self, rows: Range<usize>) -> Vec<String> {
+        let lines: Vec<&str> = self.rows.iter().map(Row::as_str).collect();
+        let options = self.file_type.highlighting_options();
+        let levels = options.get_lang().and_then(|lang| {
+            let query = options.get_indent_query();
+            indent::levels(lang, query.as_deref(), &lines, rows.clone()).ok()
+        });
+        if let Some(levels) = levels {
+            // The overrides decide over the file, the file over its type.
+            let unit = self
+                .overrides
+                .indent_unit()
+                .or_else(|| indent::unit(&lines))
+                .or_else(|| self.file_type.settings().indent_unit())
+                .unwrap_or_else(|| " ".repeat(4));
+            return levels.into_iter().map(|level| unit.repeat(level)).collect();
+        }
+        rows.map(|y| {
+            let above = lines[..y.min(lines.len())]
+                .iter()
+                .rfind(|line| !line.trim().is_empty())
+                .copied()
+                .unwrap_or_default();
+            above[..above.len() - above.trim_start().len()].to_string()
+        })
+        .collect()
+    }
+
+    pub fn file_type(&self) -> String {
+        self.file_type.name()
+    }
+
+    /// Whether the file is drawn without highlighting because it is big.
+    pub fn is_plain(&self) -> bool {
+        self.plain
+    }
+
+    /// Highlights the big file after all; `start_lsp` starts its language
+    /// server.
+    pub fn enable_highlighting(&mut self) {
+        let _ = self.finish_stream();
+        self.plain = false;
+        (self.highlighter, self.highlight_error) = highlighter(&self.file_type, &self.config);
+        self.reset_highlight();
+    }
+
+    /// Compiles the queries of the file type again, e.g. after they were
+    /// edited, and highlights the buffer with them.
+    pub fn reload_highlighter(&mut self) {
+        if self.plain {
             return;
         }
+        (self.highlighter, self.highlight_error) = highlighter(&self.file_type, &self.config);
+        self.rows.clear_highlights();
+        self.reset_highlight();
+    }
+
+    /// Why the queries of the file type do not compile, if the buffer is
+    /// highlighted by the basic highlighter instead.
+    pub fn highlight_error(&self) -> Option<&str> {
+        self.highlight_error.as_deref()
+    }
+
+    /// Byte offset and tree-sitter point of `at`, counting line breaks as
+    /// one byte like `Highlight` does.
+    fn point(&self, at: &Position) -> (usize, Point) {
+        let line_start = self.rows.offset(at.y);
+        let column = self.rows.get(at.y).map_or(0, |row| row.byte_index(at.x));
+        (line_start + column, Point::new(at.y, column))
+    }
+
+    /// The position of the tree-sitter point `point`.
+    fn position(&self, point: Point) -> Position {
+        Position {
+            x: self
+                .rows
+                .get(point.row)
+                .map_or(0, |row| row.grapheme_index(point.column)),
+            y: point.row,
+        }
+    }
+
+    /// Tells the highlighter that the text from `start` to `old_end` was
+    /// replaced with `text`, which ends at `new_end`.
+    fn edit_tree(
+        &mut self,
+        start: (usize, Point),
+        old_end: (usize, Point),
+        new_end: (usize, Point),
+        text: &str,
+    ) {
+        self.folds.edit(start.1.row, old_end.1.row, new_end.1.row);
+        self.annotations
+            .edit(start.1.row, old_end.1.row, new_end.1.row);
+        self.marks.edit(start.1.row, old_end.1.row, new_end.1.row);
         if let Some(highlighter) = self.highlighter.as_mut() {
-            if let Ok(highlight_vec) = highlighter.highlight(chars) {
-                let mut highlight_idx: usize = 0;
-                for row in &mut self.rows {
-                    let row_len = row.as_bytes().len();
-                    if let Some(new_hl) =
-                        highlight_vec.get(highlight_idx..highlight_idx.saturating_add(row_len))
-                    {
-                        row.set_highlight(new_hl.to_vec());
-                    }
-                    highlight_idx += row.as_bytes().len().saturating_add(2);
-                }
-            }
+            highlighter.edit(
+                InputEdit {
+                    start_byte: start.0,
+                    old_end_byte: old_end.0,
+                    new_end_byte: new_end.0,
+                    start_position: start.1,
+                    old_end_position: old_end.1,
+                    new_end_position: new_end.1,
+                },
+                text,
+            );
         }
     }
 
+    /// Highlights the whole buffer again from scratch.
+    fn reset_highlight(&mut self) {
+        let lines = self
+            .rows
+            .iter()
+            .map(|row| row.as_str().to_owned())
+            .collect();
+        if let Some(highlighter) = self.highlighter.as_mut() {
+            highlighter.reset(lines);
+        }
+    }
+
+    /// The bracket paired with the one at `at`, or with the one just before
+    /// it, and the position of that one.
+    pub fn matching_bracket(&self, at: &Position) -> Option<(Position, Position)> {
+        let tree = self.highlighter.as_ref()?.tree()?;
+        let row = self.rows.get(at.y)?;
+        std::iter::once(at.x)
+            .chain(at.x.checked_sub(1))
+            .find_map(|x| {
+                let column = row.byte_index(x);
+                let pair = highlighting::matching_bracket(&tree, Point::new(at.y, column))?;
+                let pair_x = self.rows.get(pair.row)?.grapheme_index(pair.column);
+                Some((
+                    Position { x, y: at.y },
+                    Position {
+                        x: pair_x,
+                        y: pair.row,
+                    },
+                ))
+            })
+    }
+
+    /// The smallest syntax node that contains `start..end` and is larger
+    /// than it.
+    pub fn enclosing_node(&self, start: &Position, end: &Position) -> Option<(Position, Position)> {
+        let tree = self.highlighter.as_ref()?.tree()?;
+        let (start, end) =
+            highlighting::enclosing_node(&tree, self.point(start).1, self.point(end).1)?;
+        Some((self.position(start), self.position(end)))
+    }
+
+    /// The settings of the file type with the overrides on top.
+    pub fn settings(&self) -> Settings {
+        self.file_type.settings().patch(&self.overrides)
+    }
+
+    /// Changes the settings of the file, e.g. by `set tab_width=8`.
+    pub fn set_overrides(&mut self, settings: &Settings) {
+        self.overrides = self.overrides.patch(settings);
+    }
+
+    pub fn tab_width(&self) -> usize {
+        self.settings().tab_width.unwrap_or(TAB_WIDTH)
+    }
+
+    pub fn annotations(&self) -> &Annotations {
+        &self.annotations
+    }
+
+    pub fn annotations_mut(&mut self) -> &mut Annotations {
+        &mut self.annotations
+    }
+
+    pub fn marks(&self) -> &Marks {
+        &self.marks
+    }
+
+    pub fn marks_mut(&mut self) -> &mut Marks {
+        &mut self.marks
+    }
+
+    pub fn folds(&self) -> &Folds {
+        &self.folds
+    }
+
+    pub fn folds_mut(&mut self) -> &mut Folds {
+        &mut self.folds
+    }
+
+    /// Folds the innermost syntax node around line `y` that is not folded
+    /// yet. Returns the first line of the fold, if there was one.
+    pub fn close_fold(&mut self, y: usize) -> Option<usize> {
+        let tree = self.highlighter.as_ref()?.tree()?;
+        let row = self.rows.get(y)?;
+        let indent = row.as_str().len() - row.as_str().trim_start().len();
+        let line = (
+            Point::new(y, indent),
+            Point::new(y, row.as_str().trim_end().len().max(indent)),
+        );
+        let fold = highlighting::foldable(&tree, line.0, line.1)
+            .into_iter()
+            .find(|fold| !self.folds.contains(fold))?;
+        let start = fold.start;
+        self.folds.close(fold);
+        Some(start)
+    }
+
+    /// Opens every fold, or folds every top-level syntax node if none is
+    /// folded. Returns whether anything is folded now.
+    pub fn toggle_all_folds(&mut self) -> bool {
+        if !self.folds.is_empty() {
+            self.folds.clear();
+            return false;
+        }
+        let Some(tree) = self.highlighter.as_ref().and_then(Background::tree) else {
+            return false;
+        };
+        for fold in highlighting::foldable_all(&tree) {
+            self.folds.close(fold);
+        }
+        !self.folds.is_empty()
+    }
+
+    /// Applies the highlights finished by the background highlighter.
+    /// Returns whether there were any.
+    pub fn poll_highlight(&mut self) -> bool {
+        let Some((start, types)) = self.highlighter.as_ref().and_then(Background::take) else {
+            return false;
+        };
+        self.rows.set_highlights(start, types);
+        true
+    }
+
+    /// Appends the lines of a big file read since the last call. Returns
+    /// whether there were any, or why the rest of the file cannot be read.
+    pub fn poll_stream(&mut self) -> Result<bool> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(false);
+        };
+        let lines = stream.take();
+        if stream.is_done() {
+            self.stream = None;
+        }
+        self.append_streamed(lines)
+    }
+
+    /// How much of a big file was read while the rest still streams in,
+    /// e.g. `Loading 40%`.
+    pub fn stream_progress(&self) -> Option<String> {
+        self.stream
+            .as_ref()
+            .map(|stream| format!("Loading {}%", stream.percent()))
+    }
+
+    /// Waits for the rest of a big file, e.g. before saving it.
+    fn finish_stream(&mut self) -> Result<()> {
+        if let Some(mut stream) = self.stream.take() {
+            let lines = stream.finish();
+            self.append_streamed(lines)?;
+        }
+        match &self.stream_error {
+            Some(e) => Err(anyhow!("Only part of the file was read: {e}")),
+            None => Ok(()),
+        }
+    }
+
+    fn append_streamed(&mut self, lines: Result<Vec<String>>) -> Result<bool> {
+        let lines = lines.inspect_err(|e| {
+            self.stream = None;
+            self.stream_error = Some(e.to_string());
+        })?;
+        for line in &lines {
+            self.rows.push(Row::from(line.as_str()));
+        }
+        Ok(!lines.is_empty())
+    }
+
     pub fn clear_floating(&mut self) {
         self.floatings.clear();
     }
 
-    pub fn hover(&mut self, x: u32, y: u32) {
+    pub fn show_floating(&mut self, floating: FloatingItem) {
+        self.floatings.clear();
+        self.floatings.push(floating);
+    }
+
+    /// Scrolls the lines of the topmost floating item that are too wide for
+    /// it by `delta` columns.
+    pub fn scroll_floating(&mut self, delta: isize) {
+        if let Some(floating) = self.floatings.last_mut() {
+            floating.scroll_horizontally(delta);
+        }
+    }
+
+    /// Scrolls the lines of the topmost floating item that does not show
+    /// all of them by `delta` lines.
+    pub fn scroll_floating_vertically(&mut self, delta: isize) {
+        if let Some(floating) = self.floatings.last_mut() {
+            floating.scroll_vertically(delta);
+        }
+    }
+
+    /// Shows `floating` on top of those already shown.
+    pub fn add_floating(&mut self, floating: FloatingItem) {
+        self.floatings.push(floating);
+    }
+
+    /// Shows the hover of the symbol at `x`, `y`, at most `max_width`
+    /// columns wide and `max_height` lines high; wider code blocks and
+    /// longer documentation can be scrolled.
+    pub fn hover(&mut self, x: u32, y: u32, max_width: usize, max_height: usize) {
+        self.sync_lsp();
         if let Some(lsp) = self.lsp.as_mut() {
             if !lsp.is_initialized() {
                 let a = self.rows.iter().map(|r| r.as_str()).collect::<Vec<&str>>();
-                lsp.init(a.join("\r\n"));
+                if lsp.init(a.join("\r\n")).is_err() {
+                    self.lsp = None;
+                    return;
+                }
             }
 
             if let Some(hover) = lsp.hover(y, x) {
-                match hover.contents {
-                    HoverContents::Scalar(_) => (),
-                    HoverContents::Markup(content) => {
-                        let txt = content.value;
-                        self.floatings.clear();
-                        let width = txt
-                            .lines()
-                            .map(|x| x.graphemes(true).count())
-                            .max()
-                            .unwrap_or(0);
-                        self.floatings.append(&mut vec![FloatingItem::new(
-                            Position {
-                                x: x as usize,
-                                y: y.saturating_add(1) as usize,
-                            },
-                            width,
-                            txt.lines().filter(|x| !x.is_empty()).count(),
-                            txt.lines()
-                                .map(ToString::to_string)
-                                .filter(|x| !x.is_empty())
-                                .collect::<Vec<String>>(),
-                        )]);
-                    }
-                    HoverContents::Array(_) => (),
-                    // TODO
-                }
+                self.show_hover(hover, x, y, max_width, max_height);
             }
         }
     }
+
+    /// Shows `hover` at `x`, `y`, rendering markdown and keeping plain
+    /// text as it is.
+    pub fn show_hover(
+        &mut self,
+        hover: Hover,
+        x: u32,
+        y: u32,
+        max_width: usize,
+        max_height: usize,
+    ) {
+        let floating = match hover_text(hover.contents) {
+            (text, MarkupKind::Markdown) => markdown::floating(&text, max_width),
+            (text, MarkupKind::PlainText) => markdown::floating_plain(&text, max_width),
+        };
+        self.show_floating(floating.with_max_height(max_height).with_anchor(Position {
+            x: x as usize,
+            y: y as usize,
+        }));
+    }
+}
+
+/// The background highlighter of `file_type`, if it has a grammar, and
+/// why its queries do not compile if they do not.
+fn highlighter(file_type: &FileType, config: &Config) -> (Option<Background>, Option<String>) {
+    let Some(highlight) = Highlight::from_options(&file_type.highlighting_options()) else {
+        return (None, None);
+    };
+    let error = highlight.error().map(str::to_owned);
+    let highlight = highlight.with_rainbow_brackets(config.rainbow_brackets());
+    (Some(Background::new(highlight)), error)
+}
+
+/// The first lines of `lines`, which file types are told by.
+fn sample(lines: &[&str]) -> String {
+    lines[..lines.len().min(SAMPLE_LINES)].join("\n")
+}
+
+/// The text of `bytes` and whether they are binary, in which case bytes
+/// that are not UTF-8 become replacement characters.
+fn decode(bytes: Vec<u8>) -> (String, bool) {
+    if is_binary(&bytes) {
+        (String::from_utf8_lossy(&bytes).into_owned(), true)
+    } else {
+        (String::from_utf8(bytes).unwrap_or_default(), false)
+    }
+}
+
+/// Whether `bytes` have a NUL byte, which text does not, or are not UTF-8.
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.contains(&0) || std::str::from_utf8(bytes).is_err()
+}
+
+fn modified(file_name: &str) -> Option<SystemTime> {
+    fs::metadata(file_name)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Where following `file_name` starts: at its end.
+fn follow_from(file_name: &str) -> Result<Follow> {
+    let (read, last) = last_byte(file_name)?;
+    Ok(Follow {
+        read,
+        line_start: last.is_none_or(|last| last == b'\n'),
+    })
+}
+
+/// The size of `file_name` and its last byte, if it is not empty.
+fn last_byte(file_name: &str) -> Result<(u64, Option<u8>)> {
+    let mut file = fs::File::open(file_name)?;
+    let size = file.metadata()?.len();
+    if size == 0 {
+        return Ok((size, None));
+    }
+    let mut last = [0];
+    file.seek(SeekFrom::Start(size - 1))?;
+    file.read_exact(&mut last)?;
+    Ok((size, Some(last[0])))
+}
+
+/// The text of hover contents and whether it is markdown or plain text.
+fn hover_text(contents: HoverContents) -> (String, MarkupKind) {
+    match contents {
+        HoverContents::Scalar(MarkedString::String(text)) => (text, MarkupKind::PlainText),
+        HoverContents::Array(marked)
+            if marked
+                .iter()
+                .all(|marked| matches!(marked, MarkedString::String(_))) =>
+        {
+            let texts: Vec<String> = marked.into_iter().map(marked_text).collect();
+            (texts.join("\n\n"), MarkupKind::PlainText)
+        }
+        HoverContents::Scalar(marked) => (marked_markdown(marked), MarkupKind::Markdown),
+        HoverContents::Array(marked) => {
+            let texts: Vec<String> = marked.into_iter().map(marked_markdown).collect();
+            (texts.join("\n\n"), MarkupKind::Markdown)
+        }
+        HoverContents::Markup(content) => (content.value, content.kind),
+    }
+}
+
+/// The plain text of `marked`.
+fn marked_text(marked: MarkedString) -> String {
+    match marked {
+        MarkedString::String(text) => text,
+        MarkedString::LanguageString(code) => code.value,
+    }
+}
+
+/// `marked` as markdown, escaping plain strings so their `*` and `_`
+/// are kept next to code blocks.
+fn marked_markdown(marked: MarkedString) -> String {
+    match marked {
+        MarkedString::String(text) => markdown::escape(&text),
+        MarkedString::LanguageString(code) => {
+            format!("```{}\n{}\n```", code.language, code.value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Range;
+
+    use super::*;
+
+    fn edit(line: u32, start: u32, end: u32, new_text: &str) -> TextEdit {
+        TextEdit {
+            range: Range {
+                start: lsp_types::Position {
+                    line,
+                    character: start,
+                },
+                end: lsp_types::Position {
+                    line,
+                    character: end,
+                },
+            },
+            new_text: new_text.to_string(),
+        }
+    }
+
+    #[test]
+    fn edits_at_utf16_columns() {
+        let mut document = Document::default();
+        document.set_text("a😀b\né c\n");
+        let edits = [edit(0, 3, 4, "B"), edit(1, 1, 2, "_")];
+        assert_eq!(document.text_with_edits(&edits), "a😀B\né_c\n");
+    }
+
+    #[test]
+    fn edits_past_the_line() {
+        let mut document = Document::default();
+        document.set_text("one\ntwo\n");
+        let edits = [edit(0, 9, 9, "!"), edit(1, 0, 0, "> ")];
+        assert_eq!(document.text_with_edits(&edits), "one!\n> two\n");
+    }
 }
@@ -1,21 +1,81 @@
 use std::env;
-use std::time::{Duration, Instant};
+use std::fs;
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
+use lsp_types::{Diagnostic, DiagnosticSeverity, Location, TextEdit, Url};
 use termion::color;
 use termion::event::Key;
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::annotation;
+use crate::args::Args;
+use crate::buffer::Buffer;
+use crate::calc;
+use crate::check::{Check, Problem};
+use crate::clipboard;
+use crate::command::Command;
+use crate::completion::{Completion, Menu};
+use crate::config::Config;
+use crate::count::Counts;
+use crate::diff;
+use crate::file_state::FileState;
+use crate::filetype::{self, Settings};
+
+use crate::document::LineEnding;
+use crate::floating_item::FloatingItem;
+use crate::grep;
+use crate::health;
+use crate::hex::{self, Hex};
+use crate::history;
+use crate::index::Index;
+use crate::list::Continuation;
+use crate::locale::{self, Catalog};
+use crate::log;
+use crate::lsp::Goto;
+use crate::markdown;
+use crate::measure;
+use crate::narrow::{self, Narrowing};
+use crate::progress::{Cancellation, Progress};
+use crate::prompt::{Outcome, Prompt};
+use crate::row::Mark;
+use crate::save::{self, OnFailure, Step};
+use crate::snippet::{self, Session};
+use crate::sort::{self, Order};
+use crate::spell::{self, Dictionary};
+use crate::terminal::{CTRL_DOWN, CTRL_PAGE_DOWN, CTRL_PAGE_UP, CTRL_UP};
+use crate::textobject::Object;
+use crate::theme::{self, Background, Style, Theme};
+use crate::trash::Operation;
+use crate::workspace::{Trust, Workspace};
 use crate::Document;
+use crate::FileType;
 use crate::Row;
 use crate::Terminal;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
-const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
 const QUIT_TIMES: u8 = 3;
+const TICK: Duration = Duration::from_millis(100);
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+const HISTORY_LIST_HEIGHT: usize = 8;
+/// Columns `Alt-<` and `Alt->` scroll wide code in a hover by.
+const HOVER_SCROLL_STEP: isize = 8;
+/// Lines `Ctrl-Up` and `Ctrl-Down` scroll long documentation in a hover by.
+const HOVER_LINE_STEP: isize = 1;
+/// Columns of the gutter that signs annotated lines, shown while the file
+/// has annotations.
+const GUTTER_WIDTH: usize = 2;
+const ANNOTATION_SIGN: &str = "✎";
+/// How long a message stays in the message bar.
+const MESSAGE_DURATION: Duration = Duration::from_secs(5);
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, PartialEq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
@@ -29,6 +89,8 @@ pub enum SearchDirection {
 
 struct StatusMessage {
     text: String,
+    /// Drawn dimmed after the text, e.g. the placeholder of a prompt.
+    hint: Option<String>,
     time: Instant,
 }
 
@@ -37,8 +99,13 @@ impl StatusMessage {
         Self {
             time: Instant::now(),
             text: message,
+            hint: None,
         }
     }
+
+    fn is_shown(&self) -> bool {
+        !self.text.is_empty() && self.time.elapsed() < MESSAGE_DURATION
+    }
 }
 
 pub struct Editor {
@@ -49,136 +116,670 @@ pub struct Editor {
     document: Document,
     status_message: StatusMessage,
     quit_times: u8,
+    config: Rc<Config>,
+    workspace: Workspace,
+    trust: Trust,
+    completion: Completion,
+    menu: Option<Menu>,
+    snippet: Option<Session>,
+    last_snapshot: Instant,
+    /// The query whose matches are highlighted, until `nohighlight`.
+    search_highlight: Option<String>,
+    /// Text of the long running operation in progress, if any.
+    progress: Option<String>,
+    /// File operations whose overwritten files are in the trash, latest
+    /// last, for `undo-file-operation`.
+    file_operations: Vec<Operation>,
+    /// The selection, after the ones it grew from; the first is the empty
+    /// one at the cursor where growing started.
+    selection: Vec<(Position, Position)>,
+    /// The dictionary misspellings are underlined with, set once it is
+    /// loaded; `None` unless `spell_check` is on.
+    dictionary: Option<Arc<OnceLock<Dictionary>>>,
+    /// Whether the screen was drawn with the loaded dictionary.
+    spelled: bool,
+    /// The `on_save` command while it runs.
+    check: Option<Check>,
+    /// What the `on_save` command reported the last time it ran.
+    problems: Vec<Problem>,
+    /// Whether the screen was drawn with the message bar.
+    message_bar_drawn: bool,
+    /// The files, symbols and TODOs of the workspace, unless `index` is off.
+    index: Option<Index>,
+    /// The whole document while the view is narrowed to some of its lines.
+    narrowing: Option<Narrowing>,
+    /// The name of the theme in use, which `reload-runtime` reads again.
+    theme: String,
+    /// The open buffers other than the current one, in order.
+    buffers: Vec<Buffer>,
+    /// The place of the current buffer among `buffers`.
+    buffer: usize,
 }
 
 impl Editor {
     pub fn run(&mut self) {
+        self.ask_workspace_trust();
+        let mut needs_refresh = true;
         loop {
-            if let Err(error) = self.refresh_screen() {
-                die(error);
+            if needs_refresh {
+                if self.message_bar_drawn != self.shows_message_bar() {
+                    // The text lost or gained a row.
+                    self.scroll();
+                }
+                if let Err(error) = self.refresh_screen() {
+                    die(&self.terminal, error);
+                }
+                self.message_bar_drawn = self.shows_message_bar();
             }
             if self.should_quit {
+                self.remember_file_state();
                 break;
             }
-            if let Err(error) = self.process_keypress() {
-                die(error);
+            match self.process_event() {
+                Ok(changed) => needs_refresh = changed,
+                Err(error) => die(&self.terminal, error),
+            }
+        }
+    }
+
+    /// Waits one tick for a key and services the language server.
+    /// Returns whether the screen needs to be redrawn.
+    fn process_event(&mut self) -> Result<bool> {
+        let pressed = match self.terminal.poll_key(TICK)? {
+            Some(key) => {
+                self.process_keypress(key);
+                true
+            }
+            None => false,
+        };
+        let received = self.document.poll_lsp();
+        let highlighted = self.document.poll_highlight();
+        let streamed = match self.document.poll_stream() {
+            Ok(streamed) => streamed,
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not read the rest: {e}"));
+                true
             }
+        };
+        if self.last_snapshot.elapsed() >= SNAPSHOT_INTERVAL {
+            self.last_snapshot = Instant::now();
+            self.snapshot();
         }
+        let followed = self.poll_follow();
+        let reloaded = self.document.changed_on_disk();
+        if reloaded {
+            self.handle_disk_change();
+        }
+        let spelled = !self.spelled && self.dictionary().is_some();
+        self.spelled |= spelled;
+        let checked = self.check.is_some();
+        self.poll_check();
+        let collapsed = self.message_bar_drawn && !self.shows_message_bar();
+        Ok(pressed
+            || collapsed
+            || received
+            || highlighted
+            || streamed
+            || followed
+            || reloaded
+            || spelled
+            || checked
+            || self.document.lsp_progress().is_some()
+            || self.document.stream_progress().is_some())
     }
 
     pub fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
-        let mut initial_status =
-            String::from("HELP: Ctrl-S = Save | Ctrl-F = Search | Ctrl-Q = Quit");
-        let document = if args.len() > 1 {
-            let file_name = &args[1];
-            let doc = Document::open(&file_name);
-            if doc.is_ok() {
-                doc.unwrap()
-            } else {
-                initial_status = format!("ERR: Could not open file: {}", file_name);
-                Document::default()
+        let args = Args::parse(env::args().skip(1));
+        let workspace = Workspace::discover(args.files.first().map(String::as_str));
+        let trust = workspace.trust();
+        let (config, config_error) = match load_config(&workspace, trust) {
+            Ok(config) => (config, None),
+            Err(e) => (Config::default(), Some(e)),
+        };
+        let language = config
+            .locale()
+            .map(str::to_string)
+            .or_else(locale::from_env);
+        let mut initial_status = match language.as_deref().map(Catalog::load) {
+            Some(Err(e)) => format!("ERR: Could not load locale: {e}"),
+            Some(Ok(catalog)) => {
+                locale::set(catalog);
+                locale::text("help", &[])
             }
-        } else {
-            Document::default()
+            None => locale::text("help", &[]),
         };
+        if let Some(e) = config_error {
+            initial_status = format!("ERR: Could not load config: {e}");
+        }
+        if let Some(option) = &args.unknown {
+            initial_status = format!("ERR: Unknown option {option}");
+        }
+        let terminal = Terminal::default().expect("Failed to Initialize Terminal");
+        let background = config
+            .background()
+            .or_else(|| terminal.background_color().map(Background::of))
+            .or_else(Background::from_env)
+            .unwrap_or(Background::Dark);
+        theme::set_background(background);
+        theme::set_colors(config.colors(), config.background_colors());
+        match Theme::load(config.theme()) {
+            Ok(theme) => theme::set(theme),
+            Err(e) => {
+                initial_status = format!("ERR: Could not load theme: {e}");
+                theme::set(Theme::default());
+            }
+        }
+        let index = config
+            .index()
+            .then(|| Index::start(workspace.root(), config.ignore()));
+        let mut editor = Self::new(terminal, Rc::new(config), workspace, trust, initial_status);
+        editor.index = index;
+        for file_name in &args.files {
+            editor.show_file(file_name);
+        }
+        if !editor.buffers.is_empty() {
+            editor.switch_buffer(0);
+        }
+        if args.read_only {
+            editor.document.set_read_only(true);
+            for buffer in &mut editor.buffers {
+                buffer.document.set_read_only(true);
+            }
+        }
+        if args.follow {
+            editor.toggle_follow();
+        }
+        editor
+    }
+
+    fn new(
+        terminal: Terminal,
+        config: Rc<Config>,
+        workspace: Workspace,
+        trust: Trust,
+        status: String,
+    ) -> Self {
+        let dictionary = load_dictionary(&config);
+        let theme = config.theme().to_string();
         Self {
             should_quit: false,
-            terminal: Terminal::default().expect("Failed to Initialize Terminal"),
+            terminal,
             cursor_position: Position::default(),
             offset: Position::default(),
-            status_message: StatusMessage::from(initial_status),
+            status_message: StatusMessage::from(status),
             quit_times: QUIT_TIMES,
-            document,
+            document: Document::default(),
+            config,
+            workspace,
+            trust,
+            completion: Completion::default(),
+            menu: None,
+            snippet: None,
+            last_snapshot: Instant::now(),
+            search_highlight: None,
+            progress: None,
+            file_operations: Vec::new(),
+            selection: Vec::new(),
+            dictionary,
+            spelled: false,
+            check: None,
+            problems: Vec::new(),
+            message_bar_drawn: true,
+            index: None,
+            narrowing: None,
+            theme,
+            buffers: Vec::new(),
+            buffer: 0,
+        }
+    }
+
+    /// The dictionary to check spelling with, once it is loaded.
+    fn dictionary(&self) -> Option<&Dictionary> {
+        self.dictionary.as_deref().and_then(OnceLock::get)
+    }
+
+    fn restore_file_state(&mut self) {
+        let Some(state) = self.document.file_name.as_deref().and_then(FileState::load) else {
+            return;
+        };
+        // The file may have shrunk since.
+        let len = self.document.len();
+        for fold in state.folds.iter() {
+            self.document
+                .folds_mut()
+                .close(fold.start.min(len)..fold.end.min(len));
+        }
+        self.cursor_position = state.cursor();
+        self.offset = state.offset();
+        self.clamp_cursor();
+        self.offset.y = self.offset.y.min(self.cursor_position.y);
+        self.offset.x = self.offset.x.min(self.cursor_position.x);
+        self.scroll();
+    }
+
+    fn remember_file_state(&self) {
+        if let Some(file_name) = &self.document.file_name {
+            // Losing the cursor position is not worth bothering the user.
+            let _ =
+                FileState::new(&self.document, &self.cursor_position, &self.offset).save(file_name);
+        }
+        for buffer in &self.buffers {
+            if let Some(file_name) = &buffer.document.file_name {
+                let _ = FileState::new(&buffer.document, &buffer.cursor_position, &buffer.offset)
+                    .save(file_name);
+            }
+        }
+    }
+
+    /// Asks whether the workspace may run language servers and apply its
+    /// `.neonano.toml`, unless a decision was remembered earlier.
+    fn ask_workspace_trust(&mut self) {
+        if self.trust != Trust::Unknown
+            || !(self.document.has_lsp() || self.workspace.project_config().is_some())
+        {
+            return;
+        }
+        let root = self.workspace.root().display();
+        let question = locale::text("trust-workspace", &[("root", &root)]);
+        let trust = match self.confirm(&question) {
+            Some(true) => Trust::Trusted,
+            Some(false) => Trust::Untrusted,
+            None => Trust::Unknown,
+        };
+        if self.workspace.remember(trust).is_err() {
+            self.status_message =
+                StatusMessage::from("ERR: Could not remember workspace trust".to_string());
+        }
+        self.trust = trust;
+        if trust != Trust::Trusted {
+            self.status_message = StatusMessage::from(locale::text("workspace-untrusted", &[]));
+            return;
+        }
+
+        match load_config(&self.workspace, trust) {
+            Ok(config) => {
+                self.dictionary = load_dictionary(&config);
+                self.spelled = false;
+                self.config = Rc::new(config);
+            }
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not load config: {e}"));
+            }
+        }
+        if let Some(file_name) = self.document.file_name.clone() {
+            if let Ok(mut document) = Document::open(&file_name, Rc::clone(&self.config)) {
+                self.load_annotations(&mut document);
+                self.document = document;
+            }
+        }
+        self.start_lsp();
+    }
+
+    fn start_lsp(&mut self) {
+        if let Err(e) = self.document.start_lsp() {
+            let log =
+                log::path().map_or(String::new(), |path| format!(" (see {})", path.display()));
+            self.status_message = StatusMessage::from(format!("ERR: {e}{log}"));
         }
     }
 
     fn draw_welcome_message(&self) -> Vec<String> {
         let mut welcome_message = format!("Hecto Editor -- version {VERSION}");
         let width = self.terminal.size().width as usize;
-        let len = welcome_message.len();
+        let len = measure::width(&welcome_message);
         let padding = width.saturating_sub(len) / 2;
         let spaces = " ".repeat(padding);
         welcome_message = format!("~{spaces}{welcome_message}");
-        welcome_message.truncate(width);
-        welcome_message
+        measure::truncate(&welcome_message, width)
             .graphemes(true)
             .map(String::from)
             .collect::<Vec<String>>()
     }
 
-    pub fn draw_row(&self, row: &Row) -> Vec<String> {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        row.render(start, end)
+    /// Draws the visible part of `row`, which is line `y`, marking search
+    /// matches, diagnostics, text past the maximum line length, the
+    /// selection and the brackets of `brackets` on it.
+    pub fn draw_row(
+        &self,
+        row: &Row,
+        y: usize,
+        brackets: Option<&(Position, Position)>,
+    ) -> Vec<String> {
+        let mut marks: Vec<(Range<usize>, Mark)> = self
+            .search_highlight
+            .as_ref()
+            .map(|query| row.matches(query))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|range| {
+                let current = y == self.cursor_position.y && range.start == self.cursor_position.x;
+                (range, if current { Mark::Current } else { Mark::Match })
+            })
+            .collect();
+        for diagnostic in self.document.diagnostics() {
+            let (start, end) = (diagnostic.range.start, diagnostic.range.end);
+            if !(start.line as usize..=end.line as usize).contains(&y) {
+                continue;
+            }
+            let from = if start.line as usize == y {
+                start.character as usize
+            } else {
+                0
+            };
+            let to = if end.line as usize == y {
+                end.character as usize
+            } else {
+                row.len()
+            };
+            // Empty ranges mark the grapheme after them.
+            marks.push((from..to.max(from + 1), Mark::Diagnostic));
+        }
+        if let Some(dictionary) = self.dictionary() {
+            for range in row.misspellings(dictionary) {
+                marks.push((range, Mark::Misspelled));
+            }
+        }
+        if let Some(max_line_length) = self.document.settings().max_line_length {
+            if row.len() > max_line_length {
+                marks.push((max_line_length..row.len(), Mark::LongLine));
+            }
+        }
+        if let Some((start, end)) = self.selection.last() {
+            if (start.y..=end.y).contains(&y) {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = if y == end.y { end.x } else { row.len() };
+                marks.insert(0, (from..to, Mark::Selection));
+            }
+        }
+        if let Some((bracket, pair)) = brackets {
+            for position in [bracket, pair] {
+                if position.y == y {
+                    marks.insert(0, (position.x..position.x + 1, Mark::Bracket));
+                }
+            }
+        }
+        row.render(
+            self.offset.x,
+            self.text_width(),
+            &marks,
+            self.document.tab_width(),
+        )
     }
 
     fn draw_rows(&self) {
-        let height = self.terminal.size().height;
+        if let Some(hex) = self.document.hex() {
+            self.draw_hex(hex);
+            return;
+        }
+        let height = self.text_height();
+        let width = self.terminal.size().width as usize;
+        let offset = self.screen_offset();
+        let gutter = self.gutter_width();
+        let menu = self.menu.as_ref().and_then(|menu| {
+            menu.floating(self.cursor_position.clone())
+                .placed(&offset, width, height)
+                .map(|menu| menu.past_gutter(gutter, width))
+        });
+        let documentation = self
+            .menu
+            .as_ref()
+            .zip(menu.as_ref())
+            .and_then(|(menu, floating)| menu.documentation_floating(floating, width));
+        let floatings: Vec<FloatingItem> = (0..self.document.floating_len())
+            .filter_map(|index| self.document.floating(index))
+            .filter_map(|floating| floating.placed(&offset, width, height))
+            .map(|floating| floating.past_gutter(gutter, width))
+            .collect();
+        let brackets = self.document.matching_bracket(&self.cursor_position);
+        let folds = self.document.folds();
+        let mut y = self.offset.y;
         for terminal_row in 0..height {
             let mut row_array: Vec<String>;
-            Terminal::clear_current_line();
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(terminal_row as usize))
-            {
-                row_array = self.draw_row(row);
+            self.terminal.clear_current_line();
+            if let Some(row) = self.document.row(y) {
+                row_array = self.draw_row(row, y, brackets.as_ref());
+                if let Some(fold) = folds.at(y) {
+                    self.draw_fold_summary(&mut row_array, fold);
+                }
+                if gutter > 0 {
+                    row_array.splice(0..0, self.draw_gutter(y));
+                }
             } else if self.document.is_empty() && terminal_row == height / 3 {
                 row_array = self.draw_welcome_message();
             } else {
                 row_array = vec![String::from("~"), String::from("\r")];
             }
-            for floating_idx in 0..self.document.floating_len() {
-                if let Some(floating) = self.document.floating(floating_idx) {
-                    row_array = floating.render(&row_array, terminal_row as usize);
-                }
+            for floating in &floatings {
+                row_array = floating.render(&row_array, terminal_row);
+            }
+            if let Some(menu) = &menu {
+                row_array = menu.render(&row_array, terminal_row);
             }
+            if let Some(documentation) = &documentation {
+                row_array = documentation.render(&row_array, terminal_row);
+            }
+            y = folds.next_visible(y);
+
+            self.terminal.print(&format!(
+                "{}{}\r\n",
+                color::Fg(color::Reset),
+                row_array.concat()
+            ));
+        }
+    }
+
+    /// Draws the lines of the hex dump in view.
+    fn draw_hex(&self, hex: &Hex) {
+        let width = self.text_width();
+        let per_line = hex::per_line(width);
+        for y in self.offset.y..self.offset.y + self.text_height() {
+            self.terminal.clear_current_line();
+            let line = hex.line(y, per_line).unwrap_or_else(|| "~".to_string());
+            self.terminal.print(&format!(
+                "{}{}\r\n",
+                color::Fg(color::Reset),
+                measure::truncate(&line, width)
+            ));
+        }
+    }
+
+    /// The cells of the gutter in front of line `y`: the sign of an
+    /// annotated line, blank otherwise.
+    fn draw_gutter(&self, y: usize) -> Vec<String> {
+        let mut cells = vec![" ".to_string(); GUTTER_WIDTH];
+        if self.document.annotations().get(y).is_some() {
+            let style = theme::current().ui.annotation;
+            cells[0] = format!(
+                "{}{ANNOTATION_SIGN}{}",
+                style.escape(),
+                Style::default().escape()
+            );
+        }
+        cells
+    }
+
+    /// Columns in front of the text.
+    fn gutter_width(&self) -> usize {
+        if self.document.annotations().is_empty() {
+            0
+        } else {
+            GUTTER_WIDTH
+        }
+    }
+
+    /// Columns of the screen the text is drawn in.
+    fn text_width(&self) -> usize {
+        (self.terminal.size().width as usize)
+            .saturating_sub(self.gutter_width())
+            .max(1)
+    }
+
+    /// Appends `{…} (12 lines)` to `row_array`, the drawn first line of
+    /// `fold`, as far as it fits.
+    fn draw_fold_summary(&self, row_array: &mut Vec<String>, fold: &Range<usize>) {
+        let width = self.terminal.size().width as usize;
+        let closing = self
+            .document
+            .row(fold.end - 1)
+            .map(|row| row.as_str().trim())
+            .filter(|closing| closing.len() <= 3)
+            .unwrap_or_default();
+        let summary = format!("…{closing} ({} lines)", fold.len());
+        let room = (width + 1).saturating_sub(row_array.len());
+        let mut cells: Vec<String> = summary
+            .graphemes(true)
+            .take(room)
+            .map(String::from)
+            .collect();
+        let Some(first) = cells.first_mut() else {
+            return;
+        };
+        let style = theme::current().ui.fold;
+        first.insert_str(0, &style.escape());
+        cells.push(format!("{}", termion::style::Reset));
+        row_array.extend(cells);
+    }
+
+    /// Whether the message bar takes the last line, which is given to the
+    /// text while there is no message if `hide_message_bar` is on.
+    fn shows_message_bar(&self) -> bool {
+        !self.config.hide_message_bar() || self.status_message.is_shown()
+    }
+
+    /// The lines of text on the screen.
+    fn text_height(&self) -> usize {
+        let height = self.terminal.size().height as usize;
+        height + usize::from(!self.shows_message_bar())
+    }
 
-            println!("{}{}\r", color::Fg(color::Reset), row_array.concat());
+    /// The offset to place floating items at: lines hidden by folds
+    /// between the top of the screen and the cursor count as scrolled past.
+    fn screen_offset(&self) -> Position {
+        let hidden = self
+            .document
+            .folds()
+            .hidden_in(self.offset.y..self.cursor_position.y);
+        Position {
+            x: self.offset.x,
+            y: self.offset.y.saturating_add(hidden),
         }
     }
 
     fn refresh_screen(&self) -> Result<()> {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
+        self.terminal.cursor_hide();
+        self.terminal.cursor_position(&Position::default());
         if self.should_quit {
-            Terminal::clear_screen();
-            println!("Good bye \r");
+            self.terminal.clear_screen();
+            self.terminal.print("Good bye \r\n");
         } else {
             self.draw_rows();
             self.draw_status_bar();
-            self.draw_message_bar();
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
+            if self.shows_message_bar() {
+                self.terminal.print("\r\n");
+                self.draw_message_bar();
+            }
+            if let Some(hex) = self.document.hex() {
+                let (y, x) = hex.position(hex::per_line(self.text_width()));
+                self.terminal.cursor_position(&Position {
+                    x,
+                    y: y.saturating_sub(self.offset.y),
+                });
+                self.terminal.cursor_show();
+                return self.terminal.flush();
+            }
+            let offset = self.screen_offset();
+            let Position { x, y } = self.cursor_position;
+            let tab_width = self.document.tab_width();
+            let x = self
+                .document
+                .row(y)
+                .map_or(x.saturating_sub(offset.x), |row| {
+                    row.column(x, tab_width)
+                        .saturating_sub(row.column(offset.x, tab_width))
+                });
+            self.terminal.cursor_position(&Position {
+                x: x + self.gutter_width(),
+                y: y.saturating_sub(offset.y),
             });
         }
-        Terminal::cursor_show();
-        Terminal::flush()
+        self.terminal.cursor_show();
+        self.terminal.flush()
     }
 
-    fn process_keypress(&mut self) -> Result<()> {
-        let pressed_key = Terminal::read_key()?;
+    fn process_keypress(&mut self, pressed_key: Key) {
+        // What the key before changed is undone on its own.
+        self.document.end_undo_step();
+        // Commands typed after Ctrl-P act on the selection.
+        if !matches!(pressed_key, Key::Alt('=' | '-') | Key::Ctrl('7' | 'p')) {
+            self.selection.clear();
+        }
+        if self.menu.is_some() && self.process_menu_key(pressed_key) {
+            self.resolve_selected_completion();
+            return;
+        }
+        if self.snippet.is_some() && self.process_snippet_key(pressed_key) {
+            return;
+        }
+        if self.document.is_hex() && self.process_hex_key(pressed_key) {
+            return;
+        }
+        if self.document.is_read_only() && edits(pressed_key) {
+            self.report_read_only();
+            return;
+        }
         match pressed_key {
+            Key::Char('\n') => {
+                if self.continue_list() {
+                    self.document.clear_floating();
+                    return;
+                }
+                self.document.insert(&self.cursor_position, '\n');
+                self.move_cursor(Key::Right);
+                self.reindent(self.cursor_position.y..self.cursor_position.y + 1);
+                self.document.clear_floating();
+            }
+            Key::Char('\t') if self.document.settings().expand_tabs == Some(true) => {
+                // Up to the next stop of the indentation.
+                let settings = self.document.settings();
+                let width = settings.indent_width.unwrap_or(self.document.tab_width());
+                let Position { x, y } = self.cursor_position;
+                let spaces = " ".repeat(width - x % width.max(1));
+                self.cursor_position = self.document.replace(y, x, x, &spaces);
+                self.scroll();
+                self.document.clear_floating();
+            }
             Key::Char(c) => {
                 self.document.insert(&self.cursor_position, c);
                 self.move_cursor(Key::Right);
+                let y = self.cursor_position.y;
+                let starts_line = self
+                    .document
+                    .row(y)
+                    .is_some_and(|row| row.as_str().trim_start().chars().count() == 1);
+                if matches!(c, '}' | ')' | ']') && starts_line {
+                    // A closing bracket goes back to where its opening line
+                    // is indented.
+                    self.reindent(y..y + 1);
+                }
                 self.document.clear_floating();
             }
             Key::Ctrl('q') => {
-                if !self.document.is_dirty() {
+                if self.narrowing.is_some() {
+                    self.widen();
+                }
+                let dirty = self.document.is_dirty()
+                    || self.buffers.iter().any(|buffer| buffer.document.is_dirty());
+                if !dirty {
                     self.should_quit = true;
-                    return Ok(());
+                    return;
                 }
                 if self.quit_times == 1 {
                     self.should_quit = true;
                 } else {
                     self.quit_times -= 1;
-                    let unsaved_msg: String = format!(
-                        "WARNING! Unsaved changes will be discarded! Press Ctrl-Q {} times to quit.",
-                        self.quit_times
-                    );
+                    let unsaved_msg = locale::text("quit-warning", &[("times", &self.quit_times)]);
                     self.status_message = StatusMessage::from(unsaved_msg);
                 }
             }
@@ -187,7 +788,44 @@ impl Editor {
                 self.document.clear_floating();
             }
             Key::Ctrl('f') => self.search(),
+            Key::Ctrl('z') => self.undo(),
+            Key::Ctrl('y') => self.redo(),
+            Key::Ctrl('c') if self.document.cancel_lsp_progress() => {
+                self.status_message =
+                    StatusMessage::from("Asked the language server to cancel".to_string());
+            }
             Key::F(1) => self.hover(),
+            Key::Alt('<') => self.document.scroll_floating(-HOVER_SCROLL_STEP),
+            Key::Alt('>') => self.document.scroll_floating(HOVER_SCROLL_STEP),
+            CTRL_UP => self.document.scroll_floating_vertically(-HOVER_LINE_STEP),
+            CTRL_DOWN => self.document.scroll_floating_vertically(HOVER_LINE_STEP),
+            CTRL_PAGE_DOWN => self.cycle_buffers(SearchDirection::Forward),
+            CTRL_PAGE_UP => self.cycle_buffers(SearchDirection::Backward),
+            Key::Alt('o') => self.open_file(None),
+            Key::Ctrl('n') | Key::Null => self.complete(),
+            Key::Ctrl('p') => self.command_prompt(),
+            // What terminals send for Ctrl-/.
+            Key::Ctrl('7') => self.toggle_comment(),
+            Key::Alt('F') => self.format(),
+            Key::Alt('h') => self.browse_history(),
+            Key::Alt('n') => self.goto_diagnostic(SearchDirection::Forward),
+            Key::Alt('p') => self.goto_diagnostic(SearchDirection::Backward),
+            Key::Alt('d') => self.goto(Goto::Definition),
+            Key::Alt('t') => self.goto(Goto::TypeDefinition),
+            Key::Alt('i') => self.goto(Goto::Implementation),
+            Key::Alt('.') => self.code_action(),
+            Key::Alt('m') => self.goto_matching_bracket(),
+            Key::Alt('z') => {
+                if self.document.folds().at(self.cursor_position.y).is_some() {
+                    self.open_fold();
+                } else {
+                    self.close_fold();
+                }
+            }
+            Key::Alt('Z') => self.toggle_all_folds(),
+            Key::Alt('=') => self.expand_selection(),
+            Key::Alt('-') => self.shrink_selection(),
+            Key::Alt('b') => self.toggle_bom(),
             Key::Delete => {
                 self.document.clear_floating();
                 self.document.delete(&self.cursor_position);
@@ -212,33 +850,297 @@ impl Editor {
             }
             _ => (),
         }
+        if let Some(menu) = self.menu.as_mut() {
+            menu.update(&self.document, &self.cursor_position);
+            if menu.is_empty() {
+                self.menu = None;
+            }
+        }
+        self.resolve_selected_completion();
+        self.scroll();
+    }
+
+    /// Keys that move through and overwrite the bytes of a buffer edited
+    /// as a hex dump: Tab switches between the hex digits and the
+    /// characters. Returns whether the key was consumed; keys that would
+    /// edit the text only say so.
+    fn process_hex_key(&mut self, key: Key) -> bool {
+        let per_line = hex::per_line(self.text_width());
+        let page = (per_line * self.text_height()) as isize;
+        let per_line_step = per_line as isize;
+        match key {
+            Key::Left => self.document.move_hex(|hex| hex.move_by(-1)),
+            Key::Right => self.document.move_hex(|hex| hex.move_by(1)),
+            Key::Up => self.document.move_hex(|hex| hex.move_by(-per_line_step)),
+            Key::Down => self.document.move_hex(|hex| hex.move_by(per_line_step)),
+            Key::PageUp => self.document.move_hex(|hex| hex.move_by(-page)),
+            Key::PageDown => self.document.move_hex(|hex| hex.move_by(page)),
+            Key::Home => self
+                .document
+                .move_hex(|hex| hex.move_in_line(false, per_line)),
+            Key::End => self
+                .document
+                .move_hex(|hex| hex.move_in_line(true, per_line)),
+            Key::Char('\t') => self.document.move_hex(Hex::switch_column),
+            Key::Char(_) | Key::Backspace | Key::Delete if self.document.is_read_only() => {
+                self.report_read_only();
+            }
+            Key::Char(c) if c != '\n' => {
+                if !self.document.type_hex(c) {
+                    self.status_message = StatusMessage::from(format!("Not a byte here: {c}"));
+                }
+            }
+            _ if edits(key) => self.report_hex(),
+            _ => return false,
+        }
+        self.scroll();
+        true
+    }
+
+    fn report_hex(&mut self) {
+        self.status_message = StatusMessage::from(
+            "Hex mode overwrites bytes; `hex` edits the text again".to_string(),
+        );
+    }
+
+    /// Keys that drive the open completion menu. Returns whether the key
+    /// was consumed; anything else closes the menu, except for typing an
+    /// identifier or deleting, which narrows it down instead.
+    fn process_menu_key(&mut self, key: Key) -> bool {
+        let Some(menu) = self.menu.as_mut() else {
+            return false;
+        };
+        match key {
+            Key::Down | Key::Ctrl('n') => menu.select_next(),
+            Key::Up | Key::Ctrl('p') => menu.select_previous(),
+            Key::Char('\n' | '\t') => self.accept_completion(),
+            Key::Esc => self.menu = None,
+            Key::Char(c) if c.is_alphanumeric() || c == '_' || c == '/' => return false,
+            Key::Backspace => return false,
+            _ => {
+                self.menu = None;
+                return false;
+            }
+        }
+        true
+    }
+
+    fn complete(&mut self) {
+        self.document.clear_floating();
+        self.menu = self
+            .completion
+            .complete(&mut self.document, &self.cursor_position);
+        if self.menu.is_none() {
+            self.status_message = StatusMessage::from("No completions".to_string());
+        }
+    }
+
+    /// Keys while a snippet is being filled in: Tab and Shift-Tab move
+    /// between its tabstops, and typing at a tabstop first replaces its
+    /// placeholder. Returns whether the key was consumed; keys that leave
+    /// the snippet end the session.
+    fn process_snippet_key(&mut self, key: Key) -> bool {
+        let Some(session) = self.snippet.as_mut() else {
+            return false;
+        };
+        match key {
+            Key::Char('\t') => {
+                if session.next() {
+                    self.enter_tabstop();
+                } else {
+                    self.snippet = None;
+                    self.status_message = StatusMessage::from(String::new());
+                }
+                return true;
+            }
+            Key::BackTab => {
+                if session.previous() {
+                    self.enter_tabstop();
+                }
+                return true;
+            }
+            Key::Char('\n') | Key::Esc => {
+                self.snippet = None;
+                return key == Key::Esc;
+            }
+            Key::Char(_) | Key::Backspace => (),
+            Key::Left | Key::Right => return false,
+            _ => {
+                self.snippet = None;
+                return false;
+            }
+        }
+
+        let (start, end) = session.current().clone();
+        if session.placeholder_pending {
+            session.placeholder_pending = false;
+            if start.y == end.y && end.x > start.x {
+                self.document.replace(start.y, start.x, end.x, "");
+                session.shift(&start, -((end.x - start.x) as isize));
+                self.cursor_position = start;
+                if key == Key::Backspace {
+                    return true;
+                }
+            }
+        }
+        let at = self.cursor_position.clone();
+        if key == Key::Backspace {
+            if at.x > 0 {
+                session.shift(
+                    &Position {
+                        x: at.x - 1,
+                        y: at.y,
+                    },
+                    -1,
+                );
+            }
+        } else {
+            session.shift(&at, 1);
+        }
+        false
+    }
+
+    fn enter_tabstop(&mut self) {
+        if let Some(session) = &self.snippet {
+            self.cursor_position = session.current().0.clone();
+            self.status_message = StatusMessage::from(session.describe());
+            self.scroll();
+        }
+    }
+
+    /// Values of the snippet variables the editor knows about.
+    fn snippet_variable(&self, name: &str) -> Option<String> {
+        let path = self
+            .document
+            .file_name
+            .as_ref()
+            .and_then(|name| Path::new(name).canonicalize().ok());
+        match name {
+            "TM_FILENAME" => path?
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            "TM_FILENAME_BASE" => path?
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned()),
+            "TM_DIRECTORY" => path?.parent().map(|dir| dir.display().to_string()),
+            "TM_FILEPATH" => path.map(|path| path.display().to_string()),
+            "TM_LINE_INDEX" => Some(self.cursor_position.y.to_string()),
+            "TM_LINE_NUMBER" => Some(self.cursor_position.y.saturating_add(1).to_string()),
+            "TM_SELECTED_TEXT" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    fn resolve_selected_completion(&mut self) {
+        if let Some(menu) = self.menu.as_mut() {
+            menu.resolve_selected(&mut self.document);
+        }
+    }
+
+    fn accept_completion(&mut self) {
+        let Some(menu) = self.menu.take() else {
+            return;
+        };
+        let Some(candidate) = menu.selected() else {
+            return;
+        };
+        if !candidate.snippet {
+            self.cursor_position = self.document.replace(
+                self.cursor_position.y,
+                candidate.start,
+                self.cursor_position.x,
+                &candidate.insert_text,
+            );
+            self.scroll();
+            return;
+        }
+        let snippet = snippet::parse(&candidate.insert_text, &|name| self.snippet_variable(name));
+        let origin = Position {
+            x: candidate.start,
+            y: self.cursor_position.y,
+        };
+        self.cursor_position =
+            self.document
+                .replace(origin.y, origin.x, self.cursor_position.x, &snippet.text);
+        self.snippet = Session::new(&snippet, &origin);
+        self.enter_tabstop();
+        self.scroll();
+    }
+
+    /// Inserts a doc comment skeleton for the function whose signature is
+    /// at the cursor and fills it in like a snippet.
+    fn insert_doc_comment(&mut self) {
+        let Some(skeleton) = self.document.doc_comment(self.cursor_position.y) else {
+            self.status_message =
+                StatusMessage::from("No undocumented function signature here".to_string());
+            return;
+        };
+        let snippet = snippet::parse(&skeleton.snippet, &|name| self.snippet_variable(name));
+        let origin = Position {
+            x: 0,
+            y: skeleton.row,
+        };
+        self.document.replace(origin.y, 0, 0, &snippet.text);
+        self.snippet = Session::new(&snippet, &origin);
+        self.enter_tabstop();
         self.scroll();
-        Ok(())
     }
 
     fn scroll(&mut self) {
+        if let Some(hex) = self.document.hex() {
+            let (y, _) = hex.position(hex::per_line(self.text_width()));
+            let height = self.text_height();
+            if y < self.offset.y {
+                self.offset.y = y;
+            } else if y >= self.offset.y + height {
+                self.offset.y = y + 1 - height;
+            }
+            return;
+        }
         let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
+        let width = self.text_width();
+        let height = self.text_height();
+        self.document.folds_mut().reveal(y);
+        let folds = self.document.folds();
         let offset = &mut self.offset;
 
         if y < offset.y {
             offset.y = y;
-        } else if y >= offset.y.saturating_add(height) {
-            offset.y = y.saturating_sub(height).saturating_add(1);
+        } else if y.saturating_sub(offset.y) - folds.hidden_in(offset.y..y) >= height {
+            // Lines are counted upwards from the cursor to step over folds.
+            offset.y = y;
+            for _ in 1..height {
+                offset.y = folds.previous_visible(offset.y);
+            }
+        }
+        while folds.is_hidden(offset.y) {
+            offset.y = folds.previous_visible(offset.y);
         }
 
+        // Tabs take more than one column.
+        let tab_width = self.document.tab_width();
+        let column = |at| {
+            self.document
+                .row(y)
+                .map_or(at, |row| row.column(at, tab_width))
+        };
         if x < offset.x {
             offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        } else if column(x) >= column(offset.x).saturating_add(width) {
+            let end = column(x);
+            let mut first = x;
+            while first > 0 && end - column(first - 1) < width {
+                first -= 1;
+            }
+            offset.x = first;
         }
     }
 
     fn move_cursor(&mut self, key: Key) {
         let Position { mut x, mut y } = self.cursor_position;
         let height = self.document.len();
-        let terminal_height = self.terminal.size().height as usize;
+        let terminal_height = self.text_height();
+        let folds = self.document.folds();
         let mut width = if let Some(row) = self.document.row(y) {
             row.len()
         } else {
@@ -247,32 +1149,31 @@ impl Editor {
 
         match key {
             Key::PageUp => {
-                y = if y > terminal_height {
-                    y.saturating_sub(terminal_height)
-                } else {
-                    0
+                for _ in 0..terminal_height {
+                    y = folds.previous_visible(y);
                 }
             }
             Key::PageDown => {
-                y = if y.saturating_add(terminal_height) < height {
-                    y.saturating_add(terminal_height)
-                } else {
-                    height
+                for _ in 0..terminal_height {
+                    if y >= height {
+                        break;
+                    }
+                    y = folds.next_visible(y).min(height);
                 }
             }
             Key::Home => x = 0,
             Key::End => x = width,
-            Key::Up => y = y.saturating_sub(1),
+            Key::Up => y = folds.previous_visible(y),
             Key::Down => {
                 if y < height {
-                    y = y.saturating_add(1)
+                    y = folds.next_visible(y).min(height)
                 }
             }
             Key::Left => {
                 if x >= 1 {
                     x -= 1
                 } else if y >= 1 {
-                    y -= 1;
+                    y = folds.previous_visible(y);
                     if let Some(row) = self.document.row(y) {
                         x = row.len()
                     } else {
@@ -284,7 +1185,7 @@ impl Editor {
                 if x < width {
                     x += 1
                 } else if y < height {
-                    y += 1;
+                    y = folds.next_visible(y).min(height);
                     x = 0;
                 }
             }
@@ -311,110 +1212,414 @@ impl Editor {
         } else {
             ""
         };
-        let mut file_name = "[No File]".to_string();
-        if let Some(name) = &self.document.file_name {
-            file_name = name.clone();
-            file_name.truncate(20);
-        }
+        let document = self
+            .narrowing
+            .as_ref()
+            .map_or(&self.document, |narrowing| &narrowing.document);
+        let file_name = document.file_name.as_deref().map_or_else(
+            || "[No File]".to_string(),
+            |name| measure::abbreviate_start(name, 20),
+        );
+        let buffer_indicator = if self.buffers.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}/{}]", self.buffer + 1, self.buffers.len() + 1)
+        };
         status = format!(
-            "{} - {} lines{}",
+            "{}{buffer_indicator} - {} lines{}",
             file_name,
             self.document.len(),
             modified_indicator
         );
+        let diagnostics = self.document.diagnostics();
+        if !diagnostics.is_empty() {
+            let count = |severity| {
+                diagnostics
+                    .iter()
+                    .filter(|diagnostic| diagnostic.severity == Some(severity))
+                    .count()
+            };
+            status.push_str(&format!(
+                "  E:{} W:{}",
+                count(DiagnosticSeverity::ERROR),
+                count(DiagnosticSeverity::WARNING)
+            ));
+        }
+        if let Some(progress) = self
+            .progress
+            .clone()
+            .or_else(|| self.document.stream_progress())
+            .or_else(|| self.document.lsp_progress())
+            .or_else(|| self.check.as_ref().map(|check| check.command.clone()))
+        {
+            status.push_str(&format!("  {} {progress}", spinner_frame()));
+        }
 
+        let bom_indicator = if self.document.has_bom() {
+            " | BOM"
+        } else {
+            ""
+        };
+        let line_ending_indicator = if self.document.line_ending() == LineEnding::CrLf {
+            " | CRLF"
+        } else {
+            ""
+        };
+        let plain_indicator = if self.document.is_plain() && !self.document.is_binary() {
+            " | plain"
+        } else {
+            ""
+        };
+        let read_only_indicator = if self.document.is_hex() {
+            " | hex"
+        } else if self.document.is_binary() {
+            " | binary"
+        } else if self.document.is_read_only() {
+            " | read-only"
+        } else {
+            ""
+        };
+        let following_indicator = if self.document.is_following() {
+            " | following"
+        } else {
+            ""
+        };
+        let narrowed_indicator = if self.narrowing.is_some() {
+            " | narrowed"
+        } else {
+            ""
+        };
+        let word_count_indicator = if self.config.word_count() {
+            format!(" | {} words", self.counts().words)
+        } else {
+            String::new()
+        };
+        // The byte the cursor is on in a hex dump, else its line.
+        let (at, len) = self.document.hex().map_or(
+            (
+                self.cursor_position.y.saturating_add(1),
+                self.document.len(),
+            ),
+            |hex| (hex.cursor(), hex.bytes().len()),
+        );
         let line_indicator = format!(
-            "{} | {}/{}",
+            "{}{bom_indicator}{line_ending_indicator}{read_only_indicator}{plain_indicator}{following_indicator}{narrowed_indicator}{word_count_indicator} | {at}/{len}",
             self.document.file_type(),
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
         );
-        let len = status.len() + line_indicator.len();
-        if width > len {
-            status.push_str(&" ".repeat(width.saturating_sub(len)));
-        }
+        // The indicators on the right are kept whole, and the left side
+        // gives way, always leaving a space between the two.
+        let right = measure::width(&line_indicator);
+        let status = measure::abbreviate(&status, width.saturating_sub(right + 1));
+        let padding = width.saturating_sub(measure::width(&status) + right).max(1);
+        let status = format!("{status}{}{line_indicator}", " ".repeat(padding));
+        let status = measure::truncate(&status, width);
 
-        status = format!("{status}{line_indicator}");
-        status.truncate(width);
-
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{status}\r");
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
+        self.terminal
+            .print(&theme::current().ui.status_bar.escape());
+        self.terminal.print(status);
+        self.terminal.print(&Style::default().escape());
     }
 
     fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
+        // Cleared in the style so the background spans the whole line.
+        self.terminal
+            .print(&theme::current().ui.message_bar.escape());
+        self.terminal.clear_current_line();
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{text}");
+        if message.is_shown() {
+            let width = self.terminal.size().width as usize;
+            let text = measure::truncate(&message.text, width);
+            self.terminal.print(text);
+            if let Some(hint) = &message.hint {
+                let hint = measure::truncate(hint, width.saturating_sub(measure::width(text)));
+                self.terminal.print(&format!(
+                    "{}{hint}{}",
+                    termion::style::Faint,
+                    termion::style::NoFaint
+                ));
+            }
         }
+        self.terminal.print(&Style::default().escape());
     }
 
     fn save(&mut self) {
-        if self.document.file_name.is_none() {
-            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
-            if new_name.is_none() {
-                self.status_message = StatusMessage::from("Save aborted".to_string());
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        let named = self.document.file_name.is_none();
+        if named {
+            let new_name = self
+                .prompt(&locale::text("save-as", &[]), |_, _, _| {})
+                .unwrap_or(None);
+            let Some(new_name) = new_name else {
+                self.status_message = StatusMessage::from(locale::text("save-aborted", &[]));
                 return;
-            }
-            self.document.file_name = new_name;
+            };
+            self.document.set_file_name(new_name);
+        } else if self.document.is_binary() && !self.document.is_hex() {
+            self.status_message =
+                StatusMessage::from("Binary file: saving would corrupt it".to_string());
+            return;
+        } else if self.document.is_read_only()
+            && self.confirm("Read-only; save anyway? (y/n): ") != Some(true)
+        {
+            self.status_message = StatusMessage::from(locale::text("save-aborted", &[]));
+            return;
+        } else if self.document.changed_on_disk() && !self.confirm_overwrite() {
+            return;
         }
 
-        if self.document.save().is_ok() {
-            self.status_message = StatusMessage::from("File Saved successfully".to_string());
-        } else {
-            self.status_message = StatusMessage::from("Error writing file!".to_string());
+        let Some(mut report) = self.run_save_steps() else {
+            return;
+        };
+        match self.document.save() {
+            Ok(notes) => {
+                report.notes.extend(notes);
+                self.saved(&report, named);
+            }
+            Err(_) => {
+                self.status_message = StatusMessage::from(locale::text("save-failed", &[]));
+            }
         }
     }
 
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>>
-    where
-        C: FnMut(&mut Self, Key, &String),
-    {
-        let mut result = String::new();
-        loop {
-            self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
-            self.refresh_screen()?;
-
-            let key = Terminal::read_key()?;
-            match key {
-                Key::Backspace => {
-                    if !result.is_empty() {
-                        result.truncate(result.len().saturating_sub(1));
-                    }
-                }
-                Key::Char('\n') | Key::Ctrl('c') => break,
-                Key::Char(c) => {
-                    if !c.is_control() {
-                        result.push(c);
-                    }
-                }
+    /// Reports a save with what its steps did and follows it up, starting
+    /// the language server if the file was `renamed`.
+    fn saved(&mut self, report: &save::Report, renamed: bool) {
+        self.remember_file_state();
+        let saved = locale::text("saved", &[]);
+        self.status_message = StatusMessage::from(match report.summary() {
+            Some(summary) => format!("{saved} ({summary})"),
+            None => saved,
+        });
+        // Lines were inserted and deleted above the notes.
+        if let Err(e) = self.store_annotations() {
+            self.status_message =
+                StatusMessage::from(format!("ERR: Could not write annotations: {e}"));
+        }
+        if renamed && self.trust == Trust::Trusted {
+            self.start_lsp();
+        }
+        self.run_on_save();
+    }
 
-                Key::Esc => {
-                    result.truncate(0);
-                    break;
+    /// Saves the buffer to `file_name`, or a file asked for, which it is of
+    /// from then on.
+    fn save_as(&mut self, file_name: Option<String>) {
+        let Some(file_name) = self.ask_target(file_name, &locale::text("save-as", &[])) else {
+            self.status_message = StatusMessage::from(locale::text("save-aborted", &[]));
+            return;
+        };
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        let Some(mut report) = self.run_save_steps() else {
+            return;
+        };
+        // The buffer stays of its file if the new one cannot be written.
+        match self.document.save_as(file_name.clone()) {
+            Ok(notes) => {
+                report.notes.extend(notes);
+                self.saved(&report, true);
+            }
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not write {file_name}: {e}"));
+            }
+        }
+    }
+
+    /// Writes the buffer to `file_name`, or a file asked for, leaving it of
+    /// its own file.
+    fn save_copy(&mut self, file_name: Option<String>) {
+        let Some(file_name) = self.ask_target(file_name, &locale::text("save-copy", &[])) else {
+            self.status_message = StatusMessage::from(locale::text("save-aborted", &[]));
+            return;
+        };
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        self.status_message = StatusMessage::from(match self.document.save_copy(&file_name) {
+            Ok(notes) => {
+                let wrote = locale::text("wrote-copy", &[("file", &file_name)]);
+                if notes.is_empty() {
+                    wrote
+                } else {
+                    format!("{wrote} ({})", notes.join("; "))
                 }
-                _ => (),
-            };
+            }
+            Err(e) => format!("ERR: Could not write {file_name}: {e}"),
+        });
+    }
 
-            callback(self, key, &result);
+    /// Renames the file of the buffer on disk to `file_name`, or a name
+    /// asked for, and detects its file type again.
+    fn rename_file(&mut self, file_name: Option<String>) {
+        if self.document.file_name.is_none() {
+            self.status_message = StatusMessage::from(locale::text("no-file", &[]));
+            return;
         }
-        self.status_message = StatusMessage::from(String::new());
-        if result.is_empty() {
-            return Ok(None);
+        let Some(file_name) = self.ask_target(file_name, &locale::text("rename-to", &[])) else {
+            self.status_message = StatusMessage::from(locale::text("rename-aborted", &[]));
+            return;
+        };
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        match self.document.rename(file_name.clone()) {
+            Ok(()) => {
+                self.status_message =
+                    StatusMessage::from(locale::text("renamed", &[("file", &file_name)]));
+                if self.trust == Trust::Trusted {
+                    self.start_lsp();
+                }
+            }
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not rename to {file_name}: {e}"));
+            }
+        }
+    }
+
+    /// `file_name`, or a file asked for with `label`, to write to, after
+    /// confirming that an existing one is replaced. `None` if not.
+    fn ask_target(&mut self, file_name: Option<String>, label: &str) -> Option<String> {
+        let file_name = match file_name {
+            Some(file_name) => file_name,
+            None => match self.prompt(label, |_, _, _| {}).unwrap_or(None) {
+                Some(file_name) if !file_name.is_empty() => file_name,
+                _ => return None,
+            },
+        };
+        if Path::new(&file_name).exists()
+            && self.confirm(&locale::text("replace-file", &[("file", &file_name)])) != Some(true)
+        {
+            return None;
+        }
+        Some(file_name)
+    }
+
+    /// Puts the buffer through the save steps of its file type, or else of
+    /// the config, in order. `None` if a step failed and `save_failure`
+    /// says to abort, with the status telling why.
+    fn run_save_steps(&mut self) -> Option<save::Report> {
+        // The bytes of a hex dump are saved as they are.
+        if self.document.is_hex() {
+            return Some(save::Report::default());
+        }
+        let steps = self
+            .document
+            .settings()
+            .save_steps
+            .unwrap_or_else(|| self.config.save_steps().to_vec());
+        let mut report = save::Report::default();
+        for step in steps {
+            let result = self.run_save_step(step);
+            self.clamp_cursor();
+            self.scroll();
+            match result {
+                Ok(true) => report.changed.push(step.name()),
+                Ok(false) => (),
+                Err(e) if self.config.save_failure() == OnFailure::Abort => {
+                    self.status_message = StatusMessage::from(format!(
+                        "{}: {} failed: {e}",
+                        locale::text("save-aborted", &[]),
+                        step.name()
+                    ));
+                    return None;
+                }
+                Err(e) => report.notes.push(format!("{} failed: {e}", step.name())),
+            }
+        }
+        Some(report)
+    }
+
+    /// Runs `step` on the buffer. Returns whether it changed anything.
+    fn run_save_step(&mut self, step: Step) -> Result<bool> {
+        match step {
+            Step::OrganizeImports | Step::Format => self.run_lsp_save_step(step),
+            Step::TrimWhitespace => Ok(self.document.trim_trailing_whitespace()),
+            Step::FinalNewline => Ok(self.document.trim_final_newlines()),
+        }
+    }
+
+    /// Applies the edits of the language server for `step`, which is
+    /// skipped without one.
+    fn run_lsp_save_step(&mut self, step: Step) -> Result<bool> {
+        if !self.document.has_lsp() {
+            return Ok(false);
+        }
+        self.status_message = StatusMessage::from(format!("{}... (Ctrl-C to cancel)", step.name()));
+        let _ = self.refresh_screen();
+        let cancellation = self.terminal.cancellation();
+        let edits = if step == Step::OrganizeImports {
+            self.document.lsp_organize_imports(&cancellation)
+        } else {
+            self.document.lsp_formatting(&cancellation)
+        };
+        self.end_progress(&cancellation);
+        let edits = edits.ok_or_else(|| anyhow!("cancelled"))?;
+        if self.document.text_with_edits(&edits) == self.document.text() {
+            return Ok(false);
+        }
+        self.document.apply_edits(&edits);
+        Ok(true)
+    }
+
+    fn prompt<C>(&mut self, label: &str, callback: C) -> Result<Option<String>>
+    where
+        C: FnMut(&mut Self, Key, &str),
+    {
+        self.ask(Prompt::new(label), callback)
+    }
+
+    /// Asks a question answered by `y` or `n`; `None` if it was cancelled.
+    fn confirm(&mut self, question: &str) -> Option<bool> {
+        self.confirm_with(question, |_, _| {})
+    }
+
+    /// Like `confirm`, calling `callback` with the other keys pressed.
+    fn confirm_with<C>(&mut self, question: &str, mut callback: C) -> Option<bool>
+    where
+        C: FnMut(&mut Self, Key),
+    {
+        let prompt = Prompt::new(question).validate(|answer| {
+            (!matches!(answer, "y" | "Y" | "n" | "N")).then(|| locale::text("yes-or-no", &[]))
+        });
+        let answer = self
+            .ask(prompt, |editor, key, _| callback(editor, key))
+            .unwrap_or(None)?;
+        Some(answer.eq_ignore_ascii_case("y"))
+    }
+
+    /// Asks `prompt` in the message bar, calling `callback` with every key
+    /// that does not end it and the input so far.
+    fn ask<C>(&mut self, mut prompt: Prompt, mut callback: C) -> Result<Option<String>>
+    where
+        C: FnMut(&mut Self, Key, &str),
+    {
+        // The message bar may have to make room for the prompt.
+        self.status_message = StatusMessage::from(prompt.text());
+        self.scroll();
+        loop {
+            self.status_message = StatusMessage::from(prompt.text());
+            self.status_message.hint = prompt.hint();
+            self.refresh_screen()?;
+
+            let key = self.terminal.read_key()?;
+            if let Outcome::Done(answer) = prompt.process_key(key) {
+                self.status_message = StatusMessage::from(String::new());
+                return Ok(answer);
+            }
+            callback(self, key, prompt.input());
         }
-        Ok(Some(result))
     }
 
     fn search(&mut self) {
         let prev_position = self.cursor_position.clone();
         let mut direction = SearchDirection::Forward;
         let query = self
-            .prompt(&"Search: ", |editor, key, query| {
+            .prompt(&locale::text("search", &[]), |editor, key, query| {
                 let mut moved = false;
                 match key {
                     Key::Down | Key::Right => {
@@ -438,24 +1643,3273 @@ impl Editor {
                 } else if moved {
                     editor.move_cursor(Key::Left);
                 }
-                // editor.document.highlight(Some(query.as_str()));
+                editor.search_highlight = Some(query.to_string());
             })
             .unwrap_or(None);
 
+        self.search_highlight = None;
         if query.is_none() {
             self.cursor_position = prev_position;
             self.scroll();
         }
-        // self.document.highlight(None);
     }
 
     fn hover(&mut self) {
+        let max_width = self
+            .config
+            .hover_max_width()
+            .min((self.terminal.size().width as usize).saturating_sub(2));
+        self.document.hover(
+            self.cursor_position.x as u32,
+            self.cursor_position.y as u32,
+            max_width,
+            self.hover_max_height(),
+        );
+    }
+
+    /// The lines a hover shows at most: as many as fit above or below
+    /// any line of the screen.
+    fn hover_max_height(&self) -> usize {
+        ((self.terminal.size().height as usize).saturating_sub(1) / 2).max(1)
+    }
+
+    fn command_prompt(&mut self) {
+        let prompt = Prompt::new(": ").complete(Command::complete);
+        let Some(input) = self.ask(prompt, |_, _, _| {}).unwrap_or(None) else {
+            return;
+        };
+        match Command::parse(&input) {
+            Ok(command) => self.run_command(command),
+            Err(e) => self.status_message = StatusMessage::from(format!("ERR: {e}")),
+        }
+    }
+
+    fn run_command(&mut self, command: Command) {
+        if command.edits() && self.document.is_hex() {
+            self.report_hex();
+            return;
+        }
+        if command.edits() && self.document.is_read_only() {
+            self.report_read_only();
+            return;
+        }
+        match command {
+            Command::CopyPath => self.copy_location(false, false),
+            Command::CopyRelativePath => self.copy_location(true, false),
+            Command::CopyReference => self.copy_location(true, true),
+            Command::Goto(target) => self.goto(target),
+            Command::History => self.browse_history(),
+            Command::CheckHealth => self.check_health(),
+            Command::Diagnostic(direction) => self.goto_diagnostic(direction),
+            Command::NoHighlight => self.search_highlight = None,
+            Command::SpellSuggest => self.suggest_spelling(),
+            Command::DocComment => self.insert_doc_comment(),
+            Command::StructuralReplace => self.structural_replace(),
+            Command::Select { object, inner } => self.select_object(object, inner),
+            Command::Object(object, direction) => self.goto_object(object, direction),
+            Command::MatchingBracket => self.goto_matching_bracket(),
+            Command::Set { option, value } => self.set_option(&option, value.as_deref()),
+            Command::Calculate { expression, insert } => self.calculate(&expression, insert),
+            Command::Reindent => {
+                self.reindent(self.cursor_position.y..self.cursor_position.y + 1);
+            }
+            Command::ReindentFile => self.reindent(0..self.document.len()),
+            Command::Fold => self.close_fold(),
+            Command::Unfold => self.open_fold(),
+            Command::ToggleFolds => self.toggle_all_folds(),
+            Command::Theme(name) => self.switch_theme(name.as_deref()),
+            Command::Highlight { group, style } => self.highlight(&group, style),
+            Command::ExportTheme(name) => self.export_theme(&name),
+            Command::Grep(query) => self.grep(&query),
+            Command::Replace { from, to } => self.replace_all(&from, &to),
+            Command::UndoFileOperation => self.undo_file_operation(),
+            Command::Annotate(note) => self.annotate(note),
+            Command::RemoveAnnotation => self.remove_annotation(),
+            Command::ShowAnnotation => self.show_annotation(),
+            Command::Annotations => self.list_annotations(),
+            Command::Mark(name) => self.set_mark(name),
+            Command::GotoMark(name) => self.goto_mark(&name),
+            Command::Marks => self.list_marks(),
+            Command::Problems => self.list_problems(),
+            Command::CompareClipboard => self.compare_clipboard(),
+            Command::Diff => self.diff_unsaved(),
+            Command::Count => {
+                let what = if self.selection.is_empty() {
+                    "Buffer"
+                } else {
+                    "Selection"
+                };
+                self.status_message = StatusMessage::from(format!("{what}: {}", self.counts()));
+            }
+            Command::ToggleComment => self.toggle_comment(),
+            Command::FindFile(query) => self.find_file(&query),
+            Command::Symbols(query) => self.find_symbol(&query),
+            Command::Todos => self.list_todos(),
+            Command::Statistics => self.show_statistics(),
+            Command::EnableHighlighting => self.enable_highlighting(),
+            Command::Undo => self.undo(),
+            Command::Redo => self.redo(),
+            Command::ReloadRuntime => self.reload_runtime(),
+            Command::Narrow => self.narrow(),
+            Command::Widen => self.widen(),
+            Command::Open(file_name) => self.open_file(file_name),
+            Command::SaveAs(file_name) => self.save_as(file_name),
+            Command::SaveCopy(file_name) => self.save_copy(file_name),
+            Command::Rename(file_name) => self.rename_file(file_name),
+            Command::Buffer(direction) => self.cycle_buffers(direction),
+            Command::CloseBuffer => self.close_buffer(),
+            Command::Buffers => self.list_buffers(),
+            Command::ToggleBom => self.toggle_bom(),
+            Command::Follow => self.toggle_follow(),
+            Command::Hex => self.toggle_hex(),
+            Command::SortLines(order) => self.sort_lines(order),
+            Command::ReverseLines => self.reverse_lines(),
+            Command::RemoveDuplicateLines => self.remove_duplicate_lines(),
+            Command::ReadOnly if self.document.is_binary() && !self.document.is_hex() => {
+                self.status_message =
+                    StatusMessage::from("Binary file: `hex` edits its bytes".to_string());
+            }
+            Command::ReadOnly => {
+                let read_only = !self.document.is_read_only();
+                self.document.set_read_only(read_only);
+                self.status_message = StatusMessage::from(
+                    if read_only {
+                        "Read-only: editing is off"
+                    } else {
+                        "Editing is on again"
+                    }
+                    .to_string(),
+                );
+            }
+            Command::LineEnding(line_ending) => {
+                if let Some(line_ending) = line_ending {
+                    self.document.set_line_ending(line_ending);
+                }
+                let name = self.document.line_ending().name();
+                self.status_message = StatusMessage::from(format!("Lines end with {name}"));
+            }
+        }
+    }
+
+    /// Highlights a big file and starts its language server after all.
+    fn enable_highlighting(&mut self) {
+        if !self.document.is_plain() {
+            self.status_message = StatusMessage::from("Already highlighted".to_string());
+            return;
+        }
+        self.document.enable_highlighting();
+        if self.trust == Trust::Trusted {
+            self.start_lsp();
+        }
+        self.status_message = StatusMessage::from(match self.document.highlight_error() {
+            Some(error) => highlight_error(error),
+            None => format!("Highlighting {}", self.document.file_type()),
+        });
+    }
+
+    /// Draws with the theme `name` from now on; without a name, shows which
+    /// themes there are.
+    fn switch_theme(&mut self, name: Option<&str>) {
+        let text = match name {
+            Some(name) => match Theme::load(name) {
+                Ok(theme) => {
+                    theme::set(theme);
+                    self.theme = name.to_string();
+                    format!("Theme: {name}")
+                }
+                Err(e) => format!("ERR: Could not load theme: {e}"),
+            },
+            None => format!("Themes: {}", theme::available().join(", ")),
+        };
+        self.status_message = StatusMessage::from(text);
+    }
+
+    /// Compiles the query overrides and loads the theme again from disk,
+    /// listing the queries that do not compile as problems.
+    fn reload_runtime(&mut self) {
+        self.problems = filetype::query_problems();
+        let checked = self.problems_of(&self.document);
+        self.document.set_checked(checked);
+        self.document.reload_highlighter();
+        let theme = Theme::load(&self.theme);
+        self.status_message = StatusMessage::from(match (theme, self.problems.len()) {
+            (Err(e), _) => format!("ERR: Could not load theme: {e}"),
+            (Ok(theme), 0) => {
+                theme::set(theme);
+                match self.document.highlight_error() {
+                    Some(error) => highlight_error(error),
+                    None => "Reloaded the queries and the theme".to_string(),
+                }
+            }
+            (Ok(theme), count) => {
+                theme::set(theme);
+                format!("Query errors: {count}; `problems` lists them")
+            }
+        });
+    }
+
+    /// Draws `group` in `style` from now on; without a style, shows the one
+    /// it has.
+    fn highlight(&mut self, group: &str, style: Option<Style>) {
+        let style = match style {
+            Some(style) => {
+                theme::update(|theme| theme.set_style(group, style));
+                style
+            }
+            None => theme::current().style(group),
+        };
+        self.status_message = StatusMessage::from(format!("{group}: {style}"));
+    }
+
+    fn export_theme(&mut self, name: &str) {
+        let text = match theme::current().export(name) {
+            Ok(path) => format!("Wrote {}", path.display()),
+            Err(e) => format!("ERR: Could not export theme: {e}"),
+        };
+        self.status_message = StatusMessage::from(text);
+    }
+
+    /// Puts back the files the latest file operation overwrote.
+    fn undo_file_operation(&mut self) {
+        let Some(operation) = self.file_operations.pop() else {
+            self.status_message = StatusMessage::from("No file operation to undo".to_string());
+            return;
+        };
+        let name = operation.name.clone();
+        self.status_message = StatusMessage::from(match operation.undo() {
+            Ok(count) => format!("Undid {name}: restored {count} files"),
+            Err(failed) => format!("ERR: Could not restore {}", failed.join(", ")),
+        });
+    }
+
+    /// Shows `progress` in the status bar every `REPORT_INTERVAL` while an
+    /// operation runs. Returns `false` once `cancellation` was cancelled.
+    fn report_progress(&mut self, progress: &mut Progress, cancellation: &Cancellation) -> bool {
+        if cancellation.is_cancelled() {
+            return false;
+        }
+        if progress.due() {
+            self.progress = Some(progress.text());
+            let _ = self.refresh_screen();
+        }
+        true
+    }
+
+    /// Clears the progress of the operation that just ended. Keys pressed
+    /// while a cancelled one ran are dropped, including the Ctrl-C.
+    fn end_progress(&mut self, cancellation: &Cancellation) {
+        self.progress = None;
+        if cancellation.is_cancelled() {
+            self.terminal.discard_keys();
+        }
+    }
+
+    /// Opens `file_name`, showing the progress for big files. Ctrl-C stops
+    /// reading it and leaves the current document open.
+    fn open_document(&mut self, file_name: &str) -> Result<Document> {
+        let cancellation = self.terminal.cancellation();
+        let mut progress = Progress::new(format!("Opening {file_name}"));
+        let config = Rc::clone(&self.config);
+        let document = Document::open_with_progress(file_name, config, &mut |done, total| {
+            progress.update(done, Some(total));
+            self.report_progress(&mut progress, &cancellation)
+        });
+        self.end_progress(&cancellation);
+        if let Some(error) = document.as_ref().ok().and_then(Document::highlight_error) {
+            self.status_message = StatusMessage::from(highlight_error(error));
+        }
+        let mut document = document?;
+        if document.is_binary() {
+            self.status_message =
+                StatusMessage::from("Binary file: shown read-only and never saved".to_string());
+        } else if document.is_plain() {
+            self.status_message =
+                StatusMessage::from("Big file: highlighting and language server off".to_string());
+        }
+        self.load_annotations(&mut document);
+        let checked = self.problems_of(&document);
+        document.set_checked(checked);
+        Ok(document)
+    }
+
+    /// Attaches the annotations of the workspace to the lines of
+    /// `document`.
+    fn load_annotations(&mut self, document: &mut Document) {
+        let Some(path) = document
+            .file_name
+            .as_deref()
+            .and_then(|name| self.annotation_path(name))
+        else {
+            return;
+        };
+        match annotation::Project::load(self.workspace.root()) {
+            Ok(project) => *document.annotations_mut() = project.of(&path),
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not read annotations: {e}"));
+            }
+        }
+    }
+
+    /// The path annotations name `file_name` by, relative to the workspace
+    /// root.
+    fn annotation_path(&self, file_name: &str) -> Option<String> {
+        let path = Path::new(file_name).canonicalize().ok()?;
+        let path = path.strip_prefix(self.workspace.root()).unwrap_or(&path);
+        Some(path.display().to_string())
+    }
+
+    /// Writes the annotations of the buffer to the annotations file of the
+    /// workspace.
+    fn store_annotations(&self) -> Result<()> {
+        let Some(path) = self
+            .document
+            .file_name
+            .as_deref()
+            .and_then(|name| self.annotation_path(name))
+        else {
+            return Ok(());
+        };
+        let mut project = annotation::Project::load(self.workspace.root())?;
+        project.set(&path, self.document.annotations());
+        project.save()
+    }
+
+    /// The occurrences of `query` in the files of the workspace, with the
+    /// unsaved text of the open file. `None` if cancelled.
+    fn search_workspace(&mut self, query: &str) -> Option<Vec<grep::Match>> {
+        let cancellation = self.terminal.cancellation();
+        let files = grep::files(self.workspace.root(), self.config.ignore());
+        let current = self.current_path();
+        let mut progress = Progress::new("Searching".to_string());
+        let mut matches = Vec::new();
+        for (index, path) in files.iter().enumerate() {
+            progress.update(index as u64, Some(files.len() as u64));
+            if !self.report_progress(&mut progress, &cancellation) {
+                self.end_progress(&cancellation);
+                return None;
+            }
+            let text = if path.canonicalize().ok() == current {
+                Some(self.document.text())
+            } else {
+                grep::read_text(path)
+            };
+            if let Some(text) = text {
+                matches.extend(grep::search(path, &text, query));
+            }
+        }
+        self.end_progress(&cancellation);
+        Some(matches)
+    }
+
+    fn current_path(&self) -> Option<PathBuf> {
         self.document
-            .hover(self.cursor_position.x as u32, self.cursor_position.y as u32);
+            .file_name
+            .as_ref()
+            .and_then(|name| Path::new(name).canonicalize().ok())
     }
-}
 
-fn die(e: Error) {
-    Terminal::clear_screen();
-    panic!("{}", e);
+    fn grep(&mut self, query: &str) {
+        let Some(mut matches) = self.search_workspace(query) else {
+            self.status_message = StatusMessage::from(locale::text("search-cancelled", &[]));
+            return;
+        };
+        if matches.is_empty() {
+            self.status_message =
+                StatusMessage::from(locale::text("no-matches", &[("query", &query)]));
+            return;
+        }
+        let total = matches.len();
+        let shown = (self.terminal.size().height as usize)
+            .saturating_sub(2)
+            .max(1);
+        matches.truncate(shown);
+        let items = matches
+            .iter()
+            .map(|found| {
+                let path = found
+                    .path
+                    .strip_prefix(self.workspace.root())
+                    .unwrap_or(&found.path);
+                format!("{}:{}: {}", path.display(), found.line + 1, found.text)
+            })
+            .collect();
+        let prompt = if total > shown {
+            format!("{total} matches, first {shown} shown. Go to number: ")
+        } else {
+            format!("{total} matches. Go to number: ")
+        };
+        let Some(index) = self.choose(items, &prompt) else {
+            return;
+        };
+        let found = matches.swap_remove(index);
+        let Ok(uri) = Url::from_file_path(&found.path) else {
+            return;
+        };
+        let position = lsp_types::Position::new(found.line as u32, found.column as u32);
+        self.jump_to(&Location::new(
+            uri,
+            lsp_types::Range::new(position, position),
+        ));
+    }
+
+    /// Replaces `from` with `to` in every file of the workspace after
+    /// confirming. The open file is changed in the buffer, the others are
+    /// written. Ctrl-C while writing puts back the files written so far.
+    fn replace_all(&mut self, from: &str, to: &str) {
+        let Some(matches) = self.search_workspace(from) else {
+            self.status_message = StatusMessage::from("Replace cancelled".to_string());
+            return;
+        };
+        let mut paths: Vec<&Path> = matches.iter().map(|found| found.path.as_path()).collect();
+        paths.dedup();
+        if paths.is_empty() {
+            self.status_message = StatusMessage::from(format!("No matches for {from}"));
+            return;
+        }
+        let question = format!(
+            "Replace {} occurrences in {} files? (y/n): ",
+            matches.len(),
+            paths.len()
+        );
+        if self.confirm(&question) != Some(true) {
+            return;
+        }
+        let current = self.current_path();
+        let cancellation = self.terminal.cancellation();
+        let mut progress = Progress::new("Replacing".to_string());
+        // The files written so far, kept in the trash.
+        let mut operation = Operation::new(format!("replace {from} {to}"));
+        let mut failed = Vec::new();
+        let mut buffer_text = None;
+        for (index, path) in paths.iter().enumerate() {
+            progress.update(index as u64, Some(paths.len() as u64));
+            if !self.report_progress(&mut progress, &cancellation) {
+                let _ = operation.undo();
+                self.end_progress(&cancellation);
+                self.status_message =
+                    StatusMessage::from("Replace cancelled, no files were changed".to_string());
+                return;
+            }
+            if path.canonicalize().ok() == current {
+                buffer_text = Some(self.document.text().replace(from, to));
+            } else if let Some(text) = grep::read_text(path) {
+                // Files that cannot be put in the trash are left alone.
+                let written = operation
+                    .keep(path)
+                    .and_then(|()| fs::write(path, text.replace(from, to)).map_err(Into::into));
+                if written.is_err() {
+                    failed.push(path.display().to_string());
+                }
+            }
+        }
+        if !operation.is_empty() {
+            self.file_operations.push(operation);
+        }
+        // The buffer goes last, as it cannot be put back by writing.
+        if let Some(text) = buffer_text {
+            self.document.set_text(&text);
+            self.clamp_cursor();
+        }
+        self.end_progress(&cancellation);
+        self.status_message = StatusMessage::from(if failed.is_empty() {
+            format!(
+                "Replaced {} occurrences in {} files",
+                matches.len(),
+                paths.len()
+            )
+        } else {
+            format!("ERR: Could not write {}", failed.join(", "))
+        });
+    }
+
+    /// Copies the path of the file, optionally relative to the workspace
+    /// root and followed by `:line:column` of the cursor.
+    fn copy_location(&mut self, relative: bool, with_position: bool) {
+        let Some(path) = self
+            .document
+            .file_name
+            .as_ref()
+            .and_then(|name| Path::new(name).canonicalize().ok())
+        else {
+            self.status_message = StatusMessage::from("The buffer has no file".to_string());
+            return;
+        };
+        let path = if relative {
+            path.strip_prefix(self.workspace.root()).unwrap_or(&path)
+        } else {
+            &path
+        };
+        let mut text = path.display().to_string();
+        if with_position {
+            text.push_str(&format!(
+                ":{}:{}",
+                self.cursor_position.y.saturating_add(1),
+                self.cursor_position.x.saturating_add(1)
+            ));
+        }
+        self.status_message = match clipboard::copy(&text) {
+            Ok(via) => StatusMessage::from(format!("Copied {text} ({via})")),
+            Err(e) => StatusMessage::from(format!("ERR: Could not copy: {e}")),
+        };
+    }
+
+    /// Adds the buffer to the local history if it has unsaved changes.
+    fn snapshot(&mut self) {
+        if !self.document.is_dirty() {
+            return;
+        }
+        if let Some(file_name) = &self.document.file_name {
+            if history::snapshot(file_name, &self.document.text()).is_err() {
+                self.status_message =
+                    StatusMessage::from("ERR: Could not write local history".to_string());
+            }
+        }
+    }
+
+    /// Lists the local history of the file, showing what restoring the
+    /// selected snapshot would change; Enter restores it.
+    fn browse_history(&mut self) {
+        let Some(file_name) = self.document.file_name.clone() else {
+            self.status_message = StatusMessage::from("The buffer has no file".to_string());
+            return;
+        };
+        let snapshots = history::list(&file_name);
+        if snapshots.is_empty() {
+            self.status_message = StatusMessage::from("No local history".to_string());
+            return;
+        }
+        let current = self.document.text();
+        let labels: Vec<String> = snapshots.iter().map(history::Snapshot::timestamp).collect();
+        let width = self.terminal.size().width as usize;
+        let height = self.terminal.size().height as usize;
+        let mut selected: usize = 0;
+        loop {
+            let first = selected.saturating_sub(HISTORY_LIST_HEIGHT - 1);
+            let visible: Vec<String> = labels
+                .iter()
+                .skip(first)
+                .take(HISTORY_LIST_HEIGHT)
+                .cloned()
+                .collect();
+            let list_height = visible.len();
+            let list_width = visible
+                .iter()
+                .map(|label| label.graphemes(true).count())
+                .max()
+                .unwrap_or(0);
+            self.document.show_floating(
+                FloatingItem::new(Position::default(), list_width, list_height, visible)
+                    .with_selected(selected - first),
+            );
+            let snapshot_text = snapshots[selected].text().unwrap_or_default();
+            self.document.add_floating(diff::floating(
+                &current,
+                &snapshot_text,
+                Position {
+                    x: 0,
+                    y: list_height + 1,
+                },
+                width,
+                height.saturating_sub(list_height + 1),
+            ));
+            self.status_message = StatusMessage::from(
+                "Local history: Up/Down = Select | Enter = Restore | Esc = Close".to_string(),
+            );
+            if self.refresh_screen().is_err() {
+                break;
+            }
+            match self.terminal.read_key() {
+                Ok(Key::Up | Key::Ctrl('p')) => selected = selected.saturating_sub(1),
+                Ok(Key::Down | Key::Ctrl('n')) => {
+                    selected = (selected + 1).min(snapshots.len() - 1);
+                }
+                Ok(Key::Char('\n')) => {
+                    // Keep the replaced buffer in the history as well.
+                    self.snapshot();
+                    self.document.clear_floating();
+                    self.document.set_text(&snapshot_text);
+                    self.clamp_cursor();
+                    self.scroll();
+                    self.status_message =
+                        StatusMessage::from(format!("Restored snapshot from {}", labels[selected]));
+                    return;
+                }
+                Ok(Key::Esc | Key::Ctrl('q')) | Err(_) => break,
+                Ok(_) => (),
+            }
+        }
+        self.document.clear_floating();
+        self.status_message = StatusMessage::from(String::new());
+    }
+
+    /// Replaces the misspelled word at the cursor with the suggestion the
+    /// user picks.
+    fn suggest_spelling(&mut self) {
+        let Some(dictionary) = self.dictionary.clone() else {
+            self.status_message = StatusMessage::from("Spell checking is off".to_string());
+            return;
+        };
+        let Some(dictionary) = dictionary.get() else {
+            self.status_message = StatusMessage::from("The dictionary is not loaded".to_string());
+            return;
+        };
+        let Position { x, y } = self.cursor_position;
+        let Some((range, word)) = self
+            .document
+            .row(y)
+            .and_then(|row| row.misspelling_at(x, dictionary))
+        else {
+            self.status_message =
+                StatusMessage::from("No misspelled word at the cursor".to_string());
+            return;
+        };
+        let suggestions = dictionary.suggest(word);
+        if suggestions.is_empty() {
+            self.status_message = StatusMessage::from(format!("No suggestions for {word}"));
+            return;
+        }
+        let Some(index) = self.choose(suggestions.clone(), "Replace with: ") else {
+            return;
+        };
+        let end = self
+            .document
+            .replace(y, range.start, range.end, &suggestions[index]);
+        if x >= range.end {
+            self.cursor_position = end;
+        }
+    }
+
+    /// Shows the report of `health::report` until it is closed, colored by
+    /// how the checks went.
+    fn check_health(&mut self) {
+        let report = health::report(&self.config, &self.workspace, self.trust);
+        let width = self.terminal.size().width as usize;
+        let theme = theme::current();
+        let ui = &theme.ui;
+        let mut text: Vec<String> = Vec::new();
+        let mut colors: Vec<Option<color::Rgb>> = Vec::new();
+        for line in report {
+            let color = match line.status {
+                Some(health::Status::Ok) => ui.diff_added.fg,
+                Some(health::Status::Warning) => ui.diff_hunk.fg,
+                Some(health::Status::Error) => ui.diff_removed.fg,
+                None => None,
+            };
+            for wrapped in line.wrapped(width) {
+                text.push(wrapped);
+                colors.push(color);
+            }
+        }
+        let height = (self.terminal.size().height as usize).min(text.len());
+        let mut first: usize = 0;
+        loop {
+            let end = (first + height).min(text.len());
+            self.document.show_floating(
+                FloatingItem::new(
+                    Position::default(),
+                    width,
+                    height,
+                    text[first..end].to_vec(),
+                )
+                .with_line_colors(colors[first..end].to_vec()),
+            );
+            self.status_message = StatusMessage::from(
+                "Health: Up/Down/PageUp/PageDown = Scroll | Esc = Close".to_string(),
+            );
+            if self.refresh_screen().is_err() {
+                break;
+            }
+            let last = text.len() - height;
+            match self.terminal.read_key() {
+                Ok(Key::Up | Key::Ctrl('p')) => first = first.saturating_sub(1),
+                Ok(Key::Down | Key::Ctrl('n')) => first = (first + 1).min(last),
+                Ok(Key::PageUp) => first = first.saturating_sub(height),
+                Ok(Key::PageDown) => first = (first + height).min(last),
+                Ok(Key::Esc | Key::Ctrl('q') | Key::Char('\n' | 'q')) | Err(_) => break,
+                Ok(_) => (),
+            }
+        }
+        self.document.clear_floating();
+        self.status_message = StatusMessage::from(String::new());
+    }
+
+    /// Shows the selection, or the buffer, and the clipboard side by side,
+    /// e.g. to reconcile a snippet of a review comment with the code.
+    fn compare_clipboard(&mut self) {
+        let clipboard = match clipboard::paste() {
+            Ok(text) => text,
+            Err(e) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not paste: {e}"));
+                return;
+            }
+        };
+        let (title, text) = match self.selection.last() {
+            Some((start, end)) => ("Selection", self.document.text_in(start, end)),
+            None => ("Buffer", self.document.text()),
+        };
+        // A trailing newline of one side only is not worth a difference.
+        let (left, right) = diff::panes(
+            text.trim_end_matches('\n'),
+            clipboard.trim_end_matches('\n'),
+            title,
+            "Clipboard",
+        );
+        let height = self.terminal.size().height as usize;
+        let mut first: usize = 0;
+        loop {
+            self.show_panes(&left, &right, first);
+            self.status_message = StatusMessage::from(
+                "Compare: Up/Down/PageUp/PageDown = Scroll | Esc = Close".to_string(),
+            );
+            if self.refresh_screen().is_err() {
+                break;
+            }
+            let last = left.len().saturating_sub(height);
+            match self.terminal.read_key() {
+                Ok(Key::Up | Key::Ctrl('p')) => first = first.saturating_sub(1),
+                Ok(Key::Down | Key::Ctrl('n')) => first = (first + 1).min(last),
+                Ok(Key::PageUp) => first = first.saturating_sub(height),
+                Ok(Key::PageDown) => first = (first + height).min(last),
+                Ok(Key::Esc | Key::Ctrl('q') | Key::Char('\n' | 'q')) | Err(_) => break,
+                Ok(_) => (),
+            }
+        }
+        self.document.clear_floating();
+        self.status_message = StatusMessage::from(String::new());
+    }
+
+    /// Shows what saving would change in the file on disk as a unified
+    /// diff to scroll through.
+    fn diff_unsaved(&mut self) {
+        if self.document.stream_progress().is_some() {
+            self.status_message =
+                StatusMessage::from("Still loading; diff once it is read".to_string());
+            return;
+        }
+        let disk_text = match self.document.disk_text() {
+            Ok(text) => text,
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not read the file on disk: {e}"));
+                return;
+            }
+        };
+        let lines = diff::unified_text(&disk_text, &self.document.text());
+        if lines.is_empty() {
+            self.status_message =
+                StatusMessage::from("No changes against the file on disk".to_string());
+            return;
+        }
+        let width = self.terminal.size().width as usize;
+        let height = self.terminal.size().height as usize;
+        let mut first: usize = 0;
+        loop {
+            self.document.show_floating(diff::floating_lines(
+                &lines,
+                first,
+                Position::default(),
+                width,
+                height,
+            ));
+            self.status_message = StatusMessage::from(
+                "Diff: Up/Down/PageUp/PageDown = Scroll | Esc = Close".to_string(),
+            );
+            if self.refresh_screen().is_err() {
+                break;
+            }
+            let last = lines.len().saturating_sub(height);
+            match self.terminal.read_key() {
+                Ok(Key::Up | Key::Ctrl('p')) => first = first.saturating_sub(1),
+                Ok(Key::Down | Key::Ctrl('n')) => first = (first + 1).min(last),
+                Ok(Key::PageUp) => first = first.saturating_sub(height),
+                Ok(Key::PageDown) => first = (first + height).min(last),
+                Ok(Key::Esc | Key::Ctrl('q') | Key::Char('\n' | 'q')) | Err(_) => break,
+                Ok(_) => (),
+            }
+        }
+        self.document.clear_floating();
+        self.status_message = StatusMessage::from(String::new());
+    }
+
+    /// Draws the lines of `left` and `right` from `first` on over the left
+    /// and right half of the screen.
+    fn show_panes(&mut self, left: &diff::Pane, right: &diff::Pane, first: usize) {
+        let width = self.terminal.size().width as usize;
+        let height = self.terminal.size().height as usize;
+        let half = width / 2;
+        self.document
+            .show_floating(left.floating(first, Position::default(), half, height));
+        self.document.add_floating(right.floating(
+            first,
+            Position { x: half, y: 0 },
+            width - half,
+            height,
+        ));
+    }
+
+    /// Attaches `note` to the cursor line, asking for it if not given.
+    fn annotate(&mut self, note: Option<String>) {
+        if self.document.file_name.is_none() {
+            self.status_message = StatusMessage::from("The buffer has no file".to_string());
+            return;
+        }
+        let y = self.cursor_position.y;
+        let note = match note {
+            Some(note) => note,
+            None => {
+                let prompt = format!("Note for line {}: ", y.saturating_add(1));
+                let Some(note) = self.prompt(&prompt, |_, _, _| {}).unwrap_or(None) else {
+                    return;
+                };
+                note
+            }
+        };
+        self.document.annotations_mut().set(y, note);
+        self.status_message = StatusMessage::from(match self.store_annotations() {
+            Ok(()) => format!("Annotated line {}", y.saturating_add(1)),
+            Err(e) => format!("ERR: Could not write annotations: {e}"),
+        });
+    }
+
+    fn remove_annotation(&mut self) {
+        let y = self.cursor_position.y;
+        if !self.document.annotations_mut().remove(y) {
+            self.status_message = StatusMessage::from("No annotation on this line".to_string());
+            return;
+        }
+        self.status_message = StatusMessage::from(match self.store_annotations() {
+            Ok(()) => format!("Removed the annotation of line {}", y.saturating_add(1)),
+            Err(e) => format!("ERR: Could not write annotations: {e}"),
+        });
+    }
+
+    /// Shows the note of the cursor line below it, like a hover.
+    fn show_annotation(&mut self) {
+        let Some(note) = self
+            .document
+            .annotations()
+            .get(self.cursor_position.y)
+            .map(str::to_owned)
+        else {
+            self.status_message = StatusMessage::from("No annotation on this line".to_string());
+            return;
+        };
+        let max_width = self
+            .config
+            .hover_max_width()
+            .min(self.text_width().saturating_sub(2));
+        self.document.show_floating(
+            markdown::floating(&note, max_width)
+                .with_max_height(self.hover_max_height())
+                .with_anchor(self.cursor_position.clone()),
+        );
+    }
+
+    /// Lists the annotations of the workspace and jumps to the chosen one.
+    fn list_annotations(&mut self) {
+        // The notes of the buffer may have moved since it was saved.
+        let project = self
+            .store_annotations()
+            .and_then(|()| annotation::Project::load(self.workspace.root()));
+        let project = match project {
+            Ok(project) => project,
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not read annotations: {e}"));
+                return;
+            }
+        };
+        let mut annotations = project.all();
+        if annotations.is_empty() {
+            self.status_message = StatusMessage::from("No annotations".to_string());
+            return;
+        }
+        let total = annotations.len();
+        let shown = (self.terminal.size().height as usize)
+            .saturating_sub(2)
+            .max(1);
+        annotations.truncate(shown);
+        let items = annotations
+            .iter()
+            .map(|annotation| {
+                let note = annotation.note.lines().next().unwrap_or_default();
+                format!("{}:{}: {note}", annotation.path, annotation.line)
+            })
+            .collect();
+        let prompt = if total > shown {
+            format!("{total} annotations, first {shown} shown. Go to number: ")
+        } else {
+            format!("{total} annotations. Go to number: ")
+        };
+        let Some(index) = self.choose(items, &prompt) else {
+            return;
+        };
+        let annotation = annotations[index];
+        let Ok(uri) = Url::from_file_path(self.workspace.root().join(&annotation.path)) else {
+            return;
+        };
+        let position = lsp_types::Position::new(annotation.line.saturating_sub(1) as u32, 0);
+        self.jump_to(&Location::new(
+            uri,
+            lsp_types::Range::new(position, position),
+        ));
+    }
+
+    /// Names the cursor position `name`, in place of a mark of that name.
+    fn set_mark(&mut self, name: String) {
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        self.status_message = StatusMessage::from(format!("Marked {name}"));
+        self.document
+            .marks_mut()
+            .set(name, self.cursor_position.clone());
+    }
+
+    /// Jumps to the mark `name` of the buffer, or else to that of the first
+    /// other buffer having one.
+    fn goto_mark(&mut self, name: &str) {
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        if self.document.marks().get(name).is_none() {
+            let Some(index) = self
+                .buffers
+                .iter()
+                .position(|buffer| buffer.document.marks().get(name).is_some())
+            else {
+                self.status_message = StatusMessage::from(format!("No mark {name}"));
+                return;
+            };
+            // The current buffer is not among them.
+            let index = if index < self.buffer {
+                index
+            } else {
+                index + 1
+            };
+            self.switch_buffer(index);
+        }
+        if let Some(position) = self.document.marks().get(name) {
+            self.cursor_position = position.clone();
+            self.clamp_cursor();
+            self.scroll();
+        }
+    }
+
+    /// Lists the marks of every open buffer, with their lines, and jumps
+    /// to one.
+    fn list_marks(&mut self) {
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        // Every buffer in the order they are cycled through.
+        let mut documents: Vec<&Document> =
+            self.buffers.iter().map(|buffer| &buffer.document).collect();
+        documents.insert(self.buffer, &self.document);
+        let marks: Vec<(usize, String, Position, String)> = documents
+            .iter()
+            .enumerate()
+            .flat_map(|(index, document)| {
+                let file_name = document
+                    .file_name
+                    .clone()
+                    .unwrap_or_else(|| "[No File]".to_string());
+                document.marks().iter().map(move |(name, position)| {
+                    let line = document
+                        .row(position.y)
+                        .map_or("", |row| row.as_str().trim());
+                    let item = format!("{name}  {file_name}:{}: {line}", position.y + 1);
+                    (index, name.to_string(), position.clone(), item)
+                })
+            })
+            .collect();
+        if marks.is_empty() {
+            self.status_message = StatusMessage::from("No marks".to_string());
+            return;
+        }
+        let items = marks.iter().map(|(.., item)| item.clone()).collect();
+        let prompt = format!("{} marks. Go to number: ", marks.len());
+        let Some(choice) = self.choose(items, &prompt) else {
+            return;
+        };
+        let (index, _, position, _) = &marks[choice];
+        if *index != self.buffer {
+            self.switch_buffer(*index);
+        }
+        self.cursor_position = position.clone();
+        self.clamp_cursor();
+        self.scroll();
+    }
+
+    fn report_index_off(&mut self) {
+        self.status_message =
+            StatusMessage::from("Indexing is off, see `index` in the config".to_string());
+    }
+
+    fn find_file(&mut self, query: &str) {
+        let Some(index) = &self.index else {
+            self.report_index_off();
+            return;
+        };
+        let found = index
+            .find_files(query, self.workspace.root())
+            .into_iter()
+            .map(|path| grep::Match {
+                path,
+                line: 0,
+                column: 0,
+                text: String::new(),
+            })
+            .collect();
+        self.choose_match(found, "files");
+    }
+
+    fn find_symbol(&mut self, query: &str) {
+        let Some(index) = &self.index else {
+            self.report_index_off();
+            return;
+        };
+        let found = index.symbols(query);
+        self.choose_match(found, "symbols");
+    }
+
+    fn list_todos(&mut self) {
+        let Some(index) = &self.index else {
+            self.report_index_off();
+            return;
+        };
+        let found = index.todos();
+        self.choose_match(found, "TODOs");
+    }
+
+    /// Lists the first of `found`, by their place and text, and jumps to the
+    /// chosen one. Says so while the index is still being built, as more may
+    /// be found then.
+    fn choose_match(&mut self, mut found: Vec<grep::Match>, what: &str) {
+        let indexing = if self.index.as_ref().is_some_and(Index::is_ready) {
+            ""
+        } else {
+            " (still indexing)"
+        };
+        if found.is_empty() {
+            self.status_message = StatusMessage::from(format!("No {what}{indexing}"));
+            return;
+        }
+        let total = found.len();
+        let shown = (self.terminal.size().height as usize)
+            .saturating_sub(2)
+            .max(1);
+        found.truncate(shown);
+        let items = found
+            .iter()
+            .map(|found| {
+                let path = found
+                    .path
+                    .strip_prefix(self.workspace.root())
+                    .unwrap_or(&found.path);
+                match found.text.as_str() {
+                    "" => path.display().to_string(),
+                    text => format!("{}:{}: {text}", path.display(), found.line + 1),
+                }
+            })
+            .collect();
+        let prompt = if total > shown {
+            format!("{total} {what}{indexing}, first {shown} shown. Go to number: ")
+        } else {
+            format!("{total} {what}{indexing}. Go to number: ")
+        };
+        let Some(index) = self.choose(items, &prompt) else {
+            return;
+        };
+        let found = found.swap_remove(index);
+        let Ok(uri) = Url::from_file_path(&found.path) else {
+            return;
+        };
+        let position = lsp_types::Position::new(found.line as u32, found.column as u32);
+        self.jump_to(&Location::new(
+            uri,
+            lsp_types::Range::new(position, position),
+        ));
+    }
+
+    /// Shows how many files and lines of each language the workspace has.
+    fn show_statistics(&mut self) {
+        let Some(index) = &self.index else {
+            self.report_index_off();
+            return;
+        };
+        let statistics = index.statistics();
+        let ready = index.is_ready();
+        let width = statistics
+            .languages
+            .iter()
+            .map(|count| count.language.len())
+            .max()
+            .unwrap_or_default()
+            .max("Language".len());
+        let mut lines = vec![format!(
+            "{:width$} {:>7} {:>9}",
+            "Language", "Files", "Lines"
+        )];
+        for count in &statistics.languages {
+            lines.push(format!(
+                "{:width$} {:>7} {:>9}",
+                count.language, count.files, count.lines
+            ));
+        }
+        lines.push(format!(
+            "{} symbols, {} TODOs{}",
+            statistics.symbols,
+            statistics.todos,
+            if ready { "" } else { ", still indexing" }
+        ));
+        let columns = lines
+            .iter()
+            .map(|line| line.graphemes(true).count())
+            .max()
+            .unwrap_or_default();
+        self.document.show_floating(
+            FloatingItem::new(Position::default(), columns, lines.len(), lines)
+                .with_anchor(self.cursor_position.clone()),
+        );
+    }
+
+    /// Starts the `on_save` command of the config in trusted workspaces,
+    /// leaving the result of a run still going to be dropped.
+    fn run_on_save(&mut self) {
+        if self.trust != Trust::Trusted {
+            return;
+        }
+        if let Some(command) = self.config.on_save() {
+            self.check = Some(Check::spawn(command, self.workspace.root()));
+        }
+    }
+
+    /// Takes the problems of the `on_save` command once it has finished.
+    fn poll_check(&mut self) {
+        let Some(result) = self.check.as_ref().and_then(Check::poll) else {
+            return;
+        };
+        let command = self
+            .check
+            .take()
+            .map(|check| check.command)
+            .unwrap_or_default();
+        match result {
+            Ok(problems) => {
+                self.problems = problems;
+                let checked = self.problems_of(&self.document);
+                self.document.set_checked(checked);
+                self.status_message = StatusMessage::from(match self.problems.len() {
+                    0 => format!("{command}: no problems"),
+                    count => format!("{command}: {count} problems; `problems` lists them"),
+                });
+            }
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not run {command}: {e}"));
+            }
+        }
+    }
+
+    /// The problems of the `on_save` command in the file of `document`, as
+    /// its diagnostics.
+    fn problems_of(&self, document: &Document) -> Vec<Diagnostic> {
+        let Some(path) = document
+            .file_name
+            .as_ref()
+            .and_then(|name| Path::new(name).canonicalize().ok())
+        else {
+            return Vec::new();
+        };
+        self.problems
+            .iter()
+            .filter(|problem| problem.path.canonicalize().ok().as_ref() == Some(&path))
+            .map(Problem::diagnostic)
+            .collect()
+    }
+
+    fn list_problems(&mut self) {
+        if self.problems.is_empty() {
+            self.status_message = StatusMessage::from("No problems".to_string());
+            return;
+        }
+        let total = self.problems.len();
+        let shown = (self.terminal.size().height as usize)
+            .saturating_sub(2)
+            .max(1);
+        let items = self.problems[..total.min(shown)]
+            .iter()
+            .map(|problem| {
+                let path = problem
+                    .path
+                    .strip_prefix(self.workspace.root())
+                    .unwrap_or(&problem.path);
+                let severity = match problem.severity {
+                    DiagnosticSeverity::ERROR => "error",
+                    DiagnosticSeverity::WARNING => "warning",
+                    DiagnosticSeverity::INFORMATION => "info",
+                    _ => "hint",
+                };
+                format!(
+                    "{}:{}: {severity}: {}",
+                    path.display(),
+                    problem.line + 1,
+                    problem.message
+                )
+            })
+            .collect();
+        let prompt = if total > shown {
+            format!("{total} problems, first {shown} shown. Go to number: ")
+        } else {
+            format!("{total} problems. Go to number: ")
+        };
+        let Some(index) = self.choose(items, &prompt) else {
+            return;
+        };
+        let problem = &self.problems[index];
+        let Ok(uri) = Url::from_file_path(&problem.path) else {
+            return;
+        };
+        let position = lsp_types::Position::new(problem.line as u32, problem.column as u32);
+        self.jump_to(&Location::new(
+            uri,
+            lsp_types::Range::new(position, position),
+        ));
+    }
+
+    /// Moves to the next diagnostic after (or before) the cursor, wrapping
+    /// around, and shows its message.
+    fn goto_diagnostic(&mut self, direction: SearchDirection) {
+        let cursor = (self.cursor_position.y, self.cursor_position.x);
+        let start = |diagnostic: &Diagnostic| {
+            (
+                diagnostic.range.start.line as usize,
+                diagnostic.range.start.character as usize,
+            )
+        };
+        let diagnostics = self.document.diagnostics();
+        let found = match direction {
+            SearchDirection::Forward => diagnostics
+                .iter()
+                .find(|diagnostic| start(diagnostic) > cursor)
+                .or_else(|| diagnostics.first()),
+            SearchDirection::Backward => diagnostics
+                .iter()
+                .rev()
+                .find(|diagnostic| start(diagnostic) < cursor)
+                .or_else(|| diagnostics.last()),
+        };
+        let Some(diagnostic) = found else {
+            self.status_message = StatusMessage::from("No diagnostics".to_string());
+            return;
+        };
+        let (y, x) = start(diagnostic);
+        let severity = match diagnostic.severity {
+            Some(DiagnosticSeverity::ERROR) => "error",
+            Some(DiagnosticSeverity::WARNING) => "warning",
+            Some(DiagnosticSeverity::INFORMATION) => "info",
+            _ => "hint",
+        };
+        let message = format!(
+            "{severity}: {}",
+            diagnostic.message.lines().next().unwrap_or_default()
+        );
+        self.cursor_position = Position { x, y };
+        self.clamp_cursor();
+        self.scroll();
+        self.status_message = StatusMessage::from(message);
+    }
+
+    /// Selects the syntax node around the selection, or around the cursor.
+    fn expand_selection(&mut self) {
+        let cursor = (self.cursor_position.clone(), self.cursor_position.clone());
+        let (start, end) = self.selection.last().unwrap_or(&cursor);
+        let Some(node) = self.document.enclosing_node(start, end) else {
+            self.status_message = StatusMessage::from("No syntax node to select".to_string());
+            return;
+        };
+        if self.selection.is_empty() {
+            self.selection.push(cursor);
+        }
+        self.cursor_position = node.0.clone();
+        self.selection.push(node);
+    }
+
+    /// Goes back to the selection the current one grew from.
+    fn shrink_selection(&mut self) {
+        self.selection.pop();
+        if let Some((start, _)) = self.selection.last() {
+            self.cursor_position = start.clone();
+        }
+        if self.selection.len() == 1 {
+            self.selection.clear();
+        }
+    }
+
+    /// Selects the `object` around the cursor, or its body if `inner`. The
+    /// selection can be grown from there.
+    fn select_object(&mut self, object: Object, inner: bool) {
+        match self
+            .document
+            .select_object(object, &self.cursor_position, inner)
+        {
+            Ok(Some(span)) => {
+                self.selection = vec![(self.cursor_position.clone(), self.cursor_position.clone())];
+                self.cursor_position = span.0.clone();
+                self.selection.push(span);
+            }
+            Ok(None) => {
+                self.status_message =
+                    StatusMessage::from(format!("No {} at the cursor", object.name()));
+            }
+            Err(e) => self.status_message = StatusMessage::from(format!("ERR: {e}")),
+        }
+    }
+
+    /// Moves the cursor to the start of the next or previous `object`.
+    fn goto_object(&mut self, object: Object, direction: SearchDirection) {
+        match self
+            .document
+            .next_object(object, &self.cursor_position, direction)
+        {
+            Ok(Some(start)) => {
+                self.cursor_position = start;
+                self.scroll();
+            }
+            Ok(None) => {
+                let which = match direction {
+                    SearchDirection::Forward => "next",
+                    SearchDirection::Backward => "previous",
+                };
+                self.status_message = StatusMessage::from(format!("No {which} {}", object.name()));
+            }
+            Err(e) => self.status_message = StatusMessage::from(format!("ERR: {e}")),
+        }
+    }
+
+    /// Moves the cursor to the bracket paired with the one at it.
+    fn goto_matching_bracket(&mut self) {
+        let Some((_, pair)) = self.document.matching_bracket(&self.cursor_position) else {
+            self.status_message = StatusMessage::from("No bracket at the cursor".to_string());
+            return;
+        };
+        self.cursor_position = pair;
+        self.scroll();
+    }
+
+    fn set_option(&mut self, option: &str, value: Option<&str>) {
+        match (option, value) {
+            ("filetype" | "ft", None) => {
+                self.status_message =
+                    StatusMessage::from(format!("filetype={}", self.document.file_type()));
+            }
+            ("filetype" | "ft", Some(name)) => {
+                let file_type = match name {
+                    "detect" => Some(self.document.detect_file_type()),
+                    name => FileType::named(name, &self.config),
+                };
+                let Some(file_type) = file_type else {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Unknown file type: {name}"));
+                    return;
+                };
+                self.document.set_file_type(file_type);
+                self.status_message = StatusMessage::from(match self.document.highlight_error() {
+                    Some(error) => highlight_error(error),
+                    None => format!("filetype={}", self.document.file_type()),
+                });
+                if self.trust == Trust::Trusted {
+                    self.start_lsp();
+                }
+            }
+            (option, value) => {
+                let mut settings = Settings::default();
+                let number = || value.and_then(|value| value.parse::<usize>().ok());
+                match option {
+                    "tab_width" | "ts" => settings.tab_width = number(),
+                    "indent_width" | "sw" => settings.indent_width = number(),
+                    "expand_tabs" | "et" => settings.expand_tabs = Some(value != Some("false")),
+                    "noet" => settings.expand_tabs = Some(false),
+                    "comment" => settings.comment = value.map(|value| value.to_string().into()),
+                    "max_line_length" => settings.max_line_length = number(),
+                    _ => {
+                        self.status_message =
+                            StatusMessage::from(format!("ERR: Unknown option: {option}"));
+                        return;
+                    }
+                }
+                if value.is_some() || matches!(option, "expand_tabs" | "et" | "noet") {
+                    self.document.set_overrides(&settings);
+                }
+                self.status_message = StatusMessage::from(describe_settings(
+                    &self.document.settings(),
+                    self.document.tab_width(),
+                ));
+            }
+        }
+    }
+
+    fn calculate(&mut self, expression: &str, insert: bool) {
+        let (value, radix) = match calc::evaluate(expression) {
+            Ok(result) => result,
+            Err(e) => {
+                self.status_message = StatusMessage::from(format!("ERR: {e}"));
+                return;
+            }
+        };
+        if insert {
+            let Position { x, y } = self.cursor_position;
+            self.cursor_position = self.document.replace(y, x, x, &radix.format(value));
+            self.scroll();
+        }
+        self.status_message = StatusMessage::from(format!("= {}", calc::describe(value)));
+    }
+
+    /// Breaks the line after a list or quote marker, starting the new line
+    /// with the next marker, or removes a marker with nothing after it.
+    /// Returns whether the cursor was past a marker.
+    fn continue_list(&mut self) -> bool {
+        if !self.config.continue_lists() {
+            return false;
+        }
+        let Position { x, y } = self.cursor_position;
+        let Some((length, continuation)) = self.document.list_marker(y) else {
+            return false;
+        };
+        let Some(row) = self.document.row(y) else {
+            return false;
+        };
+        let end = row.len();
+        if x < row.grapheme_index(length) {
+            return false;
+        }
+        self.cursor_position = match continuation {
+            Continuation::End => self.document.replace(y, 0, end, ""),
+            Continuation::Marker(marker) => self.document.replace(y, x, x, &format!("\n{marker}")),
+        };
+        self.scroll();
+        true
+    }
+
+    /// Re-indents the lines `rows`, keeping the cursor on the same text.
+    fn reindent(&mut self, rows: Range<usize>) {
+        let indentation = |editor: &Self| {
+            editor
+                .document
+                .row(editor.cursor_position.y)
+                .map_or(0, |row| {
+                    let line = row.as_str();
+                    row.grapheme_index(line.len() - line.trim_start().len())
+                })
+        };
+        let before = indentation(self);
+        self.document.reindent(rows);
+        let after = indentation(self);
+        let x = self.cursor_position.x;
+        self.cursor_position.x = if x <= before {
+            after
+        } else {
+            x - before + after
+        };
+        self.scroll();
+    }
+
+    /// The counts of the selection, or of the buffer.
+    fn counts(&self) -> Counts {
+        match self.selection.last() {
+            Some((start, end)) => Counts::of(&self.document.text_in(start, end)),
+            None => self.document.counts(),
+        }
+    }
+
+    /// The lines of the selection, if there is one.
+    fn selected_rows(&self) -> Option<Range<usize>> {
+        self.selection.last().map(|(start, end)| {
+            // A selection up to the start of a line leaves that line out.
+            if end.y > start.y && end.x == 0 {
+                start.y..end.y
+            } else {
+                start.y..end.y + 1
+            }
+        })
+    }
+
+    /// Replaces the lines of the selection, or of the buffer, with what
+    /// `change` makes of them, as one edit to undo. Returns how many lines
+    /// there were and are.
+    fn change_lines(&mut self, change: impl FnOnce(Vec<String>) -> Vec<String>) -> (usize, usize) {
+        let rows = self.selected_rows().unwrap_or(0..self.document.len());
+        let lines = self.document.lines(rows.clone());
+        let before = lines.len();
+        let lines = change(lines);
+        self.document.replace_lines(rows, &lines);
+        self.clamp_cursor();
+        self.scroll();
+        (before, lines.len())
+    }
+
+    fn sort_lines(&mut self, order: Order) {
+        let (count, _) = self.change_lines(|mut lines| {
+            sort::sort(&mut lines, order);
+            lines
+        });
+        self.status_message = StatusMessage::from(format!("Sorted {count} lines"));
+    }
+
+    fn reverse_lines(&mut self) {
+        let (count, _) = self.change_lines(|mut lines| {
+            lines.reverse();
+            lines
+        });
+        self.status_message = StatusMessage::from(format!("Reversed {count} lines"));
+    }
+
+    fn remove_duplicate_lines(&mut self) {
+        let (before, after) = self.change_lines(sort::dedupe);
+        self.status_message =
+            StatusMessage::from(format!("Duplicate lines removed: {}", before - after));
+    }
+
+    /// Comments or uncomments the lines of the selection, or the cursor line.
+    fn toggle_comment(&mut self) {
+        let y = self.cursor_position.y;
+        let rows = self.selected_rows().unwrap_or(y..y + 1);
+        let line_len = |editor: &Self| {
+            editor
+                .document
+                .row(editor.cursor_position.y)
+                .map_or(0, Row::len)
+        };
+        let before = line_len(self);
+        if !self.document.toggle_comment(rows) {
+            self.status_message = StatusMessage::from(format!(
+                "No comment for {}; see set comment",
+                self.document.file_type()
+            ));
+            return;
+        }
+        let after = line_len(self);
+        // Keep the cursor on the same character of its line.
+        self.cursor_position.x = (self.cursor_position.x + after).saturating_sub(before);
+        self.clamp_cursor();
+    }
+
+    fn close_fold(&mut self) {
+        let Some(start) = self.document.close_fold(self.cursor_position.y) else {
+            self.status_message = StatusMessage::from("Nothing to fold".to_string());
+            return;
+        };
+        self.cursor_position.y = start;
+        self.clamp_cursor();
+        self.scroll();
+    }
+
+    fn open_fold(&mut self) {
+        if !self.document.folds_mut().open(self.cursor_position.y) {
+            self.status_message = StatusMessage::from("No fold at the cursor".to_string());
+        }
+    }
+
+    fn toggle_all_folds(&mut self) {
+        if self.document.toggle_all_folds() {
+            let y = self.cursor_position.y;
+            if self.document.folds().is_hidden(y) {
+                self.cursor_position.y = self.document.folds().previous_visible(y);
+                self.clamp_cursor();
+            }
+        }
+        self.scroll();
+    }
+
+    /// Takes back the last edits and puts the cursor where they started.
+    fn undo(&mut self) {
+        self.document.clear_floating();
+        match self.document.undo() {
+            Some(position) => self.cursor_position = position,
+            None => self.status_message = StatusMessage::from("Nothing to undo".to_string()),
+        }
+        self.clamp_cursor();
+    }
+
+    /// Makes the last undone edits again and puts the cursor after them.
+    fn redo(&mut self) {
+        self.document.clear_floating();
+        match self.document.redo() {
+            Some(position) => self.cursor_position = position,
+            None => self.status_message = StatusMessage::from("Nothing to redo".to_string()),
+        }
+        self.clamp_cursor();
+    }
+
+    /// Asks for a query and narrows the view down to the lines containing
+    /// it while it is typed. The lines are edited on their own until
+    /// `widen`.
+    fn narrow(&mut self) {
+        if self.narrowing.is_some() {
+            self.status_message = StatusMessage::from("Already narrowed, widen first".to_string());
+            return;
+        }
+        self.start_narrowing();
+        let query = self
+            .prompt("Narrow to: ", |editor, _, query| editor.narrow_to(query))
+            .unwrap_or(None);
+        let matched = self
+            .narrowing
+            .as_ref()
+            .map_or(0, |narrowing| narrowing.lines.len());
+        match query {
+            Some(_) if matched > 0 => {
+                self.status_message = StatusMessage::from(format!(
+                    "Narrowed to {matched} lines, widen to write back"
+                ));
+            }
+            Some(query) => {
+                self.cancel_narrowing();
+                self.status_message = StatusMessage::from(format!("No line contains {query}"));
+            }
+            None => self.cancel_narrowing(),
+        }
+    }
+
+    /// Puts the document aside for a view of its lines.
+    fn start_narrowing(&mut self) {
+        self.narrowing = Some(Narrowing {
+            document: mem::take(&mut self.document),
+            query: String::new(),
+            lines: Vec::new(),
+            cursor_position: self.cursor_position.clone(),
+            offset: self.offset.clone(),
+        });
+        self.narrow_to("");
+    }
+
+    /// Shows the lines of the narrowed document that contain `query`.
+    fn narrow_to(&mut self, query: &str) {
+        let Some(narrowing) = self.narrowing.as_mut() else {
+            return;
+        };
+        narrowing.lines = narrow::matching(&narrowing.document, query);
+        narrowing.query = query.to_string();
+        self.document = narrowing.document.narrowed(&narrowing.lines);
+        self.cursor_position = Position::default();
+        self.offset = Position::default();
+        self.scroll();
+    }
+
+    /// Shows the whole document again, as it was before narrowing.
+    fn cancel_narrowing(&mut self) {
+        if let Some(narrowing) = self.narrowing.take() {
+            self.document = narrowing.document;
+            self.cursor_position = narrowing.cursor_position;
+            self.offset = narrowing.offset;
+            self.scroll();
+        }
+    }
+
+    /// Writes the lines of the narrowed view back to where they came from
+    /// and shows the whole document again, with the cursor on the line it
+    /// was on in the view.
+    fn widen(&mut self) {
+        let Some(mut narrowing) = self.narrowing.take() else {
+            self.status_message = StatusMessage::from("Not narrowed".to_string());
+            return;
+        };
+        let before = narrow::rows(&narrowing.document);
+        let (whole, positions) =
+            narrow::widen(&before, &narrowing.lines, &narrow::rows(&self.document));
+        let changed = whole != before;
+        if changed {
+            narrowing.document.set_text(&whole.join("\n"));
+        }
+        let x = self.cursor_position.x;
+        let y = positions.get(self.cursor_position.y).copied();
+        self.document = narrowing.document;
+        self.offset = narrowing.offset;
+        self.cursor_position = y.map_or(narrowing.cursor_position, |y| Position { x, y });
+        self.clamp_cursor();
+        self.scroll();
+        self.status_message = StatusMessage::from(
+            if changed {
+                "Widened, edits written back"
+            } else {
+                "Widened"
+            }
+            .to_string(),
+        );
+    }
+
+    /// Opens `file_name`, or a file asked for, in a buffer of its own.
+    fn open_file(&mut self, file_name: Option<String>) {
+        let file_name = match file_name {
+            Some(file_name) => file_name,
+            None => match self.prompt("Open: ", |_, _, _| {}).unwrap_or(None) {
+                Some(file_name) if !file_name.is_empty() => file_name,
+                _ => return,
+            },
+        };
+        self.show_file(&file_name);
+        self.scroll();
+    }
+
+    /// Shows the buffer of `file_name`, opening the file in a new one
+    /// after the current one unless it is open already. An unnamed buffer
+    /// without changes is replaced. Returns whether the file is shown.
+    fn show_file(&mut self, file_name: &str) -> bool {
+        let path = Path::new(file_name).canonicalize().ok();
+        let shows = |document: &Document| {
+            path.is_some()
+                && document
+                    .file_name
+                    .as_ref()
+                    .and_then(|name| Path::new(name).canonicalize().ok())
+                    == path
+        };
+        let current = self
+            .narrowing
+            .as_ref()
+            .map_or(&self.document, |narrowing| &narrowing.document);
+        if shows(current) {
+            return true;
+        }
+        if let Some(index) = self
+            .buffers
+            .iter()
+            .position(|buffer| shows(&buffer.document))
+        {
+            // The current buffer is not among them.
+            let index = if index < self.buffer {
+                index
+            } else {
+                index + 1
+            };
+            self.switch_buffer(index);
+            return true;
+        }
+        let document = match self.open_document(file_name) {
+            Ok(document) => document,
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not open {file_name}: {e}"));
+                return false;
+            }
+        };
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        if self.document.file_name.is_some() || self.document.is_dirty() {
+            self.put_aside();
+            self.buffer += 1;
+        }
+        self.document = document;
+        self.cursor_position = Position::default();
+        self.offset = Position::default();
+        self.forget_buffer_state();
+        self.restore_file_state();
+        if self.trust == Trust::Trusted {
+            self.start_lsp();
+        }
+        true
+    }
+
+    /// Puts the current buffer aside in its place among the others.
+    fn put_aside(&mut self) {
+        let buffer = Buffer {
+            document: mem::take(&mut self.document),
+            cursor_position: mem::take(&mut self.cursor_position),
+            offset: mem::take(&mut self.offset),
+        };
+        self.buffers.insert(self.buffer, buffer);
+    }
+
+    /// Shows the buffer at `index` of all open ones.
+    fn switch_buffer(&mut self, index: usize) {
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        self.put_aside();
+        let buffer = self.buffers.remove(index);
+        self.buffer = index;
+        self.document = buffer.document;
+        self.cursor_position = buffer.cursor_position;
+        self.offset = buffer.offset;
+        self.forget_buffer_state();
+        self.clamp_cursor();
+        self.scroll();
+    }
+
+    /// Drops what belonged to the buffer shown before, like the selection.
+    fn forget_buffer_state(&mut self) {
+        self.selection.clear();
+        self.menu = None;
+        self.snippet = None;
+        self.quit_times = QUIT_TIMES;
+    }
+
+    /// Shows the next or previous buffer, the first after the last.
+    fn cycle_buffers(&mut self, direction: SearchDirection) {
+        let count = self.buffers.len() + 1;
+        if count == 1 {
+            self.status_message = StatusMessage::from("No other buffer".to_string());
+            return;
+        }
+        let index = match direction {
+            SearchDirection::Forward => (self.buffer + 1) % count,
+            SearchDirection::Backward => (self.buffer + count - 1) % count,
+        };
+        self.switch_buffer(index);
+    }
+
+    /// Closes the current buffer, asking first if it has unsaved changes,
+    /// and shows the next one.
+    fn close_buffer(&mut self) {
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        if self.document.is_dirty() && self.confirm("Discard the changes? (y/n): ") != Some(true) {
+            return;
+        }
+        if let Some(file_name) = &self.document.file_name {
+            let _ =
+                FileState::new(&self.document, &self.cursor_position, &self.offset).save(file_name);
+        }
+        if self.buffers.is_empty() {
+            self.document = Document::default();
+            self.cursor_position = Position::default();
+            self.offset = Position::default();
+            self.forget_buffer_state();
+            return;
+        }
+        let index = self.buffer.min(self.buffers.len() - 1);
+        let buffer = self.buffers.remove(index);
+        self.buffer = index;
+        self.document = buffer.document;
+        self.cursor_position = buffer.cursor_position;
+        self.offset = buffer.offset;
+        self.forget_buffer_state();
+        self.clamp_cursor();
+        self.scroll();
+    }
+
+    /// Lists the open buffers and shows the chosen one.
+    fn list_buffers(&mut self) {
+        let name = |document: &Document| {
+            let name = document.file_name.as_deref().unwrap_or("[No File]");
+            if document.is_dirty() {
+                format!("{name} (modified)")
+            } else {
+                name.to_string()
+            }
+        };
+        let current = self
+            .narrowing
+            .as_ref()
+            .map_or(&self.document, |narrowing| &narrowing.document);
+        let mut items: Vec<String> = self
+            .buffers
+            .iter()
+            .map(|buffer| name(&buffer.document))
+            .collect();
+        items.insert(self.buffer, name(current));
+        let Some(index) = self.choose(items, "Go to buffer number: ") else {
+            return;
+        };
+        if index != self.buffer {
+            self.switch_buffer(index);
+        }
+    }
+
+    /// Edits the bytes of the buffer as a hex dump, or the text again.
+    fn toggle_hex(&mut self) {
+        if self.narrowing.is_some() {
+            self.widen();
+        }
+        if self.document.is_hex() {
+            match self.document.end_hex() {
+                Ok(()) => {
+                    self.clamp_cursor();
+                    self.offset = Position::default();
+                    self.scroll();
+                    self.status_message = StatusMessage::from("Text again".to_string());
+                }
+                Err(e) => self.status_message = StatusMessage::from(format!("ERR: {e}")),
+            }
+            return;
+        }
+        match self.document.start_hex() {
+            Ok(()) => {
+                self.offset = Position::default();
+                self.status_message =
+                    StatusMessage::from("Hex: Tab switches to the characters".to_string());
+            }
+            Err(e) => self.status_message = StatusMessage::from(format!("ERR: {e}")),
+        }
+    }
+
+    fn report_read_only(&mut self) {
+        self.status_message =
+            StatusMessage::from("Read-only; `read-only` turns editing on".to_string());
+    }
+
+    /// Starts appending the lines written to the file, showing the last
+    /// one, or stops.
+    fn toggle_follow(&mut self) {
+        if self.document.is_following() {
+            self.document.unfollow();
+            self.status_message = StatusMessage::from("Stopped following".to_string());
+            return;
+        }
+        if let Err(e) = self.document.follow() {
+            self.status_message = StatusMessage::from(format!("ERR: Cannot follow: {e}"));
+            return;
+        }
+        self.cursor_position = Position {
+            x: 0,
+            y: self.document.len().saturating_sub(1),
+        };
+        self.scroll();
+        self.status_message =
+            StatusMessage::from("Following: lines written are appended".to_string());
+    }
+
+    /// Appends the lines written to a followed file, keeping the last one
+    /// in view if the cursor was on it. Returns whether there were any.
+    fn poll_follow(&mut self) -> bool {
+        let last = self.document.len().saturating_sub(1);
+        match self.document.poll_follow() {
+            Ok(false) => false,
+            Ok(true) => {
+                if self.cursor_position.y >= last {
+                    self.cursor_position = Position {
+                        x: 0,
+                        y: self.document.len().saturating_sub(1),
+                    };
+                }
+                self.clamp_cursor();
+                self.scroll();
+                true
+            }
+            Err(e) => {
+                self.document.unfollow();
+                self.status_message = StatusMessage::from(format!("ERR: Stopped following: {e}"));
+                true
+            }
+        }
+    }
+
+    fn toggle_bom(&mut self) {
+        self.document.toggle_bom();
+        let message = if self.document.has_bom() {
+            "Byte order mark will be written on save"
+        } else {
+            "Byte order mark will be removed on save"
+        };
+        self.status_message = StatusMessage::from(message.to_string());
+    }
+
+    fn format(&mut self) {
+        if !self.document.has_lsp() {
+            self.status_message = StatusMessage::from(locale::text("no-language-server", &[]));
+            return;
+        }
+        self.status_message = StatusMessage::from("Formatting... (Ctrl-C to cancel)".to_string());
+        let _ = self.refresh_screen();
+        let cancellation = self.terminal.cancellation();
+        let edits = self.document.lsp_formatting(&cancellation);
+        self.end_progress(&cancellation);
+        let Some(edits) = edits else {
+            self.status_message = StatusMessage::from("Formatting cancelled".to_string());
+            return;
+        };
+        self.status_message = StatusMessage::from(String::new());
+        self.apply_edits_with_preview("formatting", &edits);
+    }
+
+    fn code_action(&mut self) {
+        if !self.document.has_lsp() {
+            self.status_message = StatusMessage::from(locale::text("no-language-server", &[]));
+            return;
+        }
+        let mut actions = self
+            .document
+            .lsp_code_actions(self.cursor_position.x as u32, self.cursor_position.y as u32);
+        if actions.is_empty() {
+            self.status_message = StatusMessage::from("No code actions".to_string());
+            return;
+        }
+        let titles = actions.iter().map(|(title, _)| title.clone()).collect();
+        let Some(index) = self.choose(titles, "Code action number: ") else {
+            self.status_message = StatusMessage::from("Code action aborted".to_string());
+            return;
+        };
+        let (title, edits) = actions.swap_remove(index);
+        self.apply_edits_with_preview(&title, &edits);
+    }
+
+    /// Lists `items` numbered below the cursor and asks for one of them.
+    fn choose(&mut self, items: Vec<String>, prompt: &str) -> Option<usize> {
+        let count = items.len();
+        let columns = self.text_width();
+        let lines: Vec<String> = items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| measure::abbreviate(&format!("{}: {item}", index + 1), columns))
+            .collect();
+        let width = lines
+            .iter()
+            .map(|line| line.graphemes(true).count())
+            .max()
+            .unwrap_or(0);
+        self.document.show_floating(
+            FloatingItem::new(Position::default(), width, lines.len(), lines)
+                .with_anchor(self.cursor_position.clone()),
+        );
+        let number = move |choice: &str| {
+            choice
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|number| (1..=count).contains(number))
+        };
+        let prompt = Prompt::new(prompt)
+            .placeholder(&format!("1-{count}"))
+            .validate(move |choice| {
+                number(choice)
+                    .is_none()
+                    .then(|| format!("not a number from 1 to {count}"))
+            });
+        let choice = self.ask(prompt, |_, _, _| {}).unwrap_or(None);
+        self.document.clear_floating();
+        choice
+            .and_then(|choice| number(&choice))
+            .map(|number| number - 1)
+    }
+
+    fn goto(&mut self, target: Goto) {
+        if !self.document.has_lsp() {
+            self.status_message = StatusMessage::from(locale::text("no-language-server", &[]));
+            return;
+        }
+        let mut locations = self.document.lsp_goto(
+            target,
+            self.cursor_position.x as u32,
+            self.cursor_position.y as u32,
+        );
+        let index = match locations.len() {
+            0 => {
+                self.status_message = StatusMessage::from(format!("No {} found", target.name()));
+                return;
+            }
+            1 => 0,
+            _ => {
+                let items = locations
+                    .iter()
+                    .map(|location| self.describe_location(location))
+                    .collect();
+                let Some(index) = self.choose(items, &format!("Go to {} number: ", target.name()))
+                else {
+                    return;
+                };
+                index
+            }
+        };
+        let location = locations.swap_remove(index);
+        self.jump_to(&location);
+    }
+
+    fn describe_location(&self, location: &Location) -> String {
+        let path = location.uri.to_file_path().unwrap_or_default();
+        let path = path.strip_prefix(self.workspace.root()).unwrap_or(&path);
+        format!(
+            "{}:{}:{}",
+            path.display(),
+            location.range.start.line.saturating_add(1),
+            location.range.start.character.saturating_add(1)
+        )
+    }
+
+    /// Moves the cursor to `location`, opening its file in place of the
+    /// current one if needed.
+    fn jump_to(&mut self, location: &Location) {
+        let Ok(path) = location.uri.to_file_path() else {
+            self.status_message = StatusMessage::from(format!("Cannot open {}", location.uri));
+            return;
+        };
+        if !self.show_file(&path.to_string_lossy()) {
+            return;
+        }
+        self.cursor_position = Position {
+            x: location.range.start.character as usize,
+            y: location.range.start.line as usize,
+        };
+        self.clamp_cursor();
+        self.scroll();
+    }
+
+    /// Applies language server `edits`, first showing them as a diff and
+    /// asking for confirmation unless `preview_edits` is turned off.
+    /// Asks for a tree-sitter query capturing `@match` and a template to
+    /// replace the matches with, then previews the replacements.
+    fn structural_replace(&mut self) {
+        let Some(query) = self.prompt("Query: ", |_, _, _| {}).unwrap_or(None) else {
+            return;
+        };
+        let Some(template) = self
+            .prompt("Replace @match with: ", |_, _, _| {})
+            .unwrap_or(None)
+        else {
+            return;
+        };
+        match self.document.structural_replace(&query, &template) {
+            Ok(edits) if edits.is_empty() => {
+                self.status_message = StatusMessage::from("No matches".to_string());
+            }
+            Ok(edits) => {
+                let title = format!("{} replacements", edits.len());
+                self.apply_edits_with_preview(&title, &edits);
+            }
+            Err(e) => self.status_message = StatusMessage::from(format!("ERR: {e}")),
+        }
+    }
+
+    fn apply_edits_with_preview(&mut self, title: &str, edits: &[TextEdit]) {
+        let old = self.document.text();
+        let new = self.document.text_with_edits(edits);
+        if old == new {
+            self.status_message = StatusMessage::from(format!("{title}: no changes"));
+            return;
+        }
+        if self.config.preview_edits() {
+            let lines = diff::unified_text(&old, &new);
+            let width = self.terminal.size().width as usize;
+            let height = self.terminal.size().height as usize;
+            let show = |editor: &mut Self, first| {
+                editor.document.show_floating(diff::floating_lines(
+                    &lines,
+                    first,
+                    Position::default(),
+                    width,
+                    height,
+                ));
+            };
+            show(self, 0);
+            // Diffs taller than the screen are scrolled like the diff command's.
+            let last = lines.len().saturating_sub(height);
+            let question = if last > 0 {
+                format!("Apply {title}? (y/n, Up/Down = Scroll): ")
+            } else {
+                format!("Apply {title}? (y/n): ")
+            };
+            let mut first: usize = 0;
+            let answer = self.confirm_with(&question, |editor, key| {
+                first = match key {
+                    Key::Up => first.saturating_sub(1),
+                    Key::Down => (first + 1).min(last),
+                    Key::PageUp => first.saturating_sub(height),
+                    Key::PageDown => (first + height).min(last),
+                    _ => return,
+                };
+                show(editor, first);
+            });
+            self.document.clear_floating();
+            if answer != Some(true) {
+                self.status_message = StatusMessage::from(format!("{title} discarded"));
+                return;
+            }
+        }
+        self.document.apply_edits(edits);
+        self.clamp_cursor();
+        self.scroll();
+        self.status_message = StatusMessage::from(format!("Applied {title}"));
+    }
+
+    /// Lets the user reload the file that changed on disk or keep the buffer,
+    /// optionally after looking at what changed.
+    fn handle_disk_change(&mut self) {
+        let disk_text = match self.document.disk_text() {
+            Ok(text) => text,
+            Err(_) => {
+                // Deleted or unreadable; there is nothing to reload from.
+                self.document.ignore_disk_changes();
+                self.status_message = StatusMessage::from(locale::text("removed-on-disk", &[]));
+                return;
+            }
+        };
+        loop {
+            let answer = self
+                .prompt(&locale::text("changed-on-disk", &[]), |_, _, _| {})
+                .unwrap_or(None);
+            match answer.as_deref() {
+                Some("r" | "R") => {
+                    self.reload_from_disk();
+                    return;
+                }
+                Some("s" | "S") => self.show_disk_changes(&disk_text),
+                _ => {
+                    self.document.clear_floating();
+                    self.document.ignore_disk_changes();
+                    self.status_message = StatusMessage::from(locale::text("kept-buffer", &[]));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Asks what to do about the file having changed on disk since it was
+    /// read, before saving over it. Returns whether to save.
+    fn confirm_overwrite(&mut self) -> bool {
+        let Ok(disk_text) = self.document.disk_text() else {
+            // Removed; saving writes it again.
+            return true;
+        };
+        loop {
+            let answer = self
+                .prompt(&locale::text("changed-before-save", &[]), |_, _, _| {})
+                .unwrap_or(None);
+            match answer.as_deref() {
+                Some("o" | "O") => {
+                    self.document.clear_floating();
+                    return true;
+                }
+                Some("r" | "R") => {
+                    self.reload_from_disk();
+                    return false;
+                }
+                Some("s" | "S") => self.show_disk_changes(&disk_text),
+                _ => {
+                    self.document.clear_floating();
+                    self.status_message = StatusMessage::from(locale::text("save-aborted", &[]));
+                    return false;
+                }
+            }
+        }
+    }
+
+    fn reload_from_disk(&mut self) {
+        self.document.clear_floating();
+        if self.document.reload().is_ok() {
+            self.clamp_cursor();
+            self.scroll();
+            self.status_message = StatusMessage::from(locale::text("reloaded", &[]));
+        } else {
+            self.status_message = StatusMessage::from("ERR: Could not reload file".to_string());
+        }
+    }
+
+    /// Shows how the buffer differs from `disk_text`.
+    fn show_disk_changes(&mut self, disk_text: &str) {
+        let width = self.terminal.size().width as usize;
+        let height = self.terminal.size().height as usize;
+        self.document.show_floating(diff::floating(
+            &self.document.text(),
+            disk_text,
+            Position::default(),
+            width,
+            height,
+        ));
+    }
+
+    /// Keeps the cursor inside the document after its text was replaced.
+    fn clamp_cursor(&mut self) {
+        let y = self.cursor_position.y.min(self.document.len());
+        let width = self.document.row(y).map_or(0, Row::len);
+        self.cursor_position = Position {
+            x: self.cursor_position.x.min(width),
+            y,
+        };
+    }
+}
+
+/// Whether `key` changes the buffer.
+fn edits(key: Key) -> bool {
+    matches!(
+        key,
+        Key::Char(_)
+            | Key::Backspace
+            | Key::Delete
+            | Key::Null
+            | Key::Ctrl('z' | 'y' | 'n' | '7')
+            | Key::Alt('F' | '.' | 'b' | 'h')
+    )
+}
+
+fn spinner_frame() -> char {
+    let ticks = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        / TICK.as_millis();
+    SPINNER[ticks as usize % SPINNER.len()]
+}
+
+/// The message telling that the queries of a file type do not compile.
+fn highlight_error(error: &str) -> String {
+    format!("ERR: {error} (using basic highlighting)")
+}
+
+/// The settings `set` changes, as `set` takes them.
+fn describe_settings(settings: &Settings, tab_width: usize) -> String {
+    let mut description = format!("tab_width={tab_width}");
+    if let Some(width) = settings.indent_width {
+        description += &format!(" indent_width={width}");
+    }
+    if let Some(expand_tabs) = settings.expand_tabs {
+        description += &format!(" expand_tabs={expand_tabs}");
+    }
+    if let Some(comment) = &settings.comment {
+        description += &format!(" comment={comment}");
+    }
+    if let Some(length) = settings.max_line_length {
+        description += &format!(" max_line_length={length}");
+    }
+    description
+}
+
+/// Starts loading the dictionary of the config if it checks spelling.
+fn load_dictionary(config: &Config) -> Option<Arc<OnceLock<Dictionary>>> {
+    config
+        .spell_check()
+        .then(|| spell::load_in_background(spell::find(config.dictionary())))
+}
+
+/// The user config, with the project config layered on top in trusted
+/// workspaces.
+fn load_config(workspace: &Workspace, trust: Trust) -> Result<Config> {
+    let mut config = Config::load()?;
+    if trust == Trust::Trusted {
+        if let Some(path) = workspace.project_config() {
+            config.merge(Config::load_from(&path)?);
+        }
+    }
+    Ok(config)
+}
+
+fn die(terminal: &Terminal, e: Error) {
+    terminal.clear_screen();
+    let _ = terminal.flush();
+    panic!("{}", e);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::thread;
+
+    use lsp_types::{Hover, HoverContents, MarkedString, MarkupContent, MarkupKind};
+
+    use super::*;
+    use crate::terminal::Size;
+    use crate::test_backend::{assert_snapshot, Screen, TestBackend};
+    use crate::FileType;
+
+    const WIDTH: u16 = 48;
+    const HEIGHT: u16 = 12;
+    const HIGHLIGHT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// An editor drawing on a test screen, with `text` open as `name` once
+    /// it is highlighted.
+    fn editor(file: Option<(&str, &str)>) -> (Editor, Rc<RefCell<Screen>>) {
+        editor_with(Config::default(), file)
+    }
+
+    fn editor_with(config: Config, file: Option<(&str, &str)>) -> (Editor, Rc<RefCell<Screen>>) {
+        // Query overrides of the user would change the frames.
+        env::set_var("XDG_CONFIG_HOME", "target/snap/config");
+        // Nor should the state of the user's own files be touched.
+        env::set_var("XDG_DATA_HOME", "target/snap/data");
+        let (backend, screen) = TestBackend::new(WIDTH.into(), HEIGHT.into());
+        let size = Size {
+            width: WIDTH,
+            height: HEIGHT,
+        };
+        let terminal = Terminal::with_backend(Box::new(backend), size);
+        let config = Rc::new(config);
+        let workspace = Workspace::discover(None);
+        let status = String::from("HELP: Ctrl-Q = Quit");
+        let mut editor = Editor::new(terminal, config, workspace, Trust::Untrusted, status);
+        if let Some((name, text)) = file {
+            // Relative, so the status bar reads the same on every machine.
+            let path = format!("target/snap/{name}");
+            fs::create_dir_all("target/snap").unwrap();
+            fs::write(&path, text).unwrap();
+            editor.document = Document::open(&path, Rc::clone(&editor.config)).unwrap();
+            // Languages without their grammar get the basic highlighter.
+            if FileType::from(&path, text).is_some() {
+                wait_for_highlight(&mut editor);
+            }
+        }
+        (editor, screen)
+    }
+
+    fn wait_for_highlight(editor: &mut Editor) {
+        let start = Instant::now();
+        while !editor.document.poll_highlight() && start.elapsed() < HIGHLIGHT_TIMEOUT {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn assert_frame(name: &str, editor: &Editor, screen: &Rc<RefCell<Screen>>) {
+        editor.refresh_screen().unwrap();
+        assert_snapshot(name, &screen.borrow().frame());
+    }
+
+    /// The status bar as last drawn.
+    fn status_line(editor: &Editor, screen: &Rc<RefCell<Screen>>) -> String {
+        screen.borrow().line(editor.text_height())
+    }
+
+    const RUST: &str =
+        "/// Adds one.\nfn add_one(x: u32) -> u32 {\n    let y = \"one\";\n    x + 1 // y\n}\n";
+
+    #[test]
+    fn welcome_screen() {
+        let (editor, screen) = editor(None);
+        assert_frame("welcome_screen", &editor, &screen);
+    }
+
+    #[test]
+    fn status_bar() {
+        let (mut editor, screen) = editor(Some(("status.rs", RUST)));
+        editor.document.insert(&Position { x: 0, y: 2 }, ' ');
+        editor.cursor_position = Position { x: 1, y: 2 };
+        editor.status_message = StatusMessage::from(String::from("ERR: Something failed"));
+        assert_frame("status_bar", &editor, &screen);
+        assert_eq!(
+            status_line(&editor, &screen),
+            "…rget/snap/status.rs - 5 lines (modi… Rust | 3/5"
+        );
+    }
+
+    #[test]
+    fn highlighting() {
+        let (editor, screen) = editor(Some(("highlight.rs", RUST)));
+        assert_frame("highlighting", &editor, &screen);
+    }
+
+    #[test]
+    fn basic_highlighting() {
+        let text = "-- Adds one.\nlocal function add_one(x)\n  return x + 1 --[[ y ]]\nend\nprint(\"one\")\n";
+        let (editor, screen) = editor(Some(("basic.lua", text)));
+        assert_frame("basic_highlighting", &editor, &screen);
+    }
+
+    #[test]
+    fn shebang() {
+        let text = "#!/usr/bin/env python3\nimport os\n\nprint(\"built\", os.getcwd())\n";
+        let (editor, screen) = editor(Some(("build", text)));
+        assert_frame("shebang", &editor, &screen);
+    }
+
+    #[test]
+    fn modeline() {
+        let text = "def f(x):\n\tif x:\n\t\treturn x\n# vim: set ft=python ts=4 sw=2 et:\n";
+        let (mut editor, screen) = editor(Some(("settings", text)));
+        editor.cursor_position = Position { x: 9, y: 0 };
+        editor.process_keypress(Key::Char('\n'));
+        editor.process_keypress(Key::Char('y'));
+        assert_frame("modeline", &editor, &screen);
+    }
+
+    #[test]
+    fn file_type_settings() {
+        let config =
+            toml::from_str("[filetype.python]\nindent_width = 2\nmax_line_length = 20").unwrap();
+        let text = "total = 0  # the sum of all the values\ndef add(x):\n";
+        let (mut editor, screen) = editor_with(config, Some(("settings.py", text)));
+        editor.cursor_position = Position { x: 11, y: 1 };
+        editor.process_keypress(Key::Char('\n'));
+        editor.process_keypress(Key::Char('y'));
+        assert_frame("file_type_settings", &editor, &screen);
+    }
+
+    #[test]
+    fn redetected_file_type() {
+        let text = "def add(x):\n    return x + 1  # one\n";
+        let (mut editor, screen) = editor(Some(("redetect.py", text)));
+        editor.set_option("filetype", Some("none"));
+        editor.set_option("filetype", Some("detect"));
+        wait_for_highlight(&mut editor);
+        assert_frame("redetected_file_type", &editor, &screen);
+    }
+
+    #[test]
+    fn content_heuristics() {
+        let text =
+            "#pragma once\n\nnamespace geometry {\ntemplate <typename T>\nT area(T w, T h);\n}\n";
+        let (editor, screen) = editor(Some(("area.h", text)));
+        assert_frame("content_heuristics", &editor, &screen);
+    }
+
+    #[test]
+    fn rainbow_brackets() {
+        let config = toml::from_str("rainbow_brackets = true").unwrap();
+        let text = "fn main() {\n    let v = [(1, 2), (3, f(4))];\n}\n";
+        let (editor, screen) = editor_with(config, Some(("rainbow.rs", text)));
+        assert_frame("rainbow_brackets", &editor, &screen);
+    }
+
+    #[test]
+    fn matching_bracket() {
+        let text = "fn main() {\n    let s = \")\";\n    f((1, 2));\n}\n";
+        let (mut editor, screen) = editor(Some(("brackets.rs", text)));
+        editor.cursor_position = Position { x: 10, y: 0 };
+        assert_frame("matching_bracket", &editor, &screen);
+    }
+
+    #[test]
+    fn expanded_selection() {
+        let text = "fn main() {\n    let x = f(1 + 2,\n        3);\n}\n";
+        let (mut editor, screen) = editor(Some(("selection.rs", text)));
+        editor.cursor_position = Position { x: 14, y: 1 };
+        editor.expand_selection();
+        editor.expand_selection();
+        editor.expand_selection();
+        assert_frame("expanded_selection", &editor, &screen);
+    }
+
+    #[test]
+    fn parameter_references() {
+        let text = "fn f(count: i32) -> i32 {\n    let total = count + 1;\n    let count = 2;\n    total * count\n}\n";
+        let (editor, screen) = editor(Some(("locals.rs", text)));
+        assert_frame("parameter_references", &editor, &screen);
+    }
+
+    #[test]
+    fn auto_indented() {
+        let (mut editor, screen) = editor(Some(("indent.rs", "")));
+        for c in "fn main() {\nif x {\nf(1,\n2);\n}\n}".chars() {
+            editor.process_keypress(Key::Char(c));
+        }
+        assert_frame("auto_indented", &editor, &screen);
+    }
+
+    #[test]
+    fn continued_lists() {
+        let (mut editor, screen) = editor(Some(("lists.md", "")));
+        for c in "- one\ntwo\n\n9. nine\nten\n\n> - [x] done\nnext\n\nend".chars() {
+            editor.process_keypress(Key::Char(c));
+        }
+        assert_frame("continued_lists", &editor, &screen);
+    }
+
+    #[test]
+    fn folded() {
+        let text = "// One\n// two\nfn main() {\n    let x = 1;\n    f(x);\n}\nfn g() {}\n";
+        let (mut editor, screen) = editor(Some(("fold.rs", text)));
+        editor.cursor_position = Position { x: 8, y: 3 };
+        editor.close_fold();
+        editor.cursor_position = Position { x: 0, y: 0 };
+        editor.close_fold();
+        editor.move_cursor(Key::Down);
+        editor.move_cursor(Key::Down);
+        assert_frame("folded", &editor, &screen);
+    }
+
+    #[test]
+    fn reopened_with_fold() {
+        let text = "fn main() {\n    let x = 1;\n    f(x);\n}\n";
+        let (mut closed, _) = editor(Some(("fold_state.rs", text)));
+        closed.cursor_position = Position { x: 4, y: 1 };
+        closed.close_fold();
+        closed.remember_file_state();
+        let (mut reopened, _) = editor(None);
+        reopened.document =
+            Document::open("target/snap/fold_state.rs", Rc::clone(&reopened.config)).unwrap();
+        reopened.restore_file_state();
+        assert!(reopened.document.folds().contains(&(0..4)));
+        assert_eq!(reopened.cursor_position.y, 0);
+    }
+
+    #[test]
+    fn selected_function_body() {
+        let text = "fn main() {\n    f(1);\n}\n\nfn f(x: i32) -> i32 {\n    x + 1\n}\n";
+        let (mut editor, screen) = editor(Some(("textobject.rs", text)));
+        editor.cursor_position = Position { x: 8, y: 5 };
+        editor.select_object(Object::Function, true);
+        assert_frame("selected_function_body", &editor, &screen);
+    }
+
+    #[test]
+    fn search_closed() {
+        let (mut editor, _) = editor(Some(("search_closed.rs", RUST)));
+        editor.search_highlight = Some(String::from("one"));
+        editor.search();
+        assert!(editor.search_highlight.is_none());
+    }
+
+    #[test]
+    fn search_matches() {
+        let (mut editor, screen) = editor(Some(("search.rs", RUST)));
+        editor.search_highlight = Some(String::from("one"));
+        editor.cursor_position = Position { x: 12, y: 2 };
+        assert_frame("search_matches", &editor, &screen);
+    }
+
+    #[test]
+    fn misspelled_words() {
+        let text = "// The wrods are speled\nlet wrods = \"the words\";\n";
+        let (mut editor, screen) = editor(Some(("spell.rs", text)));
+        let dictionary =
+            Dictionary::parse("SFX S Y 1\nSFX S 0 s .\n", "4\nthe\nword/S\nare\nlet\n");
+        editor.dictionary = Some(Arc::new(OnceLock::from(dictionary)));
+        assert_frame("misspelled_words", &editor, &screen);
+    }
+
+    #[test]
+    fn control_characters() {
+        let text = "\u{1b}[31mred\u{1b}[0m and a bell\u{7}\n";
+        let (editor, screen) = editor(Some(("colors.log", text)));
+        assert_frame("control_characters", &editor, &screen);
+    }
+
+    #[test]
+    fn annotated_line() {
+        let (mut editor, screen) = editor(Some(("annotated.rs", RUST)));
+        editor
+            .document
+            .annotations_mut()
+            .set(1, String::from("Why not `x.saturating_add(1)`?"));
+        editor.cursor_position = Position { x: 3, y: 1 };
+        editor.show_annotation();
+        assert_frame("annotated_line", &editor, &screen);
+    }
+
+    #[test]
+    fn prompt_error() {
+        let (mut editor, screen) = editor(Some(("prompt.rs", RUST)));
+        let mut prompt = Prompt::new("Line: ")
+            .placeholder("1-5")
+            .validate(|line| line.parse::<usize>().err().map(|error| error.to_string()));
+        for c in "4x\n".chars() {
+            prompt.process_key(Key::Char(c));
+        }
+        editor.status_message = StatusMessage::from(prompt.text());
+        editor.status_message.hint = prompt.hint();
+        assert_frame("prompt_error", &editor, &screen);
+    }
+
+    #[test]
+    fn japanese_messages() {
+        let (mut editor, screen) = editor(Some(("locale.rs", RUST)));
+        let catalog = Catalog::load("ja").unwrap();
+        editor.status_message =
+            StatusMessage::from(catalog.text("no-matches", &[("query", &"add_one")]));
+        assert_frame("japanese_messages", &editor, &screen);
+    }
+
+    #[test]
+    fn compared_panes() {
+        let (mut editor, screen) = editor(Some(("compare.rs", RUST)));
+        let snippet = "fn add_one(x: u64) -> u64 {\n    x + 1\n}";
+        let (left, right) = diff::panes(&editor.document.text(), snippet, "Buffer", "Clipboard");
+        editor.show_panes(&left, &right, 0);
+        assert_frame("compared_panes", &editor, &screen);
+    }
+
+    #[test]
+    fn unsaved_diff() {
+        let (mut editor, screen) = editor(Some(("unsaved.rs", RUST)));
+        editor.run_command(Command::Diff);
+        assert_eq!(
+            editor.status_message.text,
+            "No changes against the file on disk"
+        );
+        editor.document.insert(&Position { x: 4, y: 3 }, '2');
+        editor.document.insert(&Position { x: 0, y: 5 }, '}');
+        // Without keys to scroll by, the diff closes after the first frame.
+        editor.run_command(Command::Diff);
+        assert_snapshot("unsaved_diff", &screen.borrow().frame());
+        assert!(editor.status_message.text.is_empty());
+    }
+
+    #[test]
+    fn hidden_message_bar() {
+        let config = toml::from_str("hide_message_bar = true").unwrap();
+        let text = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let (mut editor, screen) = editor_with(config, Some(("lines.txt", text)));
+        editor.status_message = StatusMessage::from(String::new());
+        editor.cursor_position = Position { x: 0, y: 9 };
+        editor.scroll();
+        assert_frame("hidden_message_bar", &editor, &screen);
+    }
+
+    #[test]
+    fn toggled_comments() {
+        let text = "def add(x):\n    if x:\n\n        return x + 1\n    # done\n";
+        let (mut editor, screen) = editor(Some(("comment.py", text)));
+        editor.selection = vec![(Position { x: 0, y: 1 }, Position { x: 0, y: 4 })];
+        editor.process_keypress(Key::Ctrl('7'));
+        editor.selection.clear();
+        editor.cursor_position = Position { x: 6, y: 4 };
+        editor.process_keypress(Key::Ctrl('7'));
+        assert_frame("toggled_comments", &editor, &screen);
+    }
+
+    #[test]
+    fn tab_stops() {
+        let text = "all: build\n\tgo build\t# compile\nx := 1\t# one\n";
+        let (mut editor, screen) = editor(Some(("Makefile", text)));
+        editor.cursor_position = Position { x: 10, y: 1 };
+        assert_frame("tab_stops", &editor, &screen);
+    }
+
+    #[test]
+    fn project_statistics() {
+        let root = Path::new("target/snap/project");
+        fs::create_dir_all(root).unwrap();
+        fs::write(root.join("lib.rs"), RUST).unwrap();
+        fs::write(
+            root.join("main.py"),
+            "def main():\n    pass  # TODO: work\n",
+        )
+        .unwrap();
+        fs::write(root.join("notes.txt"), "XXX\n").unwrap();
+        let (mut editor, screen) = editor(Some(("stats.rs", RUST)));
+        let index = Index::start(root, &[]);
+        let start = Instant::now();
+        while !index.is_ready() && start.elapsed() < HIGHLIGHT_TIMEOUT {
+            thread::sleep(Duration::from_millis(10));
+        }
+        editor.index = Some(index);
+        editor.show_statistics();
+        assert_frame("project_statistics", &editor, &screen);
+    }
+
+    #[test]
+    fn markdown_attributes() {
+        let text = "# Notes\n\nSome **bold**, *italic* and ~~old~~ text.\nRun `cargo test` in snake_case_dirs.\n";
+        let (editor, screen) = editor(Some(("notes.md", text)));
+        assert_frame("markdown_attributes", &editor, &screen);
+    }
+
+    #[test]
+    fn save_steps() {
+        let config = toml::from_str(
+            "save_steps = [\"trim_whitespace\"]\n[filetype.python]\nsave_steps = [\"final_newline\"]",
+        )
+        .unwrap();
+        let text = "x = 1  \ny = 2\n\n";
+        let (mut editor, screen) = editor_with(config, Some(("steps.py", text)));
+        editor.document.insert(&Position { x: 0, y: 2 }, '\n');
+        editor.save();
+        assert_eq!(
+            fs::read_to_string("target/snap/steps.py").unwrap(),
+            "x = 1  \ny = 2\n"
+        );
+        assert_frame("save_steps", &editor, &screen);
+    }
+
+    #[test]
+    fn big_file() {
+        let config = toml::from_str("big_file_line_length = 40").unwrap();
+        let (mut editor, screen) = editor_with(config, None);
+        let text = format!("{RUST}const ONES: [u32; 12] = [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1];\n");
+        fs::create_dir_all("target/snap").unwrap();
+        fs::write("target/snap/big.rs", text).unwrap();
+        editor.document = editor.open_document("target/snap/big.rs").unwrap();
+        assert_frame("big_file", &editor, &screen);
+        assert!(status_line(&editor, &screen).ends_with(" Rust | plain | 1/6"));
+    }
+
+    #[test]
+    fn streamed() {
+        let config = toml::from_str("big_file_size = 1000").unwrap();
+        let (mut editor, screen) = editor_with(config, None);
+        let text: String = (0..200_000).map(|n| format!("line {n}\n")).collect();
+        fs::create_dir_all("target/snap").unwrap();
+        fs::write("target/snap/streamed.log", &text).unwrap();
+        editor.document = editor.open_document("target/snap/streamed.log").unwrap();
+        assert!(editor.document.len() < 200_000);
+        editor.document.insert(&Position { x: 0, y: 0 }, '>');
+        let start = Instant::now();
+        while editor.document.stream_progress().is_some() && start.elapsed() < HIGHLIGHT_TIMEOUT {
+            editor.document.poll_stream().unwrap();
+        }
+        assert_eq!(editor.document.len(), 200_000);
+        editor.save();
+        assert_eq!(
+            fs::read_to_string("target/snap/streamed.log").unwrap(),
+            format!(">{text}")
+        );
+        assert_frame("streamed", &editor, &screen);
+    }
+
+    #[test]
+    fn scrolled_horizontally() {
+        let line = "let numbers = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];\n";
+        let (mut editor, screen) = editor(Some(("wide.rs", line)));
+        editor.cursor_position = Position { x: 60, y: 0 };
+        editor.scroll();
+        assert_frame("scrolled_horizontally", &editor, &screen);
+    }
+
+    #[test]
+    fn hover_floating() {
+        let (mut editor, screen) = editor(Some(("hover.rs", RUST)));
+        let hover = "# add_one\n\nAdds *one* to `x`, see [docs](https://docs.rs).\n\n```rust\nfn add_one(x: u32) -> u32\n```";
+        editor
+            .document
+            .show_floating(markdown::floating(hover, 30).with_anchor(Position { x: 3, y: 1 }));
+        editor.cursor_position = Position { x: 3, y: 1 };
+        assert_frame("hover_floating", &editor, &screen);
+    }
+
+    #[test]
+    fn hover_plain_text() {
+        let (mut editor, screen) = editor(Some(("hover_plain.rs", RUST)));
+        let hover = Hover {
+            contents: HoverContents::Array(vec![
+                MarkedString::String(String::from("fn add_one(x: u32) -> u32")),
+                MarkedString::String(String::from("Adds *one* to x_y.")),
+            ]),
+            range: None,
+        };
+        editor.document.show_hover(hover, 3, 1, 30, 8);
+        editor.cursor_position = Position { x: 3, y: 1 };
+        assert_frame("hover_plain_text", &editor, &screen);
+    }
+
+    #[test]
+    fn hover_markup() {
+        let (mut editor, screen) = editor(Some(("hover_markup.rs", RUST)));
+        let hover = Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: String::from("```rust\nfn add_one(x: u32) -> u32\n```\n\nAdds *one*."),
+            }),
+            range: None,
+        };
+        editor.document.show_hover(hover, 3, 1, 30, 8);
+        editor.cursor_position = Position { x: 3, y: 1 };
+        assert_frame("hover_markup", &editor, &screen);
+    }
+
+    #[test]
+    fn reloaded_runtime() {
+        fs::create_dir_all("target/snap/config/neonano/queries/go").unwrap();
+        let query = "(function_declaration) @function\n(no_such_node) @keyword\n";
+        let name = "config/neonano/queries/go/highlights.scm";
+        let (mut editor, screen) = editor(Some((name, query)));
+        editor.run_command(Command::ReloadRuntime);
+        assert_frame("reloaded_runtime", &editor, &screen);
+        fs::remove_dir_all("target/snap/config/neonano/queries/go").unwrap();
+    }
+
+    #[test]
+    fn undo_redo() {
+        let (mut editor, screen) = editor(Some(("undo.txt", "one\ntwo\n")));
+        editor.cursor_position = Position { x: 3, y: 0 };
+        for c in " 1".chars() {
+            editor.process_keypress(Key::Char(c));
+        }
+        editor.cursor_position = Position { x: 0, y: 1 };
+        editor.process_keypress(Key::Backspace);
+        for key in [
+            Key::Ctrl('z'),
+            Key::Ctrl('z'),
+            Key::Ctrl('z'),
+            Key::Ctrl('y'),
+        ] {
+            editor.process_keypress(key);
+        }
+        assert_frame("undo_redo", &editor, &screen);
+        editor.process_keypress(Key::Ctrl('z'));
+        assert!(!editor.document.is_dirty());
+    }
+
+    #[test]
+    fn saved_atomically() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let (mut editor, _) = editor(Some(("atomic.sh", "echo one\n")));
+        let path = Path::new("target/snap/atomic.sh");
+        fs::set_permissions(path, fs::Permissions::from_mode(0o750)).unwrap();
+        let link = Path::new("target/snap/atomic-link.sh");
+        let _ = fs::remove_file(link);
+        symlink("atomic.sh", link).unwrap();
+        editor.document =
+            Document::open("target/snap/atomic-link.sh", Rc::clone(&editor.config)).unwrap();
+        editor.document.insert(&Position { x: 8, y: 0 }, '!');
+        editor.save();
+        assert_eq!(fs::read_to_string(path).unwrap(), "echo one!\n");
+        assert!(fs::symlink_metadata(link).unwrap().file_type().is_symlink());
+        let mode = fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+        let left = fs::read_dir("target/snap")
+            .unwrap()
+            .filter_map(Result::ok)
+            .any(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"));
+        assert!(!left);
+    }
+
+    #[test]
+    fn changed_before_save() {
+        let (mut editor, screen) = editor(Some(("clobber.txt", "mine\n")));
+        editor.document.insert(&Position { x: 4, y: 0 }, '!');
+        fs::write("target/snap/clobber.txt", "theirs\n").unwrap();
+        // Another time than when it was opened, however coarse the clock.
+        let past = SystemTime::now() - Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open("target/snap/clobber.txt")
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+        // No answer cancels.
+        editor.save();
+        assert_eq!(
+            fs::read_to_string("target/snap/clobber.txt").unwrap(),
+            "theirs\n"
+        );
+        assert_frame("changed_before_save", &editor, &screen);
+    }
+
+    #[test]
+    fn followed() {
+        let (mut editor, screen) = editor(Some(("follow.log", "one\ntwo")));
+        editor.run_command(Command::Follow);
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open("target/snap/follow.log")
+            .unwrap();
+        file.write_all(b" done\nthree\nfou").unwrap();
+        assert!(editor.poll_follow());
+        assert_frame("followed", &editor, &screen);
+        assert!(status_line(&editor, &screen).ends_with(" No filetype | following | 3/3"));
+    }
+
+    #[test]
+    fn read_only() {
+        let (mut editor, screen) = editor(Some(("read-only.txt", "keep\n")));
+        editor.run_command(Command::ReadOnly);
+        editor.process_keypress(Key::Char('x'));
+        editor.run_command(Command::ToggleComment);
+        assert_frame("read_only", &editor, &screen);
+        assert!(status_line(&editor, &screen).ends_with(" No filetype | read-only | 1/1"));
+        assert!(!editor.document.is_dirty());
+    }
+
+    #[test]
+    fn narrow_refused_read_only() {
+        let (mut editor, _) = editor(Some(("narrow-read-only.txt", "keep\n")));
+        editor.run_command(Command::ReadOnly);
+        // Widening would write the edits of the narrowed view back.
+        editor.run_command(Command::Narrow);
+        assert!(editor.narrowing.is_none());
+        assert_eq!(
+            editor.status_message.text,
+            "Read-only; `read-only` turns editing on"
+        );
+    }
+
+    #[test]
+    fn binary() {
+        let (mut editor, screen) = editor(None);
+        let bytes = b"ELF\0\x01\n\xff\xfe text\n";
+        fs::create_dir_all("target/snap").unwrap();
+        fs::write("target/snap/binary.bin", bytes).unwrap();
+        editor.open_file(Some("target/snap/binary.bin".to_string()));
+        assert!(editor.document.is_read_only());
+        editor.save();
+        assert_frame("binary", &editor, &screen);
+        assert!(status_line(&editor, &screen).ends_with(" No filetype | binary | 1/2"));
+        assert_eq!(fs::read("target/snap/binary.bin").unwrap(), bytes);
+    }
+
+    #[test]
+    fn hex() {
+        let (mut editor, screen) = editor(None);
+        fs::create_dir_all("target/snap").unwrap();
+        fs::write("target/snap/hex.bin", b"ELF\0\x01\n\xff\xfe text\n").unwrap();
+        editor.open_file(Some("target/snap/hex.bin".to_string()));
+        editor.run_command(Command::Hex);
+        for key in ['4', '1', '\t', 'Z', '!'] {
+            editor.process_keypress(Key::Char(key));
+        }
+        editor.process_keypress(Key::Ctrl('s'));
+        assert_frame("hex", &editor, &screen);
+        assert!(status_line(&editor, &screen).ends_with(" No filetype | hex | 3/14"));
+        assert_eq!(
+            fs::read("target/snap/hex.bin").unwrap(),
+            b"AZ!\0\x01\n\xff\xfe text\n"
+        );
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        let (mut editor, _) = editor(Some(("unterminated.txt", "one\ntwo")));
+        editor.document.insert(&Position { x: 3, y: 0 }, '!');
+        editor.save();
+        assert_eq!(
+            fs::read_to_string("target/snap/unterminated.txt").unwrap(),
+            "one!\ntwo"
+        );
+        let config = toml::from_str("save_steps = [\"final_newline\"]").unwrap();
+        let (mut editor, _) = editor_with(config, Some(("unterminated.txt", "one\ntwo")));
+        editor.save();
+        assert_eq!(
+            fs::read_to_string("target/snap/unterminated.txt").unwrap(),
+            "one\ntwo\n"
+        );
+    }
+
+    #[test]
+    fn line_endings() {
+        let (mut editor, screen) = editor(Some(("crlf.txt", "one\r\ntwo\r\n")));
+        editor.document.insert(&Position { x: 3, y: 1 }, '!');
+        editor.save();
+        assert_eq!(
+            fs::read_to_string("target/snap/crlf.txt").unwrap(),
+            "one\r\ntwo!\r\n"
+        );
+        assert_frame("line_endings", &editor, &screen);
+        assert!(status_line(&editor, &screen).ends_with(" No filetype | CRLF | 1/2"));
+        editor.run_command(Command::parse("line-ending lf").unwrap());
+        editor.save();
+        assert_eq!(
+            fs::read_to_string("target/snap/crlf.txt").unwrap(),
+            "one\ntwo!\n"
+        );
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let (mut editor, screen) = editor(Some(("bom.txt", "\u{feff}text\n")));
+        assert_frame("byte_order_mark", &editor, &screen);
+        assert!(status_line(&editor, &screen).ends_with(" No filetype | BOM | 1/1"));
+        editor.run_command(Command::ToggleBom);
+        editor.save();
+        assert_eq!(fs::read_to_string("target/snap/bom.txt").unwrap(), "text\n");
+    }
+
+    #[test]
+    fn buffers() {
+        fs::create_dir_all("target/snap").unwrap();
+        fs::write("target/snap/second.txt", "second\nfile\n").unwrap();
+        let (mut editor, screen) = editor(Some(("first.txt", "first\nfile\n")));
+        editor.cursor_position = Position { x: 2, y: 1 };
+        editor.run_command(Command::Open(Some("target/snap/second.txt".to_string())));
+        editor.cursor_position = Position { x: 0, y: 1 };
+        editor.process_keypress(Key::Char('>'));
+        editor.process_keypress(CTRL_PAGE_DOWN);
+        assert_frame("buffers_first", &editor, &screen);
+        editor.process_keypress(CTRL_PAGE_UP);
+        assert_frame("buffers_second", &editor, &screen);
+    }
+
+    #[test]
+    fn saved_as_and_renamed() {
+        for name in ["copy.txt", "saved.txt", "renamed.rs"] {
+            let _ = fs::remove_file(format!("target/snap/{name}"));
+        }
+        let (mut editor, screen) = editor(Some(("original.txt", "fn main() {}\n")));
+        editor.process_keypress(Key::Char('/'));
+        editor.run_command(Command::SaveCopy(Some("target/snap/copy.txt".to_string())));
+        assert_eq!(
+            fs::read_to_string("target/snap/copy.txt").unwrap(),
+            "/fn main() {}\n"
+        );
+        assert!(editor.document.is_dirty());
+        let missing = "target/snap/missing/saved.txt".to_string();
+        editor.run_command(Command::SaveAs(Some(missing)));
+        assert_eq!(
+            editor.document.file_name.as_deref(),
+            Some("target/snap/original.txt")
+        );
+        assert!(editor.document.is_dirty());
+        editor.run_command(Command::SaveAs(Some("target/snap/saved.txt".to_string())));
+        assert_eq!(
+            fs::read_to_string("target/snap/saved.txt").unwrap(),
+            "/fn main() {}\n"
+        );
+        assert_eq!(
+            fs::read_to_string("target/snap/original.txt").unwrap(),
+            "fn main() {}\n"
+        );
+        assert!(!editor.document.is_dirty());
+        editor.run_command(Command::Rename(Some("target/snap/renamed.rs".to_string())));
+        assert!(!Path::new("target/snap/saved.txt").exists());
+        wait_for_highlight(&mut editor);
+        assert_frame("renamed", &editor, &screen);
+    }
+
+    #[test]
+    fn saved_as() {
+        let _ = fs::remove_file("target/snap/saved-as.txt");
+        let (mut editor, _) = editor(Some(("save-as.txt", "one\n")));
+        editor.process_keypress(Key::Char('1'));
+        let missing = "target/snap/missing/saved-as.txt".to_string();
+        editor.run_command(Command::SaveAs(Some(missing)));
+        assert_eq!(
+            editor.document.file_name.as_deref(),
+            Some("target/snap/save-as.txt")
+        );
+        assert!(editor.document.is_dirty());
+        editor.run_command(Command::SaveAs(Some(
+            "target/snap/saved-as.txt".to_string(),
+        )));
+        assert_eq!(
+            editor.document.file_name.as_deref(),
+            Some("target/snap/saved-as.txt")
+        );
+        assert!(!editor.document.is_dirty());
+        assert_eq!(
+            fs::read_to_string("target/snap/saved-as.txt").unwrap(),
+            "1one\n"
+        );
+        assert_eq!(
+            fs::read_to_string("target/snap/save-as.txt").unwrap(),
+            "one\n"
+        );
+    }
+
+    #[test]
+    fn sorted_lines() {
+        let (mut editor, screen) = editor(Some(("sort.txt", "b\n10\na\n2\nb\n")));
+        let numeric = Order {
+            numeric: true,
+            ..Order::default()
+        };
+        editor.run_command(Command::SortLines(numeric));
+        editor.document.end_undo_step();
+        editor.run_command(Command::RemoveDuplicateLines);
+        assert_frame("sorted_lines", &editor, &screen);
+        editor.undo();
+        assert_eq!(editor.document.text(), "a\nb\nb\n2\n10");
+    }
+
+    #[test]
+    fn counted() {
+        let config = toml::from_str("word_count = true").unwrap();
+        let text = "one two\nthree 🇯🇵 four\n";
+        let (mut editor, screen) = editor_with(config, Some(("count.txt", text)));
+        editor.run_command(Command::Count);
+        assert_frame("counted", &editor, &screen);
+        assert!(status_line(&editor, &screen).ends_with(" No filetype | 5 words | 1/2"));
+        editor
+            .selection
+            .push((Position { x: 4, y: 0 }, Position { x: 7, y: 1 }));
+        editor.run_command(Command::Count);
+        assert_eq!(
+            editor.status_message.text,
+            "Selection: 2 lines, 3 words, 11 characters, 18 bytes"
+        );
+    }
+
+    #[test]
+    fn marks() {
+        fs::create_dir_all("target/snap").unwrap();
+        fs::write("target/snap/marked.txt", "other\nfile\n").unwrap();
+        let (mut editor, screen) = editor(Some(("marks.txt", "one\ntwo\nthree\n")));
+        editor.cursor_position = Position { x: 2, y: 2 };
+        editor.run_command(Command::Mark("a".to_string()));
+        editor.cursor_position = Position::default();
+        editor.process_keypress(Key::Char('\n'));
+        editor.run_command(Command::Open(Some("target/snap/marked.txt".to_string())));
+        editor.cursor_position = Position { x: 0, y: 1 };
+        editor.run_command(Command::Mark("b".to_string()));
+        editor.run_command(Command::GotoMark("a".to_string()));
+        assert!(editor.cursor_position == Position { x: 2, y: 3 });
+        editor.run_command(Command::GotoMark("b".to_string()));
+        assert_frame("marks", &editor, &screen);
+    }
+
+    #[test]
+    fn narrowed() {
+        let text = "let a = 1;\nprint(a);\nlet b = 2;\nprint(b);\nlet c = 3;\n";
+        let (mut editor, screen) = editor(Some(("narrow.txt", text)));
+        editor.start_narrowing();
+        editor.narrow_to("let");
+        editor.cursor_position = Position { x: 10, y: 0 };
+        for c in " // one\nlet d = 4;".chars() {
+            editor.process_keypress(Key::Char(c));
+        }
+        editor.cursor_position = Position { x: 0, y: 2 };
+        editor.process_keypress(Key::Char('/'));
+        assert_frame("narrowed", &editor, &screen);
+        assert!(status_line(&editor, &screen).ends_with(" No filetype | narrowed | 3/4"));
+        editor.widen();
+        assert_frame("widened", &editor, &screen);
+    }
+
+    #[test]
+    fn hover_scrolled_vertically() {
+        let (mut editor, screen) = editor(Some(("hover_scrolled.rs", RUST)));
+        let hover = "# add_one\n\nFirst.\n\nSecond.\n\nThird.\n\nFourth.\n\nFifth.";
+        let max_height = editor.hover_max_height();
+        editor.document.show_floating(
+            markdown::floating(hover, 30)
+                .with_max_height(max_height)
+                .with_anchor(Position { x: 3, y: 1 }),
+        );
+        editor.cursor_position = Position { x: 3, y: 1 };
+        for key in [CTRL_DOWN, CTRL_DOWN, CTRL_DOWN, CTRL_UP] {
+            editor.process_keypress(key);
+        }
+        assert_frame("hover_scrolled_vertically", &editor, &screen);
+    }
+
+    #[test]
+    fn edit_preview_scrolled() {
+        let text: String = (1..=20).map(|n| format!("line {n}\n")).collect();
+        let (mut editor, screen) = editor(Some(("preview.txt", &text)));
+        let edits: Vec<TextEdit> = [0, 10, 19]
+            .into_iter()
+            .map(|line| TextEdit {
+                range: lsp_types::Range {
+                    start: lsp_types::Position { line, character: 0 },
+                    end: lsp_types::Position { line, character: 4 },
+                },
+                new_text: String::from("row"),
+            })
+            .collect();
+        editor
+            .terminal
+            .queue_keys(&[Key::Down, Key::Down, Key::PageDown, Key::Up]);
+        editor.apply_edits_with_preview("formatting", &edits);
+        assert_snapshot("edit_preview_scrolled", &screen.borrow().frame());
+        assert_eq!(editor.status_message.text, "formatting discarded");
+        editor
+            .terminal
+            .queue_keys(&[Key::PageDown, Key::Char('y'), Key::Char('\n')]);
+        editor.apply_edits_with_preview("formatting", &edits);
+        let rows: Vec<&str> = [0, 10, 19]
+            .into_iter()
+            .filter_map(|y| editor.document.row(y).map(Row::as_str))
+            .collect();
+        assert_eq!(rows, ["row 1", "row 11", "row 20"]);
+    }
+
+    #[test]
+    fn completion_menu() {
+        let text = "alpha alphabet alpine\nal\n";
+        let (mut editor, screen) = editor(Some(("complete.txt", text)));
+        editor.cursor_position = Position { x: 2, y: 1 };
+        editor.menu = editor
+            .completion
+            .complete(&mut editor.document, &editor.cursor_position);
+        assert_frame("completion_menu", &editor, &screen);
+    }
+
+    #[test]
+    fn snippet_completed() {
+        let config =
+            toml::from_str("[filetype.rust.snippets]\nprintln = 'println!(\"${1:message}\");$0'")
+                .unwrap();
+        let text = "fn main() {\n    pri\n}\n";
+        let (mut editor, screen) = editor_with(config, Some(("snippet.rs", text)));
+        editor.cursor_position = Position { x: 7, y: 1 };
+        editor.complete();
+        assert_frame("snippet_menu", &editor, &screen);
+        editor.process_keypress(Key::Char('\t'));
+        for c in "hi".chars() {
+            editor.process_keypress(Key::Char(c));
+        }
+        assert_eq!(
+            editor.document.text(),
+            "fn main() {\n    println!(\"hi\");\n}"
+        );
+    }
 }
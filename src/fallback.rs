@@ -0,0 +1,508 @@
+use std::ops::Range;
+
+use crate::highlighting::{Span, Type};
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+const GO_KEYWORDS: &[&str] = &[
+    "break",
+    "case",
+    "chan",
+    "const",
+    "continue",
+    "default",
+    "defer",
+    "else",
+    "fallthrough",
+    "false",
+    "for",
+    "func",
+    "go",
+    "goto",
+    "if",
+    "import",
+    "interface",
+    "map",
+    "nil",
+    "package",
+    "range",
+    "return",
+    "select",
+    "struct",
+    "switch",
+    "true",
+    "type",
+    "var",
+];
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register", "return",
+    "short", "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned",
+    "void", "volatile", "while", "NULL",
+];
+const CPP_KEYWORDS: &[&str] = &[
+    "auto",
+    "bool",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "const",
+    "constexpr",
+    "continue",
+    "default",
+    "delete",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "explicit",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "friend",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "namespace",
+    "new",
+    "noexcept",
+    "nullptr",
+    "operator",
+    "override",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "typename",
+    "union",
+    "unsigned",
+    "using",
+    "virtual",
+    "void",
+    "volatile",
+    "while",
+];
+const PYTHON_KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+const JAVASCRIPT_KEYWORDS: &[&str] = &[
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "from",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "let",
+    "new",
+    "null",
+    "of",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "yield",
+];
+const TYPESCRIPT_KEYWORDS: &[&str] = &[
+    "abstract",
+    "any",
+    "as",
+    "async",
+    "await",
+    "boolean",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "declare",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "from",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "keyof",
+    "let",
+    "namespace",
+    "never",
+    "new",
+    "null",
+    "number",
+    "of",
+    "private",
+    "protected",
+    "public",
+    "readonly",
+    "return",
+    "static",
+    "string",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "type",
+    "typeof",
+    "undefined",
+    "unknown",
+    "var",
+    "void",
+    "while",
+    "yield",
+];
+const BASH_KEYWORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in",
+    "local", "readonly", "return", "select", "then", "until", "while",
+];
+const LUA_KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+const MAKE_KEYWORDS: &[&str] = &[
+    "define", "else", "endef", "endif", "export", "ifdef", "ifeq", "ifndef", "ifneq", "include",
+    "override", "unexport", "vpath",
+];
+const DATA_KEYWORDS: &[&str] = &["true", "false", "null"];
+
+/// What the highlighter that stands in for missing grammars and broken
+/// tree-sitter queries knows of a language: its keywords, comments and quotes. Numbers are the
+/// same everywhere. Markdown gets its headings, emphasis and code on top.
+pub struct Syntax {
+    keywords: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Quotes around strings, which end with their line.
+    quotes: &'static [char],
+    /// Whether `'` quotes a single character, so that a lone one, like a
+    /// Rust lifetime, starts no string.
+    char_literals: bool,
+    /// Whether the text is Markdown.
+    markup: bool,
+}
+
+impl Syntax {
+    /// The syntax of the language whose queries are in the directory
+    /// `language`, or comments, quotes and numbers as most languages write
+    /// them if it is not a built-in one.
+    pub fn of(language: &str) -> Self {
+        let c_like = |keywords| Self {
+            keywords,
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\''],
+            char_literals: true,
+            markup: false,
+        };
+        match language {
+            "rust" => c_like(RUST_KEYWORDS),
+            "go" => Self {
+                quotes: &['"', '\'', '`'],
+                ..c_like(GO_KEYWORDS)
+            },
+            "c" => c_like(C_KEYWORDS),
+            "cpp" => c_like(CPP_KEYWORDS),
+            "javascript" => Self {
+                quotes: &['"', '\'', '`'],
+                char_literals: false,
+                ..c_like(JAVASCRIPT_KEYWORDS)
+            },
+            "typescript" | "tsx" => Self {
+                quotes: &['"', '\'', '`'],
+                char_literals: false,
+                ..c_like(TYPESCRIPT_KEYWORDS)
+            },
+            "css" => Self {
+                line_comments: &[],
+                char_literals: false,
+                ..c_like(&[])
+            },
+            "json" => Self {
+                quotes: &['"'],
+                char_literals: false,
+                ..c_like(DATA_KEYWORDS)
+            },
+            "toml" | "yaml" => Self {
+                keywords: DATA_KEYWORDS,
+                line_comments: &["#"],
+                block_comment: None,
+                quotes: &['"', '\''],
+                char_literals: false,
+                markup: false,
+            },
+            "bash" => Self {
+                keywords: BASH_KEYWORDS,
+                line_comments: &["#"],
+                block_comment: None,
+                quotes: &['"', '\''],
+                char_literals: false,
+                markup: false,
+            },
+            "make" => Self {
+                keywords: MAKE_KEYWORDS,
+                line_comments: &["#"],
+                block_comment: None,
+                quotes: &['"', '\''],
+                char_literals: false,
+                markup: false,
+            },
+            "lua" => Self {
+                keywords: LUA_KEYWORDS,
+                line_comments: &["--"],
+                block_comment: Some(("--[[", "]]")),
+                quotes: &['"', '\''],
+                char_literals: false,
+                markup: false,
+            },
+            "html" | "xml" | "markdown" => Self {
+                keywords: &[],
+                line_comments: &[],
+                block_comment: Some(("<!--", "-->")),
+                quotes: if language == "markdown" {
+                    &[]
+                } else {
+                    &['"', '\'']
+                },
+                char_literals: false,
+                markup: language == "markdown",
+            },
+            "python" => Self {
+                keywords: PYTHON_KEYWORDS,
+                line_comments: &["#"],
+                block_comment: None,
+                quotes: &['"', '\''],
+                char_literals: false,
+                markup: false,
+            },
+            _ => Self {
+                keywords: &[],
+                line_comments: &["//", "#"],
+                block_comment: Some(("/*", "*/")),
+                quotes: &['"'],
+                char_literals: false,
+                markup: false,
+            },
+        }
+    }
+
+    /// Paints the keywords, strings, comments and numbers of the lines in
+    /// `rows` into `types`. The lines before them are read for block
+    /// comments that run into them.
+    pub fn paint(&self, lines: &[&str], rows: &Range<usize>, types: &mut [Vec<Type>]) {
+        let mut in_comment = false;
+        for (row, line) in lines.iter().enumerate().take(rows.end) {
+            let mut tokens = Vec::new();
+            in_comment = self.tokenize(line, in_comment, &mut tokens);
+            if row < rows.start {
+                continue;
+            }
+            let line_types = &mut types[row - rows.start];
+            for (range, token_type) in tokens {
+                for byte_type in &mut line_types[range] {
+                    *byte_type = token_type.clone();
+                }
+            }
+        }
+    }
+
+    /// Pushes the tokens of `line` to `tokens`, starting within a block
+    /// comment if `in_comment`. Returns whether the line ends within one.
+    fn tokenize(&self, line: &str, mut in_comment: bool, tokens: &mut Vec<Span>) -> bool {
+        let mut index = 0;
+        while index < line.len() {
+            let rest = &line[index..];
+            if in_comment {
+                let close = self.block_comment.map_or("", |(_, close)| close);
+                let end = match rest.find(close) {
+                    Some(end) => {
+                        in_comment = false;
+                        index + end + close.len()
+                    }
+                    None => line.len(),
+                };
+                tokens.push((index..end, Type::Comment));
+                index = end;
+                continue;
+            }
+            // Block comments first, as Lua's start like its line comments.
+            if let Some((open, _)) = self
+                .block_comment
+                .filter(|(open, _)| rest.starts_with(open))
+            {
+                tokens.push((index..index + open.len(), Type::Comment));
+                index += open.len();
+                in_comment = true;
+                continue;
+            }
+            if self
+                .line_comments
+                .iter()
+                .any(|comment| rest.starts_with(comment))
+            {
+                tokens.push((index..line.len(), Type::Comment));
+                break;
+            }
+            if self.markup && index == 0 && is_heading(line) {
+                tokens.push((0..line.len(), Type::MarkupHeading));
+                break;
+            }
+            let Some(c) = rest.chars().next() else {
+                break;
+            };
+            let after_word = line[..index]
+                .chars()
+                .next_back()
+                .is_some_and(char::is_alphanumeric);
+            if let Some((len, markup_type)) =
+                self.markup.then(|| markup(rest, after_word)).flatten()
+            {
+                tokens.push((index..index + len, markup_type));
+                index += len;
+                continue;
+            }
+            let len = if let Some(len) = self.string(rest, c) {
+                tokens.push((index..index + len, Type::String));
+                len
+            } else if c.is_ascii_digit() {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '.')
+                    .unwrap_or(rest.len());
+                tokens.push((index..index + len, Type::Number));
+                len
+            } else if c.is_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                if self.keywords.contains(&&rest[..len]) {
+                    tokens.push((index..index + len, Type::Keyword));
+                }
+                len
+            } else {
+                c.len_utf8()
+            };
+            index += len;
+        }
+        in_comment
+    }
+
+    /// The length of the string that `rest`, starting with `c`, starts
+    /// with, up to the end of the line if it is not closed.
+    fn string(&self, rest: &str, c: char) -> Option<usize> {
+        if !self.quotes.contains(&c) {
+            return None;
+        }
+        if c == '\'' && self.char_literals {
+            let mut chars = rest[1..].chars();
+            return match chars.next()? {
+                '\\' => Some(rest.get(3..)?.find('\'')? + 4),
+                next => (chars.next()? == '\'').then_some(next.len_utf8() + 2),
+            };
+        }
+        let mut escaped = false;
+        let end = rest[1..].char_indices().find_map(|(index, next)| {
+            let end = (next == c && !escaped).then_some(index + 2);
+            escaped = next == '\\' && !escaped;
+            end
+        });
+        Some(end.unwrap_or(rest.len()))
+    }
+}
+
+/// Whether `line` is a Markdown heading: one to six `#` and a space.
+fn is_heading(line: &str) -> bool {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    (1..=6).contains(&level) && line[level..].chars().next().is_none_or(|c| c == ' ')
+}
+
+/// The length and type of the Markdown span `rest` starts with: inline
+/// code, or text between `**`, `__`, `~~`, `*` or `_` closed on the same
+/// line. `after_word` tells whether a letter or digit comes before it.
+fn markup(rest: &str, after_word: bool) -> Option<(usize, Type)> {
+    if rest.starts_with('`') {
+        let ticks = rest.chars().take_while(|c| *c == '`').count();
+        let end = rest[ticks..].find(&rest[..ticks])?;
+        return Some((end + 2 * ticks, Type::MarkupRaw));
+    }
+    let (marker, markup_type) = [
+        ("**", Type::MarkupBold),
+        ("__", Type::MarkupBold),
+        ("~~", Type::MarkupStrikethrough),
+        ("*", Type::MarkupItalic),
+        ("_", Type::MarkupItalic),
+    ]
+    .into_iter()
+    .find(|(marker, _)| rest.starts_with(marker))?;
+    // `snake_case` is not emphasis.
+    if marker.starts_with('_') && after_word {
+        return None;
+    }
+    let inner = &rest[marker.len()..];
+    if inner.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let end = inner.find(marker).filter(|end| *end > 0)?;
+    Some((end + 2 * marker.len(), markup_type))
+}
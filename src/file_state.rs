@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config::data_dir;
+use crate::{Document, Position};
+
+const FILE_STATE_FILE_NAME: &str = "file_state.toml";
+
+/// Where the user left off in a file, restored when it is opened again.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FileState {
+    pub x: usize,
+    pub y: usize,
+    pub offset_x: usize,
+    pub offset_y: usize,
+    /// The closed folds.
+    pub folds: Vec<Range<usize>>,
+}
+
+impl FileState {
+    pub fn new(document: &Document, cursor: &Position, offset: &Position) -> Self {
+        Self {
+            x: cursor.x,
+            y: cursor.y,
+            offset_x: offset.x,
+            offset_y: offset.y,
+            folds: document.folds().iter().cloned().collect(),
+        }
+    }
+
+    pub fn cursor(&self) -> Position {
+        Position {
+            x: self.x,
+            y: self.y,
+        }
+    }
+
+    pub fn offset(&self) -> Position {
+        Position {
+            x: self.offset_x,
+            y: self.offset_y,
+        }
+    }
+
+    /// The remembered state of `file_name`, keyed by its canonical path.
+    pub fn load(file_name: &str) -> Option<Self> {
+        let key = key(file_name)?;
+        FileStateStore::load().files.remove(&key)
+    }
+
+    pub fn save(&self, file_name: &str) -> Result<()> {
+        let Some(key) = key(file_name) else {
+            return Ok(());
+        };
+        let mut store = FileStateStore::load();
+        store.files.insert(key, self.clone());
+        store.save()
+    }
+}
+
+fn key(file_name: &str) -> Option<String> {
+    Path::new(file_name)
+        .canonicalize()
+        .ok()
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct FileStateStore {
+    files: HashMap<String, FileState>,
+}
+
+impl FileStateStore {
+    fn path() -> Option<PathBuf> {
+        data_dir().map(|dir| dir.join(FILE_STATE_FILE_NAME))
+    }
+
+    fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = Self::path() {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(path, toml::to_string(self)?)?;
+        }
+        Ok(())
+    }
+}
@@ -1,25 +1,492 @@
-use tree_sitter::Language;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use lsp_types::DiagnosticSeverity;
+use serde::Deserialize;
+use tree_sitter::{Language, Query};
+
+use crate::check::Problem;
+use crate::config::{config_dir, Config, GrammarConfig};
+use crate::grammar;
+use crate::indent;
+use crate::locals;
+use crate::save::Step;
+use crate::textobject;
+
+/// Directory of the config directory with query overrides by language,
+/// e.g. `queries/rust/highlights.scm`.
+const QUERIES_DIR_NAME: &str = "queries";
+
+/// `queries/injections.scm` of tree-sitter-cpp, which the crate does not
+/// export: raw strings are highlighted in the language named by their
+/// delimiter, e.g. `R"py(...)py"`.
+const CPP_INJECTIONS_QUERY: &str = r#"(raw_string_literal
+  delimiter: (raw_string_delimiter) @injection.language
+  (raw_string_content) @injection.content)"#;
+
+/// The compiled-in grammar of a language and its queries.
+struct Queries {
+    language: fn() -> Language,
+    highlights: &'static str,
+    injections: Option<&'static str>,
+    textobjects: Option<&'static str>,
+    indents: Option<&'static str>,
+    locals: Option<&'static str>,
+}
+
+/// Where the grammar of a language of the registry comes from.
+enum Grammar {
+    Builtin(Queries),
+    /// `grammars/<name>.so` of the config directory if it was built, with
+    /// the queries of `queries/<name>`; until then the basic highlighter
+    /// colors the files.
+    Runtime,
+}
+
+/// A language of the registry.
+struct Definition {
+    /// Names its directory of query overrides and its runtime grammar.
+    name: &'static str,
+    /// Shown in the status bar.
+    title: &'static str,
+    language_id: &'static str,
+    extensions: &'static [&'static str],
+    /// Whole file names, for files without an extension.
+    file_names: &'static [&'static str],
+    /// Other names code fences and `set filetype` may use.
+    aliases: &'static [&'static str],
+    /// Programs, without their version, the `#!` line of scripts without
+    /// an extension runs them with, e.g. `python` for
+    /// `#!/usr/bin/env python3`.
+    interpreters: &'static [&'static str],
+    /// The suggested language server and its arguments.
+    server: Option<(&'static str, &'static [&'static str])>,
+    settings: Settings,
+    grammar: Grammar,
+}
+
+/// How the files of a language are edited, as the registry or a
+/// `[filetype.<name>]` table of the config sets it; what is not set is
+/// left to the file.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// The columns a tab is drawn in.
+    pub tab_width: Option<usize>,
+    /// The columns of one level of indentation.
+    pub indent_width: Option<usize>,
+    /// Whether to indent by spaces rather than tabs.
+    pub expand_tabs: Option<bool>,
+    /// A comment around `%s`, e.g. `// %s` or `<!-- %s -->`.
+    pub comment: Option<Cow<'static, str>>,
+    /// Text past this column is marked.
+    pub max_line_length: Option<usize>,
+    /// What the buffer goes through before it is saved, in place of the
+    /// `save_steps` of the config.
+    pub save_steps: Option<Vec<Step>>,
+    /// Bodies in the LSP snippet syntax by the word completing to them.
+    pub snippets: Option<BTreeMap<String, String>>,
+}
+
+impl Settings {
+    const NONE: Self = Self {
+        tab_width: None,
+        indent_width: None,
+        expand_tabs: None,
+        comment: None,
+        max_line_length: None,
+        save_steps: None,
+        snippets: None,
+    };
+
+    /// `self` with what `other` sets.
+    pub fn patch(&self, other: &Self) -> Self {
+        Self {
+            tab_width: other.tab_width.or(self.tab_width),
+            indent_width: other.indent_width.or(self.indent_width),
+            expand_tabs: other.expand_tabs.or(self.expand_tabs),
+            comment: other.comment.clone().or_else(|| self.comment.clone()),
+            max_line_length: other.max_line_length.or(self.max_line_length),
+            save_steps: other.save_steps.clone().or_else(|| self.save_steps.clone()),
+            snippets: other.snippets.clone().or_else(|| self.snippets.clone()),
+        }
+    }
+
+    /// The text before and after `%s` of the comment, e.g. `("/*", "*/")`.
+    pub fn comment_tokens(&self) -> Option<(&str, &str)> {
+        let (open, close) = self.comment.as_deref()?.split_once("%s")?;
+        Some((open.trim(), close.trim())).filter(|(open, _)| !open.is_empty())
+    }
+
+    /// The indentation of one level, if the settings decide it.
+    pub fn indent_unit(&self) -> Option<String> {
+        if self.expand_tabs == Some(false) {
+            return Some(String::from("\t"));
+        }
+        let width = self
+            .indent_width
+            .or(self.tab_width.filter(|_| self.expand_tabs == Some(true)))?;
+        Some(" ".repeat(width))
+    }
+}
+
+const fn commented(comment: &'static str) -> Settings {
+    Settings {
+        comment: Some(Cow::Borrowed(comment)),
+        ..Settings::NONE
+    }
+}
+
+const fn spaces(width: usize, comment: &'static str, max_line_length: Option<usize>) -> Settings {
+    Settings {
+        tab_width: None,
+        indent_width: Some(width),
+        expand_tabs: Some(true),
+        comment: Some(Cow::Borrowed(comment)),
+        max_line_length,
+        save_steps: None,
+        snippets: None,
+    }
+}
+
+const fn tabs(width: usize, comment: &'static str) -> Settings {
+    Settings {
+        tab_width: Some(width),
+        indent_width: None,
+        expand_tabs: Some(false),
+        comment: Some(Cow::Borrowed(comment)),
+        max_line_length: None,
+        save_steps: None,
+        snippets: None,
+    }
+}
+
+const LANGUAGES: &[Definition] = &[
+    Definition {
+        name: "rust",
+        title: "Rust",
+        language_id: "rust",
+        extensions: &["rs"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("rust-analyzer", &[])),
+        settings: spaces(4, "// %s", Some(100)),
+        grammar: Grammar::Builtin(Queries {
+            language: tree_sitter_rust::language,
+            highlights: tree_sitter_rust::HIGHLIGHT_QUERY,
+            injections: Some(tree_sitter_rust::INJECTIONS_QUERY),
+            textobjects: Some(textobject::RUST_QUERY),
+            indents: Some(indent::RUST_QUERY),
+            locals: Some(locals::RUST_QUERY),
+        }),
+    },
+    Definition {
+        name: "go",
+        title: "Go",
+        language_id: "go",
+        extensions: &["go"],
+        file_names: &[],
+        aliases: &["golang"],
+        interpreters: &[],
+        server: Some(("gopls", &[])),
+        settings: tabs(4, "// %s"),
+        grammar: Grammar::Builtin(Queries {
+            language: tree_sitter_go::language,
+            highlights: tree_sitter_go::HIGHLIGHT_QUERY,
+            injections: None,
+            textobjects: Some(textobject::GO_QUERY),
+            indents: Some(indent::GO_QUERY),
+            locals: Some(locals::GO_QUERY),
+        }),
+    },
+    Definition {
+        name: "cpp",
+        title: "Cpp",
+        language_id: "cpp",
+        extensions: &["cpp", "cc", "cxx", "hpp", "hh", "hxx"],
+        file_names: &[],
+        aliases: &["c++"],
+        interpreters: &[],
+        server: Some(("clangd", &[])),
+        settings: commented("// %s"),
+        grammar: Grammar::Builtin(Queries {
+            language: tree_sitter_cpp::language,
+            highlights: tree_sitter_cpp::HIGHLIGHT_QUERY,
+            injections: Some(CPP_INJECTIONS_QUERY),
+            textobjects: Some(textobject::CPP_QUERY),
+            indents: Some(indent::CPP_QUERY),
+            locals: Some(locals::CPP_QUERY),
+        }),
+    },
+    Definition {
+        name: "c",
+        title: "C",
+        language_id: "c",
+        extensions: &["c", "h"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("clangd", &[])),
+        settings: commented("/* %s */"),
+        grammar: Grammar::Builtin(Queries {
+            language: tree_sitter_c::language,
+            highlights: tree_sitter_c::HIGHLIGHT_QUERY,
+            injections: None,
+            textobjects: Some(textobject::C_QUERY),
+            indents: Some(indent::C_QUERY),
+            locals: Some(locals::C_QUERY),
+        }),
+    },
+    Definition {
+        name: "python",
+        title: "Python",
+        language_id: "python",
+        extensions: &["py", "pyi"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &["python", "pypy"],
+        server: Some(("pyright", &["--stdio"])),
+        settings: spaces(4, "# %s", Some(79)),
+        grammar: Grammar::Builtin(Queries {
+            language: tree_sitter_python::language,
+            highlights: tree_sitter_python::HIGHLIGHT_QUERY,
+            injections: None,
+            textobjects: Some(textobject::PYTHON_QUERY),
+            indents: Some(indent::PYTHON_QUERY),
+            locals: Some(locals::PYTHON_QUERY),
+        }),
+    },
+    Definition {
+        name: "javascript",
+        title: "JavaScript",
+        language_id: "javascript",
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        file_names: &[],
+        aliases: &["node"],
+        interpreters: &["node", "nodejs", "bun"],
+        server: Some(("typescript-language-server", &["--stdio"])),
+        settings: spaces(2, "// %s", None),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "typescript",
+        title: "TypeScript",
+        language_id: "typescript",
+        extensions: &["ts", "mts", "cts"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &["ts-node", "tsx", "deno"],
+        server: Some(("typescript-language-server", &["--stdio"])),
+        settings: spaces(2, "// %s", None),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "tsx",
+        title: "TSX",
+        language_id: "typescriptreact",
+        extensions: &["tsx"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("typescript-language-server", &["--stdio"])),
+        settings: spaces(2, "// %s", None),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "json",
+        title: "JSON",
+        language_id: "json",
+        extensions: &["json", "jsonc"],
+        file_names: &[".prettierrc", ".eslintrc"],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("vscode-json-language-server", &["--stdio"])),
+        settings: Settings {
+            indent_width: Some(2),
+            expand_tabs: Some(true),
+            ..Settings::NONE
+        },
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "toml",
+        title: "TOML",
+        language_id: "toml",
+        extensions: &["toml"],
+        file_names: &["Cargo.lock"],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("taplo", &["lsp", "stdio"])),
+        settings: commented("# %s"),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "yaml",
+        title: "YAML",
+        language_id: "yaml",
+        extensions: &["yaml", "yml"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("yaml-language-server", &["--stdio"])),
+        settings: spaces(2, "# %s", None),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "markdown",
+        title: "Markdown",
+        language_id: "markdown",
+        extensions: &["md", "markdown"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("marksman", &["server"])),
+        settings: commented("<!-- %s -->"),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "html",
+        title: "HTML",
+        language_id: "html",
+        extensions: &["html", "htm"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("vscode-html-language-server", &["--stdio"])),
+        settings: spaces(2, "<!-- %s -->", None),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "xml",
+        title: "XML",
+        language_id: "xml",
+        extensions: &["xml", "xsd", "xsl", "xslt", "svg", "plist"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("lemminx", &[])),
+        settings: spaces(2, "<!-- %s -->", None),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "css",
+        title: "CSS",
+        language_id: "css",
+        extensions: &["css"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &[],
+        server: Some(("vscode-css-language-server", &["--stdio"])),
+        settings: spaces(2, "/* %s */", None),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "bash",
+        title: "Bash",
+        language_id: "shellscript",
+        extensions: &["sh", "bash"],
+        file_names: &[".bashrc", ".bash_profile", ".profile", "PKGBUILD"],
+        aliases: &["shell", "zsh"],
+        interpreters: &["sh", "bash", "zsh", "dash", "ksh"],
+        server: Some(("bash-language-server", &["start"])),
+        settings: commented("# %s"),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "lua",
+        title: "Lua",
+        language_id: "lua",
+        extensions: &["lua"],
+        file_names: &[],
+        aliases: &[],
+        interpreters: &["lua", "luajit"],
+        server: Some(("lua-language-server", &[])),
+        settings: spaces(2, "-- %s", None),
+        grammar: Grammar::Runtime,
+    },
+    Definition {
+        name: "make",
+        title: "Makefile",
+        language_id: "makefile",
+        extensions: &["mk"],
+        file_names: &["Makefile", "makefile", "GNUmakefile"],
+        aliases: &["makefile"],
+        interpreters: &["make"],
+        server: None,
+        // Recipes must start with a tab.
+        settings: tabs(8, "# %s"),
+        grammar: Grammar::Runtime,
+    },
+];
 
 pub struct FileType {
     name: String,
-    lsp_name: Option<&'static str>,
-    lsp_args: Option<Vec<&'static str>>,
+    language_id: String,
+    lsp_name: Option<String>,
+    lsp_args: Vec<String>,
     hl_opts: HighlightingOptions,
+    settings: Settings,
 }
 
 #[derive(Default, Clone)]
 pub struct HighlightingOptions {
+    /// Names the directory of the query overrides.
+    language: String,
     hl_query: Option<&'static str>,
     inj_query: Option<&'static str>,
+    textobject_query: Option<&'static str>,
+    indent_query: Option<&'static str>,
+    locals_query: Option<&'static str>,
     lang: Option<Language>,
 }
 
 impl HighlightingOptions {
-    pub fn get_hl_query(&self) -> Option<&str> {
-        self.hl_query
+    /// `highlights.scm` of the query overrides if there is one, else the
+    /// compiled-in query.
+    pub fn get_hl_query(&self) -> Option<Cow<'static, str>> {
+        self.query("highlights.scm", self.hl_query)
     }
-    pub fn get_inj_query(&self) -> Option<&str> {
-        self.inj_query
+    /// `injections.scm` of the query overrides if there is one, else the
+    /// compiled-in query.
+    pub fn get_inj_query(&self) -> Option<Cow<'static, str>> {
+        self.query("injections.scm", self.inj_query)
+    }
+    /// `textobjects.scm` of the query overrides if there is one, else the
+    /// compiled-in query.
+    pub fn get_textobject_query(&self) -> Option<Cow<'static, str>> {
+        self.query("textobjects.scm", self.textobject_query)
+    }
+    /// `indents.scm` of the query overrides if there is one, else the
+    /// compiled-in query.
+    pub fn get_indent_query(&self) -> Option<Cow<'static, str>> {
+        self.query("indents.scm", self.indent_query)
+    }
+    /// `locals.scm` of the query overrides if there is one, else the
+    /// compiled-in query.
+    pub fn get_locals_query(&self) -> Option<Cow<'static, str>> {
+        self.query("locals.scm", self.locals_query)
+    }
+    fn query(&self, file_name: &str, builtin: Option<&'static str>) -> Option<Cow<'static, str>> {
+        if self.language.is_empty() {
+            return builtin.map(Cow::Borrowed);
+        }
+        let path = config_dir()?
+            .join(QUERIES_DIR_NAME)
+            .join(&self.language)
+            .join(file_name);
+        match fs::read_to_string(path) {
+            Ok(query) => Some(Cow::Owned(query)),
+            Err(_) => builtin.map(Cow::Borrowed),
+        }
+    }
+    /// The name of the directory of the query overrides, e.g. `rust`;
+    /// empty for queries that cannot be overridden.
+    pub fn get_language(&self) -> &str {
+        &self.language
     }
     pub fn get_lang(&self) -> Option<Language> {
         self.lang
@@ -30,9 +497,11 @@ impl Default for FileType {
     fn default() -> Self {
         Self {
             name: String::from("No filetype"),
+            language_id: String::from("plaintext"),
             lsp_name: None,
-            lsp_args: None,
+            lsp_args: Vec::new(),
             hl_opts: HighlightingOptions::default(),
+            settings: Settings::default(),
         }
     }
 }
@@ -42,74 +511,321 @@ impl FileType {
         self.name.clone()
     }
 
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
     pub fn highlighting_options(&self) -> HighlightingOptions {
         self.hl_opts.clone()
     }
 
+    pub fn language_id(&self) -> &str {
+        &self.language_id
+    }
+
     pub fn lsp_name(&self) -> Option<&str> {
-        self.lsp_name
-    }
-    pub fn lsp_args(&self) -> Option<Vec<&str>> {
-        self.lsp_args.clone()
-    }
-
-    pub fn from(file_name: &str) -> Option<Self> {
-        if file_name.ends_with(".rs") {
-            return Some(Self {
-                name: String::from("Rust"),
-                lsp_name: Some("rust-analyzer"),
-                lsp_args: None,
-                hl_opts: HighlightingOptions {
-                    hl_query: Some(tree_sitter_rust::HIGHLIGHT_QUERY),
-                    inj_query: Some(""),
-                    lang: Some(tree_sitter_rust::language()),
-                },
-            });
-        } else if file_name.ends_with(".go") {
-            return Some(Self {
-                name: String::from("Go"),
-                lsp_name: Some("gopls"),
-                lsp_args: None,
-                hl_opts: HighlightingOptions {
-                    hl_query: Some(tree_sitter_go::HIGHLIGHT_QUERY),
-                    inj_query: Some(""),
-                    lang: Some(tree_sitter_go::language()),
-                },
-            });
-        } else if file_name.ends_with(".cpp") {
-            return Some(Self {
-                name: String::from("Cpp"),
-                lsp_name: Some("clangd"),
-                lsp_args: None,
-                hl_opts: HighlightingOptions {
-                    hl_query: Some(tree_sitter_cpp::HIGHLIGHT_QUERY),
-                    inj_query: Some(""),
-                    lang: Some(tree_sitter_cpp::language()),
-                },
-            });
-        } else if file_name.ends_with(".c") {
-            return Some(Self {
-                name: String::from("C"),
-                lsp_name: Some("clangd"),
-                lsp_args: None,
-                hl_opts: HighlightingOptions {
-                    hl_query: Some(tree_sitter_c::HIGHLIGHT_QUERY),
-                    inj_query: Some(""),
-                    lang: Some(tree_sitter_c::language()),
-                },
-            });
-        } else if file_name.ends_with(".py") {
-            return Some(Self {
-                name: String::from("Python"),
-                lsp_name: Some("pyright"),
-                lsp_args: Some(vec!["--stdio"]),
-                hl_opts: HighlightingOptions {
-                    hl_query: Some(tree_sitter_python::HIGHLIGHT_QUERY),
-                    inj_query: Some(""),
-                    lang: Some(tree_sitter_python::language()),
-                },
-            });
+        self.lsp_name.as_deref()
+    }
+    pub fn lsp_args(&self) -> &[String] {
+        &self.lsp_args
+    }
+
+    /// Detects the file type of `file_name`, whose text starts with
+    /// `sample`, letting `[[grammar]]` and `[[language_server]]` entries
+    /// of the config register extensions, in place of the languages of the
+    /// registry, and the latter override the suggested server. The
+    /// `[filetype.<name>]` table of the config overrides its settings.
+    pub fn detect(file_name: &str, sample: &str, config: &Config) -> Option<Self> {
+        let mut file_type = Self::detect_language(file_name, sample, config)?;
+        let name = match file_type.hl_opts.get_language() {
+            "" => file_type.language_id.clone(),
+            language => language.to_string(),
+        };
+        if let Some(settings) = config.file_type_settings(&name) {
+            file_type.settings = file_type.settings.patch(settings);
+        }
+        Some(file_type)
+    }
+
+    fn detect_language(file_name: &str, sample: &str, config: &Config) -> Option<Self> {
+        let builtin = config
+            .grammar(file_name)
+            .map(Self::from_grammar)
+            .or_else(|| Self::from(file_name, sample));
+        let Some(server) = config.language_server(file_name) else {
+            return builtin;
+        };
+        let mut file_type = builtin.unwrap_or_else(|| Self {
+            name: server.language_id.clone(),
+            ..Self::default()
+        });
+        file_type.language_id = server.language_id.clone();
+        file_type.lsp_name = Some(server.command.clone());
+        file_type.lsp_args = server.args.clone();
+        Some(file_type)
+    }
+
+    /// The file type named by the info string of a markdown code fence,
+    /// e.g. `rust` or `py`, or by an injection. Names without a built-in
+    /// grammar are looked up in the `grammars` directory.
+    pub fn from_language(name: &str) -> Option<Self> {
+        if let Some(definition) = definition(name) {
+            return Some(Self::of(definition));
+        }
+        let name = name.to_lowercase();
+        let library = grammar::default_library(&name)?;
+        library.exists().then(|| {
+            Self::from_grammar(&GrammarConfig {
+                name,
+                extensions: Vec::new(),
+                library: Some(library),
+            })
+        })
+    }
+
+    /// The file type `set filetype=<name>` names: a built-in language, an
+    /// extension registered in the config, or a grammar. `none` is the
+    /// file type of plain text.
+    pub fn named(name: &str, config: &Config) -> Option<Self> {
+        if name == "none" {
+            return Some(Self::default());
+        }
+        let extension = builtin_extension(name).unwrap_or(name);
+        Self::detect(&format!("file.{extension}"), "", config).or_else(|| Self::from_language(name))
+    }
+
+    /// The file type of a grammar loaded at runtime. It is not highlighted
+    /// if the grammar cannot be loaded.
+    fn from_grammar(grammar: &GrammarConfig) -> Self {
+        let library = grammar
+            .library
+            .clone()
+            .or_else(|| grammar::default_library(&grammar.name));
+        Self {
+            name: grammar.name.clone(),
+            language_id: grammar.name.clone(),
+            hl_opts: HighlightingOptions {
+                language: grammar.name.clone(),
+                hl_query: None,
+                inj_query: None,
+                textobject_query: None,
+                indent_query: None,
+                locals_query: None,
+                lang: library.and_then(|library| grammar::load(&grammar.name, &library).ok()),
+            },
+            ..Self::default()
+        }
+    }
+
+    /// The file type of `file_name` by the languages of the registry,
+    /// matching its whole name first, then its extension, telling apart the
+    /// languages sharing it by `sample`, the start of the text, and then
+    /// the interpreter of a `#!` line or what the text starts with.
+    pub fn from(file_name: &str, sample: &str) -> Option<Self> {
+        let path = Path::new(file_name);
+        let name = path.file_name()?.to_str()?;
+        let extension = path.extension().and_then(OsStr::to_str);
+        LANGUAGES
+            .iter()
+            .find(|definition| definition.file_names.contains(&name))
+            .or_else(|| {
+                let extension = extension?;
+                AMBIGUOUS
+                    .iter()
+                    .find(|(shared, _, matches)| *shared == extension && matches(sample))
+                    .and_then(|(_, name, _)| definition(name))
+                    .or_else(|| {
+                        LANGUAGES
+                            .iter()
+                            .find(|definition| definition.extensions.contains(&extension))
+                    })
+            })
+            .or_else(|| {
+                let interpreter = interpreter(sample.lines().next()?)?;
+                LANGUAGES
+                    .iter()
+                    .find(|definition| definition.interpreters.contains(&interpreter))
+            })
+            .or_else(|| definition(by_content(sample)?))
+            .map(Self::of)
+    }
+
+    fn of(definition: &Definition) -> Self {
+        let name = definition.name;
+        let (lsp_name, lsp_args) = match definition.server {
+            Some((command, args)) => (
+                Some(command.to_string()),
+                args.iter().map(|arg| (*arg).to_string()).collect(),
+            ),
+            None => (None, Vec::new()),
+        };
+        let hl_opts = match &definition.grammar {
+            Grammar::Builtin(queries) => HighlightingOptions {
+                language: name.to_string(),
+                hl_query: Some(queries.highlights),
+                inj_query: queries.injections,
+                textobject_query: queries.textobjects,
+                indent_query: queries.indents,
+                locals_query: queries.locals,
+                lang: Some((queries.language)()),
+            },
+            Grammar::Runtime => HighlightingOptions {
+                language: name.to_string(),
+                lang: grammar::default_library(name)
+                    .filter(|library| library.exists())
+                    .and_then(|library| grammar::load(name, &library).ok()),
+                ..HighlightingOptions::default()
+            },
+        };
+        Self {
+            name: definition.title.to_string(),
+            language_id: definition.language_id.to_string(),
+            lsp_name,
+            lsp_args,
+            hl_opts,
+            settings: definition.settings.clone(),
         }
+    }
+}
+
+/// The languages of the registry by the names of their query directories,
+/// e.g. `rust`.
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    LANGUAGES.iter().map(|definition| definition.name)
+}
+
+/// The query overrides of the config directory that do not compile with
+/// the grammar of their language, as problems where the error is.
+pub fn query_problems() -> Vec<Problem> {
+    let Some(dir) = config_dir().map(|dir| dir.join(QUERIES_DIR_NAME)) else {
+        return Vec::new();
+    };
+    let mut languages: Vec<_> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    languages.sort();
+    let mut problems = Vec::new();
+    for language in languages {
+        let lang = language
+            .file_name()
+            .and_then(OsStr::to_str)
+            .and_then(FileType::from_language)
+            .and_then(|file_type| file_type.hl_opts.get_lang());
+        let Some(lang) = lang else {
+            continue;
+        };
+        let mut files: Vec<_> = fs::read_dir(&language)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension() == Some(OsStr::new("scm")))
+            .collect();
+        files.sort();
+        for path in files {
+            let Ok(query) = fs::read_to_string(&path) else {
+                continue;
+            };
+            if let Err(e) = Query::new(lang, &query) {
+                problems.push(Problem {
+                    path,
+                    line: e.row,
+                    column: e.column,
+                    severity: DiagnosticSeverity::ERROR,
+                    message: e.to_string().replace('\n', " "),
+                });
+            }
+        }
+    }
+    problems
+}
+
+/// Whether a sample of a file is written in a language.
+type Detector = fn(&str) -> bool;
+
+/// Extensions of one language of the registry that another one uses as
+/// well, that language and whether a sample of a file is written in it.
+const AMBIGUOUS: &[(&str, &str, Detector)] = &[("h", "cpp", is_cpp)];
+
+/// Whether the sample of a header declares classes, templates or
+/// namespaces, or uses the standard library of C++.
+fn is_cpp(sample: &str) -> bool {
+    sample.lines().any(|line| {
+        let line = line.trim_start();
+        [
+            "class ",
+            "template",
+            "namespace ",
+            "using ",
+            "public:",
+            "private:",
+        ]
+        .iter()
+        .any(|start| line.starts_with(start))
+            || line.contains("std::")
+    })
+}
+
+/// The language of a file of unknown name by how its text starts: the
+/// declaration of XML, the doctype of HTML, an object or array of JSON or
+/// the document marker of YAML.
+fn by_content(sample: &str) -> Option<&'static str> {
+    let text = sample.trim_start_matches('\u{feff}').trim_start();
+    let after = |prefix: char| text[prefix.len_utf8()..].trim_start().chars().next();
+    if text.starts_with("<?xml") {
+        Some("xml")
+    } else if text
+        .get(..14)
+        .is_some_and(|start| start.eq_ignore_ascii_case("<!doctype html"))
+        || text.starts_with("<html")
+    {
+        Some("html")
+    } else if text.starts_with('{') && matches!(after('{'), Some('"' | '}'))
+        || text.starts_with('[') && matches!(after('['), Some('{' | '[' | '"' | ']'))
+    {
+        Some("json")
+    } else if text.starts_with("%YAML") || text.lines().next() == Some("---") {
+        Some("yaml")
+    } else {
         None
     }
 }
+
+/// The program a `#!` line runs the script with, without the directory and
+/// a version: `python` for `#!/usr/bin/python3.11` and for
+/// `#!/usr/bin/env -S python3 -u`.
+fn interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // Past the options and variables of `env`.
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+    let unversioned = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    Some(if unversioned.is_empty() {
+        program
+    } else {
+        unversioned
+    })
+}
+
+/// The definition of the language `name`: its name, an alias or one of its
+/// extensions, in any case.
+fn definition(name: &str) -> Option<&'static Definition> {
+    let name = name.to_lowercase();
+    let name = name.as_str();
+    LANGUAGES.iter().find(|definition| {
+        definition.name == name
+            || definition.aliases.contains(&name)
+            || definition.extensions.contains(&name)
+    })
+}
+
+/// The extension of the language `name` of the registry, e.g. `rs` for
+/// `rust`.
+fn builtin_extension(name: &str) -> Option<&'static str> {
+    definition(name)?.extensions.first().copied()
+}
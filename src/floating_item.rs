@@ -1,17 +1,26 @@
 use termion::color;
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::row;
+use crate::theme::{self, Style};
 use crate::Position;
 
-const TXT_COLOR: color::Rgb = color::Rgb(76, 79, 105);
-
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct FloatingItem {
     pos: Position,
     width: usize,
     height: usize,
     msg: Vec<String>,
-    bg_color: (u8, u8, u8),
+    selected: Option<usize>,
+    line_colors: Vec<Option<color::Rgb>>,
+    styles: Vec<Vec<Style>>,
+    /// Document position the item belongs to. Anchored items are placed by
+    /// [`FloatingItem::placed`]; others stay at `pos` on the screen.
+    anchor: Option<Position>,
+    /// Columns the lines wider than the item are scrolled by.
+    scroll_x: usize,
+    /// Lines the item is scrolled down by, when it has more than it is high.
+    scroll_y: usize,
 }
 
 impl FloatingItem {
@@ -21,8 +30,100 @@ impl FloatingItem {
             width,
             height,
             msg: msg,
-            bg_color: (0, 0, 0),
+            selected: None,
+            line_colors: Vec::new(),
+            styles: Vec::new(),
+            anchor: None,
+            scroll_x: 0,
+            scroll_y: 0,
+        }
+    }
+
+    /// Attaches the item to the document position `anchor`, e.g. the
+    /// cursor, so it follows the text when scrolling.
+    pub fn with_anchor(mut self, anchor: Position) -> Self {
+        self.anchor = Some(anchor);
+        self
+    }
+
+    /// The item at its screen position for a viewport of `width` x `height`
+    /// scrolled to `offset`: below the anchor's line, or above it when
+    /// there is no room below, and shifted left to stay inside. `None`
+    /// while the anchor is scrolled out of view.
+    pub fn placed(&self, offset: &Position, width: usize, height: usize) -> Option<Self> {
+        let Some(anchor) = &self.anchor else {
+            return Some(self.clone());
+        };
+        if anchor.y < offset.y || anchor.y >= offset.y.saturating_add(height) {
+            return None;
         }
+        let line = anchor.y - offset.y;
+        let y = if line + 1 + self.height <= height || line < self.height {
+            line + 1
+        } else {
+            line - self.height
+        };
+        let x = anchor
+            .x
+            .saturating_sub(offset.x)
+            .min(width.saturating_sub(self.width));
+        let mut placed = self.clone();
+        placed.pos = Position { x, y };
+        Some(placed)
+    }
+
+    /// The placed item moved `columns` to the right, as far as it stays
+    /// inside `width`, if it follows the text, which starts after a gutter
+    /// of that width.
+    pub fn past_gutter(mut self, columns: usize, width: usize) -> Self {
+        if self.anchor.is_some() {
+            self.pos.x = (self.pos.x + columns).min(width.saturating_sub(self.width));
+        }
+        self
+    }
+
+    /// Scrolls the lines that do not fit by `delta` columns, stopping once
+    /// the widest one is shown up to its end.
+    pub fn scroll_horizontally(&mut self, delta: isize) {
+        let overflow = self
+            .msg
+            .iter()
+            .map(|line| line.graphemes(true).count())
+            .max()
+            .unwrap_or(0)
+            .saturating_sub(self.width);
+        self.scroll_x = self.scroll_x.saturating_add_signed(delta).min(overflow);
+    }
+
+    /// Shows at most `height` lines; the others are reached by
+    /// [`FloatingItem::scroll_vertically`].
+    pub fn with_max_height(mut self, height: usize) -> Self {
+        self.height = self.height.min(height);
+        self
+    }
+
+    /// Scrolls the lines by `delta`, stopping once the last one is shown.
+    pub fn scroll_vertically(&mut self, delta: isize) {
+        let overflow = self.msg.len().saturating_sub(self.height);
+        self.scroll_y = self.scroll_y.saturating_add_signed(delta).min(overflow);
+    }
+
+    /// Styles every grapheme of every line; missing entries use the default.
+    pub fn with_styles(mut self, styles: Vec<Vec<Style>>) -> Self {
+        self.styles = styles;
+        self
+    }
+
+    /// Overrides the text color of individual lines; `None` keeps the default.
+    pub fn with_line_colors(mut self, colors: Vec<Option<color::Rgb>>) -> Self {
+        self.line_colors = colors;
+        self
+    }
+
+    /// Highlights the line at `index`, e.g. the selected entry of a menu.
+    pub fn with_selected(mut self, index: usize) -> Self {
+        self.selected = Some(index);
+        self
     }
 
     pub fn get_pos(&self) -> &Position {
@@ -41,10 +142,6 @@ impl FloatingItem {
         self.msg.get(index)
     }
 
-    pub fn get_bg(&self) -> (u8, u8, u8) {
-        self.bg_color
-    }
-
     // TODO Direction
     pub fn render(&self, plain_row: &Vec<String>, drawing_y: usize) -> Vec<String> {
         if drawing_y < self.pos.y || self.pos.y.saturating_add(self.height) <= drawing_y {
@@ -52,62 +149,75 @@ impl FloatingItem {
         }
 
         let x = self.pos.x;
-        let y = self.pos.y;
-        let (r, g, b) = self.bg_color;
+        let line = drawing_y.saturating_sub(self.pos.y) + self.scroll_y;
+        let ui = &theme::current().ui;
+        let mut line_style = ui.floating;
+        if self.selected == Some(line) {
+            line_style = line_style.patch(ui.floating_selected);
+            if !theme::backgrounds() {
+                // The whole window is in reverse video.
+                line_style.bold = true;
+                line_style.underline = true;
+            }
+        }
+        if let Some(color) = self.line_colors.get(line).copied().flatten() {
+            line_style.fg = Some(color);
+        }
         let mut result: Vec<String> = plain_row.clone();
-        let floating_str = match self.msg.get(drawing_y.saturating_sub(y)) {
+        let floating_str = match self.msg.get(line) {
             Some(s) => String::from(s),
             None => String::new(),
         };
 
+        let line_styles = self.styles.get(line);
+        let style_at = |i: usize| {
+            line_styles
+                .and_then(|styles| styles.get(i))
+                .copied()
+                .unwrap_or_default()
+        };
+        let start_style = |style: Style| line_style.patch(style).escape();
+        let end_style = format!(
+            "{}{}{}",
+            termion::style::Reset,
+            color::Fg(color::Reset),
+            color::Bg(color::Reset)
+        );
+
+        let line_width = floating_str.graphemes(true).count();
+        let scroll = self.scroll_x.min(line_width.saturating_sub(self.width));
         let mut floating_vec = vec![];
-        let floating_str_len = floating_str.graphemes(true).count();
-        if self.width <= floating_str_len {
-            for (i, v) in floating_str.graphemes(true).enumerate() {
-                if i == 0 {
-                    floating_vec.push(format!(
-                        "{}{}{}",
-                        color::Fg(TXT_COLOR),
-                        color::Bg(color::Rgb(r, g, b)),
-                        v
-                    ));
-                } else if floating_str_len.saturating_sub(1) == i {
-                    floating_vec.push(format!(
-                        "{}{}{}",
-                        v,
-                        color::Fg(color::Reset),
-                        color::Bg(color::Reset)
-                    ));
-                } else {
-                    floating_vec.push(String::from(v));
-                }
-            }
-        } else {
-            for (i, v) in floating_str.graphemes(true).enumerate() {
-                if i == 0 {
-                    floating_vec.push(format!(
-                        "{}{}{}",
-                        color::Fg(TXT_COLOR),
-                        color::Bg(color::Rgb(r, g, b)),
-                        v
-                    ));
-                } else {
-                    floating_vec.push(String::from(v));
-                }
+        let mut current_style = None;
+        for (i, v) in floating_str
+            .graphemes(true)
+            .enumerate()
+            .skip(scroll)
+            .take(self.width)
+        {
+            let style = style_at(i);
+            let v = row::printable(v);
+            if current_style == Some(style) {
+                floating_vec.push(v.into_owned());
+            } else {
+                current_style = Some(style);
+                floating_vec.push(start_style(style) + &v);
             }
-            let padding_size = self.width.saturating_sub(floating_str_len);
-            for i in 0..padding_size {
-                if i == padding_size.saturating_sub(1) {
-                    floating_vec.push(format!(
-                        " {}{}",
-                        color::Fg(color::Reset),
-                        color::Bg(color::Reset)
-                    ));
-                } else {
-                    floating_vec.push(String::from(" "));
-                }
+        }
+        if floating_vec.is_empty() && self.width > 0 {
+            floating_vec.push(start_style(Style::default()) + " ");
+        } else if current_style.is_some_and(|style| style != Style::default()) {
+            // Padding is drawn without the attributes of the last span, and
+            // takes the columns the span leaves.
+            if let Some(last) = floating_vec.last_mut() {
+                last.push_str(&start_style(Style::default()));
             }
         }
+        while floating_vec.len() < self.width {
+            floating_vec.push(String::from(" "));
+        }
+        if let Some(last) = floating_vec.last_mut() {
+            last.push_str(&end_style);
+        }
 
         if x >= plain_row.len() {
             let padding_size = x.saturating_sub(plain_row.len().saturating_sub(1));
@@ -128,6 +238,11 @@ impl FloatingItem {
             for s in plain_row.iter().skip(x.saturating_add(self.width)) {
                 result.push(s.clone());
             }
+            if plain_row.len() <= x.saturating_add(self.width) {
+                // Rows end in an element that is not a column, which later
+                // floating items count on.
+                result.push(String::new());
+            }
         }
         result
     }
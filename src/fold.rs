@@ -0,0 +1,185 @@
+use std::ops::Range;
+
+/// Collapsed ranges of lines. The first line of a fold stays visible,
+/// followed by a summary of the rest, which is hidden.
+#[derive(Default)]
+pub struct Folds {
+    /// Sorted and disjoint.
+    folds: Vec<Range<usize>>,
+}
+
+impl Folds {
+    pub fn is_empty(&self) -> bool {
+        self.folds.is_empty()
+    }
+
+    /// The fold whose first line is `row`.
+    pub fn at(&self, row: usize) -> Option<&Range<usize>> {
+        self.folds.iter().find(|fold| fold.start == row)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Range<usize>> {
+        self.folds.iter()
+    }
+
+    pub fn contains(&self, rows: &Range<usize>) -> bool {
+        self.folds.contains(rows)
+    }
+
+    /// Collapses `rows`, which takes in the folds within it. Rows within a
+    /// fold or of a single line are left alone.
+    pub fn close(&mut self, rows: Range<usize>) {
+        if rows.len() < 2
+            || self
+                .folds
+                .iter()
+                .any(|fold| fold.start <= rows.start && rows.end <= fold.end)
+        {
+            return;
+        }
+        self.folds
+            .retain(|fold| fold.end <= rows.start || rows.end <= fold.start);
+        let index = self.folds.partition_point(|fold| fold.start < rows.start);
+        self.folds.insert(index, rows);
+    }
+
+    /// Expands the fold whose first line is `row`. Returns whether there
+    /// was one.
+    pub fn open(&mut self, row: usize) -> bool {
+        let len = self.folds.len();
+        self.folds.retain(|fold| fold.start != row);
+        self.folds.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.folds.clear();
+    }
+
+    /// Expands the fold hiding `row`, if any.
+    pub fn reveal(&mut self, row: usize) {
+        self.folds.retain(|fold| !hides(fold, row));
+    }
+
+    pub fn is_hidden(&self, row: usize) -> bool {
+        self.folds.iter().any(|fold| hides(fold, row))
+    }
+
+    /// The first visible line after `row`.
+    pub fn next_visible(&self, row: usize) -> usize {
+        self.at(row).map_or(row + 1, |fold| fold.end)
+    }
+
+    /// The last visible line before `row`, or 0.
+    pub fn previous_visible(&self, row: usize) -> usize {
+        let previous = row.saturating_sub(1);
+        self.folds
+            .iter()
+            .find(|fold| hides(fold, previous))
+            .map_or(previous, |fold| fold.start)
+    }
+
+    /// How many of the lines `rows` are hidden.
+    pub fn hidden_in(&self, rows: Range<usize>) -> usize {
+        self.folds
+            .iter()
+            .map(|fold| {
+                let start = rows.start.max(fold.start + 1);
+                let end = rows.end.min(fold.end);
+                end.saturating_sub(start)
+            })
+            .sum()
+    }
+
+    /// Follows an edit that replaced the lines `start..=old_end` with
+    /// `start..=new_end`: folds below it move, and those it touches other
+    /// than on their first line open.
+    pub fn edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+        self.folds.retain_mut(|fold| {
+            if old_end < fold.start {
+                let moved = |row: usize| (row + new_end).saturating_sub(old_end);
+                *fold = moved(fold.start)..moved(fold.end);
+                true
+            } else {
+                start >= fold.end || (start == fold.start && old_end == start && new_end == start)
+            }
+        });
+    }
+}
+
+fn hides(fold: &Range<usize>, row: usize) -> bool {
+    fold.start < row && row < fold.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folds(ranges: &[(usize, usize)]) -> Folds {
+        let mut folds = Folds::default();
+        for &(start, end) in ranges {
+            folds.close(start..end);
+        }
+        folds
+    }
+
+    fn ranges(folds: &Folds) -> Vec<(usize, usize)> {
+        folds.iter().map(|fold| (fold.start, fold.end)).collect()
+    }
+
+    #[test]
+    fn edit_above_moves() {
+        let mut folds = folds(&[(5, 9)]);
+        folds.edit(1, 1, 3);
+        assert_eq!(ranges(&folds), [(7, 11)]);
+        folds.edit(0, 2, 0);
+        assert_eq!(ranges(&folds), [(5, 9)]);
+    }
+
+    #[test]
+    fn edit_inside_opens() {
+        let mut folds = folds(&[(2, 6), (8, 10)]);
+        folds.edit(4, 4, 4);
+        assert_eq!(ranges(&folds), [(8, 10)]);
+    }
+
+    #[test]
+    fn edit_on_first_line() {
+        let mut folds = folds(&[(2, 6)]);
+        folds.edit(2, 2, 2);
+        assert_eq!(ranges(&folds), [(2, 6)]);
+        // Splitting the first line changes what the fold holds.
+        folds.edit(2, 2, 3);
+        assert!(folds.is_empty());
+    }
+
+    #[test]
+    fn edit_below_keeps() {
+        let mut folds = folds(&[(2, 6)]);
+        folds.edit(6, 6, 9);
+        assert_eq!(ranges(&folds), [(2, 6)]);
+    }
+
+    #[test]
+    fn nested_closes() {
+        let mut folds = folds(&[(3, 5), (7, 9)]);
+        folds.close(2..10);
+        assert_eq!(ranges(&folds), [(2, 10)]);
+        folds.close(3..5);
+        assert_eq!(ranges(&folds), [(2, 10)]);
+        folds.close(4..4);
+        assert_eq!(ranges(&folds), [(2, 10)]);
+    }
+
+    #[test]
+    fn hidden() {
+        let folds = folds(&[(2, 6), (8, 10)]);
+        assert_eq!(folds.hidden_in(0..20), 3 + 1);
+        assert_eq!(folds.hidden_in(4..10), 2 + 1);
+        assert_eq!(folds.hidden_in(6..8), 0);
+        assert!(!folds.is_hidden(2));
+        assert!(folds.is_hidden(5));
+        assert_eq!(folds.next_visible(2), 6);
+        assert_eq!(folds.previous_visible(6), 2);
+        assert_eq!(folds.previous_visible(0), 0);
+    }
+}
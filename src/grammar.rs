@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{anyhow, Result};
+use tree_sitter::{Language, LANGUAGE_VERSION, MIN_COMPATIBLE_LANGUAGE_VERSION};
+
+use crate::config::config_dir;
+
+/// Directory of the config directory with the grammars loaded at runtime.
+const GRAMMARS_DIR_NAME: &str = "grammars";
+#[cfg(target_os = "macos")]
+const LIBRARY_EXTENSION: &str = "dylib";
+#[cfg(not(target_os = "macos"))]
+const LIBRARY_EXTENSION: &str = "so";
+
+/// The grammars loaded so far by library, or why they could not be. The
+/// libraries are never unloaded, as the syntax trees of open files point
+/// into them.
+fn loaded() -> &'static Mutex<HashMap<PathBuf, Result<Language, String>>> {
+    static LOADED: OnceLock<Mutex<HashMap<PathBuf, Result<Language, String>>>> = OnceLock::new();
+    LOADED.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// `grammars/<name>.so` (`.dylib` on macOS) of the config directory.
+/// `None` for names that are not plain words, as they may come from the
+/// text, e.g. the delimiter of a C++ raw string, and must not reach other
+/// directories.
+pub fn default_library(name: &str) -> Option<PathBuf> {
+    let plain = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if name.is_empty() || !name.chars().all(plain) {
+        return None;
+    }
+    config_dir().map(|dir| {
+        dir.join(GRAMMARS_DIR_NAME)
+            .join(format!("{name}.{LIBRARY_EXTENSION}"))
+    })
+}
+
+/// Loads the grammar `name` from `library`, a tree-sitter parser built as
+/// a shared library exporting `tree_sitter_<name>`, e.g. with
+/// `cc -shared -fPIC -O2 -Isrc src/parser.c -o toml.so`.
+pub fn load(name: &str, library: &Path) -> Result<Language> {
+    let mut loaded = loaded()
+        .lock()
+        .map_err(|_| anyhow!("Grammars are poisoned"))?;
+    let language = loaded
+        .entry(library.to_path_buf())
+        .or_insert_with(|| open(name, library).map_err(|e| e.to_string()));
+    language.clone().map_err(|e| anyhow!(e))
+}
+
+fn open(name: &str, library: &Path) -> Result<Language> {
+    let path = CString::new(library.as_os_str().as_bytes())?;
+    let symbol = CString::new(format!("tree_sitter_{}", name.replace('-', "_")))?;
+    // SAFETY: the library is a tree-sitter parser the user installed; its
+    // constructor takes no arguments and returns a pointer to static data.
+    let language = unsafe {
+        let handle = libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if handle.is_null() {
+            return Err(anyhow!(
+                "Could not load {}: {}",
+                library.display(),
+                dl_error()
+            ));
+        }
+        let constructor = libc::dlsym(handle, symbol.as_ptr());
+        if constructor.is_null() {
+            return Err(anyhow!(
+                "{} has no {}",
+                library.display(),
+                symbol.to_string_lossy()
+            ));
+        }
+        let constructor: unsafe extern "C" fn() -> Language = std::mem::transmute(constructor);
+        constructor()
+    };
+    let version = language.version();
+    if !(MIN_COMPATIBLE_LANGUAGE_VERSION..=LANGUAGE_VERSION).contains(&version) {
+        return Err(anyhow!(
+            "{} has ABI version {version}, only {MIN_COMPATIBLE_LANGUAGE_VERSION} to {LANGUAGE_VERSION} are supported",
+            library.display()
+        ));
+    }
+    Ok(language)
+}
+
+/// The message of the last `dlopen` error.
+unsafe fn dl_error() -> String {
+    let error = libc::dlerror();
+    if error.is_null() {
+        return String::from("unknown error");
+    }
+    CStr::from_ptr(error).to_string_lossy().into_owned()
+}
@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::ignore::Ignore;
+
+/// An occurrence of the query of a project search.
+pub struct Match {
+    pub path: PathBuf,
+    pub line: usize,
+    /// In graphemes.
+    pub column: usize,
+    pub text: String,
+}
+
+/// The files below `root`, skipping hidden files and directories and what
+/// the `.gitignore` files and `globs` ignore.
+pub fn files(root: &Path, globs: &[String]) -> Vec<PathBuf> {
+    let mut ignore = Ignore::new(root, globs);
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        if dir != root {
+            ignore.read_dir(&dir);
+        }
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() && !ignore.is_ignored(&path, true) => {
+                    dirs.push(path);
+                }
+                Ok(file_type) if file_type.is_file() && !ignore.is_ignored(&path, false) => {
+                    files.push(path);
+                }
+                _ => (),
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// The occurrences of `query` in `text`, the contents of `path`.
+pub fn search(path: &Path, text: &str, query: &str) -> Vec<Match> {
+    let mut matches = Vec::new();
+    if query.is_empty() {
+        return matches;
+    }
+    for (line, line_text) in text.lines().enumerate() {
+        for (byte_index, _) in line_text.match_indices(query) {
+            matches.push(Match {
+                path: path.to_path_buf(),
+                line,
+                column: line_text[..byte_index].graphemes(true).count(),
+                text: line_text.trim().to_string(),
+            });
+        }
+    }
+    matches
+}
+
+/// The contents of `path` if it is a text file.
+pub fn read_text(path: &Path) -> Option<String> {
+    let text = fs::read_to_string(path).ok()?;
+    (!text.contains('\0')).then_some(text)
+}
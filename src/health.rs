@@ -0,0 +1,394 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::clipboard;
+use crate::config::{self, Config};
+use crate::filetype::{self, FileType};
+use crate::grammar;
+use crate::highlighting::Highlight;
+use crate::measure;
+use crate::spell;
+use crate::theme::{self, Colors};
+use crate::trash;
+use crate::workspace::{Trust, Workspace};
+use tree_sitter::Query;
+
+/// The built-in languages, by the names of their query directories.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Ok => "OK   ",
+            Self::Warning => "WARN ",
+            Self::Error => "ERROR",
+        }
+    }
+}
+
+/// A line of the report: a heading without a status, or the outcome of a
+/// check with what to do about it.
+pub struct Line {
+    pub status: Option<Status>,
+    pub text: String,
+}
+
+impl Line {
+    fn heading(text: &str) -> Self {
+        Self {
+            status: None,
+            text: text.to_string(),
+        }
+    }
+
+    fn check(status: Status, text: String) -> Self {
+        Self {
+            status: Some(status),
+            text,
+        }
+    }
+
+    /// The line with its status, broken at spaces into lines of at most
+    /// `width` columns that continue below the text.
+    pub fn wrapped(&self, width: usize) -> Vec<String> {
+        let Some(status) = self.status else {
+            return vec![self.text.clone()];
+        };
+        let prefix = format!("  {} ", status.label());
+        let indent = " ".repeat(prefix.len());
+        let mut lines = vec![prefix];
+        for word in self.text.split_whitespace() {
+            let Some(line) = lines.last_mut() else {
+                break;
+            };
+            let start = line.len() == indent.len();
+            if !start && measure::width(line) + 1 + measure::width(word) > width {
+                lines.push(format!("{indent}{word}"));
+            } else {
+                if !start {
+                    line.push(' ');
+                }
+                line.push_str(word);
+            }
+        }
+        lines
+    }
+}
+
+/// Checks what NeoNano depends on: the config files, the language servers,
+/// the clipboard, the colors of the terminal, the grammars and their
+/// queries, the dictionary, and the directories it keeps its state in.
+pub fn report(config: &Config, workspace: &Workspace, trust: Trust) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut section = |heading: &str, checks: Vec<Line>| {
+        if !lines.is_empty() {
+            lines.push(Line::heading(""));
+        }
+        lines.push(Line::heading(heading));
+        lines.extend(checks);
+    };
+    section("Config", configs(workspace, trust));
+    section("Language servers", language_servers(config));
+    section("Clipboard", vec![copy_program()]);
+    section("Colors", vec![colors(config)]);
+    section("Grammars", grammars(config));
+    section("Spelling", vec![spelling(config)]);
+    section("State directories", state_directories());
+    lines
+}
+
+fn configs(workspace: &Workspace, trust: Trust) -> Vec<Line> {
+    let mut checks = Vec::new();
+    match config::config_dir().map(|dir| dir.join(config::CONFIG_FILE_NAME)) {
+        Some(path) => checks.push(config_file(&path)),
+        None => checks.push(Line::check(
+            Status::Warning,
+            "No config directory; set $XDG_CONFIG_HOME or $HOME".to_string(),
+        )),
+    }
+    if let Some(path) = workspace.project_config() {
+        if trust == Trust::Trusted {
+            checks.push(config_file(&path));
+        } else {
+            checks.push(Line::check(
+                Status::Warning,
+                format!(
+                    "{} is ignored until the workspace is trusted",
+                    path.display()
+                ),
+            ));
+        }
+    }
+    checks
+}
+
+fn config_file(path: &Path) -> Line {
+    if !path.exists() {
+        return Line::check(
+            Status::Ok,
+            format!("{} does not exist, using the defaults", path.display()),
+        );
+    }
+    match Config::load_from(path) {
+        Ok(_) => Line::check(Status::Ok, format!("{} is valid", path.display())),
+        Err(e) => Line::check(
+            Status::Error,
+            format!("{}: {}", path.display(), e.to_string().replace('\n', " ")),
+        ),
+    }
+}
+
+/// The servers of the languages of the registry, unless the config replaces
+/// them, and those of the config.
+fn language_servers(config: &Config) -> Vec<Line> {
+    let mut checks = Vec::new();
+    let mut checked: Vec<String> = Vec::new();
+    for server in config.language_servers() {
+        let command = &server.command;
+        if checked.contains(command) {
+            continue;
+        }
+        checks.push(match find_program(command) {
+            Some(path) => Line::check(Status::Ok, format!("{command}: {}", path.display())),
+            None => Line::check(
+                Status::Warning,
+                format!(
+                    "{command} is not in $PATH; install it or point the `command` of its [[language_server]] entry at it"
+                ),
+            ),
+        });
+        checked.push(command.clone());
+    }
+    // Few edit every language of the registry, so the servers missing of
+    // those take a single line.
+    let mut missing = Vec::new();
+    for command in filetype::languages()
+        .filter_map(|name| FileType::named(name, config)?.lsp_name().map(str::to_owned))
+    {
+        if checked.contains(&command) {
+            continue;
+        }
+        match find_program(&command) {
+            Some(path) => checks.push(Line::check(
+                Status::Ok,
+                format!("{command}: {}", path.display()),
+            )),
+            None => missing.push(command.clone()),
+        }
+        checked.push(command);
+    }
+    if !missing.is_empty() {
+        checks.push(Line::check(
+            Status::Warning,
+            format!(
+                "Not in $PATH: {}; install those of the languages you edit, or point the `command` of a [[language_server]] entry at them",
+                missing.join(", ")
+            ),
+        ));
+    }
+    checks
+}
+
+fn copy_program() -> Line {
+    let programs = clipboard::programs();
+    match programs.iter().find(|program| find_program(program).is_some()) {
+        Some(program) => Line::check(Status::Ok, format!("Copying with {program}")),
+        None => Line::check(
+            Status::Warning,
+            format!(
+                "None of {} is installed; copying falls back to OSC 52, which the terminal has to allow",
+                programs.join(", ")
+            ),
+        ),
+    }
+}
+
+fn colors(config: &Config) -> Line {
+    if config.colors() != Colors::Auto {
+        return Line::check(
+            Status::Ok,
+            format!("colors = \"{}\", as configured", config.colors().name()),
+        );
+    }
+    match theme::detect_colors() {
+        Colors::Truecolor => Line::check(Status::Ok, "Truecolor".to_string()),
+        Colors::Monochrome => Line::check(
+            Status::Warning,
+            format!(
+                "No colors, as the terminfo entry of $TERM={} has none; set colors = \"16\" if the terminal draws them",
+                env::var("TERM").unwrap_or_default()
+            ),
+        ),
+        colors => Line::check(
+            Status::Warning,
+            format!(
+                "Drawing the theme in the nearest of {} colors, as $TERM is {}; set $COLORTERM=truecolor if the terminal draws more, or colors = \"{}\" if it draws fewer",
+                colors.name(),
+                env::var("TERM").unwrap_or_default(),
+                if colors == Colors::Palette256 { "16" } else { "monochrome" }
+            ),
+        ),
+    }
+}
+
+fn spelling(config: &Config) -> Line {
+    if !config.spell_check() {
+        return Line::check(
+            Status::Ok,
+            "Off; spell_check = true underlines misspelled words of comments and strings"
+                .to_string(),
+        );
+    }
+    match spell::find(config.dictionary()) {
+        Some(path) if path.exists() => Line::check(Status::Ok, format!("{}", path.display())),
+        Some(path) => Line::check(
+            Status::Error,
+            format!("{} does not exist; fix `dictionary`", path.display()),
+        ),
+        None => Line::check(
+            Status::Error,
+            "No en_US.dic in the dictionaries directory of the config or /usr/share/hunspell; install a hunspell dictionary or set `dictionary`".to_string(),
+        ),
+    }
+}
+
+/// Whether the queries of the languages of the registry compile, which of
+/// them lack their grammar, and whether the grammars of the config load.
+fn grammars(config: &Config) -> Vec<Line> {
+    let mut checks = Vec::new();
+    let mut missing = Vec::new();
+    for name in filetype::languages() {
+        let Some(file_type) = FileType::from_language(name) else {
+            continue;
+        };
+        let options = file_type.highlighting_options();
+        if options.get_lang().is_none() {
+            missing.push(name);
+            continue;
+        }
+        let mut errors: Vec<String> = Highlight::from_options(&options)
+            .and_then(|highlight| highlight.error().map(str::to_owned))
+            .into_iter()
+            .collect();
+        let queries = [
+            ("textobjects.scm", options.get_textobject_query()),
+            ("indents.scm", options.get_indent_query()),
+        ];
+        if let Some(lang) = options.get_lang() {
+            for (file_name, query) in queries {
+                if let Some(Err(e)) = query.map(|query| Query::new(lang, &query)) {
+                    errors.push(format!("{file_name}: {e}").replace('\n', " "));
+                }
+            }
+        }
+        checks.push(if errors.is_empty() {
+            Line::check(Status::Ok, format!("{name}: the queries compile"))
+        } else {
+            Line::check(
+                Status::Error,
+                format!(
+                    "{name}: {}; fix or remove the override in queries/{name}",
+                    errors.join("; ")
+                ),
+            )
+        });
+    }
+    if !missing.is_empty() {
+        checks.push(Line::check(
+            Status::Warning,
+            format!(
+                "{} get basic highlighting; build their grammars into grammars/<name>.so of the config directory",
+                missing.join(", ")
+            ),
+        ));
+    }
+    for entry in config.grammars() {
+        let name = &entry.name;
+        let library = entry
+            .library
+            .clone()
+            .or_else(|| grammar::default_library(name));
+        let Some(library) = library else {
+            checks.push(Line::check(
+                Status::Error,
+                format!("{name}: no library; set `library` of its [[grammar]] entry"),
+            ));
+            continue;
+        };
+        checks.push(match grammar::load(name, &library) {
+            Err(e) => Line::check(
+                Status::Error,
+                format!("{name}: {e}; build the grammar into {}", library.display()),
+            ),
+            Ok(_) => match FileType::from_language(name)
+                .map(|file_type| file_type.highlighting_options())
+                .filter(|options| options.get_hl_query().is_some())
+            {
+                None => Line::check(
+                    Status::Warning,
+                    format!("{name}: loaded, but without queries/{name}/highlights.scm its files get basic highlighting"),
+                ),
+                Some(options) => {
+                    match Highlight::from_options(&options).and_then(|highlight| highlight.error().map(str::to_owned)) {
+                        Some(e) => Line::check(Status::Error, format!("{name}: {e}")),
+                        None => Line::check(Status::Ok, format!("{name}: loaded")),
+                    }
+                }
+            },
+        });
+    }
+    checks
+}
+
+/// Whether the local history, the log, the workspace trust and the trash
+/// can be written.
+fn state_directories() -> Vec<Line> {
+    [config::data_dir(), trash::dir()]
+        .into_iter()
+        .map(|dir| match dir {
+            None => Line::check(
+                Status::Error,
+                "No data directory; set $XDG_DATA_HOME or $HOME".to_string(),
+            ),
+            Some(dir) => match writable(&dir) {
+                Ok(()) => Line::check(Status::Ok, format!("{} is writable", dir.display())),
+                Err(e) => Line::check(
+                    Status::Error,
+                    format!("{}: {e}; check its permissions", dir.display()),
+                ),
+            },
+        })
+        .collect()
+}
+
+/// Creates `dir` if needed and writes a file to it.
+fn writable(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".neonano-health");
+    fs::write(&probe, "")?;
+    fs::remove_file(probe)
+}
+
+/// Where `program` is run from: itself if it is a path, else the first
+/// executable of that name in `$PATH`.
+fn find_program(program: &str) -> Option<PathBuf> {
+    let executable = |path: &Path| {
+        fs::metadata(path)
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    };
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return executable(&path).then_some(path);
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| executable(path))
+}
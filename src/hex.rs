@@ -0,0 +1,205 @@
+/// Columns of the byte offset at the start of a line of the dump.
+const OFFSET_WIDTH: usize = 8;
+
+/// The bytes of a buffer while they are edited as a hex dump, and the byte
+/// the cursor is on. Bytes are overwritten, never inserted or deleted, so
+/// the offsets of the others stay as they are.
+pub struct Hex {
+    bytes: Vec<u8>,
+    cursor: usize,
+    /// Whether the cursor is in the characters at the end of the lines
+    /// instead of the hex digits.
+    text: bool,
+    /// Whether the next hex digit typed is the low half of the byte.
+    low: bool,
+}
+
+impl Hex {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            cursor: 0,
+            text: false,
+            low: false,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The lines of the dump with `per_line` bytes each.
+    pub fn lines(&self, per_line: usize) -> usize {
+        self.bytes.len().div_ceil(per_line).max(1)
+    }
+
+    /// Line `y` of the dump: the offset of its first byte, the bytes in
+    /// hex and as characters, `.` for those that are not printable ASCII.
+    pub fn line(&self, y: usize, per_line: usize) -> Option<String> {
+        if y >= self.lines(per_line) {
+            return None;
+        }
+        let start = y * per_line;
+        let bytes = &self.bytes[start..self.bytes.len().min(start + per_line)];
+        let digits: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+        let characters: String = bytes
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    char::from(byte)
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        Some(format!(
+            "{start:0OFFSET_WIDTH$x}  {:per_line$}  {characters}",
+            digits.join(" "),
+            per_line = per_line * 3 - 1
+        ))
+    }
+
+    /// The line and column of the dump the cursor is drawn at.
+    pub fn position(&self, per_line: usize) -> (usize, usize) {
+        let (y, x) = (self.cursor / per_line, self.cursor % per_line);
+        let column = if self.text {
+            OFFSET_WIDTH + 2 + per_line * 3 + 1 + x
+        } else {
+            OFFSET_WIDTH + 2 + x * 3 + usize::from(self.low)
+        };
+        (y, column)
+    }
+
+    /// Moves the cursor `by` bytes, as far as there are any.
+    pub fn move_by(&mut self, by: isize) {
+        let last = self.bytes.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(by).min(last);
+        self.low = false;
+    }
+
+    /// Moves the cursor to the first byte of its line, or its last.
+    pub fn move_in_line(&mut self, end: bool, per_line: usize) {
+        let start = self.cursor - self.cursor % per_line;
+        let to = if end { start + per_line - 1 } else { start };
+        self.cursor = to.min(self.bytes.len().saturating_sub(1));
+        self.low = false;
+    }
+
+    /// Moves the cursor between the hex digits and the characters.
+    pub fn switch_column(&mut self) {
+        self.text = !self.text;
+        self.low = false;
+    }
+
+    /// Overwrites the byte at the cursor with `c`: a hex digit sets one
+    /// half of it, or, among the characters, an ASCII character all of it.
+    /// Returns whether `c` was taken.
+    pub fn type_char(&mut self, c: char) -> bool {
+        let Some(byte) = self.bytes.get_mut(self.cursor) else {
+            return false;
+        };
+        if self.text {
+            if !c.is_ascii() {
+                return false;
+            }
+            *byte = c as u8;
+        } else {
+            let Some(digit) = c.to_digit(16) else {
+                return false;
+            };
+            let digit = digit as u8;
+            *byte = if self.low {
+                *byte & 0xf0 | digit
+            } else {
+                *byte & 0x0f | digit << 4
+            };
+            if !self.low {
+                self.low = true;
+                return true;
+            }
+        }
+        self.move_by(1);
+        true
+    }
+}
+
+/// The bytes on a line of the dump `width` columns wide: 16, or fewer to
+/// fit a narrow screen.
+pub fn per_line(width: usize) -> usize {
+    [16, 8]
+        .into_iter()
+        .find(|&bytes| OFFSET_WIDTH + 2 + bytes * 3 + 1 + bytes <= width)
+        .unwrap_or(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_lines() {
+        let hex = Hex::new(b"AB\0\xff".to_vec());
+        assert_eq!(hex.lines(4), 1);
+        assert_eq!(hex.line(0, 4).unwrap(), "00000000  41 42 00 ff  AB..");
+        assert_eq!(
+            hex.line(0, 8).unwrap(),
+            "00000000  41 42 00 ff              AB.."
+        );
+        assert!(hex.line(1, 4).is_none());
+        assert_eq!(Hex::new(Vec::new()).lines(16), 1);
+        assert_eq!(per_line(80), 16);
+        assert_eq!(per_line(50), 8);
+        assert_eq!(per_line(10), 4);
+    }
+
+    #[test]
+    fn nibbles() {
+        let mut hex = Hex::new(vec![0x00, 0x11]);
+        assert!(hex.type_char('a'));
+        assert_eq!(hex.position(16), (0, 11));
+        assert!(hex.type_char('B'));
+        assert!(!hex.type_char('g'));
+        assert_eq!(hex.bytes(), [0xab, 0x11]);
+        assert_eq!(hex.cursor(), 1);
+    }
+
+    #[test]
+    fn nibbles_at_end_of_file() {
+        let mut hex = Hex::new(vec![0x00, 0x11]);
+        hex.move_by(5);
+        assert_eq!(hex.cursor(), 1);
+        assert!(hex.type_char('2'));
+        assert!(hex.type_char('3'));
+        // The cursor stays on the last byte, at its high half again.
+        assert_eq!(hex.cursor(), 1);
+        assert_eq!(hex.position(16), (0, 13));
+        assert!(hex.type_char('4'));
+        assert_eq!(hex.into_bytes(), [0x00, 0x43]);
+        assert!(!Hex::new(Vec::new()).type_char('1'));
+    }
+
+    #[test]
+    fn characters() {
+        let mut hex = Hex::new(b"abcdef".to_vec());
+        hex.switch_column();
+        hex.move_in_line(true, 4);
+        assert_eq!(hex.cursor(), 3);
+        assert_eq!(hex.position(4), (0, 8 + 2 + 12 + 1 + 3));
+        assert!(hex.type_char('X'));
+        assert!(!hex.type_char('é'));
+        assert_eq!(hex.cursor(), 4);
+        hex.move_in_line(true, 4);
+        assert_eq!(hex.cursor(), 5);
+        hex.move_by(-10);
+        assert_eq!(hex.cursor(), 0);
+        assert_eq!(hex.into_bytes(), b"abcXef");
+    }
+}
@@ -1,8 +1,27 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
 use anyhow::{anyhow, Result};
-use termion::color;
-use tree_sitter::Language;
-use tree_sitter_highlight::{Error, HighlightConfiguration};
-use tree_sitter_highlight::{HighlightEvent, Highlighter};
+use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query, QueryCursor, Tree};
+
+use crate::fallback::Syntax;
+use crate::filetype::{FileType, HighlightingOptions};
+
+/// How deep injected languages nest, e.g. macros inside of macros.
+const MAX_INJECTION_DEPTH: usize = 4;
+/// The theme names of the colors of nested brackets, repeating.
+const RAINBOW: [&str; 6] = [
+    "rainbow.0",
+    "rainbow.1",
+    "rainbow.2",
+    "rainbow.3",
+    "rainbow.4",
+    "rainbow.5",
+];
+const BRACKETS: [&str; 6] = ["(", ")", "[", "]", "{", "}"];
 
 const HIGHLIGHTS: [(&str, Type); 52] = [
     ("attribute", Type::Attribute),
@@ -58,49 +77,856 @@ const HIGHLIGHTS: [(&str, Type); 52] = [
     ("variable.member", Type::VariableMember),
     ("variable.parameter", Type::VariableParameter),
 ];
+/// Highlights a buffer with a tree-sitter grammar. The syntax tree is kept
+/// between calls and updated with the edits made to the text, so only the
+/// lines whose syntax changed need to be highlighted again.
 pub struct Highlight {
-    highlighter: Highlighter,
-    config: HighlightConfiguration,
+    /// Without a language for the basic highlighter, which parses nothing.
+    parser: Parser,
+    /// `None` for the basic highlighter.
+    query: Option<Query>,
+    /// The `HIGHLIGHTS` type of every capture of `query`.
+    capture_types: Vec<Type>,
+    tree: Option<Tree>,
+    /// Lines touched by the edits since the last parse.
+    edited: Option<Range<usize>>,
+    /// Finds the parts written in another language, e.g. the token trees of
+    /// Rust macros.
+    injection_query: Option<Query>,
+    /// The highlighters of the injected languages by name, `None` for the
+    /// languages without a grammar.
+    injections: HashMap<String, Option<Highlight>>,
+    /// How many languages this one is injected into.
+    depth: usize,
+    /// Colors brackets by how deep they nest.
+    rainbow_brackets: bool,
+    /// Resolves names to their definitions, with the `Local` of every
+    /// capture.
+    locals: Option<(Query, Vec<Local>)>,
+    /// Colors keywords, strings, comments and numbers instead of the
+    /// queries, which did not compile.
+    fallback: Option<Syntax>,
+    /// Why the queries did not compile.
+    error: Option<String>,
+}
+
+/// What a capture of a locals query is.
+enum Local {
+    Scope,
+    /// Defines a name, whose references get the type if there is one.
+    Definition(Option<Type>),
+    Reference,
+    Other,
+}
+
+/// A scope of a locals query: the byte it ends at and the type of each
+/// name defined in it.
+type Scope = (usize, HashMap<Vec<u8>, Option<Type>>);
+
+impl Local {
+    fn of(name: &str) -> Self {
+        match name {
+            "local.scope" => Self::Scope,
+            "local.reference" => Self::Reference,
+            "local.definition" => Self::Definition(None),
+            name => match name.strip_prefix("local.definition.") {
+                Some(capture) => Self::Definition(Some(capture_type(capture))),
+                None => Self::Other,
+            },
+        }
+    }
 }
 
 impl Highlight {
-    pub fn new(lang: Language, hl_query: &str, inj_query: &str) -> Result<Self> {
-        let highlighter = Highlighter::new();
-        let config = HighlightConfiguration::new(lang, hl_query, inj_query, "");
-        if let Ok(mut config) = config {
-            config.configure(&HIGHLIGHTS.map(|x| x.0));
-            return Ok(Self {
-                highlighter,
-                config,
-            });
+    pub fn new(
+        lang: Language,
+        hl_query: &str,
+        inj_query: Option<&str>,
+        locals_query: Option<&str>,
+    ) -> Result<Self> {
+        let mut parser = Parser::new();
+        parser.set_language(lang)?;
+        let query = Query::new(lang, hl_query).map_err(|e| anyhow!("highlights.scm: {e}"))?;
+        let injection_query = match inj_query {
+            Some(inj_query) if !inj_query.is_empty() => {
+                Some(Query::new(lang, inj_query).map_err(|e| anyhow!("injections.scm: {e}"))?)
+            }
+            _ => None,
+        };
+        let locals = match locals_query {
+            Some(locals_query) if !locals_query.is_empty() => {
+                let query =
+                    Query::new(lang, locals_query).map_err(|e| anyhow!("locals.scm: {e}"))?;
+                let kinds = query
+                    .capture_names()
+                    .iter()
+                    .map(|name| Local::of(name))
+                    .collect();
+                Some((query, kinds))
+            }
+            _ => None,
+        };
+        let capture_types = query
+            .capture_names()
+            .iter()
+            .map(|name| capture_type(name))
+            .collect();
+        Ok(Self {
+            parser,
+            query: Some(query),
+            capture_types,
+            tree: None,
+            edited: None,
+            injection_query,
+            injections: HashMap::new(),
+            depth: 0,
+            rainbow_brackets: false,
+            locals,
+            fallback: None,
+            error: None,
+        })
+    }
+
+    /// Colors brackets by how deep they nest instead of as punctuation.
+    pub fn with_rainbow_brackets(mut self, enabled: bool) -> Self {
+        self.rainbow_brackets = enabled;
+        self
+    }
+
+    /// The highlighter for a file type. A basic one for keywords, strings,
+    /// comments and numbers stands in while its grammar or highlights are
+    /// not installed, and if its queries do not compile, telling why in
+    /// `error`. `None` for plain text.
+    pub fn from_options(options: &HighlightingOptions) -> Option<Self> {
+        let language = options.get_language();
+        let Some(lang) = options.get_lang() else {
+            return Self::basic(language);
+        };
+        let Some(hl_query) = options.get_hl_query() else {
+            return Self::parsing(lang, language);
+        };
+        let error = match Self::new(
+            lang,
+            &hl_query,
+            options.get_inj_query().as_deref(),
+            options.get_locals_query().as_deref(),
+        ) {
+            Ok(highlight) => return Some(highlight),
+            Err(error) => error,
+        };
+        let mut highlight = Self::parsing(lang, language)?;
+        highlight.error = Some(format!("{error}").replace('\n', " "));
+        Some(highlight)
+    }
+
+    /// The basic highlighter of the language whose queries are in the
+    /// directory `language`, parsing with `lang` for folds, indents and
+    /// selections.
+    fn parsing(lang: Language, language: &str) -> Option<Self> {
+        let mut highlight = Self::new(lang, "", None, None).ok()?;
+        highlight.fallback = Some(Syntax::of(language));
+        Some(highlight)
+    }
+
+    /// The basic highlighter of the language whose queries are in the
+    /// directory `language`, without a grammar; `None` for plain text.
+    fn basic(language: &str) -> Option<Self> {
+        if language.is_empty() {
+            return None;
         }
-        Err(anyhow!("Failed to initialize config"))
+        Some(Self {
+            parser: Parser::new(),
+            query: None,
+            capture_types: Vec::new(),
+            tree: None,
+            edited: None,
+            injection_query: None,
+            injections: HashMap::new(),
+            depth: 0,
+            rainbow_brackets: false,
+            locals: None,
+            fallback: Some(Syntax::of(language)),
+            error: None,
+        })
     }
 
-    pub fn highlight(&mut self, code: &[u8]) -> Result<Vec<Type>> {
-        let mut res: Vec<Type> = vec![];
-        let mut current_hl: Type = Type::None;
-        for event in self
-            .highlighter
-            .highlight(&self.config, code, None, |_| None)?
-        {
-            match event.unwrap() {
-                HighlightEvent::Source { start, end } => {
-                    if current_hl == Type::CarriageReturn {
-                        continue;
+    /// Why the queries did not compile, if this is the basic highlighter
+    /// standing in for them.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Applies an edit of the text to the syntax tree. Offsets count a
+    /// line break as one byte.
+    pub fn edit(&mut self, edit: &InputEdit) {
+        if let Some(tree) = self.tree.as_mut() {
+            tree.edit(edit);
+        }
+        let rows = edit.start_position.row..edit.new_end_position.row + 1;
+        self.edited = Some(union(self.edited.take(), rows));
+    }
+
+    /// Forgets the syntax tree, e.g. after the whole text was replaced.
+    pub fn reset(&mut self) {
+        self.tree = None;
+        self.edited = None;
+    }
+
+    /// Parses `lines`, reusing the tree of the previous parse, and returns
+    /// the lines that have to be highlighted again: those edited since and
+    /// those whose syntax changed.
+    pub fn parse(&mut self, lines: &[&str]) -> Range<usize> {
+        let old_tree = self.tree.take();
+        let Some(tree) = self.parser.parse_with(
+            &mut |_, point: Point| chunk(lines, point),
+            old_tree.as_ref(),
+        ) else {
+            return 0..lines.len();
+        };
+        let edited = self.edited.take();
+        let rows = match &old_tree {
+            Some(old_tree) => old_tree.changed_ranges(&tree).fold(edited, |rows, range| {
+                Some(union(rows, range.start_point.row..range.end_point.row + 1))
+            }),
+            None => Some(0..lines.len()),
+        };
+        self.tree = Some(tree);
+        rows.map_or(0..0, |rows| {
+            rows.start.min(lines.len())..rows.end.min(lines.len())
+        })
+    }
+
+    /// The spans of the lines in `rows`, as of the last parse.
+    pub fn highlight_lines(&mut self, lines: &[&str], rows: Range<usize>) -> Vec<Vec<Span>> {
+        let mut types: Vec<Vec<Type>> = lines[rows.clone()]
+            .iter()
+            .map(|line| vec![Type::None; line.len()])
+            .collect();
+        self.paint(lines, &rows, &mut types);
+        types.iter().map(|line| spans(line)).collect()
+    }
+
+    /// Paints the types of the bytes of the lines in `rows` into `types`,
+    /// then those of the languages injected into them on top and finally
+    /// the brackets, so they nest the same across languages.
+    fn paint(&mut self, lines: &[&str], rows: &Range<usize>, types: &mut [Vec<Type>]) {
+        if let Some(fallback) = &self.fallback {
+            fallback.paint(lines, rows, types);
+        }
+        self.paint_captures(lines, rows, types);
+        self.paint_locals(lines, rows, types);
+        for (language, ranges) in self.find_injections(lines, rows) {
+            let depth = self.depth + 1;
+            let injected = self
+                .injections
+                .entry(language)
+                .or_insert_with_key(|language| {
+                    let mut injected = FileType::from_language(language).and_then(|file_type| {
+                        Self::from_options(&file_type.highlighting_options())
+                    })?;
+                    injected.depth = depth;
+                    Some(injected)
+                });
+            if let Some(injected) = injected {
+                if injected.parse_ranges(lines, &ranges) {
+                    injected.paint(lines, rows, types);
+                }
+            }
+        }
+        if let (true, Some(tree)) = (self.rainbow_brackets, &self.tree) {
+            paint_brackets(tree.root_node(), 0, rows, types);
+        }
+    }
+
+    fn paint_captures(&self, lines: &[&str], rows: &Range<usize>, types: &mut [Vec<Type>]) {
+        let (Some(tree), Some(query)) = (&self.tree, &self.query) else {
+            return;
+        };
+        // Captures nest, so they are painted byte by byte.
+        let mut cursor = QueryCursor::new();
+        cursor.set_point_range(Point::new(rows.start, 0)..Point::new(rows.end, 0));
+        // A node captured by several patterns gets the type of the first.
+        let mut captured = HashSet::new();
+        let text = |node: Node| node_text(lines, node);
+        for (query_match, index) in cursor.captures(query, tree.root_node(), text) {
+            let capture = query_match.captures[index];
+            if !captured.insert(capture.node.id()) {
+                continue;
+            }
+            let capture_type = &self.capture_types[capture.index as usize];
+            if *capture_type == Type::None {
+                continue;
+            }
+            let (start, end) = (capture.node.start_position(), capture.node.end_position());
+            for row in start.row.max(rows.start)..=end.row.min(rows.end.saturating_sub(1)) {
+                let line = &mut types[row - rows.start];
+                let from = if row == start.row { start.column } else { 0 };
+                let to = if row == end.row {
+                    end.column
+                } else {
+                    line.len()
+                };
+                for byte_type in line.iter_mut().take(to).skip(from) {
+                    *byte_type = capture_type.clone();
+                }
+            }
+        }
+    }
+
+    /// Paints the references to local definitions, and the definitions, in
+    /// the type of the definition, over variables and uncaptured text.
+    fn paint_locals(&self, lines: &[&str], rows: &Range<usize>, types: &mut [Vec<Type>]) {
+        let (Some(tree), Some((query, kinds))) = (&self.tree, &self.locals) else {
+            return;
+        };
+        // Scopes reach outside of `rows`, e.g. to the parameters of the
+        // function, so the whole top-level nodes touching them are resolved.
+        let root = tree.root_node();
+        let (mut from, mut to) = (rows.start, rows.end);
+        let mut tree_cursor = root.walk();
+        for child in root.children(&mut tree_cursor) {
+            let (start, end) = (child.start_position().row, child.end_position().row + 1);
+            if start < rows.end && end > rows.start {
+                from = from.min(start);
+                to = to.max(end);
+            }
+        }
+        let mut paint = |node: Node, kind: &Type| {
+            let (start, end) = (node.start_position(), node.end_position());
+            if start.row != end.row || !rows.contains(&start.row) {
+                return;
+            }
+            let line = &mut types[start.row - rows.start];
+            for byte_type in line.iter_mut().take(end.column).skip(start.column) {
+                if matches!(byte_type, Type::None | Type::Variable) {
+                    *byte_type = kind.clone();
+                }
+            }
+        };
+        // The innermost scope is last, with the byte it ends at.
+        let mut scopes: Vec<Scope> = vec![(usize::MAX, HashMap::new())];
+        let mut definitions = HashSet::new();
+        let mut cursor = QueryCursor::new();
+        cursor.set_point_range(Point::new(from, 0)..Point::new(to, 0));
+        let text = |node: Node| node_text(lines, node);
+        for (query_match, index) in cursor.captures(query, root, text) {
+            let node = query_match.captures[index].node;
+            while scopes.len() > 1
+                && scopes
+                    .last()
+                    .is_some_and(|(end, _)| *end <= node.start_byte())
+            {
+                scopes.pop();
+            }
+            let name = || -> Vec<u8> { node_text(lines, node).flatten().copied().collect() };
+            match &kinds[query_match.captures[index].index as usize] {
+                Local::Scope => scopes.push((node.end_byte(), HashMap::new())),
+                Local::Definition(kind) => {
+                    definitions.insert(node.id());
+                    if let Some((_, names)) = scopes.last_mut() {
+                        names.insert(name(), kind.clone());
+                    }
+                    if let Some(kind) = kind {
+                        paint(node, kind);
                     }
-                    for _ in start..end {
-                        res.push(current_hl.clone())
+                }
+                Local::Reference if !definitions.contains(&node.id()) => {
+                    let name = name();
+                    let definition = scopes.iter().rev().find_map(|(_, names)| names.get(&name));
+                    if let Some(Some(kind)) = definition {
+                        paint(node, kind);
+                    }
+                }
+                Local::Reference | Local::Other => (),
+            }
+        }
+    }
+
+    /// The language and the ranges of the text of every injection touching
+    /// `rows`. The language is set by the query with `#set!
+    /// injection.language` or is the text of the `@injection.language`
+    /// capture; the text is the `@injection.content` capture without its
+    /// children, unless `injection.include-children` is set.
+    fn find_injections(
+        &self,
+        lines: &[&str],
+        rows: &Range<usize>,
+    ) -> Vec<(String, Vec<tree_sitter::Range>)> {
+        let (Some(tree), Some(query)) = (&self.tree, &self.injection_query) else {
+            return Vec::new();
+        };
+        if self.depth >= MAX_INJECTION_DEPTH {
+            return Vec::new();
+        }
+        let content_index = query.capture_index_for_name("injection.content");
+        let language_index = query.capture_index_for_name("injection.language");
+        let mut cursor = QueryCursor::new();
+        cursor.set_point_range(Point::new(rows.start, 0)..Point::new(rows.end, 0));
+        let mut injections = Vec::new();
+        let text = |node: Node| node_text(lines, node);
+        for query_match in cursor.matches(query, tree.root_node(), text) {
+            let properties = query.property_settings(query_match.pattern_index);
+            let property = |key: &str| properties.iter().find(|property| &*property.key == key);
+            let mut language = property("injection.language")
+                .and_then(|property| property.value.as_deref())
+                .map(String::from);
+            let include_children = property("injection.include-children").is_some();
+            let mut content = None;
+            for capture in query_match.captures {
+                if Some(capture.index) == language_index {
+                    let name: Vec<u8> = node_text(lines, capture.node).flatten().copied().collect();
+                    language = Some(String::from_utf8_lossy(&name).to_lowercase());
+                } else if Some(capture.index) == content_index {
+                    content = Some(capture.node);
+                }
+            }
+            let (Some(language), Some(content)) = (language, content) else {
+                continue;
+            };
+            let ranges = content_ranges(content, include_children);
+            // Parsing no ranges would parse the whole text.
+            if !ranges.is_empty() {
+                injections.push((language, ranges));
+            }
+        }
+        injections
+    }
+
+    /// Parses only the `ranges` of `lines` from scratch, as an injection.
+    /// `false` if they could not be parsed.
+    fn parse_ranges(&mut self, lines: &[&str], ranges: &[tree_sitter::Range]) -> bool {
+        self.tree = None;
+        if self.parser.set_included_ranges(ranges).is_err() {
+            return false;
+        }
+        self.tree = self
+            .parser
+            .parse_with(&mut |_, point: Point| chunk(lines, point), None);
+        self.tree.is_some()
+    }
+
+    /// The spans of every line of `code`, highlighted from scratch.
+    pub fn highlight(&mut self, code: &str) -> Vec<Vec<Span>> {
+        let lines: Vec<&str> = code.split('\n').collect();
+        self.reset();
+        let rows = self.parse(&lines);
+        self.highlight_lines(&lines, rows)
+    }
+}
+
+/// A run of bytes of a line with the same type. Bytes outside of any span
+/// are `Type::None`.
+pub type Span = (Range<usize>, Type);
+
+/// The type of the byte at `index` of a line with `spans`.
+pub fn type_at(spans: &[Span], index: usize) -> &Type {
+    let after = spans.partition_point(|(range, _)| range.end <= index);
+    match spans.get(after) {
+        Some((range, highlight)) if range.start <= index => highlight,
+        _ => &Type::None,
+    }
+}
+
+/// Collapses the type of every byte into spans.
+fn spans(types: &[Type]) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    for (index, highlight) in types.iter().enumerate() {
+        if *highlight == Type::None {
+            continue;
+        }
+        match spans.last_mut() {
+            Some((range, last)) if range.end == index && last == highlight => range.end += 1,
+            _ => spans.push((index..index + 1, highlight.clone())),
+        }
+    }
+    spans
+}
+
+/// A change of the text, sent to the highlighting thread.
+enum Job {
+    /// The whole text was replaced by `lines`.
+    Reset { version: u64, lines: Vec<String> },
+    /// `text` replaced the range of `edit`.
+    Edit {
+        version: u64,
+        edit: InputEdit,
+        text: String,
+    },
+}
+
+/// Highlights as of the latest job the worker applied.
+#[derive(Default)]
+struct Highlighted {
+    version: u64,
+    /// The spans of every line.
+    lines: Vec<Vec<Span>>,
+    /// Lines highlighted since the document last took them.
+    changed: Option<Range<usize>>,
+    /// The syntax tree of the text.
+    tree: Option<Tree>,
+}
+
+/// Runs a `Highlight` on a worker thread, so typing never waits for the
+/// parser. Every edit is sent as a job; the worker catches up on all queued
+/// jobs before parsing, and results are only handed out once they are for
+/// the latest version of the text.
+pub struct Background {
+    jobs: Sender<Job>,
+    highlighted: Arc<Mutex<Highlighted>>,
+    version: u64,
+}
+
+impl Background {
+    pub fn new(highlight: Highlight) -> Self {
+        let (jobs, receiver) = mpsc::channel();
+        let highlighted = Arc::new(Mutex::new(Highlighted::default()));
+        let shared = Arc::clone(&highlighted);
+        thread::spawn(move || work(highlight, &receiver, &shared));
+        Self {
+            jobs,
+            highlighted,
+            version: 0,
+        }
+    }
+
+    /// Replaces the whole text.
+    pub fn reset(&mut self, lines: Vec<String>) {
+        self.version += 1;
+        let _ = self.jobs.send(Job::Reset {
+            version: self.version,
+            lines,
+        });
+    }
+
+    /// Replaces the range of `edit` with `text`.
+    pub fn edit(&mut self, edit: InputEdit, text: &str) {
+        self.version += 1;
+        let _ = self.jobs.send(Job::Edit {
+            version: self.version,
+            edit,
+            text: text.to_owned(),
+        });
+    }
+
+    /// The lines highlighted since the last call and their types, if the
+    /// worker is done with the latest version.
+    pub fn take(&self) -> Option<(usize, Vec<Vec<Span>>)> {
+        let mut highlighted = self.highlighted.lock().ok()?;
+        if highlighted.version != self.version {
+            return None;
+        }
+        let rows = highlighted.changed.take()?;
+        let rows = rows.start.min(highlighted.lines.len())..rows.end.min(highlighted.lines.len());
+        Some((rows.start, highlighted.lines[rows].to_vec()))
+    }
+
+    /// The syntax tree, if the worker is done with the latest version.
+    pub fn tree(&self) -> Option<Tree> {
+        let highlighted = self.highlighted.lock().ok()?;
+        if highlighted.version != self.version {
+            return None;
+        }
+        highlighted.tree.clone()
+    }
+}
+
+fn work(mut highlight: Highlight, jobs: &Receiver<Job>, highlighted: &Mutex<Highlighted>) {
+    let mut lines: Vec<String> = Vec::new();
+    while let Ok(job) = jobs.recv() {
+        for job in std::iter::once(job).chain(jobs.try_iter()) {
+            let Ok(mut highlighted) = highlighted.lock() else {
+                return;
+            };
+            match job {
+                Job::Reset {
+                    version,
+                    lines: new_lines,
+                } => {
+                    highlighted.version = version;
+                    lines = new_lines;
+                    highlight.reset();
+                    highlighted.lines = vec![Vec::new(); lines.len()];
+                    highlighted.changed = None;
+                }
+                Job::Edit {
+                    version,
+                    edit,
+                    text,
+                } => {
+                    highlighted.version = version;
+                    let (start, old_end) = (edit.start_position, edit.old_end_position);
+                    while lines.len() <= old_end.row {
+                        lines.push(String::new());
+                        highlighted.lines.push(Vec::new());
                     }
+                    let prefix = lines[start.row].get(..start.column).unwrap_or_default();
+                    let suffix = lines[old_end.row].get(old_end.column..).unwrap_or_default();
+                    let replaced = format!("{prefix}{text}{suffix}");
+                    let new_lines: Vec<String> = replaced.split('\n').map(String::from).collect();
+                    let spans = vec![Vec::new(); new_lines.len()];
+                    highlighted.lines.splice(start.row..=old_end.row, spans);
+                    lines.splice(start.row..=old_end.row, new_lines);
+                    highlighted.changed = highlighted.changed.take().map(|rows| {
+                        let shift = |row: usize| {
+                            if row <= start.row {
+                                row
+                            } else if row > old_end.row {
+                                row - old_end.row + edit.new_end_position.row
+                            } else {
+                                start.row + 1
+                            }
+                        };
+                        shift(rows.start)..shift(rows.end)
+                    });
+                    highlight.edit(&edit);
                 }
-                HighlightEvent::HighlightStart(s) => current_hl = HIGHLIGHTS[s.0].1.clone(),
-                HighlightEvent::HighlightEnd => current_hl = Type::None,
             }
         }
-        Ok(res)
+
+        let line_refs: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let rows = highlight.parse(&line_refs);
+        let spans = highlight.highlight_lines(&line_refs, rows.clone());
+        let Ok(mut highlighted) = highlighted.lock() else {
+            return;
+        };
+        for (line, spans) in highlighted.lines[rows.clone()].iter_mut().zip(spans) {
+            *line = spans;
+        }
+        highlighted.changed = Some(union(highlighted.changed.take(), rows));
+        highlighted.tree = highlight.tree.clone();
+    }
+}
+
+/// The lines of the syntax nodes around `start..end`, a line without its
+/// indentation, that can be folded, innermost first: nodes spanning
+/// several lines and runs of comments on consecutive lines.
+pub fn foldable(tree: &Tree, start: Point, end: Point) -> Vec<Range<usize>> {
+    let root = tree.root_node();
+    let Some(node) = root.descendant_for_point_range(start, end) else {
+        return Vec::new();
+    };
+    let mut folds: Vec<Range<usize>> = Vec::new();
+    if is_comment(node) {
+        folds.push(comment_run(node));
+    }
+    let mut ancestor = Some(node);
+    while let Some(node) = ancestor.filter(|node| *node != root) {
+        let lines = lines(node);
+        if node.is_named() && folds.last() != Some(&lines) {
+            folds.push(lines);
+        }
+        ancestor = node.parent();
+    }
+    folds.retain(|lines| lines.len() > 1);
+    folds
+}
+
+/// The lines of every top-level node spanning several of them, and of
+/// top-level runs of comments.
+pub fn foldable_all(tree: &Tree) -> Vec<Range<usize>> {
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let mut folds: Vec<Range<usize>> = Vec::new();
+    for node in root.named_children(&mut cursor) {
+        let lines = if is_comment(node) {
+            comment_run(node)
+        } else {
+            lines(node)
+        };
+        if lines.len() > 1 && folds.last().is_none_or(|last| last.end <= lines.start) {
+            folds.push(lines);
+        }
+    }
+    folds
+}
+
+fn is_comment(node: Node) -> bool {
+    node.kind().contains("comment")
+}
+
+/// The lines of `node`, leaving out the one it ends at the start of.
+fn lines(node: Node) -> Range<usize> {
+    let (start, end) = (node.start_position(), node.end_position());
+    if end.column == 0 && end.row > start.row {
+        start.row..end.row
+    } else {
+        start.row..end.row + 1
+    }
+}
+
+/// The lines of the comment `node` and of the comments right before and
+/// after it.
+fn comment_run(node: Node) -> Range<usize> {
+    let mut run = lines(node);
+    let mut previous = node.prev_sibling();
+    while let Some(sibling) = previous.filter(|sibling| is_comment(*sibling)) {
+        let lines = lines(sibling);
+        if lines.end < run.start {
+            break;
+        }
+        run.start = lines.start;
+        previous = sibling.prev_sibling();
+    }
+    let mut next = node.next_sibling();
+    while let Some(sibling) = next.filter(|sibling| is_comment(*sibling)) {
+        let lines = lines(sibling);
+        if lines.start > run.end {
+            break;
+        }
+        run.end = lines.end;
+        next = sibling.next_sibling();
+    }
+    run
+}
+
+/// The start and end of the smallest named node of `tree` that contains
+/// `start..end` and is larger than it.
+pub fn enclosing_node(tree: &Tree, start: Point, end: Point) -> Option<(Point, Point)> {
+    let mut node = tree.root_node().descendant_for_point_range(start, end)?;
+    while !node.is_named() || (node.start_position(), node.end_position()) == (start, end) {
+        node = node.parent()?;
+    }
+    Some((node.start_position(), node.end_position()))
+}
+
+/// The position of the bracket paired with the one at `point` in `tree`.
+/// Brackets pair up with the sibling of the same nesting, so those in
+/// strings and comments, which are no nodes of their own, are left alone.
+pub fn matching_bracket<'tree>(tree: &'tree Tree, point: Point) -> Option<Point> {
+    let end = Point::new(point.row, point.column + 1);
+    let bracket = tree.root_node().descendant_for_point_range(point, end)?;
+    let index = BRACKETS.iter().position(|kind| *kind == bracket.kind())?;
+    if bracket.is_named() || bracket.start_position() != point {
+        return None;
+    }
+    let (own, pair) = (BRACKETS[index], BRACKETS[index ^ 1]);
+    let opening = index % 2 == 0;
+    let step = |node: Node<'tree>| {
+        if opening {
+            node.next_sibling()
+        } else {
+            node.prev_sibling()
+        }
+    };
+    let mut depth = 0;
+    let mut sibling = step(bracket);
+    while let Some(node) = sibling {
+        if !node.is_named() && node.kind() == own {
+            depth += 1;
+        } else if !node.is_named() && node.kind() == pair {
+            if depth == 0 {
+                return Some(node.start_position());
+            }
+            depth -= 1;
+        }
+        sibling = step(node);
+    }
+    None
+}
+
+/// Paints the brackets among the descendants of `node` touching `rows`
+/// with their nesting `level`, counted from that of `node`.
+fn paint_brackets(node: Node, level: usize, rows: &Range<usize>, types: &mut [Vec<Type>]) {
+    if node.end_position().row < rows.start || node.start_position().row >= rows.end {
+        return;
+    }
+    let is_bracket = |node: &Node| !node.is_named() && BRACKETS.contains(&node.kind());
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.children(&mut cursor).collect();
+    let inner = if children.iter().any(is_bracket) {
+        level + 1
+    } else {
+        level
+    };
+    for child in children {
+        if !is_bracket(&child) {
+            paint_brackets(child, inner, rows, types);
+            continue;
+        }
+        let start = child.start_position();
+        if let Some(byte_type) = start
+            .row
+            .checked_sub(rows.start)
+            .and_then(|row| types.get_mut(row))
+            .and_then(|line| line.get_mut(start.column))
+        {
+            *byte_type = Type::Rainbow(level % RAINBOW.len());
+        }
+    }
+}
+
+/// The text of `node`, line by line without the line breaks.
+fn node_text<'a>(lines: &'a [&str], node: Node) -> impl Iterator<Item = &'a [u8]> {
+    let (start, end) = (node.start_position(), node.end_position());
+    (start.row..=end.row).map(move |row| {
+        let line = lines.get(row).map_or(&[][..], |line| line.as_bytes());
+        let from = if row == start.row { start.column } else { 0 };
+        let to = if row == end.row {
+            end.column
+        } else {
+            line.len()
+        };
+        line.get(from.min(to)..to.min(line.len()))
+            .unwrap_or_default()
+    })
+}
+
+/// The ranges of `node`, leaving out those of its children unless
+/// `include_children`.
+fn content_ranges(node: Node, include_children: bool) -> Vec<tree_sitter::Range> {
+    let mut ranges = Vec::new();
+    let mut start = (node.start_byte(), node.start_position());
+    let mut push = |start: (usize, Point), end: (usize, Point)| {
+        if end.0 > start.0 {
+            ranges.push(tree_sitter::Range {
+                start_byte: start.0,
+                end_byte: end.0,
+                start_point: start.1,
+                end_point: end.1,
+            });
+        }
+    };
+    if !include_children {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            push(start, (child.start_byte(), child.start_position()));
+            start = (child.end_byte(), child.end_position());
+        }
+    }
+    push(start, (node.end_byte(), node.end_position()));
+    ranges
+}
+
+/// The text from `point` to the end of its line, or the line break.
+fn chunk<'a>(lines: &[&'a str], point: Point) -> &'a [u8] {
+    let Some(line) = lines.get(point.row) else {
+        return &[];
+    };
+    match line.as_bytes().get(point.column..) {
+        Some(rest) if !rest.is_empty() => rest,
+        _ if point.row + 1 < lines.len() => b"\n",
+        _ => &[],
     }
 }
 
+fn union(rows: Option<Range<usize>>, other: Range<usize>) -> Range<usize> {
+    match rows {
+        Some(rows) => rows.start.min(other.start)..rows.end.max(other.end),
+        None => other,
+    }
+}
+
+/// The type of a capture name: the entry of `HIGHLIGHTS` matching most of
+/// its dot separated parts, e.g. `function` for `function.method`.
+fn capture_type(name: &str) -> Type {
+    let parts: Vec<&str> = name.split('.').collect();
+    HIGHLIGHTS
+        .iter()
+        .filter(|(highlight, _)| highlight.split('.').all(|part| parts.contains(&part)))
+        .max_by_key(|(highlight, _)| highlight.split('.').count())
+        .map_or(Type::None, |(_, highlight_type)| highlight_type.clone())
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum Type {
     None,
@@ -156,49 +982,19 @@ pub enum Type {
     VariableBuiltin,
     VariableMember,
     VariableParameter,
+    /// A bracket nested this deep, modulo the number of rainbow colors.
+    Rainbow(usize),
 }
 
 impl Type {
-    pub fn to_color(&self) -> impl color::Color {
-        match self {
-            Type::None => color::Rgb(220, 138, 120),
-            Type::Keyword => color::Rgb(0, 255, 0),
-            Type::Attribute => color::Rgb(221, 120, 120),
-            Type::Boolean => color::Rgb(234, 118, 203),
-            Type::CarriageReturn => color::Rgb(136, 57, 239),
-            Type::Comment => color::Rgb(92, 95, 119),
-            Type::CommentDocumentation => color::Rgb(92, 95, 119),
-            Type::Constant => color::Rgb(210, 15, 57),
-            Type::ConstantBuiltin => color::Rgb(210, 15, 57),
-            Type::Constructor => color::Rgb(234, 118, 203),
-            Type::ConstructorBuiltin => color::Rgb(234, 118, 203),
-            Type::Embedded => color::Rgb(23, 146, 153),
-            Type::Error => color::Rgb(114, 135, 253),
-            Type::Escape => color::Rgb(32, 159, 181),
-            Type::Function => color::Rgb(223, 142, 29),
-            Type::FunctionBuiltin => color::Rgb(223, 142, 29),
-            Type::Module => color::Rgb(4, 165, 229),
-            Type::Number => color::Rgb(114, 135, 253),
-            Type::Operator => color::Rgb(32, 159, 181),
-            Type::Property => color::Rgb(114, 135, 253),
-            Type::PropertyBuiltin => color::Rgb(30, 102, 245),
-            Type::Punctuation => color::Rgb(4, 165, 229),
-            Type::PunctuationBracket => color::Rgb(4, 165, 229),
-            Type::PunctuationDelimiter => color::Rgb(4, 165, 229),
-            Type::PunctuationSpecial => color::Rgb(4, 165, 229),
-            Type::String => color::Rgb(64, 160, 43),
-            Type::StringEscape => color::Rgb(223, 142, 29),
-            Type::StringRegexp => color::Rgb(223, 142, 29),
-            Type::StringSpecial => color::Rgb(30, 102, 245),
-            Type::StringSpecialSymbol => color::Rgb(210, 15, 57),
-            Type::Tag => color::Rgb(220, 138, 120),
-            Type::Type => color::Rgb(220, 138, 120),
-            Type::TypeBuiltin => color::Rgb(220, 138, 120),
-            Type::Variable => color::Rgb(23, 146, 153),
-            Type::VariableBuiltin => color::Rgb(23, 146, 153),
-            Type::VariableMember => color::Rgb(23, 146, 153),
-            Type::VariableParameter => color::Rgb(23, 146, 153),
-            _ => color::Rgb(0, 0, 0),
+    /// The capture name of the type, empty for `None`.
+    pub fn name(&self) -> &'static str {
+        if let Self::Rainbow(level) = self {
+            return RAINBOW[level % RAINBOW.len()];
         }
+        HIGHLIGHTS
+            .iter()
+            .find(|(_, highlight_type)| highlight_type == self)
+            .map_or("", |(name, _)| name)
     }
 }
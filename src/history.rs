@@ -0,0 +1,113 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::config::data_dir;
+
+const HISTORY_DIR_NAME: &str = "history";
+/// Snapshots kept per file; the oldest are dropped first.
+const MAX_SNAPSHOTS: usize = 50;
+/// Total size of the snapshots kept per file.
+const MAX_BYTES: u64 = 4 * 1024 * 1024;
+
+/// A copy of a buffer saved to the local history.
+pub struct Snapshot {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    path: PathBuf,
+}
+
+impl Snapshot {
+    pub fn text(&self) -> Result<String> {
+        Ok(fs::read_to_string(&self.path)?)
+    }
+
+    /// `YYYY-MM-DD HH:MM:SS` in UTC.
+    pub fn timestamp(&self) -> String {
+        timestamp(self.time)
+    }
+}
+
+/// `time`, seconds since the Unix epoch, as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn timestamp(time: u64) -> String {
+    let days = (time / 86_400) as i64;
+    let seconds = time % 86_400;
+    // Howard Hinnant's days-to-civil algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Saves `text` as a new snapshot of `file_name` unless it equals the
+/// latest one, then trims the history of the file to its bounds.
+pub fn snapshot(file_name: &str, text: &str) -> Result<()> {
+    let Some(dir) = dir(file_name) else {
+        return Ok(());
+    };
+    let snapshots = list(file_name);
+    if snapshots
+        .first()
+        .is_some_and(|latest| latest.text().is_ok_and(|latest| latest == text))
+    {
+        return Ok(());
+    }
+    fs::create_dir_all(&dir)?;
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    fs::write(dir.join(time.to_string()), text)?;
+
+    let mut total = text.len() as u64;
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let size = fs::metadata(&snapshot.path).map_or(0, |metadata| metadata.len());
+        total += size;
+        if index + 1 >= MAX_SNAPSHOTS || total > MAX_BYTES {
+            fs::remove_file(&snapshot.path)?;
+        }
+    }
+    Ok(())
+}
+
+/// The snapshots of `file_name`, newest first.
+pub fn list(file_name: &str) -> Vec<Snapshot> {
+    let Some(entries) = dir(file_name).and_then(|dir| fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    let mut snapshots: Vec<Snapshot> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let time = entry.file_name().to_str()?.parse().ok()?;
+            Some(Snapshot {
+                time,
+                path: entry.path(),
+            })
+        })
+        .collect();
+    snapshots.sort_by_key(|snapshot| Reverse(snapshot.time));
+    snapshots
+}
+
+/// One directory per file, named after its canonical path.
+fn dir(file_name: &str) -> Option<PathBuf> {
+    let path = Path::new(file_name).canonicalize().ok()?;
+    let name = path
+        .to_string_lossy()
+        .replace('%', "%25")
+        .replace('/', "%2F");
+    data_dir().map(|dir| dir.join(HISTORY_DIR_NAME).join(name))
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The file of the patterns git ignores in a directory and below it.
+const GITIGNORE_FILE_NAME: &str = ".gitignore";
+
+/// A pattern of a `.gitignore` file or of `ignore` in the config.
+struct Rule {
+    /// The directory the pattern is relative to.
+    base: PathBuf,
+    glob: String,
+    /// `!pattern`, which takes back what an earlier pattern ignored.
+    negated: bool,
+    /// `pattern/`, which matches directories only.
+    directories: bool,
+    /// Patterns with a `/` before their end match the path from `base`,
+    /// the others the name at any depth.
+    anchored: bool,
+}
+
+/// What workspace searches skip, in the syntax of `.gitignore`: the
+/// patterns of the `.gitignore` files of the directories walked, and on top
+/// of them those of the config, e.g. `target/`, `node_modules/` or
+/// `*.min.js`.
+pub struct Ignore {
+    rules: Vec<Rule>,
+    /// Patterns of the config, which come after those of every
+    /// `.gitignore` so they have the last word.
+    globs: Vec<Rule>,
+}
+
+impl Ignore {
+    pub fn new(root: &Path, globs: &[String]) -> Self {
+        let mut ignore = Self {
+            rules: Vec::new(),
+            globs: globs.iter().filter_map(|glob| rule(root, glob)).collect(),
+        };
+        ignore.read_dir(root);
+        ignore
+    }
+
+    /// Adds the patterns of the `.gitignore` file of `dir`, if it has one.
+    pub fn read_dir(&mut self, dir: &Path) {
+        let Ok(text) = fs::read_to_string(dir.join(GITIGNORE_FILE_NAME)) else {
+            return;
+        };
+        self.rules
+            .extend(text.lines().filter_map(|line| rule(dir, line)));
+    }
+
+    /// Whether `path` is ignored; the last pattern matching it decides.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .chain(&self.globs)
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+fn rule(base: &Path, line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (directories, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+    let glob = line.strip_prefix('/').unwrap_or(line).to_string();
+    (!glob.is_empty()).then(|| Rule {
+        base: base.to_path_buf(),
+        glob,
+        negated,
+        directories,
+        anchored,
+    })
+}
+
+impl Rule {
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.directories && !is_dir {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        let Some(relative) = relative.to_str() else {
+            return false;
+        };
+        if self.anchored {
+            glob_match(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            glob_match(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// Whether `text` matches `glob`, where `*` and `?` stand for any text and
+/// any character within a path component, `**` for any number of
+/// components and `[a-z]` (`[!a-z]`) for a character of (not of) a set.
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            if rest.is_empty() {
+                return true;
+            }
+            (0..=text.len()).any(|start| {
+                (start == 0 || text[start - 1] == b'/') && glob_match(rest, &text[start..])
+            })
+        }
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&end| end == 0 || text[end - 1] != b'/')
+            .any(|end| glob_match(rest, &text[end..])),
+        [b'?', rest @ ..] => matches!(text, [c, ..] if *c != b'/') && glob_match(rest, &text[1..]),
+        [b'[', class @ ..] => {
+            let Some(close) = class.iter().skip(1).position(|&c| c == b']') else {
+                return literal(glob, text);
+            };
+            let (set, rest) = (&class[..=close], &class[close + 2..]);
+            let Some((&c, text)) = text.split_first() else {
+                return false;
+            };
+            let (negated, set) = match set {
+                [b'!' | b'^', set @ ..] => (true, set),
+                set => (false, set),
+            };
+            let mut within = false;
+            let mut index = 0;
+            while index < set.len() {
+                if index + 2 < set.len() && set[index + 1] == b'-' {
+                    within |= (set[index]..=set[index + 2]).contains(&c);
+                    index += 3;
+                } else {
+                    within |= set[index] == c;
+                    index += 1;
+                }
+            }
+            within != negated && c != b'/' && glob_match(rest, text)
+        }
+        _ => literal(glob, text),
+    }
+}
+
+/// Matches the first byte of `glob` literally, `\` escaping the next one.
+fn literal(glob: &[u8], text: &[u8]) -> bool {
+    let (c, rest) = match glob {
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => (c, rest),
+        [] => return text.is_empty(),
+    };
+    matches!(text, [first, ..] if first == c) && glob_match(rest, &text[1..])
+}
@@ -0,0 +1,314 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+use tree_sitter::{Language, Node, Parser, Point, Query, QueryCursor, Tree};
+
+/// `indents.scm` of the built-in languages. The lines within an `@indent`
+/// node are indented one level deeper than its first line, nodes starting
+/// on the same line counting once; a line starting with an `@outdent`
+/// node, such as a closing brace, goes one level back. An `@extend` node
+/// also indents blank lines right after it, which is how blocks without a
+/// closing token keep going.
+pub const RUST_QUERY: &str = r#"
+[
+  (block)
+  (declaration_list)
+  (field_declaration_list)
+  (enum_variant_list)
+  (match_block)
+  (use_list)
+  (arguments)
+  (parameters)
+  (closure_parameters)
+  (array_expression)
+  (tuple_expression)
+  (tuple_type)
+  (token_tree)
+  (field_initializer_list)
+  (struct_pattern)
+  (tuple_pattern)
+  (tuple_struct_pattern)
+  (type_arguments)
+  (type_parameters)
+  (where_clause)
+  (let_declaration)
+  (match_arm)
+] @indent
+["}" ")" "]" ">"] @outdent
+"#;
+
+pub const GO_QUERY: &str = r#"
+[
+  (block)
+  (literal_value)
+  (argument_list)
+  (parameter_list)
+  (field_declaration_list)
+  (interface_type)
+  (import_spec_list)
+  (const_declaration)
+  (var_declaration)
+  (type_declaration)
+  (expression_case)
+  (default_case)
+  (type_case)
+  (communication_case)
+] @indent
+["}" ")" "]"] @outdent
+"#;
+
+pub const C_QUERY: &str = r#"
+[
+  (compound_statement)
+  (field_declaration_list)
+  (enumerator_list)
+  (initializer_list)
+  (argument_list)
+  (parameter_list)
+  (case_statement)
+] @indent
+["}" ")" "]"] @outdent
+"#;
+
+pub const CPP_QUERY: &str = r#"
+[
+  (compound_statement)
+  (field_declaration_list)
+  (enumerator_list)
+  (initializer_list)
+  (argument_list)
+  (parameter_list)
+  (template_parameter_list)
+  (case_statement)
+] @indent
+["}" ")" "]" ">"] @outdent
+"#;
+
+pub const PYTHON_QUERY: &str = r#"
+[
+  (function_definition)
+  (class_definition)
+  (if_statement)
+  (for_statement)
+  (while_statement)
+  (with_statement)
+  (try_statement)
+  (match_statement)
+  (case_clause)
+] @indent @extend
+[
+  (argument_list)
+  (parameters)
+  (list)
+  (dictionary)
+  (set)
+  (tuple)
+  (parenthesized_expression)
+  (subscript)
+] @indent
+["}" ")" "]" "elif" "else" "except" "finally"] @outdent
+"#;
+
+/// Closing tokens that go back a level in languages without a query.
+const CLOSING: [&str; 3] = ["}", ")", "]"];
+
+/// What makes lines deeper: the captures of a query, or any named node
+/// spanning several lines.
+enum Rules {
+    Query {
+        indent: HashSet<usize>,
+        outdent: HashSet<usize>,
+        extend: HashSet<usize>,
+    },
+    Depth,
+}
+
+impl Rules {
+    fn indents(&self, node: Node) -> bool {
+        match self {
+            Self::Query { indent, .. } => indent.contains(&node.id()),
+            Self::Depth => {
+                node.is_named()
+                    && node.parent().is_some()
+                    && node.start_position().row < node.end_position().row
+            }
+        }
+    }
+
+    fn outdents(&self, node: Node) -> bool {
+        match self {
+            Self::Query { outdent, .. } => outdent.contains(&node.id()),
+            Self::Depth => !node.is_named() && CLOSING.contains(&node.kind()),
+        }
+    }
+
+    fn extends(&self, node: Node) -> bool {
+        match self {
+            Self::Query { extend, .. } => extend.contains(&node.id()),
+            Self::Depth => false,
+        }
+    }
+}
+
+/// How many levels deep each of the lines `rows` of `lines` belongs, by
+/// the captures of `query`, or by how deeply the syntax nodes are nested
+/// if there is none.
+pub fn levels(
+    lang: Language,
+    query: Option<&str>,
+    lines: &[&str],
+    rows: Range<usize>,
+) -> Result<Vec<usize>> {
+    let source = lines.join("\n");
+    let mut parser = Parser::new();
+    parser.set_language(lang)?;
+    let tree = parser
+        .parse(&source, None)
+        .ok_or_else(|| anyhow!("Could not parse the file"))?;
+    let rules = match query {
+        Some(query) => captures(lang, query, &tree, &source, &rows)?,
+        None => Rules::Depth,
+    };
+    Ok(rows.map(|row| level(&tree, &rules, lines, row)).collect())
+}
+
+fn captures(
+    lang: Language,
+    query: &str,
+    tree: &Tree,
+    source: &str,
+    rows: &Range<usize>,
+) -> Result<Rules> {
+    let query = Query::new(lang, query).map_err(|e| anyhow!("Invalid query: {}", e.message))?;
+    let index = |name| query.capture_index_for_name(name);
+    let (indent_index, outdent_index, extend_index) =
+        (index("indent"), index("outdent"), index("extend"));
+    let mut indent = HashSet::new();
+    let mut outdent = HashSet::new();
+    let mut extend = HashSet::new();
+    let mut cursor = QueryCursor::new();
+    cursor.set_point_range(Point::new(0, 0)..Point::new(rows.end + 1, 0));
+    for (query_match, capture) in cursor.captures(&query, tree.root_node(), source.as_bytes()) {
+        let capture = query_match.captures[capture];
+        let id = capture.node.id();
+        if Some(capture.index) == indent_index {
+            indent.insert(id);
+        }
+        if Some(capture.index) == outdent_index {
+            outdent.insert(id);
+        }
+        if Some(capture.index) == extend_index {
+            extend.insert(id);
+        }
+    }
+    Ok(Rules::Query {
+        indent,
+        outdent,
+        extend,
+    })
+}
+
+/// The level of line `row`. A blank line belongs where the text before it
+/// ends.
+fn level(tree: &Tree, rules: &Rules, lines: &[&str], row: usize) -> usize {
+    let line = lines.get(row).copied().unwrap_or_default();
+    let blank = line.trim().is_empty();
+    // Where the line starts, and the character the nodes are searched at.
+    let (point, at) = if blank {
+        let Some((row, line)) = lines[..row.min(lines.len())]
+            .iter()
+            .enumerate()
+            .rfind(|(_, line)| !line.trim().is_empty())
+        else {
+            return 0;
+        };
+        let end = line.trim_end().len();
+        (Point::new(row, end), Point::new(row, end - 1))
+    } else {
+        let indent = line.len() - line.trim_start().len();
+        (Point::new(row, indent), Point::new(row, indent))
+    };
+    let Some(leaf) = tree.root_node().descendant_for_point_range(at, at) else {
+        return 0;
+    };
+    let mut starts: Vec<usize> = Vec::new();
+    let mut ancestor = Some(leaf);
+    while let Some(node) = ancestor {
+        let start = node.start_position().row;
+        let contains =
+            node.end_position() > point || blank && (rules.extends(node) || is_unclosed(node));
+        if start < row && rules.indents(node) && contains && !starts.contains(&start) {
+            starts.push(start);
+        }
+        if node.is_error() {
+            for start in unclosed(node, point) {
+                if start < row && !starts.contains(&start) {
+                    starts.push(start);
+                }
+            }
+        }
+        ancestor = node.parent();
+    }
+    if !blank && rules.outdents(leaf) && closes(leaf, rules, row) {
+        starts.pop();
+    }
+    starts.len()
+}
+
+/// Whether `token`, which starts line `row`, ends a node indenting the
+/// lines before it, like the brace of a block or the `else` of an `if`.
+fn closes(token: Node, rules: &Rules, row: usize) -> bool {
+    let mut node = token;
+    while let Some(parent) = node.parent() {
+        if parent.start_position() != token.start_position() {
+            return rules.indents(parent) && parent.start_position().row < row;
+        }
+        node = parent;
+    }
+    false
+}
+
+/// The lines of the brackets before `point` that the text the parser could
+/// not make sense of opens and does not close, as in a function just begun.
+fn unclosed(error: Node, point: Point) -> Vec<usize> {
+    let mut open = Vec::new();
+    let mut cursor = error.walk();
+    for child in error.children(&mut cursor) {
+        if child.start_position() >= point {
+            break;
+        }
+        match child.kind() {
+            "{" | "(" | "[" => open.push(child.start_position().row),
+            "}" | ")" | "]" => {
+                open.pop();
+            }
+            _ => (),
+        }
+    }
+    open
+}
+
+/// Whether the closing token of `node` is missing, as in a block just
+/// opened.
+fn is_unclosed(node: Node) -> bool {
+    node.child(node.child_count().saturating_sub(1))
+        .is_some_and(|last| last.is_missing())
+}
+
+/// The indentation of one level in `lines`: a tab if they are indented by
+/// tabs, else as many spaces as the shallowest indented line, if any is.
+pub fn unit(lines: &[&str]) -> Option<String> {
+    let indents = lines
+        .iter()
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .filter(|indent| !indent.is_empty());
+    let mut spaces = None;
+    for indent in indents.take(100) {
+        if indent.starts_with('\t') {
+            return Some(String::from("\t"));
+        }
+        spaces = Some(spaces.map_or(indent.len(), |spaces: usize| spaces.min(indent.len())));
+    }
+    spaces.map(|spaces| " ".repeat(spaces.clamp(2, 8)))
+}
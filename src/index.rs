@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::completion::fuzzy_score;
+use crate::grep::{self, Match};
+use crate::textobject::{self, Object};
+use crate::FileType;
+
+/// How often the files of the workspace are looked at again for changes.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+/// Files larger than this are counted but not parsed for symbols.
+const MAX_PARSED_SIZE: u64 = 1024 * 1024;
+/// Words that mark a line as a TODO.
+const TODO_MARKERS: [&str; 3] = ["TODO", "FIXME", "XXX"];
+
+/// A named function or class of a file.
+struct Symbol {
+    name: String,
+    kind: Object,
+    line: usize,
+    /// In graphemes.
+    column: usize,
+}
+
+/// A line of a file with a TODO, FIXME or XXX.
+struct Todo {
+    line: usize,
+    /// In graphemes.
+    column: usize,
+    text: String,
+}
+
+/// What the index knows of one file.
+struct Entry {
+    modified: Option<SystemTime>,
+    /// The title of the file type, e.g. `Rust`.
+    language: String,
+    lines: usize,
+    symbols: Vec<Symbol>,
+    todos: Vec<Todo>,
+}
+
+#[derive(Default)]
+struct State {
+    files: BTreeMap<PathBuf, Entry>,
+    /// Whether every file was indexed once.
+    ready: bool,
+}
+
+/// How many files and lines there are of one language.
+pub struct Count {
+    pub language: String,
+    pub files: usize,
+    pub lines: usize,
+}
+
+/// The sizes of the workspace by language, most lines first.
+pub struct Statistics {
+    pub languages: Vec<Count>,
+    pub symbols: usize,
+    pub todos: usize,
+}
+
+/// The files, symbols and TODOs of the workspace, indexed by a pool of
+/// threads in the background and kept up to date by looking for files that
+/// changed every few seconds. Indexing stops when the index is dropped.
+pub struct Index {
+    state: Arc<Mutex<State>>,
+}
+
+impl Index {
+    /// Starts indexing the files below `root` that are not ignored by the
+    /// `.gitignore` files and `globs`.
+    pub fn start(root: &Path, globs: &[String]) -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let (root, globs, weak) = (root.to_path_buf(), globs.to_vec(), Arc::downgrade(&state));
+        thread::spawn(move || watch(&root, &globs, &weak));
+        Self { state }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state().ready
+    }
+
+    /// The files whose path matches `query` as a fuzzy subsequence, best
+    /// first.
+    pub fn find_files(&self, query: &str, root: &Path) -> Vec<PathBuf> {
+        let state = self.state();
+        let mut found: Vec<(i64, &PathBuf)> = state
+            .files
+            .keys()
+            .filter_map(|path| {
+                let relative = path.strip_prefix(root).unwrap_or(path);
+                fuzzy_score(query, &relative.to_string_lossy()).map(|score| (score, path))
+            })
+            .collect();
+        found.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        found.into_iter().map(|(_, path)| path.clone()).collect()
+    }
+
+    /// The symbols whose name matches `query` as a fuzzy subsequence, best
+    /// first, described by their kind and name.
+    pub fn symbols(&self, query: &str) -> Vec<Match> {
+        let state = self.state();
+        let mut found: Vec<(i64, &PathBuf, &Symbol)> = state
+            .files
+            .iter()
+            .flat_map(|(path, entry)| entry.symbols.iter().map(move |symbol| (path, symbol)))
+            .filter_map(|(path, symbol)| {
+                fuzzy_score(query, &symbol.name).map(|score| (score, path, symbol))
+            })
+            .collect();
+        found.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        found
+            .into_iter()
+            .map(|(_, path, symbol)| Match {
+                path: path.clone(),
+                line: symbol.line,
+                column: symbol.column,
+                text: format!("{} {}", symbol.kind.name(), symbol.name),
+            })
+            .collect()
+    }
+
+    /// The TODOs of every file, by path.
+    pub fn todos(&self) -> Vec<Match> {
+        self.state()
+            .files
+            .iter()
+            .flat_map(|(path, entry)| {
+                entry.todos.iter().map(move |todo| Match {
+                    path: path.clone(),
+                    line: todo.line,
+                    column: todo.column,
+                    text: todo.text.clone(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        let state = self.state();
+        let mut languages: HashMap<&str, Count> = HashMap::new();
+        for entry in state.files.values() {
+            let count = languages.entry(&entry.language).or_insert_with(|| Count {
+                language: entry.language.clone(),
+                files: 0,
+                lines: 0,
+            });
+            count.files += 1;
+            count.lines += entry.lines;
+        }
+        let mut languages: Vec<Count> = languages.into_values().collect();
+        languages.sort_by(|a, b| {
+            b.lines
+                .cmp(&a.lines)
+                .then_with(|| a.language.cmp(&b.language))
+        });
+        Statistics {
+            languages,
+            symbols: state.files.values().map(|entry| entry.symbols.len()).sum(),
+            todos: state.files.values().map(|entry| entry.todos.len()).sum(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// Indexes the files that are new or changed since the last time, by a
+/// pool of workers, and forgets those that are gone, until the index is
+/// dropped.
+fn watch(root: &Path, globs: &[String], state: &Weak<Mutex<State>>) {
+    let workers = thread::available_parallelism()
+        .map_or(2, usize::from)
+        .min(8);
+    let (paths, receiver) = mpsc::channel::<PathBuf>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let (done, finished) = mpsc::channel();
+    for _ in 0..workers {
+        let (receiver, done, state) = (Arc::clone(&receiver), done.clone(), state.clone());
+        thread::spawn(move || work(&receiver, &done, &state));
+    }
+    loop {
+        let files = grep::files(root, globs);
+        let changed: Vec<PathBuf> = {
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            let mut state = lock(&state);
+            state
+                .files
+                .retain(|path, _| files.binary_search(path).is_ok());
+            files
+                .into_iter()
+                .filter(|path| {
+                    state
+                        .files
+                        .get(path)
+                        .is_none_or(|entry| entry.modified != modified(path))
+                })
+                .collect()
+        };
+        for path in &changed {
+            if paths.send(path.clone()).is_err() {
+                return;
+            }
+        }
+        for _ in &changed {
+            if finished.recv().is_err() {
+                return;
+            }
+        }
+        match state.upgrade() {
+            Some(state) => lock(&state).ready = true,
+            None => return,
+        }
+        thread::sleep(RESCAN_INTERVAL);
+    }
+}
+
+/// Indexes the files sent by `watch`, telling it by `done` when each one is.
+fn work(receiver: &Mutex<Receiver<PathBuf>>, done: &Sender<()>, state: &Weak<Mutex<State>>) {
+    loop {
+        let path = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let Ok(path) = path else {
+            return;
+        };
+        let entry = index(&path);
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        match entry {
+            Some(entry) => lock(&state).files.insert(path, entry),
+            None => lock(&state).files.remove(&path),
+        };
+        if done.send(()).is_err() {
+            return;
+        }
+    }
+}
+
+/// What there is to know of the text file `path`.
+fn index(path: &Path) -> Option<Entry> {
+    let modified = modified(path);
+    let text = grep::read_text(path)?;
+    let file_name = path.to_string_lossy();
+    let lines: Vec<&str> = text.lines().collect();
+    let sample = lines[..lines.len().min(5)].join("\n");
+    let file_type = FileType::from(&file_name, &sample).unwrap_or_default();
+    let options = file_type.highlighting_options();
+    let small = fs::metadata(path).is_ok_and(|metadata| metadata.len() <= MAX_PARSED_SIZE);
+    let symbols = options
+        .get_lang()
+        .zip(options.get_textobject_query())
+        .filter(|_| small)
+        .and_then(|(lang, query)| textobject::symbols(lang, &query, &text).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(kind, name, start)| Symbol {
+            name,
+            kind,
+            line: start.row,
+            column: graphemes(lines.get(start.row).unwrap_or(&""), start.column),
+        })
+        .collect();
+    Some(Entry {
+        modified,
+        language: file_type.name(),
+        lines: lines.len(),
+        symbols,
+        todos: todos(&text),
+    })
+}
+
+/// The lines of `text` with a TODO marker, as a word of its own.
+fn todos(text: &str) -> Vec<Todo> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(line, line_text)| {
+            let column = TODO_MARKERS
+                .iter()
+                .filter_map(|marker| {
+                    line_text
+                        .match_indices(marker)
+                        .map(|(index, _)| index)
+                        .find(|index| {
+                            let before = line_text[..*index].chars().next_back();
+                            let after = line_text[index + marker.len()..].chars().next();
+                            !before.is_some_and(char::is_alphanumeric)
+                                && !after.is_some_and(char::is_alphanumeric)
+                        })
+                })
+                .min()?;
+            Some(Todo {
+                line,
+                column: line_text[..column].graphemes(true).count(),
+                text: line_text[column..].trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The graphemes of `line` before the byte `column`.
+fn graphemes(line: &str, column: usize) -> usize {
+    line.get(..column)
+        .map_or(column, |before| before.graphemes(true).count())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    match state.lock() {
+        Ok(state) => state,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
@@ -0,0 +1,346 @@
+use std::cell::Cell;
+use std::ops::{Index, Range};
+
+use crate::count::Counts;
+use crate::highlighting::Span;
+use crate::Row;
+
+/// Rows a chunk holds at most; a fuller one is split in two.
+const CHUNK_SIZE: usize = 1024;
+
+/// The rows of a document in chunks, like the leaves of a rope: inserting
+/// or removing a row moves the rows of its chunk only, and the offset of a
+/// row adds up the sizes of the chunks before it instead of every row.
+#[derive(Default)]
+pub struct Lines {
+    chunks: Vec<Chunk>,
+    len: usize,
+}
+
+struct Chunk {
+    /// The index of the first row of the chunk in the document.
+    start: usize,
+    rows: Vec<Row>,
+    /// The bytes of the rows and their line breaks, computed when first
+    /// needed and dropped when a row of the chunk changes.
+    bytes: Cell<Option<usize>>,
+    /// The words, graphemes and bytes of the rows without line breaks,
+    /// likewise, for the word count shown on every frame.
+    counts: Cell<Option<Counts>>,
+}
+
+impl Chunk {
+    fn new(start: usize, rows: Vec<Row>) -> Self {
+        Self {
+            start,
+            rows,
+            bytes: Cell::new(None),
+            counts: Cell::new(None),
+        }
+    }
+
+    fn changed(&self) {
+        self.bytes.set(None);
+        self.counts.set(None);
+    }
+
+    fn bytes(&self) -> usize {
+        if let Some(bytes) = self.bytes.get() {
+            return bytes;
+        }
+        let bytes = self.rows.iter().map(|row| row.as_bytes().len() + 1).sum();
+        self.bytes.set(Some(bytes));
+        bytes
+    }
+
+    fn counts(&self) -> Counts {
+        if let Some(counts) = self.counts.get() {
+            return counts;
+        }
+        let mut counts = Counts {
+            lines: self.rows.len(),
+            ..Counts::default()
+        };
+        for row in &self.rows {
+            counts.words += row.words();
+            counts.graphemes += row.len();
+            counts.bytes += row.as_bytes().len();
+        }
+        self.counts.set(Some(counts));
+        counts
+    }
+}
+
+impl FromIterator<Row> for Lines {
+    fn from_iter<I: IntoIterator<Item = Row>>(rows: I) -> Self {
+        let mut lines = Self::default();
+        for row in rows {
+            lines.push(row);
+        }
+        lines
+    }
+}
+
+impl Index<usize> for Lines {
+    type Output = Row;
+
+    fn index(&self, index: usize) -> &Row {
+        self.get(index).expect("row index out of bounds")
+    }
+}
+
+impl Lines {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Row> {
+        let (chunk, offset) = self.locate(index)?;
+        self.chunks[chunk].rows.get(offset)
+    }
+
+    /// Changes the row at `index` by `change`.
+    pub fn update<T>(&mut self, index: usize, change: impl FnOnce(&mut Row) -> T) -> Option<T> {
+        let (chunk, offset) = self.locate(index)?;
+        let chunk = &mut self.chunks[chunk];
+        chunk.changed();
+        chunk.rows.get_mut(offset).map(change)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Row> {
+        self.chunks.iter().flat_map(|chunk| chunk.rows.iter())
+    }
+
+    pub fn push(&mut self, row: Row) {
+        match self.chunks.last_mut() {
+            Some(chunk) if chunk.rows.len() < CHUNK_SIZE => {
+                chunk.rows.push(row);
+                chunk.changed();
+            }
+            _ => self.chunks.push(Chunk::new(self.len, vec![row])),
+        }
+        self.len += 1;
+    }
+
+    pub fn insert(&mut self, index: usize, row: Row) {
+        if index >= self.len {
+            self.push(row);
+            return;
+        }
+        let Some((chunk, offset)) = self.locate(index) else {
+            return;
+        };
+        let full = &mut self.chunks[chunk];
+        full.rows.insert(offset, row);
+        full.changed();
+        if full.rows.len() > CHUNK_SIZE {
+            let half = full.rows.len() / 2;
+            let rest = full.rows.split_off(half);
+            let start = full.start + half;
+            self.chunks.insert(chunk + 1, Chunk::new(start, rest));
+        }
+        self.len += 1;
+        self.renumber(chunk + 1);
+    }
+
+    /// Removes the rows `range`, dropping the chunks left empty.
+    pub fn remove(&mut self, range: Range<usize>) {
+        let range = range.start..range.end.min(self.len);
+        if range.is_empty() {
+            return;
+        }
+        let Some((first, _)) = self.locate(range.start) else {
+            return;
+        };
+        for chunk in &mut self.chunks[first..] {
+            let start = range.start.max(chunk.start);
+            let end = range.end.min(chunk.start + chunk.rows.len());
+            if start >= end {
+                break;
+            }
+            chunk.rows.drain(start - chunk.start..end - chunk.start);
+            chunk.changed();
+        }
+        self.chunks.retain(|chunk| !chunk.rows.is_empty());
+        self.len -= range.len();
+        self.renumber(first);
+    }
+
+    /// The bytes of the rows before `index` and their line breaks.
+    pub fn offset(&self, index: usize) -> usize {
+        let Some((chunk, offset)) = self.locate(index) else {
+            return self.chunks.iter().map(Chunk::bytes).sum();
+        };
+        let before: usize = self.chunks[..chunk].iter().map(Chunk::bytes).sum();
+        let rows = &self.chunks[chunk].rows[..offset];
+        before
+            + rows
+                .iter()
+                .map(|row| row.as_bytes().len() + 1)
+                .sum::<usize>()
+    }
+
+    /// The lines, words, graphemes and bytes of the rows, without line
+    /// breaks. Only the chunks changed since the last call are counted.
+    pub fn counts(&self) -> Counts {
+        let mut counts = Counts::default();
+        for chunk in &self.chunks {
+            let chunk = chunk.counts();
+            counts.lines += chunk.lines;
+            counts.words += chunk.words;
+            counts.graphemes += chunk.graphemes;
+            counts.bytes += chunk.bytes;
+        }
+        counts
+    }
+
+    /// Highlights the rows from `start` on with `spans`, one per row.
+    pub fn set_highlights(&mut self, start: usize, spans: Vec<Vec<Span>>) {
+        let rows = self
+            .chunks
+            .iter_mut()
+            .flat_map(|chunk| chunk.rows.iter_mut());
+        for (row, spans) in rows.skip(start).zip(spans) {
+            row.set_highlight(spans);
+        }
+    }
+
+    pub fn clear_highlights(&mut self) {
+        for chunk in &mut self.chunks {
+            for row in &mut chunk.rows {
+                row.set_highlight(Vec::new());
+            }
+        }
+    }
+
+    /// The chunk of the row at `index` and its place in the chunk.
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.len {
+            return None;
+        }
+        let chunk = self.chunks.partition_point(|chunk| chunk.start <= index) - 1;
+        Some((chunk, index - self.chunks[chunk].start))
+    }
+
+    /// Counts the first rows of the chunks from `chunk` on again.
+    fn renumber(&mut self, chunk: usize) {
+        let mut start = chunk.checked_sub(1).map_or(0, |before| {
+            self.chunks[before].start + self.chunks[before].rows.len()
+        });
+        for chunk in &mut self.chunks[chunk..] {
+            chunk.start = start;
+            start += chunk.rows.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(range: Range<usize>) -> Vec<String> {
+        range.map(|number| number.to_string()).collect()
+    }
+
+    fn lines(texts: &[String]) -> Lines {
+        texts.iter().map(|text| Row::from(text.as_str())).collect()
+    }
+
+    /// Checks `lines` against the rows it should hold, row by row and by
+    /// the offsets and chunk starts.
+    fn check(lines: &Lines, expected: &[String]) {
+        assert_eq!(lines.len(), expected.len());
+        let texts: Vec<&str> = lines.iter().map(Row::as_str).collect();
+        assert_eq!(texts, expected);
+        for (index, text) in expected.iter().enumerate() {
+            assert_eq!(lines[index].as_str(), text);
+        }
+        let mut start = 0;
+        for chunk in &lines.chunks {
+            assert_eq!(chunk.start, start);
+            assert!(!chunk.rows.is_empty() && chunk.rows.len() <= CHUNK_SIZE);
+            start += chunk.rows.len();
+        }
+        let bytes: usize = expected.iter().map(|text| text.len() + 1).sum();
+        assert_eq!(lines.offset(expected.len()), bytes);
+        assert_eq!(
+            lines.offset(expected.len() - 1),
+            bytes - expected[expected.len() - 1].len() - 1
+        );
+    }
+
+    #[test]
+    fn pushed_into_chunks() {
+        let expected = numbered(0..CHUNK_SIZE * 2 + 1);
+        let lines = lines(&expected);
+        assert_eq!(lines.chunks.len(), 3);
+        check(&lines, &expected);
+    }
+
+    #[test]
+    fn insert_splits_full_chunk() {
+        let mut expected = numbered(0..CHUNK_SIZE * 2);
+        let mut lines = lines(&expected);
+        // Into the first chunk, into the half it split off, into the last.
+        for index in [0, CHUNK_SIZE, CHUNK_SIZE * 2 + 1] {
+            lines.insert(index, Row::from("new"));
+            expected.insert(index, String::from("new"));
+            check(&lines, &expected);
+        }
+        assert_eq!(lines.chunks.len(), 4);
+        // At the end, the last chunk takes it.
+        lines.insert(expected.len(), Row::from("last"));
+        expected.push(String::from("last"));
+        check(&lines, &expected);
+    }
+
+    #[test]
+    fn remove_across_chunks() {
+        let mut expected = numbered(0..CHUNK_SIZE * 3);
+        let mut lines = lines(&expected);
+        let range = CHUNK_SIZE - 2..CHUNK_SIZE * 2 + 3;
+        lines.remove(range.clone());
+        expected.drain(range);
+        assert_eq!(lines.chunks.len(), 2);
+        check(&lines, &expected);
+        lines.remove(0..2);
+        expected.drain(0..2);
+        check(&lines, &expected);
+    }
+
+    #[test]
+    fn counts_follow_changes() {
+        let mut lines = lines(&numbered(0..CHUNK_SIZE * 2));
+        let before = lines.counts();
+        assert_eq!(before.lines, CHUNK_SIZE * 2);
+        assert_eq!(before.words, CHUNK_SIZE * 2);
+        lines.update(CHUNK_SIZE + 1, |row| row.insert(0, ' '));
+        lines.update(CHUNK_SIZE + 1, |row| row.insert(0, 'é'));
+        lines.insert(3, Row::from("two words"));
+        lines.remove(CHUNK_SIZE * 2..CHUNK_SIZE * 2 + 1);
+        let after = lines.counts();
+        assert_eq!(after.lines, before.lines);
+        assert_eq!(after.words, before.words + 1 + 2 - 1);
+        assert_eq!(after.graphemes, before.graphemes + 2 + 9 - 4);
+        assert_eq!(after.bytes, before.bytes + 3 + 9 - 4);
+    }
+
+    #[test]
+    fn remove_whole_chunks() {
+        let mut expected = numbered(0..CHUNK_SIZE * 3);
+        let mut lines = lines(&expected);
+        let range = CHUNK_SIZE..CHUNK_SIZE * 2;
+        lines.remove(range.clone());
+        expected.drain(range);
+        assert_eq!(lines.chunks.len(), 2);
+        check(&lines, &expected);
+        // Past the end is cut off.
+        lines.remove(CHUNK_SIZE..usize::MAX);
+        expected.truncate(CHUNK_SIZE);
+        check(&lines, &expected);
+    }
+}
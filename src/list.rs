@@ -0,0 +1,71 @@
+/// What `Enter` does at the end of a line of a list or a quote.
+pub enum Continuation {
+    /// Start the new line with this marker, e.g. `2. ` after `1. `.
+    Marker(String),
+    /// The line holds nothing but its marker: remove it to end the list.
+    End,
+}
+
+/// The marker at the start of `line`, its length and how the next line
+/// continues it, for languages whose lists are written like Markdown's:
+/// `- `, `* ` and `+ ` bullets (with `[ ] ` task boxes), `1. ` and `1) `
+/// numbers, which count up, and `> ` quotes, which may hold lists.
+pub fn marker(language_id: &str, line: &str) -> Option<(usize, Continuation)> {
+    if language_id != "markdown" {
+        return None;
+    }
+    let indent = line.len() - line.trim_start().len();
+    let mut next = line[..indent].to_string();
+    let mut rest = &line[indent..];
+    while let Some(quoted) = rest.strip_prefix('>') {
+        next.push('>');
+        let spaces = quoted.len() - quoted.trim_start_matches(' ').len();
+        next.push_str(&quoted[..spaces]);
+        rest = &quoted[spaces..];
+    }
+    if let Some((item, after)) = item(rest) {
+        next.push_str(&item);
+        rest = after;
+    } else if next.len() == indent {
+        return None;
+    }
+    let length = line.len() - rest.len();
+    if rest.trim().is_empty() {
+        return Some((length, Continuation::End));
+    }
+    Some((length, Continuation::Marker(next)))
+}
+
+/// The marker of the next item after the list item starting `text`, and the
+/// text after the marker.
+fn item(text: &str) -> Option<(String, &str)> {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (marker, after) = if digits == 0 {
+        let bullet = text
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '-' | '*' | '+'))?;
+        (bullet.to_string(), &text[1..])
+    } else {
+        // CommonMark numbers have at most nine digits.
+        let number: u32 = text[..digits].parse().ok().filter(|_| digits <= 9)?;
+        let delimiter = text[digits..]
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '.' | ')'))?;
+        (format!("{}{delimiter}", number + 1), &text[digits + 1..])
+    };
+    let spaces = after.len() - after.trim_start_matches(' ').len();
+    if spaces == 0 && !after.is_empty() {
+        return None;
+    }
+    let mut next = marker + &" ".repeat(spaces.max(1));
+    let after = &after[spaces..];
+    for checkbox in ["[ ] ", "[x] ", "[X] "] {
+        if let Some(task) = after.strip_prefix(checkbox) {
+            next.push_str("[ ] ");
+            return Some((next, task));
+        }
+    }
+    Some((next, after))
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+
+use crate::config;
+
+/// The messages of the interface in English by key. Catalogs of other
+/// languages translate them; `{name}` stands for an argument.
+const ENGLISH: &[(&str, &str)] = &[
+    (
+        "help",
+        "HELP: Ctrl-S = Save | Ctrl-F = Search | Ctrl-N = Complete | Ctrl-P = Command | Ctrl-Q = Quit",
+    ),
+    (
+        "quit-warning",
+        "WARNING! Unsaved changes will be discarded! Press Ctrl-Q {times} times to quit.",
+    ),
+    ("save-as", "Save as: "),
+    ("save-copy", "Save a copy as: "),
+    ("save-aborted", "Save aborted"),
+    ("saved", "File Saved successfully"),
+    ("save-failed", "Error writing file!"),
+    ("wrote-copy", "Wrote a copy to {file}"),
+    ("replace-file", "{file} exists; replace it? (y/n): "),
+    ("rename-to", "Rename to: "),
+    ("rename-aborted", "Rename aborted"),
+    ("renamed", "Renamed to {file}"),
+    ("no-file", "The buffer has no file"),
+    ("search", "Search: "),
+    ("search-cancelled", "Search cancelled"),
+    ("no-matches", "No matches for {query}"),
+    ("yes-or-no", "y or n"),
+    (
+        "trust-workspace",
+        "Trust {root}? It may run language servers and project config (y/n): ",
+    ),
+    (
+        "workspace-untrusted",
+        "Workspace not trusted: language servers are disabled",
+    ),
+    (
+        "changed-on-disk",
+        "File changed on disk: (r)eload, (k)eep buffer, (s)how changes: ",
+    ),
+    (
+        "changed-before-save",
+        "File changed on disk: (o)verwrite, (r)eload, (s)how changes, else cancel: ",
+    ),
+    ("removed-on-disk", "File was removed or is unreadable on disk"),
+    ("reloaded", "Reloaded from disk"),
+    ("kept-buffer", "Kept the buffer"),
+    ("no-language-server", "No language server"),
+];
+
+/// The Japanese catalog, built in as an example for translators.
+const JAPANESE: &str = include_str!("../locales/ja.toml");
+
+static CURRENT: OnceLock<Catalog> = OnceLock::new();
+
+/// Translations of the messages of [`ENGLISH`] into one language.
+#[derive(Default)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Loads the catalog of `language`, e.g. `ja`, from `locales/<language>.toml`
+    /// of the config directory, falling back to the built-in ones.
+    /// English needs no catalog.
+    pub fn load(language: &str) -> Result<Self> {
+        if language == "en" {
+            return Ok(Self::default());
+        }
+        let path = path(language).ok_or_else(|| anyhow!("Invalid locale {language:?}"))?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) if language == "ja" => JAPANESE.to_string(),
+            Err(e) => return Err(anyhow!("Could not read {}: {e}", path.display())),
+        };
+        Ok(Self {
+            messages: toml::from_str(&contents)?,
+        })
+    }
+
+    /// The message `key` with `{name}` replaced by the argument `name`,
+    /// in English if it is not translated.
+    pub fn text(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let mut text = match self.messages.get(key) {
+            Some(message) => message.clone(),
+            None => english(key).to_string(),
+        };
+        for (name, value) in args {
+            text = text.replace(&format!("{{{name}}}"), &value.to_string());
+        }
+        text
+    }
+}
+
+fn english(key: &str) -> &str {
+    ENGLISH
+        .iter()
+        .find(|(english_key, _)| *english_key == key)
+        .map_or(key, |(_, message)| message)
+}
+
+/// `locales/<language>.toml` of the config directory.
+fn path(language: &str) -> Option<PathBuf> {
+    if language.is_empty()
+        || !language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return None;
+    }
+    Some(
+        config::config_dir()?
+            .join("locales")
+            .join(format!("{language}.toml")),
+    )
+}
+
+/// The language of the user by `LC_ALL`, `LC_MESSAGES` or `LANG`, e.g. `ja`
+/// for `ja_JP.UTF-8`.
+pub fn from_env() -> Option<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|locale| !locale.is_empty())?;
+    let language = locale.split(['_', '.', '@']).next()?;
+    match language {
+        "" | "C" | "POSIX" => None,
+        language => Some(language.to_string()),
+    }
+}
+
+/// Shows the messages of `catalog` from now on; only the first call counts.
+pub fn set(catalog: Catalog) {
+    let _ = CURRENT.set(catalog);
+}
+
+/// The message `key` in the language of the interface, see [`Catalog::text`].
+pub fn text(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    CURRENT.get_or_init(Catalog::default).text(key, args)
+}
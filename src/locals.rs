@@ -0,0 +1,66 @@
+/// `locals.scm` of the built-in languages, in the captures of
+/// tree-sitter-highlight: `@local.scope` nodes hold the names that
+/// `@local.definition` nodes in them define, and `@local.reference` nodes
+/// use them. A definition captured as `@local.definition.<capture>`, e.g.
+/// `@local.definition.variable.parameter`, colors its references as that
+/// capture; one without a capture only hides the names of outer scopes.
+pub const RUST_QUERY: &str = r"
+(function_item) @local.scope
+(closure_expression) @local.scope
+(block) @local.scope
+(parameter pattern: (identifier) @local.definition.variable.parameter)
+(closure_parameters (identifier) @local.definition.variable.parameter)
+(let_declaration pattern: (identifier) @local.definition)
+(for_expression pattern: (identifier) @local.definition)
+(identifier) @local.reference
+";
+
+pub const GO_QUERY: &str = r"
+(function_declaration) @local.scope
+(method_declaration) @local.scope
+(func_literal) @local.scope
+(block) @local.scope
+(parameter_declaration name: (identifier) @local.definition.variable.parameter)
+(variadic_parameter_declaration name: (identifier) @local.definition.variable.parameter)
+(short_var_declaration left: (expression_list (identifier) @local.definition))
+(var_spec name: (identifier) @local.definition)
+(identifier) @local.reference
+";
+
+pub const C_QUERY: &str = r"
+(function_definition) @local.scope
+(compound_statement) @local.scope
+(parameter_declaration declarator: (identifier) @local.definition.variable.parameter)
+(parameter_declaration
+  declarator: (pointer_declarator declarator: (identifier) @local.definition.variable.parameter))
+(init_declarator declarator: (identifier) @local.definition)
+(declaration declarator: (identifier) @local.definition)
+(identifier) @local.reference
+";
+
+pub const CPP_QUERY: &str = r"
+(function_definition) @local.scope
+(lambda_expression) @local.scope
+(compound_statement) @local.scope
+(parameter_declaration declarator: (identifier) @local.definition.variable.parameter)
+(parameter_declaration
+  declarator: (pointer_declarator declarator: (identifier) @local.definition.variable.parameter))
+(parameter_declaration
+  declarator: (reference_declarator (identifier) @local.definition.variable.parameter))
+(init_declarator declarator: (identifier) @local.definition)
+(declaration declarator: (identifier) @local.definition)
+(identifier) @local.reference
+";
+
+pub const PYTHON_QUERY: &str = r"
+(function_definition) @local.scope
+(lambda) @local.scope
+(parameters (identifier) @local.definition.variable.parameter)
+(default_parameter name: (identifier) @local.definition.variable.parameter)
+(typed_parameter (identifier) @local.definition.variable.parameter)
+(typed_default_parameter name: (identifier) @local.definition.variable.parameter)
+(lambda_parameters (identifier) @local.definition.variable.parameter)
+(assignment left: (identifier) @local.definition)
+(for_statement left: (identifier) @local.definition)
+(identifier) @local.reference
+";
@@ -0,0 +1,31 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::data_dir;
+use crate::history;
+
+const LOG_FILE_NAME: &str = "neonano.log";
+
+/// `neonano.log` of the data directory.
+pub fn path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join(LOG_FILE_NAME))
+}
+
+/// Appends `message` to the log as a line of `source`, e.g. the name of a
+/// language server. Failing to log is not worth bothering the user.
+pub fn write(source: &str, message: &str) {
+    let Some(path) = path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+        let _ = writeln!(file, "{} [{source}] {message}", history::timestamp(time));
+    }
+}
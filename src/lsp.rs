@@ -1,22 +1,59 @@
 use anyhow::anyhow;
 use core::time::Duration;
 use lsp_types::{
-    lsp_notification, lsp_request, ClientCapabilities, DidOpenTextDocumentParams, Hover,
-    HoverClientCapabilities, HoverParams, InitializeParams, InitializedParams, MarkupKind,
-    Position, TextDocumentClientCapabilities, TextDocumentIdentifier, TextDocumentItem,
-    TextDocumentPositionParams, TextDocumentSyncClientCapabilities, Url,
-    WorkspaceClientCapabilities,
+    lsp_notification, lsp_request, CancelParams, ClientCapabilities, CodeActionClientCapabilities,
+    CodeActionContext, CodeActionKind, CodeActionKindLiteralSupport, CodeActionLiteralSupport,
+    CodeActionOrCommand, CodeActionParams, CodeActionResponse, CompletionClientCapabilities,
+    CompletionItem, CompletionItemCapability, CompletionItemCapabilityResolveSupport,
+    CompletionParams, CompletionResponse, ConfigurationParams, Diagnostic,
+    DidChangeConfigurationClientCapabilities, DidChangeConfigurationParams,
+    DidChangeTextDocumentParams, DidOpenTextDocumentParams, DocumentChanges,
+    DocumentFormattingClientCapabilities, DocumentFormattingParams, FormattingOptions,
+    GotoCapability, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverClientCapabilities,
+    HoverParams, InitializeParams, InitializedParams, Location, MarkupKind, NumberOrString, OneOf,
+    Position, ProgressParams, ProgressParamsValue, PublishDiagnosticsClientCapabilities,
+    PublishDiagnosticsParams, Range, TextDocumentClientCapabilities,
+    TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+    TextDocumentPositionParams, TextDocumentSyncClientCapabilities, TextEdit, Url,
+    VersionedTextDocumentIdentifier, WindowClientCapabilities, WorkDoneProgress,
+    WorkDoneProgressCancelParams, WorkspaceClientCapabilities, WorkspaceEdit,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::borrow::Cow;
 use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
 use std::process::{Child, Command, Stdio};
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, TryRecvError};
 use std::thread;
 use std::thread::sleep;
+use std::time::Instant;
+
+use crate::log;
+use crate::progress::Cancellation;
 
 static JSON_RPC: &str = "2.0";
+const GOTO_CAPABILITY: GotoCapability = GotoCapability {
+    dynamic_registration: None,
+    link_support: Some(true),
+};
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+/// Lines of stderr kept to explain a failed start; all of them are logged.
+const STDERR_LINES: usize = 50;
+/// How long to wait for more of stderr after a failed start.
+const STDERR_TIMEOUT: Duration = Duration::from_millis(100);
+/// How often a cancellable request checks whether it was cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const CODE_ACTION_KINDS: [&str; 8] = [
+    "",
+    "quickfix",
+    "refactor",
+    "refactor.extract",
+    "refactor.inline",
+    "refactor.rewrite",
+    "source",
+    "source.organizeImports",
+];
 
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Request {
@@ -29,11 +66,57 @@ pub struct Request {
     id: Option<i32>,
 }
 
+/// Anything the server sends: a response to one of our requests, a request
+/// of its own, or a notification.
 #[derive(Clone, Debug, Deserialize)]
-pub struct Response<'a> {
-    jsonrpc: &'a str,
+struct Message {
+    id: Option<Value>,
+    method: Option<String>,
+    params: Option<Value>,
     result: Option<Value>,
-    error: Option<Value>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Goto {
+    Definition,
+    TypeDefinition,
+    Implementation,
+}
+
+impl Goto {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Definition => "definition",
+            Self::TypeDefinition => "type definition",
+            Self::Implementation => "implementation",
+        }
+    }
+}
+
+/// A request was given up on because the user cancelled it.
+pub struct Cancelled;
+
+/// A `$/progress` work done token that has begun but not yet ended.
+struct Progress {
+    token: NumberOrString,
+    title: String,
+    message: Option<String>,
+    percentage: Option<u32>,
+    cancellable: bool,
+}
+
+impl Progress {
+    fn text(&self) -> String {
+        let mut text = self.title.clone();
+        if let Some(message) = &self.message {
+            text.push(' ');
+            text.push_str(message);
+        }
+        if let Some(percentage) = self.percentage {
+            text.push_str(&format!(" ({percentage}%)"));
+        }
+        text
+    }
 }
 
 pub struct LspConnector {
@@ -41,24 +124,50 @@ pub struct LspConnector {
     tx: Sender<String>,
     rx: Receiver<String>,
     child: Child,
+    /// The first lines the server wrote to stderr.
+    stderr: Receiver<String>,
+    name: String,
     lang: String,
     filename: String,
+    next_id: i32,
+    version: i32,
+    progress: Vec<Progress>,
+    /// The latest diagnostics published for the file, in document order.
+    diagnostics: Vec<Diagnostic>,
+    initialization_options: Option<Value>,
+    /// Answers `workspace/configuration`, looked up by section.
+    settings: Value,
 }
 
 impl LspConnector {
     fn start_process(
         sender: Sender<String>,
         receiver: Receiver<String>,
+        name: &str,
         path: &str,
-        args: Vec<&str>,
-    ) -> anyhow::Result<Child> {
+        args: &[String],
+    ) -> anyhow::Result<(Child, Receiver<String>)> {
         fn start_process_thread(
             child: &mut Child,
             sender: Sender<String>,
             receiver: Receiver<String>,
-        ) {
+            name: String,
+        ) -> Receiver<String> {
             let mut stdin = child.stdin.take().unwrap();
             let stdout = child.stdout.take().unwrap();
+            let stderr = child.stderr.take().unwrap();
+
+            let (stderr_tx, stderr_rx) = sync_channel(STDERR_LINES);
+            thread::spawn(move || {
+                for line in BufReader::new(stderr).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    log::write(&name, &line);
+                    // Beyond the first lines nobody would read them.
+                    let _ = stderr_tx.try_send(line);
+                }
+            });
 
             thread::spawn(move || {
                 let mut buf: String = String::new();
@@ -67,6 +176,8 @@ impl LspConnector {
                 loop {
                     buf.truncate(0);
                     match f.read_line(&mut buf) {
+                        // The server exited.
+                        Ok(0) => break,
                         Ok(_) => {
                             if !buf.to_lowercase().starts_with("content-length: ") {
                                 continue;
@@ -97,7 +208,10 @@ impl LspConnector {
             thread::spawn(move || loop {
                 match receiver.recv() {
                     Ok(line) => {
-                        stdin.write_all(line.as_bytes()).unwrap();
+                        // The server stopped reading; sends fail from now on.
+                        if stdin.write_all(line.as_bytes()).is_err() {
+                            break;
+                        }
                     }
                     Err(e) => {
                         println!("Error: {:?}", e);
@@ -105,59 +219,83 @@ impl LspConnector {
                     }
                 }
             });
+            stderr_rx
         }
         let mut child = Command::new(path)
             .args(args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::null())
+            .stderr(Stdio::piped())
             .spawn()?;
 
-        start_process_thread(&mut child, sender, receiver);
-        Ok(child)
+        let stderr = start_process_thread(&mut child, sender, receiver, name.to_string());
+        Ok((child, stderr))
     }
     pub fn new(
         lsp_path: &str,
-        lsp_args: Vec<&str>,
-        lang: String,
+        lsp_args: &[String],
+        lang: &str,
         filename: String,
     ) -> anyhow::Result<Self> {
         let (tx1, rx1) = channel();
         let (tx2, rx2) = channel();
 
-        let child = Self::start_process(tx1, rx2, lsp_path, lsp_args)?;
+        let name = Path::new(lsp_path)
+            .file_name()
+            .map_or(lsp_path.into(), |name| name.to_string_lossy());
+        let (child, stderr) = Self::start_process(tx1, rx2, &name, lsp_path, lsp_args)?;
         Ok(Self {
             initialized: false,
             tx: tx2,
             rx: rx1,
             child,
+            stderr,
+            name: name.into_owned(),
             filename,
-            lang,
+            lang: lang.to_owned(),
+            next_id: 0,
+            version: 0,
+            progress: Vec::new(),
+            diagnostics: Vec::new(),
+            initialization_options: None,
+            settings: Value::Null,
         })
     }
 
+    /// Server specific options, sent with `initialize`; the settings are sent
+    /// after it and answer `workspace/configuration`. Has to be called
+    /// before [`Self::init`].
+    pub fn configure(&mut self, initialization_options: Option<Value>, settings: Option<Value>) {
+        self.initialization_options = initialization_options;
+        self.settings = settings.unwrap_or(Value::Null);
+    }
+
     pub fn is_initialized(&self) -> bool {
         self.initialized
     }
 
-    pub fn init(&mut self, current_text: String) {
+    /// Initializes the server and opens the document. Fails with the line
+    /// of stderr most likely to say why if the server does not answer.
+    pub fn init(&mut self, current_text: String) -> anyhow::Result<()> {
         let init = Request::from_request::<lsp_request!("initialize")>(
-            0,
+            self.next_id(),
             InitializeParams {
                 process_id: None,
                 root_path: None,
                 root_uri: None,
-                initialization_options: None,
+                initialization_options: self.initialization_options.clone(),
                 capabilities: ClientCapabilities {
                     workspace: Some(WorkspaceClientCapabilities {
                         apply_edit: None,
                         workspace_edit: None,
-                        did_change_configuration: None,
+                        did_change_configuration: Some(
+                            DidChangeConfigurationClientCapabilities::default(),
+                        ),
                         did_change_watched_files: None,
                         symbol: None,
                         execute_command: None,
                         workspace_folders: Some(true),
-                        configuration: None,
+                        configuration: Some(true),
                         semantic_tokens: None,
                         code_lens: None,
                         file_operations: None,
@@ -172,28 +310,54 @@ impl LspConnector {
                             will_save_wait_until: None,
                             did_save: None,
                         }),
-                        completion: None,
+                        completion: Some(CompletionClientCapabilities {
+                            completion_item: Some(CompletionItemCapability {
+                                snippet_support: Some(true),
+                                documentation_format: Some(vec![
+                                    MarkupKind::Markdown,
+                                    MarkupKind::PlainText,
+                                ]),
+                                resolve_support: Some(CompletionItemCapabilityResolveSupport {
+                                    properties: vec![
+                                        String::from("documentation"),
+                                        String::from("detail"),
+                                    ],
+                                }),
+                                ..CompletionItemCapability::default()
+                            }),
+                            ..CompletionClientCapabilities::default()
+                        }),
                         hover: Some(HoverClientCapabilities {
                             dynamic_registration: Some(true),
-                            content_format: Some(vec![MarkupKind::PlainText]),
+                            content_format: Some(vec![MarkupKind::Markdown, MarkupKind::PlainText]),
                         }),
                         signature_help: None,
                         references: None,
                         document_highlight: None,
                         document_symbol: None,
-                        formatting: None,
+                        formatting: Some(DocumentFormattingClientCapabilities::default()),
                         range_formatting: None,
                         on_type_formatting: None,
                         declaration: None,
-                        definition: None,
-                        type_definition: None,
-                        implementation: None,
-                        code_action: None,
+                        definition: Some(GOTO_CAPABILITY),
+                        type_definition: Some(GOTO_CAPABILITY),
+                        implementation: Some(GOTO_CAPABILITY),
+                        code_action: Some(CodeActionClientCapabilities {
+                            code_action_literal_support: Some(CodeActionLiteralSupport {
+                                code_action_kind: CodeActionKindLiteralSupport {
+                                    value_set: CODE_ACTION_KINDS
+                                        .iter()
+                                        .map(ToString::to_string)
+                                        .collect(),
+                                },
+                            }),
+                            ..CodeActionClientCapabilities::default()
+                        }),
                         code_lens: None,
                         document_link: None,
                         color_provider: None,
                         rename: None,
-                        publish_diagnostics: None,
+                        publish_diagnostics: Some(PublishDiagnosticsClientCapabilities::default()),
                         folding_range: None,
                         selection_range: None,
                         linked_editing_range: None,
@@ -205,7 +369,11 @@ impl LspConnector {
                         inlay_hint: None,
                         diagnostic: None,
                     }),
-                    window: None,
+                    window: Some(WindowClientCapabilities {
+                        work_done_progress: Some(true),
+                        show_message: None,
+                        show_document: None,
+                    }),
                     general: None,
                     experimental: None,
                 },
@@ -216,58 +384,561 @@ impl LspConnector {
                 work_done_progress_params: Default::default(),
             },
         );
-        self.send_request(&init);
-        self.recv().unwrap();
+        if self.request(&init).is_none() {
+            return Err(anyhow!(self.startup_error()));
+        }
 
         let init_notify =
             Request::from_notification::<lsp_notification!("initialized")>(InitializedParams {});
-        self.send_request(&init_notify);
+        self.send(&init_notify)?;
+
+        // Servers that do not ask with `workspace/configuration`, e.g.
+        // pyright, take their settings from this notification.
+        if !self.settings.is_null() {
+            let settings = Request::from_notification::<
+                lsp_notification!("workspace/didChangeConfiguration"),
+            >(DidChangeConfigurationParams {
+                settings: self.settings.clone(),
+            });
+            self.send(&settings)?;
+        }
 
         let open_notify = Request::from_notification::<lsp_notification!("textDocument/didOpen")>(
             DidOpenTextDocumentParams {
                 text_document: TextDocumentItem {
-                    uri: Url::try_from(format!("file:///{}", self.filename).as_str()).unwrap(),
+                    uri: self.uri(),
                     language_id: self.lang.clone(),
                     version: 0,
                     text: current_text,
                 },
             },
         );
-        self.send_request(&open_notify);
+        self.send(&open_notify)?;
 
         self.initialized = true;
+        Ok(())
+    }
+
+    fn startup_error(&self) -> String {
+        let mut lines = Vec::new();
+        // Ends right away once the server exited and stderr is closed.
+        while let Ok(line) = self.stderr.recv_timeout(STDERR_TIMEOUT) {
+            lines.push(line);
+        }
+        let line = lines
+            .iter()
+            .find(|line| line.to_lowercase().contains("error"))
+            .or_else(|| lines.iter().find(|line| !line.trim().is_empty()));
+        match line {
+            Some(line) => line.trim().to_string(),
+            None => "no answer to initialize".to_string(),
+        }
+    }
+
+    /// Replaces the whole text of the document on the server.
+    pub fn did_change(&mut self, text: String) {
+        self.version += 1;
+        let change_notify = Request::from_notification::<lsp_notification!("textDocument/didChange")>(
+            DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: self.uri(),
+                    version: self.version,
+                },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text,
+                }],
+            },
+        );
+        // A server that is gone has nothing to keep up to date.
+        let _ = self.send(&change_notify);
+    }
+
+    pub fn completion(&mut self, line: u32, character: u32) -> Vec<CompletionItem> {
+        let completion = Request::from_request::<lsp_request!("textDocument/completion")>(
+            self.next_id(),
+            CompletionParams {
+                text_document_position: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier { uri: self.uri() },
+                    position: Position { line, character },
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            },
+        );
+
+        match self
+            .request(&completion)
+            .and_then(|res| serde_json::from_value::<CompletionResponse>(res).ok())
+        {
+            Some(CompletionResponse::Array(items)) => items,
+            Some(CompletionResponse::List(list)) => list.items,
+            None => Vec::new(),
+        }
+    }
+
+    /// The edits formatting the document, `None` if `cancellation` is
+    /// cancelled before the server answers.
+    pub fn formatting(
+        &mut self,
+        tab_size: u32,
+        insert_spaces: bool,
+        cancellation: &Cancellation,
+    ) -> Option<Vec<TextEdit>> {
+        let formatting = Request::from_request::<lsp_request!("textDocument/formatting")>(
+            self.next_id(),
+            DocumentFormattingParams {
+                text_document: TextDocumentIdentifier { uri: self.uri() },
+                options: FormattingOptions {
+                    tab_size,
+                    insert_spaces,
+                    ..FormattingOptions::default()
+                },
+                work_done_progress_params: Default::default(),
+            },
+        );
+
+        match self.request_cancellable(&formatting, cancellation) {
+            Ok(res) => Some(
+                res.and_then(|res| serde_json::from_value::<Vec<TextEdit>>(res).ok())
+                    .unwrap_or_default(),
+            ),
+            Err(Cancelled) => None,
+        }
+    }
+
+    /// Code actions that come with an edit. Actions that only name a
+    /// command would have to be executed by the server and are left out.
+    pub fn code_actions(&mut self, line: u32, character: u32) -> Vec<(String, Vec<TextEdit>)> {
+        let position = Position { line, character };
+        let code_action = Request::from_request::<lsp_request!("textDocument/codeAction")>(
+            self.next_id(),
+            CodeActionParams {
+                text_document: TextDocumentIdentifier { uri: self.uri() },
+                range: Range {
+                    start: position,
+                    end: position,
+                },
+                context: CodeActionContext::default(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            },
+        );
+
+        let actions = self
+            .request(&code_action)
+            .and_then(|res| serde_json::from_value::<CodeActionResponse>(res).ok())
+            .unwrap_or_default();
+        actions
+            .into_iter()
+            .filter_map(|action| match action {
+                CodeActionOrCommand::CodeAction(action) => {
+                    let edits = self.edits_for_document(action.edit?);
+                    Some((action.title, edits))
+                }
+                CodeActionOrCommand::Command(_) => None,
+            })
+            .collect()
+    }
+
+    /// The edits of the action organizing the imports of the document of
+    /// `lines` lines, `None` if `cancellation` is cancelled before the
+    /// server answers.
+    pub fn organize_imports(
+        &mut self,
+        lines: u32,
+        cancellation: &Cancellation,
+    ) -> Option<Vec<TextEdit>> {
+        let code_action = Request::from_request::<lsp_request!("textDocument/codeAction")>(
+            self.next_id(),
+            CodeActionParams {
+                text_document: TextDocumentIdentifier { uri: self.uri() },
+                range: Range {
+                    start: Position::new(0, 0),
+                    end: Position::new(lines, 0),
+                },
+                context: CodeActionContext {
+                    only: Some(vec![CodeActionKind::SOURCE_ORGANIZE_IMPORTS]),
+                    ..CodeActionContext::default()
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            },
+        );
+
+        let actions = match self.request_cancellable(&code_action, cancellation) {
+            Ok(res) => res
+                .and_then(|res| serde_json::from_value::<CodeActionResponse>(res).ok())
+                .unwrap_or_default(),
+            Err(Cancelled) => return None,
+        };
+        Some(
+            actions
+                .into_iter()
+                .find_map(|action| match action {
+                    CodeActionOrCommand::CodeAction(action) => action.edit,
+                    CodeActionOrCommand::Command(_) => None,
+                })
+                .map(|edit| self.edits_for_document(edit))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// The part of `edit` that touches this connector's document.
+    fn edits_for_document(&self, edit: WorkspaceEdit) -> Vec<TextEdit> {
+        let uri = self.uri();
+        let mut edits = Vec::new();
+        if let Some(changes) = edit.changes {
+            for (changed_uri, mut text_edits) in changes {
+                if changed_uri == uri {
+                    edits.append(&mut text_edits);
+                }
+            }
+        }
+        if let Some(DocumentChanges::Edits(document_edits)) = edit.document_changes {
+            for document_edit in document_edits {
+                if document_edit.text_document.uri == uri {
+                    edits.extend(document_edit.edits.into_iter().map(|edit| match edit {
+                        OneOf::Left(edit) => edit,
+                        OneOf::Right(annotated) => annotated.text_edit,
+                    }));
+                }
+            }
+        }
+        edits
+    }
+
+    pub fn goto(&mut self, target: Goto, line: u32, character: u32) -> Vec<Location> {
+        match target {
+            Goto::Definition => {
+                self.goto_request::<lsp_request!("textDocument/definition")>(line, character)
+            }
+            Goto::TypeDefinition => {
+                self.goto_request::<lsp_request!("textDocument/typeDefinition")>(line, character)
+            }
+            Goto::Implementation => {
+                self.goto_request::<lsp_request!("textDocument/implementation")>(line, character)
+            }
+        }
+    }
+
+    /// The definition, type definition and implementation requests share
+    /// their parameters and response.
+    fn goto_request<R>(&mut self, line: u32, character: u32) -> Vec<Location>
+    where
+        R: lsp_types::request::Request<Params = GotoDefinitionParams>,
+    {
+        let goto = Request::from_request::<R>(
+            self.next_id(),
+            GotoDefinitionParams {
+                text_document_position_params: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier { uri: self.uri() },
+                    position: Position { line, character },
+                },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            },
+        );
+
+        match self
+            .request(&goto)
+            .and_then(|res| serde_json::from_value::<GotoDefinitionResponse>(res).ok())
+        {
+            Some(GotoDefinitionResponse::Scalar(location)) => vec![location],
+            Some(GotoDefinitionResponse::Array(locations)) => locations,
+            Some(GotoDefinitionResponse::Link(links)) => links
+                .into_iter()
+                .map(|link| Location {
+                    uri: link.target_uri,
+                    range: link.target_selection_range,
+                })
+                .collect(),
+            None => Vec::new(),
+        }
     }
 
-    pub fn hover(&self, line: u32, character: u32) -> Option<Hover> {
+    /// Fills in the lazily computed parts of `item`, e.g. its documentation.
+    pub fn resolve_completion(&mut self, item: &CompletionItem) -> Option<CompletionItem> {
+        let resolve = Request::from_request::<lsp_request!("completionItem/resolve")>(
+            self.next_id(),
+            item.clone(),
+        );
+        let res = self.request(&resolve)?;
+        serde_json::from_value::<CompletionItem>(res).ok()
+    }
+
+    pub fn hover(&mut self, line: u32, character: u32) -> Option<Hover> {
         let hover = Request::from_request::<lsp_request!("textDocument/hover")>(
-            1,
+            self.next_id(),
             HoverParams {
                 text_document_position_params: TextDocumentPositionParams {
-                    text_document: TextDocumentIdentifier {
-                        uri: Url::try_from(format!("file:///{}", self.filename).as_str()).unwrap(),
-                    },
+                    text_document: TextDocumentIdentifier { uri: self.uri() },
                     position: Position { line, character },
                 },
                 work_done_progress_params: Default::default(),
             },
         );
 
-        self.send_request(&hover);
-        let res = self.recv().unwrap_or_default();
-        if let Ok(res) = serde_json::from_str::<Response>(res.as_str()) {
-            if let Some(params) = res.result {
-                if let Ok(hover) = serde_json::from_value::<Hover>(params) {
-                    return Some(hover);
+        let res = self.request(&hover)?;
+        serde_json::from_value::<Hover>(res).ok()
+    }
+
+    /// Handles everything the server sent since the last call without
+    /// blocking. Returns whether anything arrived.
+    pub fn poll(&mut self) -> bool {
+        let mut received = false;
+        while let Some(line) = self.try_recv() {
+            received = true;
+            if let Ok(message) = serde_json::from_str::<Message>(&line) {
+                self.handle_message(message);
+            }
+        }
+        received
+    }
+
+    /// The most recently started work done progress, e.g.
+    /// `rust-analyzer: Indexing 3/120 (2%)`.
+    pub fn progress(&self) -> Option<String> {
+        self.progress
+            .last()
+            .map(|progress| format!("{}: {}", self.name, progress.text()))
+    }
+
+    /// Sends `window/workDoneProgress/cancel` for the most recently started
+    /// progress that can be cancelled. Returns whether there was one to
+    /// tell the server about.
+    pub fn cancel_progress(&mut self) -> bool {
+        let Some(progress) = self
+            .progress
+            .iter()
+            .rev()
+            .find(|progress| progress.cancellable)
+        else {
+            return false;
+        };
+        let cancel = Request::from_notification::<
+            lsp_notification!("window/workDoneProgress/cancel"),
+        >(WorkDoneProgressCancelParams {
+            token: progress.token.clone(),
+        });
+        self.send(&cancel).is_ok()
+    }
+
+    /// Asks the server to shut down and exit, giving it `SHUTDOWN_TIMEOUT`
+    /// for each step before the process is killed.
+    pub fn shutdown(&mut self) {
+        if self.initialized {
+            let shutdown = Request::from_request::<lsp_request!("shutdown")>(self.next_id(), ());
+            let id = shutdown.id.map(Value::from);
+            // Once the server is gone, there is nobody to ask; it is only
+            // waited for below.
+            if self.send(&shutdown).is_ok() {
+                let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+                while Instant::now() < deadline {
+                    let Some(line) = self.try_recv() else {
+                        sleep(Duration::from_millis(10));
+                        continue;
+                    };
+                    if serde_json::from_str::<Message>(&line)
+                        .is_ok_and(|message| message.method.is_none() && message.id == id)
+                    {
+                        break;
+                    }
                 }
+                let _ = self.send(&Request::from_notification::<lsp_notification!("exit")>(()));
             }
+            self.initialized = false;
         }
-        None
+
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while Instant::now() < deadline {
+            if !matches!(self.child.try_wait(), Ok(None)) {
+                return;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn uri(&self) -> Url {
+        Url::from_file_path(&self.filename).unwrap_or_else(|()| {
+            Url::try_from(format!("file:///{}", self.filename).as_str()).unwrap()
+        })
     }
 
-    fn send_request(&self, req: &Request) {
-        let s = serde_json::to_string(req).unwrap();
+    fn next_id(&mut self) -> i32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Sends `req` and waits for its result, handling whatever else the
+    /// server sends in the meantime.
+    fn request(&mut self, req: &Request) -> Option<Value> {
+        let id = Value::from(req.id?);
+        self.send(req).ok()?;
+        loop {
+            let line = self.recv().ok()?;
+            let Ok(message) = serde_json::from_str::<Message>(&line) else {
+                continue;
+            };
+            if message.method.is_none() && message.id.as_ref() == Some(&id) {
+                return message.result;
+            }
+            self.handle_message(message);
+        }
+    }
+
+    /// Like `request`, but gives up once `cancellation` is cancelled and
+    /// tells the server with `$/cancelRequest`.
+    fn request_cancellable(
+        &mut self,
+        req: &Request,
+        cancellation: &Cancellation,
+    ) -> Result<Option<Value>, Cancelled> {
+        let Some(id) = req.id else {
+            return Ok(None);
+        };
+        if self.send(req).is_err() {
+            return Ok(None);
+        }
+        loop {
+            if cancellation.is_cancelled() {
+                let cancel = Request::from_notification::<lsp_notification!("$/cancelRequest")>(
+                    CancelParams {
+                        id: NumberOrString::Number(id),
+                    },
+                );
+                let _ = self.send(&cancel);
+                return Err(Cancelled);
+            }
+            let Some(line) = self.try_recv() else {
+                sleep(CANCEL_POLL_INTERVAL);
+                continue;
+            };
+            let Ok(message) = serde_json::from_str::<Message>(&line) else {
+                continue;
+            };
+            if message.method.is_none() && message.id == Some(Value::from(id)) {
+                return Ok(message.result);
+            }
+            self.handle_message(message);
+        }
+    }
+
+    /// The settings under a dotted `section` such as `python.analysis`, or
+    /// all of them without one.
+    fn setting(&self, section: Option<&str>) -> Value {
+        let Some(section) = section.filter(|section| !section.is_empty()) else {
+            return self.settings.clone();
+        };
+        section
+            .split('.')
+            .try_fold(&self.settings, |value, key| value.get(key))
+            .cloned()
+            .unwrap_or(Value::Null)
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        match (message.id, message.method) {
+            (Some(id), Some(method)) => self.handle_server_request(id, &method, message.params),
+            (None, Some(method)) => self.handle_notification(&method, message.params),
+            _ => (),
+        }
+    }
+
+    fn handle_server_request(&self, id: Value, method: &str, params: Option<Value>) {
+        let reply = match method {
+            "window/workDoneProgress/create" => {
+                json!({ "jsonrpc": JSON_RPC, "id": id, "result": null })
+            }
+            "workspace/configuration" => {
+                let items = params
+                    .and_then(|params| serde_json::from_value::<ConfigurationParams>(params).ok())
+                    .map(|params| params.items)
+                    .unwrap_or_default();
+                let result: Vec<Value> = items
+                    .iter()
+                    .map(|item| self.setting(item.section.as_deref()))
+                    .collect();
+                json!({ "jsonrpc": JSON_RPC, "id": id, "result": result })
+            }
+            _ => json!({
+                "jsonrpc": JSON_RPC,
+                "id": id,
+                "error": { "code": -32601, "message": format!("Unhandled method {method}") },
+            }),
+        };
+        let _ = self.send(&reply);
+    }
+
+    fn handle_notification(&mut self, method: &str, params: Option<Value>) {
+        match method {
+            "$/progress" => self.handle_progress(params),
+            "textDocument/publishDiagnostics" => self.handle_diagnostics(params),
+            _ => (),
+        }
+    }
+
+    fn handle_diagnostics(&mut self, params: Option<Value>) {
+        let Some(Ok(params)) = params.map(serde_json::from_value::<PublishDiagnosticsParams>)
+        else {
+            return;
+        };
+        if params.uri == self.uri() {
+            self.diagnostics = params.diagnostics;
+            self.diagnostics.sort_by_key(|diagnostic| {
+                (
+                    diagnostic.range.start.line,
+                    diagnostic.range.start.character,
+                )
+            });
+        }
+    }
+
+    fn handle_progress(&mut self, params: Option<Value>) {
+        let Some(Ok(params)) = params.map(serde_json::from_value::<ProgressParams>) else {
+            return;
+        };
+        let ProgressParamsValue::WorkDone(work_done) = params.value;
+        match work_done {
+            WorkDoneProgress::Begin(begin) => {
+                self.progress.retain(|p| p.token != params.token);
+                self.progress.push(Progress {
+                    token: params.token,
+                    title: begin.title,
+                    message: begin.message,
+                    percentage: begin.percentage,
+                    cancellable: begin.cancellable.unwrap_or(false),
+                });
+            }
+            WorkDoneProgress::Report(report) => {
+                if let Some(progress) = self.progress.iter_mut().find(|p| p.token == params.token) {
+                    if report.message.is_some() {
+                        progress.message = report.message;
+                    }
+                    progress.percentage = report.percentage;
+                }
+            }
+            WorkDoneProgress::End(_) => self.progress.retain(|p| p.token != params.token),
+        }
+    }
+
+    /// Writes `message` to the server, which fails once the server and
+    /// the thread writing to it are gone.
+    fn send<T: Serialize>(&self, message: &T) -> anyhow::Result<()> {
+        let s = serde_json::to_string(message)?;
         let payload = format!("Content-Length: {}\r\n\r\n{}", s.len(), s);
-        self.tx.send(payload).unwrap();
+        self.tx
+            .send(payload)
+            .map_err(|_| anyhow!("The language server {} is gone", self.name))
     }
 
     fn try_recv(&self) -> Option<String> {
@@ -295,6 +966,12 @@ impl LspConnector {
     }
 }
 
+impl Drop for LspConnector {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
 impl Request {
     fn from_request<R>(id: i32, params: R::Params) -> Self
     where
@@ -320,3 +997,19 @@ impl Request {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_after_the_server_exited() {
+        let mut lsp =
+            LspConnector::new("true", &[], "plaintext", String::from("gone.txt")).unwrap();
+        let _ = lsp.child.wait();
+        lsp.initialized = true;
+        // Shutting down writes to the exited server until the writer gives
+        // up, which must not panic.
+        drop(lsp);
+    }
+}
@@ -16,14 +16,64 @@ pub use filetype::FileType;
 pub use row::Row;
 pub use terminal::Terminal;
 
+mod annotation;
+mod args;
+mod atomic;
+mod buffer;
+mod calc;
+mod check;
+mod clipboard;
+mod command;
+mod comment;
+mod completion;
+mod config;
+mod count;
+mod diff;
+mod doc_comment;
 mod document;
 mod editor;
+mod fallback;
+mod file_state;
 mod filetype;
 mod floating_item;
+mod fold;
+mod grammar;
+mod grep;
+mod health;
+mod hex;
 mod highlighting;
+mod history;
+mod ignore;
+mod indent;
+mod index;
+mod lines;
+mod list;
+mod locale;
+mod locals;
+mod log;
 mod lsp;
+mod mark;
+mod markdown;
+mod measure;
+mod modeline;
+mod narrow;
+mod progress;
+mod prompt;
 mod row;
+mod save;
+mod snippet;
+mod sort;
+mod spell;
+mod stream;
+mod structural;
 mod terminal;
+#[cfg(test)]
+mod test_backend;
+mod textobject;
+mod theme;
+mod trash;
+mod undo;
+mod workspace;
 
 fn main() {
     Editor::default().run();
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+
+use crate::Position;
+
+/// Named positions of a buffer to jump back to. They move along as lines
+/// are inserted or deleted above them.
+#[derive(Clone, Default)]
+pub struct Marks {
+    positions: BTreeMap<String, Position>,
+}
+
+impl Marks {
+    pub fn get(&self, name: &str) -> Option<&Position> {
+        self.positions.get(name)
+    }
+
+    pub fn set(&mut self, name: String, position: Position) {
+        self.positions.insert(name, position);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Position)> {
+        self.positions
+            .iter()
+            .map(|(name, position)| (name.as_str(), position))
+    }
+
+    /// Moves the marks after the rows `start..=old_end`, which were
+    /// replaced by `start..=new_end`. Marks of rows that were joined into
+    /// `start` go to its start.
+    pub fn edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+        if old_end == new_end {
+            return;
+        }
+        for position in self.positions.values_mut() {
+            if position.y > old_end {
+                position.y = position.y + new_end - old_end;
+            } else if position.y > start && position.y > new_end {
+                *position = Position { x: 0, y: start };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marks() -> Marks {
+        let mut marks = Marks::default();
+        for (name, y) in [("a", 1), ("b", 3), ("c", 6)] {
+            marks.set(name.to_string(), Position { x: 2, y });
+        }
+        marks
+    }
+
+    fn rows(marks: &Marks) -> Vec<(&str, usize, usize)> {
+        marks
+            .iter()
+            .map(|(name, position)| (name, position.x, position.y))
+            .collect()
+    }
+
+    #[test]
+    fn inserted_lines() {
+        let mut marks = marks();
+        marks.edit(3, 3, 5);
+        assert_eq!(rows(&marks), [("a", 2, 1), ("b", 2, 3), ("c", 2, 8)]);
+    }
+
+    #[test]
+    fn deleted_lines() {
+        let mut marks = marks();
+        marks.edit(2, 4, 2);
+        assert_eq!(rows(&marks), [("a", 2, 1), ("b", 0, 2), ("c", 2, 4)]);
+    }
+
+    #[test]
+    fn same_lines() {
+        let mut marks = marks();
+        marks.edit(3, 3, 3);
+        marks.set(String::from("a"), Position { x: 0, y: 0 });
+        assert_eq!(rows(&marks), [("a", 0, 0), ("b", 2, 3), ("c", 2, 6)]);
+        assert!(marks.get("d").is_none());
+    }
+}
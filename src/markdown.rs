@@ -0,0 +1,442 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::filetype::FileType;
+use crate::floating_item::FloatingItem;
+use crate::highlighting::{type_at, Highlight};
+use crate::theme::{self, Style};
+use crate::Position;
+
+const RULE: &str = "─";
+
+/// A rendered line: the text to show and a style for each of its graphemes.
+#[derive(Default)]
+pub struct StyledLine {
+    pub text: String,
+    pub styles: Vec<Style>,
+}
+
+impl StyledLine {
+    fn push(&mut self, text: &str, style: Style) {
+        self.text.push_str(text);
+        self.styles
+            .extend(std::iter::repeat_n(style, text.graphemes(true).count()));
+    }
+
+    pub fn width(&self) -> usize {
+        self.styles.len()
+    }
+}
+
+/// A floating item showing `text` rendered at most `max_width` wide.
+pub fn floating(text: &str, max_width: usize) -> FloatingItem {
+    floating_lines(render(text, max_width), max_width)
+}
+
+/// A floating item showing plain `text` as it is, breaking lines wider
+/// than `max_width` at spaces.
+pub fn floating_plain(text: &str, max_width: usize) -> FloatingItem {
+    let lines = text
+        .lines()
+        .flat_map(|line| {
+            let mut styled = StyledLine::default();
+            styled.push(line, Style::default());
+            wrap(styled, max_width)
+        })
+        .collect();
+    floating_lines(lines, max_width)
+}
+
+/// A floating item showing `lines`, at most `max_width` wide.
+fn floating_lines(lines: Vec<StyledLine>, max_width: usize) -> FloatingItem {
+    let width = lines
+        .iter()
+        .map(StyledLine::width)
+        .max()
+        .unwrap_or(0)
+        .min(max_width);
+    let height = lines.len();
+    let (text, styles): (Vec<String>, Vec<Vec<Style>>) = lines
+        .into_iter()
+        .map(|line| (line.text, line.styles))
+        .unzip();
+    FloatingItem::new(Position::default(), width, height, text).with_styles(styles)
+}
+
+/// `text` with a `\` before each ASCII punctuation character, so that
+/// markdown shows it as it is.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Renders the subset of markdown language servers use in hovers:
+/// headings, emphasis, inline code, links, rules and fenced code blocks.
+/// Markers are dropped, runs of blank lines are collapsed. Paragraphs and
+/// headings are reflowed to `max_width`; code blocks are kept verbatim.
+pub fn render(text: &str, max_width: usize) -> Vec<StyledLine> {
+    let mut lines: Vec<StyledLine> = Vec::new();
+    let mut rules = Vec::new();
+    // The language and lines of the fenced code block being read.
+    let mut code_block: Option<(&str, Vec<&str>)> = None;
+    // The source lines of the paragraph being read.
+    let mut paragraph: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if code_block.is_none() && !paragraph.is_empty() {
+            let continues = !trimmed.is_empty()
+                && !trimmed.starts_with("```")
+                && !is_rule(trimmed)
+                && heading(trimmed).is_none()
+                && !starts_block(trimmed);
+            if continues {
+                paragraph.push(trimmed);
+                continue;
+            }
+            render_paragraph(&paragraph, max_width, &mut lines);
+            paragraph.clear();
+        }
+        if let Some(info) = trimmed.strip_prefix("```") {
+            match code_block.take() {
+                Some((language, code)) => lines.extend(highlight_code(language, &code)),
+                None => {
+                    code_block = Some((info.split_whitespace().next().unwrap_or(""), Vec::new()))
+                }
+            }
+            continue;
+        }
+        if let Some((_, code)) = code_block.as_mut() {
+            code.push(line);
+            continue;
+        }
+        if is_rule(trimmed) {
+            rules.push(lines.len());
+            lines.push(StyledLine::default());
+        } else if let Some((level, heading)) = heading(trimmed) {
+            let style = theme::current().syntax("markup.heading").patch(Style {
+                bold: true,
+                underline: level == 1,
+                ..Style::default()
+            });
+            let mut styled = StyledLine::default();
+            render_inline(heading, style, &mut styled);
+            lines.extend(wrap(styled, max_width));
+        } else if trimmed.is_empty() {
+            if lines.last().is_some_and(|last| !last.text.is_empty()) {
+                lines.push(StyledLine::default());
+            }
+        } else {
+            paragraph.push(trimmed);
+        }
+    }
+    if let Some((language, code)) = code_block {
+        lines.extend(highlight_code(language, &code));
+    }
+    render_paragraph(&paragraph, max_width, &mut lines);
+    while lines.last().is_some_and(|last| last.text.is_empty()) {
+        lines.pop();
+    }
+
+    let width = lines
+        .iter()
+        .map(StyledLine::width)
+        .max()
+        .unwrap_or(0)
+        .min(max_width);
+    for index in rules {
+        if let Some(line) = lines.get_mut(index) {
+            line.push(&RULE.repeat(width.max(1)), Style::default());
+        }
+    }
+    lines
+}
+
+/// Whether `line` starts a list item or quote rather than continuing the
+/// paragraph above it.
+fn starts_block(line: &str) -> bool {
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    ["- ", "* ", "+ ", "> "]
+        .iter()
+        .any(|marker| line.starts_with(marker))
+        || (digits > 0 && line[digits..].starts_with(". "))
+}
+
+/// Joins the source lines of a paragraph and reflows them to `max_width`.
+fn render_paragraph(paragraph: &[&str], max_width: usize, lines: &mut Vec<StyledLine>) {
+    if paragraph.is_empty() {
+        return;
+    }
+    let mut styled = StyledLine::default();
+    render_inline(&paragraph.join(" "), Style::default(), &mut styled);
+    lines.extend(wrap(styled, max_width));
+}
+
+/// Breaks `line` at spaces into lines of at most `width` graphemes. Words
+/// longer than that are split.
+fn wrap(line: StyledLine, width: usize) -> Vec<StyledLine> {
+    if line.width() <= width || width == 0 {
+        return vec![line];
+    }
+    let graphemes: Vec<(&str, Style)> = line
+        .text
+        .graphemes(true)
+        .zip(line.styles.iter().copied())
+        .collect();
+    let mut wrapped = Vec::new();
+    let mut current = StyledLine::default();
+    for word in graphemes.split(|(grapheme, _)| *grapheme == " ") {
+        if word.is_empty() {
+            continue;
+        }
+        if current.width() > 0 && current.width() + 1 + word.len() > width {
+            wrapped.push(std::mem::take(&mut current));
+        }
+        if current.width() > 0 {
+            current.push(" ", Style::default());
+        }
+        for (grapheme, style) in word {
+            if current.width() == width {
+                wrapped.push(std::mem::take(&mut current));
+            }
+            current.push(grapheme, *style);
+        }
+    }
+    if current.width() > 0 {
+        wrapped.push(current);
+    }
+    wrapped
+}
+
+/// Colours `code` with the tree-sitter highlighter of `language` the way the
+/// buffer would be, or in a single colour if the language is unknown.
+fn highlight_code(language: &str, code: &[&str]) -> Vec<StyledLine> {
+    let spans = FileType::from_language(language)
+        .and_then(|file_type| Highlight::from_options(&file_type.highlighting_options()))
+        .map(|mut highlighter| highlighter.highlight(&code.join("\n")));
+    let theme = theme::current();
+    code.iter()
+        .enumerate()
+        .map(|(row, line)| {
+            let mut styled = StyledLine::default();
+            for (index, grapheme) in line.grapheme_indices(true) {
+                let name = match &spans {
+                    Some(spans) => type_at(spans.get(row).map_or(&[], Vec::as_slice), index).name(),
+                    None => "markup.raw",
+                };
+                styled.push(grapheme, theme.syntax(name));
+            }
+            styled
+        })
+        .collect()
+}
+
+fn is_rule(line: &str) -> bool {
+    line.len() >= 3
+        && ["-", "*", "_"]
+            .iter()
+            .any(|marker| line.chars().all(|c| c.to_string() == *marker))
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let rest = line.get(level..)?;
+    (1..=6)
+        .contains(&level)
+        .then_some(())
+        .and_then(|()| rest.strip_prefix(' '))
+        .map(|heading| (level, heading.trim_end_matches('#').trim_end()))
+}
+
+/// Emphasis, strikethrough, inline code and links inside one line. A marker only opens a
+/// span if it is closed later on the same line, so stray `*` stay visible.
+fn render_inline(line: &str, base: Style, styled: &mut StyledLine) {
+    let mut style = base;
+    let mut rest = line;
+    // Which of `*` and `_` opened the current bold and italic spans.
+    let mut bold_marker = None;
+    let mut italic_marker = None;
+    let mut struck = false;
+    while let Some(grapheme) = rest.graphemes(true).next() {
+        let c = grapheme.chars().next().unwrap_or_default();
+        let previous_is_word = styled
+            .text
+            .chars()
+            .last()
+            .is_some_and(char::is_alphanumeric);
+        if c == '\\' {
+            if let Some(escaped) = rest[1..].chars().next().filter(char::is_ascii_punctuation) {
+                styled.push(&escaped.to_string(), style);
+                rest = &rest[1 + escaped.len_utf8()..];
+                continue;
+            }
+        } else if c == '`' {
+            let ticks = rest.chars().take_while(|c| *c == '`').count();
+            let fence = &rest[..ticks];
+            if let Some(end) = rest[ticks..].find(fence) {
+                let code = rest[ticks..ticks + end].trim();
+                let code_style = style.patch(theme::current().syntax("markup.raw"));
+                styled.push(code, code_style);
+                rest = &rest[ticks + end + ticks..];
+                continue;
+            }
+        } else if c == '[' {
+            if let Some((text, after)) = link(rest) {
+                let link_style = style.patch(Style {
+                    underline: true,
+                    ..theme::current().syntax("markup.link")
+                });
+                render_inline(text, link_style, styled);
+                rest = after;
+                continue;
+            }
+        } else if rest.starts_with("~~") {
+            let opens = !struck && rest[2..].contains("~~");
+            if struck || opens {
+                struck = opens;
+                style.strikethrough = opens || base.strikethrough;
+                rest = &rest[2..];
+                continue;
+            }
+        } else if c == '*' || c == '_' {
+            let strong = rest[1..].starts_with(c);
+            let marker = if strong { &rest[..2] } else { &rest[..1] };
+            let after = &rest[marker.len()..];
+            let (open_marker, active) = if strong {
+                (&mut bold_marker, &mut style.bold)
+            } else {
+                (&mut italic_marker, &mut style.italic)
+            };
+            // `snake_case` is not emphasis.
+            let opens =
+                open_marker.is_none() && after.contains(marker) && !(c == '_' && previous_is_word);
+            if *open_marker == Some(c) || opens {
+                *open_marker = if opens { Some(c) } else { None };
+                *active = opens || base_flag(base, strong);
+                rest = after;
+                continue;
+            }
+        }
+        styled.push(grapheme, style);
+        rest = &rest[grapheme.len()..];
+    }
+}
+
+fn base_flag(base: Style, strong: bool) -> bool {
+    if strong {
+        base.bold
+    } else {
+        base.italic
+    }
+}
+
+/// `[text](target)` at the start of `text`: the link text and what follows.
+fn link(text: &str) -> Option<(&str, &str)> {
+    let close = text.find("](")?;
+    let end = text[close..].find(')')? + close;
+    Some((&text[1..close], &text[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lines: &[StyledLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    fn inline(line: &str) -> StyledLine {
+        let mut styled = StyledLine::default();
+        render_inline(line, Style::default(), &mut styled);
+        styled
+    }
+
+    fn plain(text: &str) -> StyledLine {
+        let mut styled = StyledLine::default();
+        styled.push(text, Style::default());
+        styled
+    }
+
+    #[test]
+    fn rendered() {
+        let text = "# Title #\n\nOne two\nthree four five.\n\n\n\n---\n```rust\nfn  main() {}\n```\n- item";
+        let lines = render(text, 10);
+        assert_eq!(
+            texts(&lines),
+            [
+                "Title",
+                "",
+                "One two",
+                "three four",
+                "five.",
+                "",
+                "──────────",
+                "fn  main() {}",
+                "- item",
+            ]
+        );
+        assert!(lines[0]
+            .styles
+            .iter()
+            .all(|style| style.bold && style.underline));
+        assert!(lines[2].styles.iter().all(|style| !style.bold));
+    }
+
+    #[test]
+    fn unterminated_code_block_rendered() {
+        assert_eq!(texts(&render("```\n  code\n\n", 80)), ["  code"]);
+    }
+
+    #[test]
+    fn wrapped() {
+        assert_eq!(
+            texts(&wrap(plain("one two three"), 7)),
+            ["one two", "three"]
+        );
+        assert_eq!(
+            texts(&wrap(plain("abcdefghij k"), 4)),
+            ["abcd", "efgh", "ij k"]
+        );
+        assert_eq!(texts(&wrap(plain("fits"), 4)), ["fits"]);
+        let wrapped = wrap(plain("über straße"), 6);
+        assert_eq!(texts(&wrapped), ["über", "straße"]);
+        assert_eq!(wrapped[1].width(), 6);
+    }
+
+    #[test]
+    fn inline_rendered() {
+        let styled = inline("**bold** *it* ~~gone~~ `a*b` [docs](https://docs.rs)");
+        assert_eq!(styled.text, "bold it gone a*b docs");
+        assert_eq!(styled.width(), styled.styles.len());
+        let style = |index: usize| styled.styles[index];
+        assert!(style(0).bold && !style(0).italic);
+        assert!(style(5).italic && !style(5).bold);
+        assert!(style(8).strikethrough);
+        assert!(!style(13).italic);
+        assert!(style(17).underline);
+        assert!(!style(4).bold && !style(16).underline);
+    }
+
+    #[test]
+    fn markers_kept() {
+        // Unclosed markers and the underscores of identifiers are text.
+        assert_eq!(inline("2 * 3 = 6").text, "2 * 3 = 6");
+        let styled = inline("snake_case_name and `x`");
+        assert_eq!(styled.text, "snake_case_name and x");
+        assert!(styled.styles.iter().all(|style| !style.italic));
+        assert_eq!(inline(r"\*not\* [no link").text, "*not* [no link");
+    }
+
+    #[test]
+    fn escaped() {
+        let text = "*a* `b` [c](d) _e_ \\";
+        assert_eq!(escape("a.b"), r"a\.b");
+        let styled = inline(&escape(text));
+        assert_eq!(styled.text, text);
+        assert!(styled.styles.iter().all(|style| *style == Style::default()));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::filetype::Settings;
+
+/// How many lines at the start and at the end of a file are looked at for
+/// modelines, as by Vim.
+const LINES: usize = 5;
+
+/// The settings of a file its modelines make.
+#[derive(Default)]
+pub struct Modeline {
+    pub file_type: Option<String>,
+    /// Over those of the file type.
+    pub settings: Settings,
+}
+
+impl Modeline {
+    /// The settings of the Vim and Emacs modelines among the first and
+    /// last lines of `lines`, later ones winning:
+    ///
+    /// ```text
+    /// # vim: set ft=yaml ts=2 sw=2 et:
+    /// // vim: ft=c noexpandtab
+    /// # -*- mode: python; tab-width: 4; indent-tabs-mode: nil -*-
+    /// ```
+    pub fn parse(lines: &[&str]) -> Self {
+        let mut modeline = Self::default();
+        let end = lines
+            .len()
+            .saturating_sub(LINES)
+            .max(LINES.min(lines.len()));
+        for line in lines[..LINES.min(lines.len())].iter().chain(&lines[end..]) {
+            if let Some(options) = vim_options(line) {
+                modeline.vim(options);
+            } else if let Some(variables) = emacs_variables(line) {
+                modeline.emacs(variables);
+            }
+        }
+        modeline
+    }
+
+    fn vim(&mut self, options: &str) {
+        let settings = &mut self.settings;
+        for option in options.split(|c: char| c.is_whitespace() || c == ':') {
+            let (name, value) = option.split_once('=').unwrap_or((option, ""));
+            let number = value.parse::<usize>().ok();
+            match name {
+                "ft" | "filetype" | "syn" | "syntax" if !value.is_empty() => {
+                    self.file_type = Some(value.to_string());
+                }
+                "ts" | "tabstop" => settings.tab_width = number.filter(|&width| width > 0),
+                // 0 indents by the tab width, as in Vim.
+                "sw" | "shiftwidth" => {
+                    settings.indent_width =
+                        number.filter(|&width| width > 0).or(settings.tab_width);
+                }
+                "et" | "expandtab" => settings.expand_tabs = Some(true),
+                "noet" | "noexpandtab" => settings.expand_tabs = Some(false),
+                "tw" | "textwidth" => settings.max_line_length = number.filter(|&width| width > 0),
+                _ => (),
+            }
+        }
+    }
+
+    fn emacs(&mut self, variables: &str) {
+        if !variables.contains(':') {
+            // `-*- yaml -*-` names the mode alone.
+            self.file_type = Some(variables.trim().to_string());
+            return;
+        }
+        for variable in variables.split(';') {
+            let Some((name, value)) = variable.split_once(':') else {
+                continue;
+            };
+            let (name, value) = (name.trim(), value.trim());
+            let number = value.parse::<usize>().ok().filter(|&width| width > 0);
+            match name {
+                "mode" => {
+                    self.file_type = Some(value.trim_end_matches("-ts").to_string());
+                }
+                "tab-width" => self.settings.tab_width = number,
+                "indent-tabs-mode" => self.settings.expand_tabs = Some(value == "nil"),
+                "fill-column" => self.settings.max_line_length = number,
+                // The indentation of most modes, e.g. `c-basic-offset` or
+                // `js-indent-level`.
+                name if name.ends_with("-offset") || name.ends_with("-indent-level") => {
+                    self.settings.indent_width = number;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// The options of a Vim modeline: `vim: set <options>:` or
+/// `vim: <options>`, after `vi:` or `ex:` as well, at the start of the line
+/// or after white space.
+fn vim_options(line: &str) -> Option<&str> {
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|(index, _)| *index == 0 || line[..*index].ends_with(char::is_whitespace))
+            .map(|(index, _)| index + marker.len())
+    })?;
+    let options = line[start..].trim_start();
+    match options
+        .strip_prefix("set ")
+        .or_else(|| options.strip_prefix("se "))
+    {
+        // Text may follow the `:` ending the options, e.g. a closing `*/`.
+        Some(options) => Some(
+            options
+                .split_once(':')
+                .map_or(options, |(options, _)| options),
+        ),
+        None => Some(options),
+    }
+}
+
+/// The variables of an Emacs modeline: `-*- <variables> -*-`.
+fn emacs_variables(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (variables, _) = rest.split_once("-*-")?;
+    Some(variables)
+}
@@ -0,0 +1,133 @@
+use crate::diff::{self, Change};
+use crate::{Document, Position, Row};
+
+/// A document narrowed down to the lines containing a query. The lines are
+/// edited as a document of their own, which is written back into this one
+/// when narrowing ends.
+pub struct Narrowing {
+    /// The whole document, put aside.
+    pub document: Document,
+    pub query: String,
+    /// The line of `document` each line of the view was made of.
+    pub lines: Vec<usize>,
+    /// Where the cursor and the view were before narrowing.
+    pub cursor_position: Position,
+    pub offset: Position,
+}
+
+/// The lines of `document` that contain `query`.
+pub fn matching(document: &Document, query: &str) -> Vec<usize> {
+    (0..document.len())
+        .filter(|&y| {
+            document
+                .row(y)
+                .is_some_and(|row| row.as_str().contains(query))
+        })
+        .collect()
+}
+
+/// The lines of `document`.
+pub fn rows(document: &Document) -> Vec<&str> {
+    (0..document.len())
+        .filter_map(|y| document.row(y))
+        .map(Row::as_str)
+        .collect()
+}
+
+/// Writes a narrowed view back: `before` are the lines of the document,
+/// `lines` those of them the view was made of and `after` the lines of the
+/// view now. A line edited in the view replaces its line, a line added to
+/// it goes after the one it follows and a line removed from it is removed.
+/// Returns the lines of the document and the line each line of the view
+/// ended up at.
+pub fn widen(before: &[&str], lines: &[usize], after: &[&str]) -> (Vec<String>, Vec<usize>) {
+    let narrowed: Vec<&str> = lines
+        .iter()
+        .filter_map(|&y| before.get(y).copied())
+        .collect();
+    // What each line of the view became.
+    let mut slots: Vec<Vec<&str>> = vec![Vec::new(); narrowed.len().max(1)];
+    // The lines removed and added since the last unchanged one, which is
+    // at `last`.
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let mut last = 0;
+    let mut old = 0;
+    for change in diff::lines(&narrowed, after) {
+        match change {
+            Change::Same(line) => {
+                place(&narrowed, &removed, &added, last, &mut slots);
+                (removed, added) = (Vec::new(), Vec::new());
+                slots[old].push(line);
+                last = old;
+                old += 1;
+            }
+            Change::Removed(_) => {
+                removed.push(old);
+                old += 1;
+            }
+            Change::Added(line) => added.push(line),
+        }
+    }
+    place(&narrowed, &removed, &added, last, &mut slots);
+    let mut whole = Vec::with_capacity(before.len() + after.len());
+    let mut positions = Vec::with_capacity(after.len());
+    for (y, line) in before.iter().enumerate() {
+        match lines.binary_search(&y) {
+            Ok(index) => {
+                for line in &slots[index] {
+                    positions.push(whole.len());
+                    whole.push((*line).to_string());
+                }
+            }
+            Err(_) => whole.push((*line).to_string()),
+        }
+    }
+    if lines.is_empty() {
+        // Lines typed into an empty view go at the end.
+        for line in &slots[0] {
+            positions.push(whole.len());
+            whole.push((*line).to_string());
+        }
+    }
+    (whole, positions)
+}
+
+/// Puts the lines `added` in place of the lines `removed` from the view: an
+/// added line replaces the next removed one if it looks like an edit of it
+/// or if there are no more added lines left than removed ones, and else
+/// goes after the line before, which starts out as the one at `last`.
+fn place<'a>(
+    narrowed: &[&str],
+    removed: &[usize],
+    added: &[&'a str],
+    last: usize,
+    slots: &mut [Vec<&'a str>],
+) {
+    let mut target = last;
+    let mut next = 0;
+    for (index, line) in added.iter().enumerate() {
+        if let Some(&slot) = removed.get(next) {
+            let left = added.len() - index;
+            if left <= removed.len() - next || similar(narrowed[slot], line) {
+                target = slot;
+                next += 1;
+            }
+        }
+        slots[target].push(line);
+    }
+}
+
+/// Whether `new` keeps at least half the words of `old`.
+fn similar(old: &str, new: &str) -> bool {
+    let word = |text: &str| text.chars().any(char::is_alphanumeric);
+    let changes = diff::words(old, new);
+    let kept = changes
+        .iter()
+        .filter(|change| matches!(change, Change::Same(text) if word(text)))
+        .count();
+    let words = changes
+        .iter()
+        .filter(|change| matches!(change, Change::Same(text) | Change::Removed(text) if word(text)))
+        .count();
+    kept * 2 >= words
+}
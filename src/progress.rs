@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often a running operation redraws its progress and checks whether
+/// it was cancelled. Operations that finish sooner never show up.
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A long running operation, e.g. searching the project: what it is doing
+/// and how far it got.
+pub struct Progress {
+    title: String,
+    done: u64,
+    total: Option<u64>,
+    last_report: Instant,
+}
+
+impl Progress {
+    pub fn new(title: String) -> Self {
+        Self {
+            title,
+            done: 0,
+            total: None,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Records that `done` of `total` units of work are finished. `None`
+    /// totals show a spinner only.
+    pub fn update(&mut self, done: u64, total: Option<u64>) {
+        self.done = done;
+        self.total = total;
+    }
+
+    /// Whether `REPORT_INTERVAL` passed since the last report, restarting
+    /// the interval if so.
+    pub fn due(&mut self) -> bool {
+        if self.last_report.elapsed() < REPORT_INTERVAL {
+            return false;
+        }
+        self.last_report = Instant::now();
+        true
+    }
+
+    /// E.g. `Searching 12/40 (30%)`.
+    pub fn text(&self) -> String {
+        match self.total {
+            Some(total) if total > 0 => format!(
+                "{} {}/{total} ({}%)",
+                self.title,
+                self.done,
+                self.done * 100 / total
+            ),
+            _ => self.title.clone(),
+        }
+    }
+}
+
+/// Set when the user presses Ctrl-C, which the key reader thread notices
+/// even while the editor is busy. Long operations check it in their inner
+/// loops.
+#[derive(Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
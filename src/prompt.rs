@@ -0,0 +1,141 @@
+use termion::event::Key;
+
+/// Tells what is wrong with an answer, if anything.
+type Validator<'a> = Box<dyn Fn(&str) -> Option<String> + 'a>;
+/// The answers that complete the input typed so far.
+type Completer<'a> = Box<dyn Fn(&str) -> Vec<String> + 'a>;
+
+/// A question asked in the message bar, answered by a line of input.
+pub struct Prompt<'a> {
+    label: String,
+    /// Stands in for the input while there is none, e.g. the bounds of the
+    /// numbers it takes.
+    placeholder: Option<String>,
+    /// Draws the input as `*`, e.g. for passphrases.
+    masked: bool,
+    validator: Option<Validator<'a>>,
+    completer: Option<Completer<'a>>,
+    input: String,
+    /// What is wrong with the input `Enter` was pressed on.
+    error: Option<String>,
+    /// The completions `Tab` goes through and the one shown, while it is
+    /// pressed again and again.
+    completions: Vec<String>,
+    completion: usize,
+}
+
+/// What a key does to a prompt.
+pub enum Outcome {
+    Editing,
+    /// The prompt is over: with the input, or with nothing when it was
+    /// cancelled or left empty.
+    Done(Option<String>),
+}
+
+impl<'a> Prompt<'a> {
+    pub fn new(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            placeholder: None,
+            masked: false,
+            validator: None,
+            completer: None,
+            input: String::new(),
+            error: None,
+            completions: Vec::new(),
+            completion: 0,
+        }
+    }
+
+    pub fn placeholder(mut self, placeholder: &str) -> Self {
+        self.placeholder = Some(placeholder.to_string());
+        self
+    }
+
+    // No prompt asks for a secret yet.
+    #[allow(dead_code)]
+    pub fn mask(mut self) -> Self {
+        self.masked = true;
+        self
+    }
+
+    /// Keeps asking while `validator` tells what is wrong with the input.
+    /// Empty input is not validated: it cancels the prompt.
+    pub fn validate(mut self, validator: impl Fn(&str) -> Option<String> + 'a) -> Self {
+        self.validator = Some(Box::new(validator));
+        self
+    }
+
+    /// Completes the input by `Tab`, going through the answers of
+    /// `completer` on every press.
+    pub fn complete(mut self, completer: impl Fn(&str) -> Vec<String> + 'a) -> Self {
+        self.completer = Some(Box::new(completer));
+        self
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// The label and the input, as drawn in the message bar.
+    pub fn text(&self) -> String {
+        if self.masked {
+            let stars = "*".repeat(self.input.chars().count());
+            return format!("{}{stars}", self.label);
+        }
+        format!("{}{}", self.label, self.input)
+    }
+
+    /// What is drawn dimmed after the input: the error, or the placeholder
+    /// while there is no input.
+    pub fn hint(&self) -> Option<String> {
+        if let Some(error) = &self.error {
+            return Some(format!(" ({error})"));
+        }
+        self.placeholder.clone().filter(|_| self.input.is_empty())
+    }
+
+    pub fn process_key(&mut self, key: Key) -> Outcome {
+        if key != Key::Char('\t') {
+            self.completions.clear();
+        }
+        self.error = None;
+        match key {
+            Key::Backspace => {
+                self.input.pop();
+            }
+            Key::Char('\n') => {
+                if self.input.is_empty() {
+                    return Outcome::Done(None);
+                }
+                let error = self.validator.as_ref().and_then(|valid| valid(&self.input));
+                if error.is_none() {
+                    return Outcome::Done(Some(self.input.clone()));
+                }
+                self.error = error;
+            }
+            Key::Char('\t') => self.complete_input(),
+            Key::Char(c) if !c.is_control() => self.input.push(c),
+            // Ctrl-C cancels here as it does long operations.
+            Key::Esc | Key::Ctrl('c') => return Outcome::Done(None),
+            _ => (),
+        }
+        Outcome::Editing
+    }
+
+    fn complete_input(&mut self) {
+        let Some(completer) = &self.completer else {
+            return;
+        };
+        if self.completions.is_empty() {
+            self.completions = completer(&self.input);
+            self.completion = 0;
+        } else {
+            self.completion = (self.completion + 1) % self.completions.len();
+        }
+        match self.completions.get(self.completion) {
+            Some(completion) => self.input = completion.clone(),
+            None => self.error = Some("no completions".to_string()),
+        }
+    }
+}
@@ -1,16 +1,106 @@
+use std::borrow::Cow;
+use std::cell::OnceCell;
 use std::cmp;
+use std::ops::Range;
 
-use termion::color;
+use termion::{color, style};
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::highlighting::Type;
+use crate::highlighting::{type_at, Span, Type};
+use crate::spell::{self, Dictionary};
+use crate::theme::{self, Style, Theme};
 use crate::SearchDirection;
 
+/// How a grapheme is marked as part of a search match, a bracket pair, the
+/// selection, a diagnostic or a line too long.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mark {
+    None,
+    Match,
+    /// The match at the cursor.
+    Current,
+    /// The bracket at the cursor or the one it pairs with.
+    Bracket,
+    Selection,
+    Diagnostic,
+    /// A word the spell checker does not know.
+    Misspelled,
+    /// Text past the maximum line length of the file type.
+    LongLine,
+}
+
+impl Mark {
+    /// The escape sequences drawing the mark on top of the text style: the
+    /// background and attributes of its theme style, and attributes alone
+    /// where backgrounds are not drawn.
+    fn escape(self, theme: &Theme) -> String {
+        let ui = &theme.ui;
+        let (style, fallback) = match self {
+            Self::None => return String::new(),
+            Self::Current => return style::Invert.to_string(),
+            Self::Selection if !theme::backgrounds() => return style::Invert.to_string(),
+            Self::Match => (
+                ui.search_match,
+                Style {
+                    bold: true,
+                    underline: true,
+                    ..Style::default()
+                },
+            ),
+            Self::Bracket => (
+                ui.matching_bracket,
+                Style {
+                    bold: true,
+                    ..Style::default()
+                },
+            ),
+            Self::Selection => (ui.selection, Style::default()),
+            Self::Diagnostic => (ui.diagnostic, Style::default()),
+            Self::Misspelled => (ui.misspelled, Style::default()),
+            Self::LongLine => (
+                ui.long_line,
+                Style {
+                    underline: true,
+                    ..Style::default()
+                },
+            ),
+        };
+        let style = Style {
+            fg: None,
+            ..if theme::backgrounds() || style.bg.is_none() {
+                style
+            } else {
+                style.patch(fallback)
+            }
+        };
+        theme::bg(style.bg) + &style.attributes()
+    }
+
+    /// The escape sequences taking any mark off again, which also switches
+    /// off attributes of the text style that have to be drawn again.
+    fn off() -> String {
+        format!(
+            "{}{}{}{}",
+            style::NoInvert,
+            color::Bg(color::Reset),
+            style::NoUnderline,
+            style::NoFaint
+        )
+    }
+}
+
 #[derive(Default)]
 pub struct Row {
     string: String,
-    highlight: Vec<Type>,
+    highlight: Vec<Span>,
     len: usize,
+    /// Byte offset of every grapheme, computed when first needed and
+    /// dropped on edits, so long lines are not walked from their start for
+    /// every column.
+    boundaries: OnceCell<Vec<usize>>,
+    /// The words of the line, as `wc` counts them, also computed when
+    /// first needed and dropped on edits.
+    words: OnceCell<usize>,
 }
 
 impl From<&str> for Row {
@@ -19,49 +109,104 @@ impl From<&str> for Row {
             string: String::from(slice),
             highlight: Vec::new(),
             len: slice.graphemes(true).count(),
+            boundaries: OnceCell::new(),
+            words: OnceCell::new(),
         }
     }
 }
 
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> Vec<String> {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
+    /// Renders the graphemes `start..end`, tabs as `tab_width` spaces.
+    /// `marks` are grapheme ranges to mark, such as search matches; the
+    /// first one containing a grapheme wins.
+    /// Draws the graphemes from `start` on in `width` columns, one element
+    /// per column, with tabs reaching to the next multiple of `tab_width`.
+    pub fn render(
+        &self,
+        start: usize,
+        width: usize,
+        marks: &[(Range<usize>, Mark)],
+        tab_width: usize,
+    ) -> Vec<String> {
+        let start = cmp::min(start, self.len);
+        let first_byte = self.byte_index(start);
+        let first_column = self.column(start, tab_width);
+        let mut column = first_column;
         let mut result: Vec<String> = Vec::new();
+        let theme = theme::current();
         let mut current_highlighting = &Type::None;
-        for (index, graphme) in self
-            .string
-            .as_str()
-            .graphemes(true)
-            .enumerate()
-            .skip(start)
-            .take(end - start)
+        let mut current_style = Style::default();
+        let mut current_mark = Mark::None;
+        for (index, (offset, graphme)) in
+            (start..).zip(self.string[first_byte..].grapheme_indices(true))
         {
+            if column - first_column >= width {
+                break;
+            }
+            let byte_index = first_byte + offset;
             if let Some(c) = graphme.chars().next() {
                 let mut current_str = String::new();
-                let highlighting_type = self.highlight.get(index).unwrap_or(&Type::None);
+                let mark = marks
+                    .iter()
+                    .find(|(range, _)| range.contains(&index))
+                    .map_or(Mark::None, |(_, mark)| *mark);
+                let highlighting_type = if is_control(c) {
+                    &Type::Escape
+                } else {
+                    type_at(&self.highlight, byte_index)
+                };
                 if highlighting_type != current_highlighting {
                     current_highlighting = highlighting_type;
-                    if current_highlighting == &Type::None {
-                        let start_highlight = format!("{}", color::Fg(color::Reset));
-                        current_str.push_str(start_highlight.as_str());
-                    } else {
-                        let start_highlight =
-                            format!("{}", color::Fg(highlighting_type.to_color()));
-                        current_str.push_str(start_highlight.as_str());
+                    // Backgrounds are left to search matches.
+                    let style = Style {
+                        bg: None,
+                        ..theme.syntax(highlighting_type.name())
+                    };
+                    current_str.push_str(&current_style.switch_to(style));
+                    current_style = style;
+                    if mark == current_mark {
+                        // Switching attributes may have taken off some
+                        // of the mark.
+                        current_str.push_str(&mark.escape(&theme));
+                    }
+                }
+                if mark != current_mark {
+                    if current_mark != Mark::None {
+                        current_str.push_str(&Mark::off());
+                        current_str.push_str(&current_style.attributes());
                     }
+                    current_mark = mark;
+                    current_str.push_str(&mark.escape(&theme));
                 }
 
                 if c == '\t' {
-                    current_str.push_str("  ");
+                    let stop = next_tab_stop(column, tab_width).min(first_column + width);
+                    current_str.push(' ');
+                    result.push(current_str);
+                    result.extend((column + 1..stop).map(|_| String::from(" ")));
+                    column = stop;
+                    continue;
+                } else if is_control(c) {
+                    current_str.push(control_picture(c));
                 } else {
                     current_str.push(c);
                 }
                 result.push(current_str);
+                column += 1;
             }
         }
 
-        let end_highlight = format!("{}", color::Fg(color::Reset));
+        let mut end_highlight = format!("{}", color::Fg(color::Reset));
+        end_highlight.push_str(
+            &Style {
+                fg: None,
+                ..current_style
+            }
+            .switch_to(Style::default()),
+        );
+        if current_mark != Mark::None {
+            end_highlight.push_str(&Mark::off());
+        }
         result.push(end_highlight);
         result
     }
@@ -69,73 +214,67 @@ impl Row {
     pub fn len(&self) -> usize {
         self.len
     }
+
+    /// The column the grapheme at `at` is drawn at, counting from the
+    /// start of the line.
+    pub fn column(&self, at: usize, tab_width: usize) -> usize {
+        self.string[..self.byte_index(at)].graphemes(true).fold(
+            0,
+            |column, grapheme| match grapheme {
+                "\t" => next_tab_stop(column, tab_width),
+                _ => column + 1,
+            },
+        )
+    }
     pub fn is_empty(&self) -> bool {
         self.len == 0
     }
 
-    pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.len() {
-            self.string.push(c);
-            self.len += 1;
-            return;
-        }
+    /// The words of the line: runs of characters between whitespace.
+    pub fn words(&self) -> usize {
+        *self
+            .words
+            .get_or_init(|| self.string.split_whitespace().count())
+    }
 
-        let mut result: String = String::new();
-        let mut length = 0;
-        for (index, graphme) in self.string[..].graphemes(true).enumerate() {
-            length += 1;
-            if index == at {
-                length += 1;
-                result.push(c);
-            }
-            result.push_str(graphme);
-        }
-        self.len = length;
-        self.string = result;
+    pub fn insert(&mut self, at: usize, c: char) {
+        let index = self.byte_index(at);
+        self.string.insert(index, c);
+        self.len += 1;
+        self.boundaries = OnceCell::new();
+        self.words = OnceCell::new();
     }
 
     pub fn delete(&mut self, at: usize) {
         if at >= self.len() {
             return;
         }
-
-        let mut result: String = String::new();
-        let mut length = 0;
-        for (index, graphme) in self.string[..].graphemes(true).enumerate() {
-            if index != at {
-                length += 1;
-                result.push_str(graphme);
-            }
-        }
-        self.len = length;
-        self.string = result;
+        let range = self.byte_index(at)..self.byte_index(at + 1);
+        self.string.replace_range(range, "");
+        self.len -= 1;
+        self.boundaries = OnceCell::new();
+        self.words = OnceCell::new();
     }
 
     pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
+        self.string.push_str(&new.string);
         self.len += new.len();
+        self.boundaries = OnceCell::new();
+        self.words = OnceCell::new();
     }
 
     pub fn split(&mut self, at: usize) -> Self {
-        let mut row: String = String::new();
-        let mut length = 0;
-        let mut splitted_row: String = String::new();
-        let mut splitted_length = 0;
-        for (index, graphme) in self.string[..].graphemes(true).enumerate() {
-            if index < at {
-                length += 1;
-                row.push_str(graphme);
-            } else {
-                splitted_length += 1;
-                splitted_row.push_str(graphme);
-            }
-        }
-        self.string = row;
-        self.len = length;
+        let rest = self.string.split_off(self.byte_index(at));
+        let len = self.len.saturating_sub(at);
+        self.len -= len;
+        self.boundaries = OnceCell::new();
+        self.words = OnceCell::new();
         Self {
-            string: splitted_row,
+            string: rest,
             highlight: Vec::new(),
-            len: splitted_length,
+            len,
+            boundaries: OnceCell::new(),
+            words: OnceCell::new(),
         }
     }
 
@@ -155,26 +294,91 @@ impl Row {
             after
         };
 
-        let sub_string: String = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
+        let first_byte = self.byte_index(start);
+        let sub_string = &self.string[first_byte..self.byte_index(end)];
         let matching_byte_index = if direction == SearchDirection::Forward {
             sub_string.find(query)
         } else {
             sub_string.rfind(query)
+        }?;
+        let index = self.grapheme_index(first_byte + matching_byte_index);
+        // Matches starting inside of a grapheme do not count.
+        (self.byte_index(index) == first_byte + matching_byte_index).then_some(index)
+    }
+
+    /// The grapheme ranges of all occurrences of `query`.
+    pub fn matches(&self, query: &str) -> Vec<Range<usize>> {
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let length = query.graphemes(true).count();
+        let starts = self.boundaries();
+        self.string
+            .match_indices(query)
+            .filter_map(|(byte_index, _)| starts.binary_search(&byte_index).ok())
+            .map(|start| start..start + length)
+            .collect()
+    }
+
+    /// The grapheme ranges of the words of comments and strings that are
+    /// not in `dictionary`.
+    pub fn misspellings(&self, dictionary: &Dictionary) -> Vec<Range<usize>> {
+        let checked = |range: &Range<usize>| {
+            matches!(
+                type_at(&self.highlight, range.start),
+                Type::Comment | Type::CommentDocumentation | Type::String
+            )
         };
-        if let Some(matching_byte_index) = matching_byte_index {
-            for (graphme_index, (byte_index, _)) in
-                sub_string[..].grapheme_indices(true).enumerate()
-            {
-                if matching_byte_index == byte_index {
-                    return Some(start + graphme_index);
-                }
-            }
+        spell::words(&self.string)
+            .into_iter()
+            .filter(|range| checked(range) && !dictionary.check(&self.string[range.clone()]))
+            .map(|range| self.grapheme_index(range.start)..self.grapheme_index(range.end))
+            .collect()
+    }
+
+    /// The grapheme range of the word at grapheme `at` that is not in
+    /// `dictionary`, with the word.
+    pub fn misspelling_at(
+        &self,
+        at: usize,
+        dictionary: &Dictionary,
+    ) -> Option<(Range<usize>, &str)> {
+        let range = self
+            .misspellings(dictionary)
+            .into_iter()
+            .find(|range| range.start <= at && at <= range.end)?;
+        let bytes = self.byte_index(range.start)..self.byte_index(range.end);
+        Some((range, &self.string[bytes]))
+    }
+
+    /// Byte offset of the grapheme at `at`, or the length if it is past
+    /// the end.
+    pub fn byte_index(&self, at: usize) -> usize {
+        self.boundaries()
+            .get(at)
+            .copied()
+            .unwrap_or(self.string.len())
+    }
+
+    /// The grapheme containing the byte at `byte_index`, or the length if
+    /// it is past the end.
+    pub fn grapheme_index(&self, byte_index: usize) -> usize {
+        if byte_index >= self.string.len() {
+            return self.len;
+        }
+        match self.boundaries().binary_search(&byte_index) {
+            Ok(index) => index,
+            Err(index) => index - 1,
         }
-        None
+    }
+
+    fn boundaries(&self) -> &[usize] {
+        self.boundaries.get_or_init(|| {
+            self.string
+                .grapheme_indices(true)
+                .map(|(index, _)| index)
+                .collect()
+        })
     }
 
     pub fn as_bytes(&self) -> &[u8] {
@@ -185,7 +389,38 @@ impl Row {
         self.string.as_str()
     }
 
-    pub fn set_highlight(&mut self, vector: Vec<Type>) {
-        self.highlight = vector;
+    pub fn set_highlight(&mut self, spans: Vec<Span>) {
+        self.highlight = spans;
+    }
+}
+
+/// Whether `c` would be interpreted by the terminal instead of shown, like
+/// the escape starting a color sequence. Tabs are drawn as spaces.
+fn is_control(c: char) -> bool {
+    c.is_control() && c != '\t'
+}
+
+/// The column a tab at `column` reaches to.
+fn next_tab_stop(column: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    (column / tab_width + 1) * tab_width
+}
+
+/// `grapheme`, or the picture of the control character it is, so the
+/// terminal shows it instead of acting on it.
+pub fn printable(grapheme: &str) -> Cow<'_, str> {
+    match grapheme.chars().next() {
+        Some(c) if is_control(c) => Cow::Owned(control_picture(c).to_string()),
+        _ => Cow::Borrowed(grapheme),
+    }
+}
+
+/// The character standing in for the control character `c` in one column:
+/// its symbol of the Control Pictures block, e.g. `␛` for an escape.
+fn control_picture(c: char) -> char {
+    match u32::from(c) {
+        code @ 0..=0x1f => char::from_u32(0x2400 + code).unwrap_or('\u{fffd}'),
+        0x7f => '\u{2421}',
+        _ => '\u{fffd}',
     }
 }
@@ -0,0 +1,61 @@
+use serde::Deserialize;
+
+/// A step of the pipeline the buffer goes through before it is saved, in
+/// the order of `save_steps`.
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Sorts and prunes the imports by the language server.
+    OrganizeImports,
+    /// Formats the buffer by the language server.
+    Format,
+    /// Removes spaces and tabs at the ends of lines.
+    TrimWhitespace,
+    /// Drops the empty lines at the end, so the file ends in exactly one
+    /// newline.
+    FinalNewline,
+}
+
+impl Step {
+    /// The name of the step in the config.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::OrganizeImports => "organize_imports",
+            Self::Format => "format",
+            Self::TrimWhitespace => "trim_whitespace",
+            Self::FinalNewline => "final_newline",
+        }
+    }
+}
+
+/// What becomes of the save when a step fails.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    /// Leave the file as it is on disk.
+    Abort,
+    /// Save the buffer as the steps before left it.
+    #[default]
+    Save,
+}
+
+/// What the steps did, for the message after saving: the steps that
+/// changed the buffer, then those that were skipped or failed and what
+/// the file could not keep.
+#[derive(Default)]
+pub struct Report {
+    pub changed: Vec<&'static str>,
+    pub notes: Vec<String>,
+}
+
+impl Report {
+    /// `None` if no step did anything worth telling.
+    pub fn summary(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if !self.changed.is_empty() {
+            parts.push(format!("applied {}", self.changed.join(", ")));
+        }
+        parts.extend(self.notes.iter().cloned());
+        (!parts.is_empty()).then(|| parts.join("; "))
+    }
+}
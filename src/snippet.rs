@@ -0,0 +1,289 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::Position;
+
+/// An expanded LSP snippet: the text to insert and its tabstops.
+pub struct Snippet {
+    pub text: String,
+    /// In the order they are visited, `$0` last.
+    tabstops: Vec<Tabstop>,
+}
+
+struct Tabstop {
+    number: usize,
+    /// Byte range of the placeholder text in `Snippet::text`.
+    start: usize,
+    end: usize,
+}
+
+/// Expands `body` in the LSP snippet syntax: `$1`, `${1}`,
+/// `${1:placeholder}` (nestable), `${1|one,two|}` choices (the first one is
+/// inserted) and `$NAME`/`${NAME:default}` variables looked up with
+/// `variable`.
+pub fn parse(body: &str, variable: &dyn Fn(&str) -> Option<String>) -> Snippet {
+    let mut parser = Parser {
+        chars: body.chars().collect(),
+        index: 0,
+        text: String::new(),
+        tabstops: Vec::new(),
+    };
+    parser.parse_until(None, variable);
+    let mut tabstops = parser.tabstops;
+    // Only the first occurrence of a number is a stop of its own.
+    let mut seen = Vec::new();
+    tabstops.retain(|stop| {
+        let first = !seen.contains(&stop.number);
+        seen.push(stop.number);
+        first
+    });
+    tabstops.sort_by_key(|stop| {
+        if stop.number == 0 {
+            usize::MAX
+        } else {
+            stop.number
+        }
+    });
+    Snippet {
+        text: parser.text,
+        tabstops,
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    text: String,
+    tabstops: Vec<Tabstop>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    /// Appends literal text and expansions up to the unescaped `end`, which
+    /// is consumed.
+    fn parse_until(&mut self, end: Option<char>, variable: &dyn Fn(&str) -> Option<String>) {
+        while let Some(c) = self.peek() {
+            self.index += 1;
+            match c {
+                '\\' => match self.peek() {
+                    Some(escaped @ ('$' | '}' | '\\' | ',' | '|')) => {
+                        self.index += 1;
+                        self.text.push(escaped);
+                    }
+                    _ => self.text.push('\\'),
+                },
+                '$' => self.parse_dollar(variable),
+                c if Some(c) == end => return,
+                c => self.text.push(c),
+            }
+        }
+    }
+
+    fn parse_dollar(&mut self, variable: &dyn Fn(&str) -> Option<String>) {
+        let braced = self.peek() == Some('{');
+        if braced {
+            self.index += 1;
+        }
+        let number = self.take_while(|c| c.is_ascii_digit());
+        if !number.is_empty() {
+            let number = number.parse().unwrap_or_default();
+            let start = self.text.len();
+            if braced {
+                match self.peek() {
+                    Some(':') => {
+                        self.index += 1;
+                        self.parse_until(Some('}'), variable);
+                    }
+                    Some('|') => {
+                        self.index += 1;
+                        let choices = self.take_while(|c| c != '|');
+                        self.text
+                            .push_str(choices.split(',').next().unwrap_or_default());
+                        self.index += 2; // `|}`
+                    }
+                    _ => self.index += 1, // `}`
+                }
+            }
+            self.tabstops.push(Tabstop {
+                number,
+                start,
+                end: self.text.len(),
+            });
+            return;
+        }
+
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if name.is_empty() {
+            self.text.push('$');
+            if braced {
+                self.text.push('{');
+            }
+            return;
+        }
+        let value = variable(&name);
+        if braced {
+            if self.peek() == Some(':') {
+                self.index += 1;
+                let start = self.text.len();
+                self.parse_until(Some('}'), variable);
+                if value.is_some() {
+                    self.text.truncate(start);
+                }
+            } else {
+                self.index += 1; // `}`
+            }
+        }
+        self.text.push_str(&value.unwrap_or_default());
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let start = self.index;
+        while self.peek().is_some_and(&predicate) {
+            self.index += 1;
+        }
+        self.chars[start..self.index].iter().collect()
+    }
+}
+
+/// A snippet being filled in: where its tabstops are in the document.
+pub struct Session {
+    /// Start and end of every tabstop; `end.x - start.x` is the length of
+    /// the placeholder.
+    stops: Vec<(Position, Position)>,
+    current: usize,
+    /// True until the placeholder of the current stop is typed over.
+    pub placeholder_pending: bool,
+}
+
+impl Session {
+    /// Starts filling in `snippet`, which was inserted at `origin`. `None`
+    /// if it has no tabstops.
+    pub fn new(snippet: &Snippet, origin: &Position) -> Option<Self> {
+        let position = |offset: usize| {
+            let before = &snippet.text[..offset];
+            match before.rsplit_once('\n') {
+                Some((lines, last)) => Position {
+                    x: last.graphemes(true).count(),
+                    y: origin.y + lines.matches('\n').count() + 1,
+                },
+                None => Position {
+                    x: origin.x + before.graphemes(true).count(),
+                    y: origin.y,
+                },
+            }
+        };
+        let stops: Vec<(Position, Position)> = snippet
+            .tabstops
+            .iter()
+            .map(|stop| (position(stop.start), position(stop.end)))
+            .collect();
+        if stops.is_empty() {
+            return None;
+        }
+        Some(Self {
+            stops,
+            current: 0,
+            placeholder_pending: true,
+        })
+    }
+
+    /// The current tabstop.
+    pub fn current(&self) -> &(Position, Position) {
+        &self.stops[self.current]
+    }
+
+    pub fn describe(&self) -> String {
+        format!("Snippet: tabstop {}/{}", self.current + 1, self.stops.len())
+    }
+
+    /// Moves to the next tabstop. `false` if there is none.
+    pub fn next(&mut self) -> bool {
+        if self.current + 1 >= self.stops.len() {
+            return false;
+        }
+        self.current += 1;
+        self.placeholder_pending = true;
+        true
+    }
+
+    pub fn previous(&mut self) -> bool {
+        if self.current == 0 {
+            return false;
+        }
+        self.current -= 1;
+        self.placeholder_pending = true;
+        true
+    }
+
+    /// Keeps the stops in place after `delta` graphemes were inserted (or
+    /// removed if negative) at `at`, which is on a single line.
+    pub fn shift(&mut self, at: &Position, delta: isize) {
+        let current = self.current;
+        for (index, (start, end)) in self.stops.iter_mut().enumerate() {
+            for (is_end, point) in [(false, start), (true, end)] {
+                // Typing at the current stop grows it instead of moving it.
+                let grows = index == current && is_end;
+                if point.y == at.y && (point.x > at.x || (grows && point.x >= at.x)) {
+                    point.x = point.x.saturating_add_signed(delta);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stops(snippet: &Snippet) -> Vec<(usize, &str)> {
+        snippet
+            .tabstops
+            .iter()
+            .map(|stop| (stop.number, &snippet.text[stop.start..stop.end]))
+            .collect()
+    }
+
+    #[test]
+    fn tabstops() {
+        let snippet = parse("fn ${1:name}(${2|a,b|}) {\n\t$0\n}", &|_| None);
+        assert_eq!(snippet.text, "fn name(a) {\n\t\n}");
+        assert_eq!(stops(&snippet), [(1, "name"), (2, "a"), (0, "")]);
+    }
+
+    #[test]
+    fn nested_and_repeated() {
+        let snippet = parse("${1:a${2:b}c} $1 ${2}", &|_| None);
+        assert_eq!(snippet.text, "abc  ");
+        assert_eq!(stops(&snippet), [(1, "abc"), (2, "b")]);
+    }
+
+    #[test]
+    fn variables_and_escapes() {
+        let variable = |name: &str| (name == "TM_FILENAME").then(|| String::from("x.rs"));
+        let snippet = parse(
+            "$TM_FILENAME ${NONE:none} ${TM_FILENAME:y} \\$1 $",
+            &variable,
+        );
+        assert_eq!(snippet.text, "x.rs none x.rs $1 $");
+        assert!(snippet.tabstops.is_empty());
+    }
+
+    #[test]
+    fn session() {
+        let snippet = parse("if ${1:x} {\n    ${2:y}\n}", &|_| None);
+        let origin = Position { x: 4, y: 7 };
+        let mut session = Session::new(&snippet, &origin).unwrap();
+        assert!(!session.previous());
+        assert!(*session.current() == (Position { x: 7, y: 7 }, Position { x: 8, y: 7 }));
+        // Typing over the placeholder grows the stop.
+        session.shift(&Position { x: 8, y: 7 }, 2);
+        assert!(*session.current() == (Position { x: 7, y: 7 }, Position { x: 10, y: 7 }));
+        assert!(session.next());
+        assert!(*session.current() == (Position { x: 4, y: 8 }, Position { x: 5, y: 8 }));
+        assert!(!session.next());
+        assert_eq!(session.describe(), "Snippet: tabstop 2/2");
+        assert!(Session::new(&parse("plain", &|_| None), &origin).is_none());
+    }
+}
@@ -0,0 +1,125 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// How `sort-lines` orders the lines.
+#[derive(Clone, Copy, Default)]
+pub struct Order {
+    /// Last to first.
+    pub reverse: bool,
+    /// By the number the lines start with, as `sort -n`.
+    pub numeric: bool,
+    /// Upper and lower case alike.
+    pub ignore_case: bool,
+}
+
+impl Order {
+    /// Parses the words after `sort-lines`: `reverse`, `numeric` and
+    /// `nocase`, in any order.
+    pub fn parse<'a>(words: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let mut order = Self::default();
+        for word in words {
+            match word {
+                "reverse" => order.reverse = true,
+                "numeric" => order.numeric = true,
+                "nocase" => order.ignore_case = true,
+                _ => return None,
+            }
+        }
+        Some(order)
+    }
+}
+
+/// Sorts `lines` by `order`. Lines that compare equal keep their order.
+pub fn sort(lines: &mut [String], order: Order) {
+    lines.sort_by(|a, b| {
+        let ordering = compare(a, b, order);
+        if order.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+fn compare(a: &str, b: &str, order: Order) -> Ordering {
+    if order.numeric {
+        // Lines without a number count as 0, as with `sort -n`.
+        let number = |line: &str| leading_number(line).unwrap_or(0.0);
+        let ordering = number(a).total_cmp(&number(b));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    if order.ignore_case {
+        a.to_lowercase().cmp(&b.to_lowercase())
+    } else {
+        a.cmp(b)
+    }
+}
+
+/// The number at the start of `line`, after its indentation, e.g. `-1.5`
+/// of `-1.5 kg`.
+fn leading_number(line: &str) -> Option<f64> {
+    let line = line.trim_start();
+    let end = line
+        .char_indices()
+        .find(|&(index, c)| !(c.is_ascii_digit() || c == '.' || (index == 0 && c == '-')))
+        .map_or(line.len(), |(index, _)| index);
+    line[..end].parse().ok()
+}
+
+/// `lines` without the lines that came before, keeping the first of each.
+pub fn dedupe(lines: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    lines
+        .into_iter()
+        .filter(|line| seen.insert(line.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(lines: &[&str], words: &str) -> Vec<String> {
+        let mut lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        sort(&mut lines, Order::parse(words.split_whitespace()).unwrap());
+        lines
+    }
+
+    #[test]
+    fn orders() {
+        assert_eq!(sorted(&["b", "B", "a", "A"], ""), ["A", "B", "a", "b"]);
+        assert_eq!(
+            sorted(&["b", "B", "a", "A"], "nocase"),
+            ["a", "A", "b", "B"]
+        );
+        // Equal lines keep their order reversed too.
+        assert_eq!(
+            sorted(&["b", "B", "a", "A"], "nocase reverse"),
+            ["b", "B", "a", "A"]
+        );
+        assert_eq!(
+            sorted(&["10 kg", "x", "-1.5", "  2", "9"], "numeric"),
+            ["-1.5", "x", "  2", "9", "10 kg"]
+        );
+        assert!(Order::parse(["numeric", "up"].into_iter()).is_none());
+    }
+
+    #[test]
+    fn empty() {
+        assert!(sorted(&[], "numeric").is_empty());
+        assert!(dedupe(Vec::new()).is_empty());
+        assert_eq!(leading_number(""), None);
+        assert_eq!(leading_number("-"), None);
+    }
+
+    #[test]
+    fn trailing_empty_line() {
+        // The empty line after a final newline is a line like the others.
+        assert_eq!(sorted(&["b", "a", ""], ""), ["", "a", "b"]);
+        assert_eq!(sorted(&["b", "a", ""], "reverse"), ["b", "a", ""]);
+        let lines = ["a", "", "b", "a", ""].map(String::from).to_vec();
+        assert_eq!(dedupe(lines), ["a", "", "b"]);
+    }
+}
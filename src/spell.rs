@@ -0,0 +1,377 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use anyhow::{anyhow, Result};
+
+use crate::config::config_dir;
+use crate::log;
+
+/// Directory of the config directory searched for dictionaries first.
+const DICTIONARIES_DIR_NAME: &str = "dictionaries";
+/// Where distributions install hunspell dictionaries.
+const SYSTEM_DIRS: [&str; 3] = [
+    "/usr/share/hunspell",
+    "/usr/share/myspell",
+    "/usr/share/myspell/dicts",
+];
+const MAX_SUGGESTIONS: usize = 8;
+/// How many edits a suggestion may be away from the misspelled word.
+const MAX_DISTANCE: usize = 2;
+
+/// The words of a hunspell dictionary, with the forms its affix rules
+/// derive from them, e.g. `checks` and `checked` of `check/SD`.
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+/// A prefix or suffix rule of an `.aff` file: `strip` is taken off words
+/// matching `condition` at that end, then `add` is put on.
+struct Affix {
+    strip: String,
+    add: String,
+    condition: Vec<Condition>,
+    /// Whether the rule combines with rules of the other end.
+    cross_product: bool,
+}
+
+/// A character of the condition of an affix rule.
+enum Condition {
+    Any,
+    Char(char),
+    /// `[abc]`, or `[^abc]` if negated.
+    Class(Vec<char>, bool),
+}
+
+impl Condition {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Char(expected) => c == *expected,
+            Self::Class(chars, negated) => chars.contains(&c) != *negated,
+        }
+    }
+}
+
+/// How flags are written after the `/` of a word.
+#[derive(Clone, Copy)]
+enum FlagType {
+    Char,
+    Long,
+    Numeric,
+}
+
+impl FlagType {
+    fn split(self, flags: &str) -> Vec<String> {
+        match self {
+            Self::Char => flags.chars().map(String::from).collect(),
+            Self::Long => {
+                let chars: Vec<char> = flags.chars().collect();
+                chars.chunks(2).map(|pair| pair.iter().collect()).collect()
+            }
+            Self::Numeric => flags.split(',').map(str::to_owned).collect(),
+        }
+    }
+}
+
+impl Dictionary {
+    /// Reads the hunspell dictionary `dic` and the `.aff` file next to it,
+    /// if there is one.
+    pub fn load(dic: &Path) -> Result<Self> {
+        let aff = fs::read(dic.with_extension("aff")).unwrap_or_default();
+        let latin1 = String::from_utf8_lossy(&aff)
+            .lines()
+            .any(|line| line.trim() == "SET ISO8859-1");
+        let decode = |bytes: Vec<u8>| {
+            if latin1 {
+                bytes.into_iter().map(char::from).collect()
+            } else {
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+        };
+        let words = fs::read(dic).map_err(|e| anyhow!("{}: {e}", dic.display()))?;
+        Ok(Self::parse(&decode(aff), &decode(words)))
+    }
+
+    /// The dictionary of the `.aff` text `aff` and the `.dic` text `dic`.
+    pub fn parse(aff: &str, dic: &str) -> Self {
+        let mut flag_type = FlagType::Char;
+        let mut prefixes: HashMap<String, Vec<Affix>> = HashMap::new();
+        let mut suffixes: HashMap<String, Vec<Affix>> = HashMap::new();
+        let mut cross_products: HashMap<String, bool> = HashMap::new();
+        for line in aff.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["FLAG", "long", ..] => flag_type = FlagType::Long,
+                ["FLAG", "num", ..] => flag_type = FlagType::Numeric,
+                [kind @ ("PFX" | "SFX"), flag, cross, count]
+                    if count.parse::<usize>().is_ok() && matches!(*cross, "Y" | "N") =>
+                {
+                    cross_products.insert(format!("{kind}{flag}"), *cross == "Y");
+                }
+                [kind @ ("PFX" | "SFX"), flag, strip, add, rest @ ..] => {
+                    let zero = |text: &str| {
+                        if text == "0" {
+                            String::new()
+                        } else {
+                            text.to_owned()
+                        }
+                    };
+                    let add = add.split('/').next().unwrap_or_default();
+                    let affix = Affix {
+                        strip: zero(strip),
+                        add: zero(add),
+                        condition: condition(rest.first().copied().unwrap_or(".")),
+                        cross_product: cross_products
+                            .get(&format!("{kind}{flag}"))
+                            .copied()
+                            .unwrap_or(false),
+                    };
+                    let rules = if *kind == "PFX" {
+                        &mut prefixes
+                    } else {
+                        &mut suffixes
+                    };
+                    rules.entry((*flag).to_owned()).or_default().push(affix);
+                }
+                _ => (),
+            }
+        }
+        let mut words = HashSet::new();
+        // The first line is the number of words.
+        for line in dic.lines().skip(1) {
+            let entry = line.split(['\t', ' ']).next().unwrap_or_default();
+            let (word, flags) = entry.split_once('/').unwrap_or((entry, ""));
+            if word.is_empty() {
+                continue;
+            }
+            let flags = flag_type.split(flags);
+            let (prefix_rules, suffix_rules) = (rules(&flags, &prefixes), rules(&flags, &suffixes));
+            let mut suffixed = Vec::new();
+            for rule in &suffix_rules {
+                if let Some(form) = rule.apply_suffix(word) {
+                    if rule.cross_product {
+                        suffixed.push(form.clone());
+                    }
+                    words.insert(form);
+                }
+            }
+            for rule in &prefix_rules {
+                if let Some(form) = rule.apply_prefix(word) {
+                    words.insert(form);
+                }
+                if rule.cross_product {
+                    for form in &suffixed {
+                        if let Some(form) = rule.apply_prefix(form) {
+                            words.insert(form);
+                        }
+                    }
+                }
+            }
+            words.insert(word.to_owned());
+        }
+        Self { words }
+    }
+
+    /// Whether `word` is spelled right. Capitalized words are also looked
+    /// up in lowercase, as at the start of a sentence.
+    pub fn check(&self, word: &str) -> bool {
+        self.words.contains(word) || {
+            let lower = word.to_lowercase();
+            lower != word && is_capitalized(word) && self.words.contains(&lower)
+        }
+    }
+
+    /// The words closest to `word`, capitalized like it.
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let lower = word.to_lowercase();
+        let target: Vec<char> = lower.chars().collect();
+        let mut candidates: Vec<(usize, &String)> = self
+            .words
+            .iter()
+            .filter(|candidate| candidate.chars().count().abs_diff(target.len()) <= MAX_DISTANCE)
+            .filter_map(|candidate| {
+                let chars: Vec<char> = candidate.to_lowercase().chars().collect();
+                let distance = distance(&target, &chars);
+                (distance <= MAX_DISTANCE).then_some((distance, candidate))
+            })
+            .collect();
+        candidates.sort();
+        let mut suggestions: Vec<String> = Vec::new();
+        for (_, candidate) in candidates {
+            let suggestion = if is_capitalized(word) {
+                capitalize(candidate)
+            } else {
+                candidate.clone()
+            };
+            if !suggestions.contains(&suggestion) {
+                suggestions.push(suggestion);
+            }
+            if suggestions.len() == MAX_SUGGESTIONS {
+                break;
+            }
+        }
+        suggestions
+    }
+}
+
+impl Affix {
+    fn apply_suffix(&self, word: &str) -> Option<String> {
+        let chars: Vec<char> = word.chars().collect();
+        let start = chars.len().checked_sub(self.condition.len())?;
+        let matches = self
+            .condition
+            .iter()
+            .zip(&chars[start..])
+            .all(|(condition, c)| condition.matches(*c));
+        let stem = word.strip_suffix(self.strip.as_str())?;
+        (matches && !stem.is_empty()).then(|| format!("{stem}{}", self.add))
+    }
+
+    fn apply_prefix(&self, word: &str) -> Option<String> {
+        let matches = self.condition.len() <= word.chars().count()
+            && self
+                .condition
+                .iter()
+                .zip(word.chars())
+                .all(|(condition, c)| condition.matches(c));
+        let stem = word.strip_prefix(self.strip.as_str())?;
+        (matches && !stem.is_empty()).then(|| format!("{}{stem}", self.add))
+    }
+}
+
+/// The rules of `affixes` for `flags`.
+fn rules<'a>(flags: &[String], affixes: &'a HashMap<String, Vec<Affix>>) -> Vec<&'a Affix> {
+    flags
+        .iter()
+        .filter_map(|flag| affixes.get(flag))
+        .flatten()
+        .collect()
+}
+
+/// Parses the condition of an affix rule, like `[^aeiou]y`.
+fn condition(pattern: &str) -> Vec<Condition> {
+    let mut condition = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        condition.push(match c {
+            '.' => Condition::Any,
+            '[' => {
+                let mut class: Vec<char> = chars.by_ref().take_while(|c| *c != ']').collect();
+                let negated = class.first() == Some(&'^');
+                if negated {
+                    class.remove(0);
+                }
+                Condition::Class(class, negated)
+            }
+            c => Condition::Char(c),
+        });
+    }
+    condition
+}
+
+/// How many insertions, deletions, substitutions and swaps of neighbours
+/// turn `a` into `b`.
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+fn is_capitalized(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(char::is_uppercase) && chars.all(|c| !c.is_uppercase())
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or(String::new(), |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+/// The byte ranges of the words of `text` worth checking: runs of letters,
+/// with apostrophes inside, that are neither acronyms nor identifiers like
+/// `snake_case`, `camelCase` or `utf8`. Words of URLs, paths and addresses
+/// are skipped.
+pub fn words(text: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut offset = 0;
+    for token in text.split_inclusive(char::is_whitespace) {
+        let start = offset;
+        offset += token.len();
+        if token.contains("://") || token.contains(['/', '@', '\\']) {
+            continue;
+        }
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+        let mut chars = token.char_indices().peekable();
+        while let Some((index, c)) = chars.next() {
+            if !is_word_char(c) {
+                continue;
+            }
+            let mut end = index + c.len_utf8();
+            while let Some((next, c)) = chars.next_if(|(_, c)| is_word_char(*c)) {
+                end = next + c.len_utf8();
+            }
+            let word = token[index..end].trim_matches('\'');
+            let word_start = start + index + token[index..end].find(word).unwrap_or(0);
+            let mut letters = word.chars();
+            let checked = word.chars().count() > 1
+                && word.chars().all(|c| c.is_alphabetic() || c == '\'')
+                && letters.next().is_some()
+                && letters.all(|c| !c.is_uppercase());
+            if checked {
+                words.push(word_start..word_start + word.len());
+            }
+        }
+    }
+    words
+}
+
+/// Loads the dictionary at `path` on another thread; the lock is set once
+/// it is read. Failures go to the log.
+pub fn load_in_background(path: Option<PathBuf>) -> Arc<OnceLock<Dictionary>> {
+    let dictionary = Arc::new(OnceLock::new());
+    let loading = Arc::clone(&dictionary);
+    thread::spawn(move || {
+        let loaded = path
+            .ok_or_else(|| anyhow!("No dictionary found"))
+            .and_then(|path| Dictionary::load(&path));
+        match loaded {
+            Ok(loaded) => {
+                let _ = loading.set(loaded);
+            }
+            Err(e) => log::write("spell", &e.to_string()),
+        }
+    });
+    dictionary
+}
+
+/// The dictionary `path` of the config, or else `en_US.dic` of the
+/// `dictionaries` directory of the config directory or of the system.
+pub fn find(path: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = path {
+        return Some(path.to_path_buf());
+    }
+    let config = config_dir().map(|dir| dir.join(DICTIONARIES_DIR_NAME));
+    config
+        .into_iter()
+        .chain(SYSTEM_DIRS.iter().map(PathBuf::from))
+        .map(|dir| dir.join("en_US.dic"))
+        .find(|path| path.exists())
+}
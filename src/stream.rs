@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use anyhow::{anyhow, Result};
+
+/// Bytes read from the file at a time.
+const CHUNK_SIZE: usize = 1024 * 1024;
+/// Chunks handed to the document per poll, so a tick stays short even when
+/// the worker is far ahead.
+const CHUNKS_PER_POLL: usize = 8;
+
+/// What the worker sends: the next lines and how many bytes of the file
+/// it read so far, or why it could not read on.
+enum Message {
+    Lines(Vec<String>, u64),
+    Failed(String),
+}
+
+/// The rest of a big file, read on a worker thread while the lines read
+/// first are already shown and edited.
+pub struct Stream {
+    receiver: Receiver<Message>,
+    read: u64,
+    size: u64,
+    done: bool,
+}
+
+impl Stream {
+    /// Reads `file` on: `rest` are the bytes already read after the last
+    /// whole line, `read` all bytes read and `size` those of the file.
+    /// Bytes that are not UTF-8 fail the reading unless `lossy`, which
+    /// replaces them.
+    pub fn new(file: File, rest: Vec<u8>, read: u64, size: u64, lossy: bool) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || work(file, rest, read, lossy, &sender));
+        Self {
+            receiver,
+            read,
+            size,
+            done: false,
+        }
+    }
+
+    /// The lines read since the last call.
+    pub fn take(&mut self) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        for _ in 0..CHUNKS_PER_POLL {
+            match self.receiver.try_recv() {
+                Ok(message) => lines.extend(self.receive(message)?),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        Ok(lines)
+    }
+
+    /// The lines not taken yet, waiting until the whole file was read.
+    pub fn finish(&mut self) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        while let Ok(message) = self.receiver.recv() {
+            lines.extend(self.receive(message)?);
+        }
+        self.done = true;
+        Ok(lines)
+    }
+
+    /// Whether the whole file was read and taken.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// How much of the file was read, in percent.
+    pub fn percent(&self) -> u64 {
+        (self.read * 100).checked_div(self.size).unwrap_or(100)
+    }
+
+    fn receive(&mut self, message: Message) -> Result<Vec<String>> {
+        match message {
+            Message::Lines(lines, read) => {
+                self.read = read;
+                Ok(lines)
+            }
+            Message::Failed(error) => {
+                self.done = true;
+                Err(anyhow!(error))
+            }
+        }
+    }
+}
+
+fn work(mut file: File, mut rest: Vec<u8>, mut read: u64, lossy: bool, sender: &Sender<Message>) {
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let count = match file.read(&mut chunk) {
+            Ok(count) => count,
+            Err(e) => {
+                let _ = sender.send(Message::Failed(e.to_string()));
+                return;
+            }
+        };
+        read += count as u64;
+        rest.extend_from_slice(&chunk[..count]);
+        // Only whole lines are sent, so none is cut in two, nor a
+        // character.
+        let end = match rest.iter().rposition(|&byte| byte == b'\n') {
+            _ if count == 0 => rest.len(),
+            Some(index) => index + 1,
+            None => continue,
+        };
+        let tail = rest.split_off(end);
+        let text = match String::from_utf8(std::mem::replace(&mut rest, tail)) {
+            Ok(text) => text,
+            Err(e) if lossy => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+            Err(e) => {
+                let _ = sender.send(Message::Failed(e.to_string()));
+                return;
+            }
+        };
+        let lines = text.lines().map(str::to_string).collect();
+        // The document is gone once nobody receives.
+        if sender.send(Message::Lines(lines, read)).is_err() || count == 0 {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn open(name: &str, bytes: &[u8], rest: &[u8], lossy: bool) -> Stream {
+        let path = format!("target/snap/{name}");
+        fs::create_dir_all("target/snap").unwrap();
+        fs::write(&path, bytes).unwrap();
+        let size = (rest.len() + bytes.len()) as u64;
+        let file = File::open(path).unwrap();
+        Stream::new(file, rest.to_vec(), rest.len() as u64, size, lossy)
+    }
+
+    #[test]
+    fn finished() {
+        let mut stream = open("stream.txt", b"ef\ngh\n\nij", b"ab\ncd", false);
+        assert_eq!(stream.finish().unwrap(), ["ab", "cdef", "gh", "", "ij"]);
+        assert!(stream.is_done());
+        assert_eq!(stream.percent(), 100);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut stream = open("stream-invalid.txt", b"a\xff\n", b"", false);
+        assert!(stream.finish().is_err());
+        assert!(stream.is_done());
+        let mut stream = open("stream-lossy.txt", b"a\xff\n", b"", true);
+        assert_eq!(stream.finish().unwrap(), ["a\u{fffd}"]);
+    }
+}
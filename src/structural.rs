@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use lsp_types::{Position, Range, TextEdit};
+use tree_sitter::{Language, Node, Parser, Point, Query, QueryCursor};
+
+/// The capture whose node is replaced.
+const MATCH_CAPTURE: &str = "match";
+
+/// The edits replacing every node captured as `@match` by the tree-sitter
+/// `query` with `template`, in which `$name` (or `${name}`) stands for the
+/// text of the capture `name` of the same match and `$$` for a `$`. Matches
+/// inside of an earlier one are left alone.
+pub fn replace(
+    lang: Language,
+    lines: &[&str],
+    query: &str,
+    template: &str,
+) -> Result<Vec<TextEdit>> {
+    let query = Query::new(lang, query).map_err(|e| anyhow!("Invalid query: {}", e.message))?;
+    let match_index = query
+        .capture_index_for_name(MATCH_CAPTURE)
+        .ok_or_else(|| anyhow!("The query has to capture the node to replace as @match"))?;
+    let template = Template::parse(template, &query)?;
+    let source = lines.join("\n");
+    let mut parser = Parser::new();
+    parser.set_language(lang)?;
+    let tree = parser
+        .parse(&source, None)
+        .ok_or_else(|| anyhow!("Could not parse the file"))?;
+
+    let mut replaced: Vec<(Node, String)> = Vec::new();
+    let mut cursor = QueryCursor::new();
+    for query_match in cursor.matches(&query, tree.root_node(), source.as_bytes()) {
+        let capture = |index: u32| {
+            query_match
+                .captures
+                .iter()
+                .find(|capture| capture.index == index)
+                .map(|capture| capture.node)
+        };
+        let Some(node) = capture(match_index) else {
+            continue;
+        };
+        let text = template.expand(|index| {
+            capture(index)
+                .and_then(|node| node.utf8_text(source.as_bytes()).ok())
+                .unwrap_or_default()
+        });
+        replaced.push((node, text));
+    }
+    replaced.sort_by_key(|(node, _)| (node.start_byte(), std::cmp::Reverse(node.end_byte())));
+    let mut end = 0;
+    let mut edits = Vec::new();
+    for (node, text) in replaced {
+        if node.start_byte() < end {
+            continue;
+        }
+        end = node.end_byte();
+        let position = |point: Point| {
+            let line = lines.get(point.row).copied().unwrap_or_default();
+            let character = line.get(..point.column).unwrap_or(line).chars().count();
+            Position::new(point.row as u32, character as u32)
+        };
+        edits.push(TextEdit::new(
+            Range::new(
+                position(node.start_position()),
+                position(node.end_position()),
+            ),
+            text,
+        ));
+    }
+    Ok(edits)
+}
+
+enum Part {
+    Text(String),
+    Capture(u32),
+}
+
+struct Template(Vec<Part>);
+
+impl Template {
+    fn parse(template: &str, query: &Query) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                text.push(c);
+                continue;
+            }
+            let mut name = String::new();
+            match chars.peek() {
+                Some('$') => {
+                    chars.next();
+                    text.push('$');
+                    continue;
+                }
+                Some('{') => {
+                    chars.next();
+                    name.extend(chars.by_ref().take_while(|c| *c != '}'));
+                }
+                _ => {
+                    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                        name.push(c);
+                    }
+                }
+            }
+            let index = query
+                .capture_index_for_name(&name)
+                .ok_or_else(|| anyhow!("The query has no capture @{name}"))?;
+            parts.push(Part::Text(std::mem::take(&mut text)));
+            parts.push(Part::Capture(index));
+        }
+        parts.push(Part::Text(text));
+        Ok(Self(parts))
+    }
+
+    fn expand<'a>(&self, capture: impl Fn(u32) -> &'a str) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text,
+                Part::Capture(index) => capture(*index),
+            })
+            .collect()
+    }
+}
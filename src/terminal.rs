@@ -1,92 +1,288 @@
+use crate::progress::Cancellation;
+use crate::theme;
 use crate::Position;
 use anyhow::{anyhow, Result};
+use std::cell::RefCell;
 use std::io::{self, stdout, Stdout, Write};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 use termion::color;
-use termion::event::Key;
+use termion::event::{Event, Key};
 use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
 
 const STATUS_HEIGHT: u16 = 2;
+/// Ctrl-Up and Ctrl-Down, which termion does not read as keys. They are
+/// passed on as Alt with an arrow, which no terminal sends.
+pub const CTRL_UP: Key = Key::Alt('↑');
+pub const CTRL_DOWN: Key = Key::Alt('↓');
+/// Nor Ctrl-PageUp and Ctrl-PageDown.
+pub const CTRL_PAGE_UP: Key = Key::Alt('⇞');
+pub const CTRL_PAGE_DOWN: Key = Key::Alt('⇟');
+/// How long the terminal has to answer the background color query.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
 
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
 
+/// Where the output of the terminal goes: the real terminal, or a
+/// `TestBackend` that keeps the frames to compare them in tests.
+pub trait Backend {
+    fn write(&mut self, text: &str);
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+impl Backend for RawTerminal<Stdout> {
+    fn write(&mut self, text: &str) {
+        // There is nowhere to report a failing terminal.
+        let _ = self.write_all(text.as_bytes());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(self)
+    }
+}
+
 pub struct Terminal {
     size: Size,
-    _stdout: RawTerminal<Stdout>,
+    keys: Receiver<Result<Key, io::Error>>,
+    cancellation: Cancellation,
+    output: RefCell<Box<dyn Backend>>,
+    background_color: Option<(u8, u8, u8)>,
 }
 
 impl Terminal {
     pub fn default() -> Result<Self> {
         let size = termion::terminal_size()?;
+        let mut output = stdout().into_raw_mode()?;
+        // Asked before the keys are read, which would swallow the answer.
+        let background_color = query_background_color(&mut output);
+        let (tx, rx) = channel();
+        let cancellation = Cancellation::default();
+        let cancel = cancellation.clone();
+        // Keys are read on their own thread so the editor can keep
+        // servicing the language server while no key is pressed.
+        thread::spawn(move || {
+            for event in io::stdin().events() {
+                let key = match event {
+                    Ok(Event::Key(key)) => Ok(key),
+                    Ok(Event::Unsupported(sequence)) => match sequence.as_slice() {
+                        b"\x1b[1;5A" => Ok(CTRL_UP),
+                        b"\x1b[1;5B" => Ok(CTRL_DOWN),
+                        b"\x1b[5;5~" => Ok(CTRL_PAGE_UP),
+                        b"\x1b[6;5~" => Ok(CTRL_PAGE_DOWN),
+                        _ => continue,
+                    },
+                    Ok(Event::Mouse(_)) => continue,
+                    Err(e) => Err(e),
+                };
+                if matches!(key, Ok(Key::Ctrl('c'))) {
+                    cancel.cancel();
+                }
+                if tx.send(key).is_err() {
+                    break;
+                }
+            }
+        });
         Ok(Self {
             size: Size {
                 width: size.0,
                 height: size.1.saturating_sub(STATUS_HEIGHT),
             },
-            _stdout: stdout().into_raw_mode()?,
+            keys: rx,
+            cancellation,
+            output: RefCell::new(Box::new(output)),
+            background_color,
         })
     }
 
+    /// A terminal of `size` writing to `backend`, without any keys.
+    #[cfg(test)]
+    pub fn with_backend(backend: Box<dyn Backend>, size: Size) -> Self {
+        let (_, keys) = channel();
+        Self {
+            size: Size {
+                width: size.width,
+                height: size.height.saturating_sub(STATUS_HEIGHT),
+            },
+            keys,
+            cancellation: Cancellation::default(),
+            output: RefCell::new(backend),
+            background_color: None,
+        }
+    }
+
+    /// Makes `keys` the next keys read, after which the input ends.
+    #[cfg(test)]
+    pub fn queue_keys(&mut self, keys: &[Key]) {
+        let (tx, rx) = channel();
+        for key in keys {
+            let _ = tx.send(Ok(*key));
+        }
+        self.keys = rx;
+    }
+
+    /// The background color the terminal reported on startup.
+    pub fn background_color(&self) -> Option<(u8, u8, u8)> {
+        self.background_color
+    }
+
     pub fn size(&self) -> &Size {
         &self.size
     }
 
-    pub fn clear_screen() {
-        print!("{}", termion::clear::All);
+    pub fn print(&self, text: &str) {
+        self.output.borrow_mut().write(text);
+    }
+
+    pub fn clear_screen(&self) {
+        self.print(termion::clear::All.as_ref());
     }
 
-    pub fn cursor_position(position: &Position) {
+    pub fn cursor_position(&self, position: &Position) {
         let Position { mut x, mut y } = position;
         x = x.saturating_add(1);
         y = y.saturating_add(1);
         let x = x as u16;
         let y = y as u16;
-        print!("{}", termion::cursor::Goto(x, y));
+        self.print(&termion::cursor::Goto(x, y).to_string());
     }
 
-    pub fn flush() -> Result<()> {
-        match stdout().flush() {
+    pub fn flush(&self) -> Result<()> {
+        match self.output.borrow_mut().flush() {
             Ok(_) => Ok(()),
             Err(e) => Err(anyhow!("{}", e)),
         }
     }
 
-    pub fn read_key() -> Result<Key, io::Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
-            }
+    pub fn read_key(&self) -> Result<Key, io::Error> {
+        match self.keys.recv() {
+            Ok(key) => key,
+            Err(e) => Err(io::Error::new(io::ErrorKind::BrokenPipe, e)),
+        }
+    }
+
+    /// Waits at most `timeout` for a key.
+    pub fn poll_key(&self, timeout: Duration) -> Result<Option<Key>, io::Error> {
+        match self.keys.recv_timeout(timeout) {
+            Ok(key) => key.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(e) => Err(io::Error::new(io::ErrorKind::BrokenPipe, e)),
         }
     }
 
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+    /// Starts a cancellable operation: the returned token is cancelled by
+    /// the next Ctrl-C.
+    pub fn cancellation(&self) -> Cancellation {
+        self.cancellation.reset();
+        self.cancellation.clone()
     }
 
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
+    /// Drops the keys pressed so far, e.g. those typed while an operation
+    /// was running.
+    pub fn discard_keys(&self) {
+        while self.keys.try_recv().is_ok() {}
     }
 
-    pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color))
+    pub fn set_bg_color(&self, color: color::Rgb) {
+        self.print(&theme::bg(Some(color)));
     }
 
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset))
+    pub fn reset_bg_color(&self) {
+        self.print(&color::Bg(color::Reset).to_string());
     }
 
-    pub fn cursor_hide() {
-        print!("{}", termion::cursor::Hide);
+    pub fn set_fg_color(&self, color: color::Rgb) {
+        self.print(&theme::fg(Some(color)));
     }
 
-    pub fn cursor_show() {
-        print!("{}", termion::cursor::Show);
+    pub fn reset_fg_color(&self) {
+        self.print(&color::Fg(color::Reset).to_string());
     }
 
-    pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine);
+    pub fn cursor_hide(&self) {
+        self.print(termion::cursor::Hide.as_ref());
+    }
+
+    pub fn cursor_show(&self) {
+        self.print(termion::cursor::Show.as_ref());
+    }
+
+    pub fn clear_current_line(&self) {
+        self.print(termion::clear::CurrentLine.as_ref());
+    }
+}
+
+/// Asks the terminal for its background color with OSC 11. The primary
+/// device attributes are asked right after, which all terminals answer, so
+/// those ignoring OSC 11 are not waited for until the timeout.
+fn query_background_color(output: &mut RawTerminal<Stdout>) -> Option<(u8, u8, u8)> {
+    write!(output, "\x1b]11;?\x07\x1b[c").ok()?;
+    Write::flush(output).ok()?;
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut reply = String::new();
+    while !answered_attributes(&reply) {
+        let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+            break;
+        };
+        let mut poll = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let mut buffer = [0_u8; 64];
+        // SAFETY: `poll` and `buffer` outlive the calls, which are given
+        // their sizes. Reading stdin directly keeps the bytes out of the
+        // buffer the keys are read from later.
+        let read = unsafe {
+            if libc::poll(&mut poll, 1, left.as_millis() as libc::c_int) <= 0 {
+                break;
+            }
+            libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr().cast(), buffer.len())
+        };
+        let Ok(read @ 1..) = usize::try_from(read) else {
+            break;
+        };
+        reply.push_str(&String::from_utf8_lossy(&buffer[..read]));
+    }
+    parse_background_color(&reply)
+}
+
+/// Whether `reply` contains the answer to the device attributes request,
+/// `ESC [ ? <attributes> c`.
+fn answered_attributes(reply: &str) -> bool {
+    reply.find("\x1b[?").is_some_and(|start| {
+        reply[start + 3..]
+            .chars()
+            .find(|c| !c.is_ascii_digit() && *c != ';')
+            == Some('c')
+    })
+}
+
+/// The color of an OSC 11 answer, `ESC ] 11 ; rgb:<r>/<g>/<b>` with 1 to 4
+/// hex digits per channel.
+fn parse_background_color(reply: &str) -> Option<(u8, u8, u8)> {
+    let start = reply.find("\x1b]11;rgb:")? + "\x1b]11;rgb:".len();
+    let rest = &reply[start..];
+    let end = rest.find(['\x07', '\x1b']).unwrap_or(rest.len());
+    let mut channels = rest[..end].split('/').map(|channel| {
+        if !(1..=4).contains(&channel.len()) {
+            return None;
+        }
+        let value = u32::from_str_radix(channel, 16).ok()?;
+        let max = 16_u32.checked_pow(u32::try_from(channel.len()).ok()?)? - 1;
+        u8::try_from(value * 255 / max).ok()
+    });
+    match (
+        channels.next(),
+        channels.next(),
+        channels.next(),
+        channels.next(),
+    ) {
+        (Some(r), Some(g), Some(b), None) => Some((r?, g?, b?)),
+        _ => None,
     }
 }
@@ -0,0 +1,332 @@
+use std::cell::RefCell;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::Chars;
+
+use crate::diff;
+use crate::terminal::Backend;
+
+const SNAPSHOT_DIR: &str = "src/snapshots";
+/// Keys of the styles in the style grid of a frame, in order of appearance.
+const STYLE_KEYS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Color {
+    Rgb(u8, u8, u8),
+    /// One of the 256 (or 16) palette colors.
+    Indexed(u8),
+}
+
+impl Color {
+    fn describe(self) -> String {
+        match self {
+            Self::Rgb(r, g, b) => format!("#{r:02x}{g:02x}{b:02x}"),
+            Self::Indexed(index) => format!("color{index}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+struct CellStyle {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    invert: bool,
+    strikethrough: bool,
+}
+
+impl CellStyle {
+    fn describe(self) -> String {
+        let mut parts = Vec::new();
+        if let Some(fg) = self.fg {
+            parts.push(format!("fg={}", fg.describe()));
+        }
+        if let Some(bg) = self.bg {
+            parts.push(format!("bg={}", bg.describe()));
+        }
+        for (set, name) in [
+            (self.bold, "bold"),
+            (self.italic, "italic"),
+            (self.underline, "underline"),
+            (self.invert, "invert"),
+            (self.strikethrough, "strikethrough"),
+        ] {
+            if set {
+                parts.push(name.to_string());
+            }
+        }
+        parts.join(" ")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Cell {
+    symbol: char,
+    style: CellStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: ' ',
+            style: CellStyle::default(),
+        }
+    }
+}
+
+/// A terminal screen that interprets the escape sequences the editor
+/// writes: cursor movement, clearing and colors.
+pub struct Screen {
+    width: usize,
+    cells: Vec<Vec<Cell>>,
+    cursor: (usize, usize),
+    cursor_visible: bool,
+    style: CellStyle,
+}
+
+impl Screen {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            cells: vec![vec![Cell::default(); width]; height],
+            cursor: (0, 0),
+            cursor_visible: true,
+            style: CellStyle::default(),
+        }
+    }
+
+    fn feed(&mut self, text: &str) {
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => match chars.next() {
+                    Some('[') => self.control_sequence(&mut chars),
+                    // Operating system commands, e.g. OSC 52 copying, end
+                    // with BEL or ST.
+                    Some(']') => {
+                        while let Some(c) = chars.next() {
+                            if c == '\x07' {
+                                break;
+                            }
+                            if c == '\x1b' {
+                                chars.next();
+                                break;
+                            }
+                        }
+                    }
+                    _ => (),
+                },
+                '\r' => self.cursor.0 = 0,
+                '\n' => self.cursor.1 += 1,
+                c => self.put(c),
+            }
+        }
+    }
+
+    fn put(&mut self, symbol: char) {
+        let (x, y) = self.cursor;
+        if let Some(cell) = self.cells.get_mut(y).and_then(|row| row.get_mut(x)) {
+            *cell = Cell {
+                symbol,
+                style: self.style,
+            };
+        }
+        self.cursor.0 += 1;
+    }
+
+    fn control_sequence(&mut self, chars: &mut Peekable<Chars>) {
+        let mut params = String::new();
+        let mut command = None;
+        for c in chars.by_ref() {
+            if ('\x40'..='\x7e').contains(&c) {
+                command = Some(c);
+                break;
+            }
+            params.push(c);
+        }
+        let numbers: Vec<usize> = params
+            .trim_start_matches('?')
+            .split(';')
+            .map(|number| number.parse().unwrap_or(0))
+            .collect();
+        let number = |index: usize| numbers.get(index).copied().unwrap_or(0);
+        // Erased cells keep the background color, like most terminals do.
+        let blank = Cell {
+            symbol: ' ',
+            style: CellStyle {
+                bg: self.style.bg,
+                ..CellStyle::default()
+            },
+        };
+        let (x, y) = self.cursor;
+        match command {
+            Some('H') => self.cursor = (number(1).max(1) - 1, number(0).max(1) - 1),
+            Some('K') => {
+                if let Some(row) = self.cells.get_mut(y) {
+                    let erased = match number(0) {
+                        1 => 0..(x + 1).min(row.len()),
+                        2 => 0..row.len(),
+                        _ => x.min(row.len())..row.len(),
+                    };
+                    row[erased].fill(blank);
+                }
+            }
+            Some('J') if number(0) == 2 => {
+                for row in &mut self.cells {
+                    row.fill(blank);
+                }
+            }
+            Some('h') if params == "?25" => self.cursor_visible = true,
+            Some('l') if params == "?25" => self.cursor_visible = false,
+            Some('m') => self.select_graphic_rendition(&numbers),
+            _ => (),
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, numbers: &[usize]) {
+        let mut numbers = numbers.iter().copied();
+        while let Some(number) = numbers.next() {
+            let color = |numbers: &mut dyn Iterator<Item = usize>| match numbers.next() {
+                Some(2) => {
+                    let mut channel = || numbers.next().unwrap_or(0) as u8;
+                    Some(Color::Rgb(channel(), channel(), channel()))
+                }
+                Some(5) => Some(Color::Indexed(numbers.next().unwrap_or(0) as u8)),
+                _ => None,
+            };
+            let style = &mut self.style;
+            match number {
+                0 => *style = CellStyle::default(),
+                1 => style.bold = true,
+                3 => style.italic = true,
+                4 => style.underline = true,
+                7 => style.invert = true,
+                9 => style.strikethrough = true,
+                21 | 22 => style.bold = false,
+                23 => style.italic = false,
+                24 => style.underline = false,
+                27 => style.invert = false,
+                29 => style.strikethrough = false,
+                30..=37 => style.fg = Some(Color::Indexed((number - 30) as u8)),
+                90..=97 => style.fg = Some(Color::Indexed((number - 90 + 8) as u8)),
+                40..=47 => style.bg = Some(Color::Indexed((number - 40) as u8)),
+                100..=107 => style.bg = Some(Color::Indexed((number - 100 + 8) as u8)),
+                38 => style.fg = color(&mut numbers),
+                48 => style.bg = color(&mut numbers),
+                39 => style.fg = None,
+                49 => style.bg = None,
+                _ => (),
+            }
+        }
+    }
+
+    /// The characters of line `y`.
+    pub fn line(&self, y: usize) -> String {
+        self.cells[y].iter().map(|cell| cell.symbol).collect()
+    }
+
+    /// The screen as text: the characters, a grid with a key for the style
+    /// of every cell (blank for the default style), what the keys stand for
+    /// and where the cursor is.
+    pub fn frame(&self) -> String {
+        let mut styles: Vec<CellStyle> = Vec::new();
+        let mut text = String::new();
+        let mut grid = String::new();
+        let border = format!("+{}+\n", "-".repeat(self.width));
+        for row in &self.cells {
+            let line: String = row.iter().map(|cell| cell.symbol).collect();
+            let _ = writeln!(text, "|{line}|");
+            let keys: String = row
+                .iter()
+                .map(|cell| {
+                    if cell.style == CellStyle::default() {
+                        return ' ';
+                    }
+                    let index = styles
+                        .iter()
+                        .position(|style| *style == cell.style)
+                        .unwrap_or_else(|| {
+                            styles.push(cell.style);
+                            styles.len() - 1
+                        });
+                    STYLE_KEYS.chars().nth(index).unwrap_or('?')
+                })
+                .collect();
+            let _ = writeln!(grid, "|{keys}|");
+        }
+        let mut frame = format!("{border}{text}{border}{grid}{border}");
+        for (key, style) in STYLE_KEYS.chars().zip(&styles) {
+            let _ = writeln!(frame, "{key}: {}", style.describe());
+        }
+        let _ = writeln!(
+            frame,
+            "cursor: {}:{}{}",
+            self.cursor.1 + 1,
+            self.cursor.0 + 1,
+            if self.cursor_visible { "" } else { " (hidden)" }
+        );
+        frame
+    }
+}
+
+/// A `Backend` drawing on a `Screen` instead of the terminal.
+pub struct TestBackend(Rc<RefCell<Screen>>);
+
+impl TestBackend {
+    /// A backend for a screen of `width` x `height` and the screen, to look
+    /// at what was drawn.
+    pub fn new(width: usize, height: usize) -> (Self, Rc<RefCell<Screen>>) {
+        let screen = Rc::new(RefCell::new(Screen::new(width, height)));
+        (Self(Rc::clone(&screen)), screen)
+    }
+}
+
+impl Backend for TestBackend {
+    fn write(&mut self, text: &str) {
+        self.0.borrow_mut().feed(text);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compares `frame` with the snapshot `name` in `src/snapshots`. A missing
+/// or differing snapshot fails, leaving the new frame beside it as
+/// `<name>.snap.new` to review; `UPDATE_SNAPSHOTS=1` accepts new frames.
+pub fn assert_snapshot(name: &str, frame: &str) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(SNAPSHOT_DIR);
+    let path = dir.join(format!("{name}.snap"));
+    let new_path = dir.join(format!("{name}.snap.new"));
+    let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let expected = fs::read_to_string(&path).ok();
+    if expected.as_deref() == Some(frame) {
+        let _ = fs::remove_file(&new_path);
+        return;
+    }
+    fs::create_dir_all(&dir).expect("Could not create the snapshot directory");
+    if update {
+        fs::write(&path, frame).expect("Could not write the snapshot");
+        let _ = fs::remove_file(&new_path);
+        return;
+    }
+    fs::write(&new_path, frame).expect("Could not write the new snapshot");
+    let Some(expected) = expected else {
+        panic!("No snapshot {}, see {}", path.display(), new_path.display());
+    };
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = frame.lines().collect();
+    panic!(
+        "Snapshot {} differs, see {}:\n{}",
+        path.display(),
+        new_path.display(),
+        diff::unified(&diff::lines(&old, &new), 2).join("\n")
+    );
+}
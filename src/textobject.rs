@@ -0,0 +1,223 @@
+use anyhow::{anyhow, Result};
+use tree_sitter::{Language, Node, Parser, Point, Query, QueryCursor};
+
+use crate::SearchDirection;
+
+/// `textobjects.scm` of the built-in languages. A pattern captures the
+/// whole object as `@<object>.around` and, optionally, its body as
+/// `@<object>.inside`.
+pub const RUST_QUERY: &str = r"
+(function_item body: (_) @function.inside) @function.around
+(function_signature_item) @function.around
+(closure_expression body: (_) @function.inside) @function.around
+(struct_item body: (_) @class.inside) @class.around
+(enum_item body: (_) @class.inside) @class.around
+(union_item body: (_) @class.inside) @class.around
+(trait_item body: (_) @class.inside) @class.around
+(impl_item body: (_) @class.inside) @class.around
+(parameters (_) @parameter.around)
+(closure_parameters (_) @parameter.around)
+(type_parameters (_) @parameter.around)
+(arguments (_) @parameter.around)
+";
+
+pub const GO_QUERY: &str = r"
+(function_declaration body: (block) @function.inside) @function.around
+(method_declaration body: (block) @function.inside) @function.around
+(func_literal body: (block) @function.inside) @function.around
+(type_declaration (type_spec type: (struct_type (field_declaration_list) @class.inside))) @class.around
+(type_declaration (type_spec type: (interface_type) @class.inside)) @class.around
+(parameter_list (_) @parameter.around)
+(argument_list (_) @parameter.around)
+";
+
+pub const C_QUERY: &str = r"
+(function_definition body: (_) @function.inside) @function.around
+(struct_specifier body: (_) @class.inside) @class.around
+(union_specifier body: (_) @class.inside) @class.around
+(enum_specifier body: (_) @class.inside) @class.around
+(parameter_list (_) @parameter.around)
+(argument_list (_) @parameter.around)
+";
+
+pub const CPP_QUERY: &str = r"
+(function_definition body: (_) @function.inside) @function.around
+(lambda_expression body: (_) @function.inside) @function.around
+(class_specifier body: (_) @class.inside) @class.around
+(struct_specifier body: (_) @class.inside) @class.around
+(union_specifier body: (_) @class.inside) @class.around
+(enum_specifier body: (_) @class.inside) @class.around
+(parameter_list (_) @parameter.around)
+(template_parameter_list (_) @parameter.around)
+(argument_list (_) @parameter.around)
+";
+
+pub const PYTHON_QUERY: &str = r"
+(function_definition body: (_) @function.inside) @function.around
+(lambda body: (_) @function.inside) @function.around
+(class_definition body: (_) @class.inside) @class.around
+(parameters (_) @parameter.around)
+(lambda_parameters (_) @parameter.around)
+(argument_list (_) @parameter.around)
+";
+
+/// What the `select`, `next` and `previous` commands work on.
+#[derive(Clone, Copy)]
+pub enum Object {
+    Function,
+    /// Classes, structs, enums, traits and the like.
+    Class,
+    /// Parameters and arguments.
+    Parameter,
+}
+
+impl Object {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "function" => Some(Self::Function),
+            "class" => Some(Self::Class),
+            "parameter" => Some(Self::Parameter),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Function => "function",
+            Self::Class => "class",
+            Self::Parameter => "parameter",
+        }
+    }
+}
+
+/// The start and end of a piece of the text.
+pub type Span = (Point, Point);
+
+/// An object of the text and its body, which is all of it for objects
+/// without one.
+struct Found {
+    around: Span,
+    inside: Span,
+}
+
+/// The objects of kind `object` that `query` finds in `lines`, in order.
+fn find(lang: Language, query: &str, lines: &[&str], object: Object) -> Result<Vec<Found>> {
+    let query = Query::new(lang, query).map_err(|e| anyhow!("Invalid query: {}", e.message))?;
+    let around = query.capture_index_for_name(&format!("{}.around", object.name()));
+    let inside = query.capture_index_for_name(&format!("{}.inside", object.name()));
+    let Some(around) = around else {
+        return Ok(Vec::new());
+    };
+    let source = lines.join("\n");
+    let mut parser = Parser::new();
+    parser.set_language(lang)?;
+    let tree = parser
+        .parse(&source, None)
+        .ok_or_else(|| anyhow!("Could not parse the file"))?;
+    let mut cursor = QueryCursor::new();
+    let mut found: Vec<Found> = cursor
+        .matches(&query, tree.root_node(), source.as_bytes())
+        .filter_map(|query_match| {
+            let span = |index: u32| {
+                query_match
+                    .captures
+                    .iter()
+                    .find(|capture| capture.index == index)
+                    .map(|capture| (capture.node.start_position(), capture.node.end_position()))
+            };
+            let around = span(around)?;
+            Some(Found {
+                around,
+                inside: inside.and_then(span).unwrap_or(around),
+            })
+        })
+        .collect();
+    found.sort_by_key(|found| (found.around.0, std::cmp::Reverse(found.around.1)));
+    Ok(found)
+}
+
+/// The innermost object around `at`, or its body if `inner`.
+pub fn select(
+    lang: Language,
+    query: &str,
+    lines: &[&str],
+    object: Object,
+    at: Point,
+    inner: bool,
+) -> Result<Option<Span>> {
+    let found = find(lang, query, lines, object)?;
+    let innermost = found
+        .iter()
+        .rfind(|found| found.around.0 <= at && at < found.around.1);
+    Ok(innermost.map(|found| if inner { found.inside } else { found.around }))
+}
+
+/// The start of the first object after `at`, or of the last one before it.
+pub fn next(
+    lang: Language,
+    query: &str,
+    lines: &[&str],
+    object: Object,
+    at: Point,
+    direction: SearchDirection,
+) -> Result<Option<Point>> {
+    let starts = find(lang, query, lines, object)?
+        .into_iter()
+        .map(|found| found.around.0);
+    Ok(match direction {
+        SearchDirection::Forward => starts.filter(|start| *start > at).min(),
+        SearchDirection::Backward => starts.filter(|start| *start < at).max(),
+    })
+}
+
+/// The functions and classes with a name that `query` finds in `source`:
+/// their kind, their name and where the name starts.
+pub fn symbols(lang: Language, query: &str, source: &str) -> Result<Vec<(Object, String, Point)>> {
+    let query = Query::new(lang, query).map_err(|e| anyhow!("Invalid query: {}", e.message))?;
+    let objects = [Object::Function, Object::Class].map(|object| {
+        let index = query.capture_index_for_name(&format!("{}.around", object.name()));
+        (object, index)
+    });
+    let mut parser = Parser::new();
+    parser.set_language(lang)?;
+    let tree = parser
+        .parse(source, None)
+        .ok_or_else(|| anyhow!("Could not parse the file"))?;
+    let mut cursor = QueryCursor::new();
+    let mut symbols = Vec::new();
+    for query_match in cursor.matches(&query, tree.root_node(), source.as_bytes()) {
+        for capture in query_match.captures {
+            let Some((object, _)) = objects
+                .iter()
+                .find(|(_, index)| *index == Some(capture.index))
+            else {
+                continue;
+            };
+            let Some(name) = name(capture.node) else {
+                continue;
+            };
+            if let Ok(text) = name.utf8_text(source.as_bytes()) {
+                symbols.push((*object, text.to_string(), name.start_position()));
+            }
+        }
+    }
+    symbols.sort_by_key(|(_, _, start)| *start);
+    symbols.dedup_by_key(|(_, _, start)| *start);
+    Ok(symbols)
+}
+
+/// The node naming a function or class: its `name`, the innermost of the
+/// `declarator`s of C and C++, or the `name` of a Go `type_spec`.
+fn name(node: Node) -> Option<Node> {
+    if let Some(name) = node.child_by_field_name("name") {
+        return Some(name);
+    }
+    if let Some(declarator) = node.child_by_field_name("declarator") {
+        return name(declarator).or(Some(declarator));
+    }
+    let mut cursor = node.walk();
+    let spec = node
+        .named_children(&mut cursor)
+        .find(|child| child.kind() == "type_spec");
+    spec.and_then(|spec| spec.child_by_field_name("name"))
+}
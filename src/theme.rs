@@ -0,0 +1,1089 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use anyhow::{anyhow, Result};
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+use termion::color;
+
+use crate::config::config_dir;
+
+/// Directory of the config directory with the theme files.
+const THEMES_DIR_NAME: &str = "themes";
+/// The built-in theme, used unless the config names another one.
+pub const DEFAULT_THEME: &str = "default";
+/// The built-in theme with few, strong colors and attributes on top.
+const HIGH_CONTRAST_THEME: &str = "high-contrast";
+
+/// Colors of the built-in theme by capture name, made for dark backgrounds.
+const DEFAULT_SYNTAX: [(&str, color::Rgb); 45] = [
+    ("attribute", color::Rgb(221, 120, 120)),
+    ("boolean", color::Rgb(234, 118, 203)),
+    ("carriage-return", color::Rgb(136, 57, 239)),
+    ("comment", color::Rgb(92, 95, 119)),
+    ("comment.documentation", color::Rgb(92, 95, 119)),
+    ("constant", color::Rgb(210, 15, 57)),
+    ("constant.builtin", color::Rgb(210, 15, 57)),
+    ("constructor", color::Rgb(234, 118, 203)),
+    ("constructor.builtin", color::Rgb(234, 118, 203)),
+    ("embedded", color::Rgb(23, 146, 153)),
+    ("error", color::Rgb(114, 135, 253)),
+    ("escape", color::Rgb(32, 159, 181)),
+    ("function", color::Rgb(223, 142, 29)),
+    ("function.builtin", color::Rgb(223, 142, 29)),
+    ("keyword", color::Rgb(0, 255, 0)),
+    ("markup.heading", color::Rgb(30, 102, 245)),
+    ("markup.link", color::Rgb(4, 165, 229)),
+    ("markup.raw", color::Rgb(223, 142, 29)),
+    ("module", color::Rgb(4, 165, 229)),
+    ("number", color::Rgb(114, 135, 253)),
+    ("operator", color::Rgb(32, 159, 181)),
+    ("property", color::Rgb(114, 135, 253)),
+    ("property.builtin", color::Rgb(30, 102, 245)),
+    ("punctuation", color::Rgb(4, 165, 229)),
+    ("punctuation.bracket", color::Rgb(4, 165, 229)),
+    ("punctuation.delimiter", color::Rgb(4, 165, 229)),
+    ("punctuation.special", color::Rgb(4, 165, 229)),
+    ("string", color::Rgb(64, 160, 43)),
+    ("string.escape", color::Rgb(223, 142, 29)),
+    ("string.regexp", color::Rgb(223, 142, 29)),
+    ("string.special", color::Rgb(30, 102, 245)),
+    ("string.special.symbol", color::Rgb(210, 15, 57)),
+    ("tag", color::Rgb(220, 138, 120)),
+    ("type", color::Rgb(220, 138, 120)),
+    ("type.builtin", color::Rgb(220, 138, 120)),
+    ("variable", color::Rgb(23, 146, 153)),
+    ("variable.builtin", color::Rgb(23, 146, 153)),
+    ("variable.member", color::Rgb(23, 146, 153)),
+    ("variable.parameter", color::Rgb(234, 118, 203)),
+    ("rainbow.0", color::Rgb(210, 15, 57)),
+    ("rainbow.1", color::Rgb(254, 100, 11)),
+    ("rainbow.2", color::Rgb(223, 142, 29)),
+    ("rainbow.3", color::Rgb(64, 160, 43)),
+    ("rainbow.4", color::Rgb(32, 159, 181)),
+    ("rainbow.5", color::Rgb(136, 57, 239)),
+];
+
+/// Colors of the built-in theme on light backgrounds, where they differ.
+const LIGHT_SYNTAX: [(&str, color::Rgb); 8] = [
+    ("attribute", color::Rgb(230, 69, 83)),
+    ("function", color::Rgb(30, 102, 245)),
+    ("function.builtin", color::Rgb(30, 102, 245)),
+    ("keyword", color::Rgb(136, 57, 239)),
+    ("property.builtin", color::Rgb(114, 135, 253)),
+    ("tag", color::Rgb(223, 142, 29)),
+    ("type", color::Rgb(223, 142, 29)),
+    ("type.builtin", color::Rgb(223, 142, 29)),
+];
+
+/// Attributes of every built-in theme by capture name, for markup whose
+/// meaning is the attribute.
+const MARKUP_ATTRIBUTES: [(&str, Style); 4] = [
+    ("markup.bold", Style::attributed(true, false, false)),
+    ("markup.heading", Style::attributed(true, false, false)),
+    ("markup.italic", Style::attributed(false, true, false)),
+    (
+        "markup.strikethrough",
+        Style::attributed(false, false, true),
+    ),
+];
+
+/// Whether the terminal draws on a light or a dark background.
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Background {
+    Dark,
+    Light,
+}
+
+impl Background {
+    /// The background a color is, by its luminance.
+    pub fn of((r, g, b): (u8, u8, u8)) -> Self {
+        let luminance = 0.2126 * f64::from(r) + 0.7152 * f64::from(g) + 0.0722 * f64::from(b);
+        if luminance > 127.5 {
+            Self::Light
+        } else {
+            Self::Dark
+        }
+    }
+
+    /// The background `$COLORFGBG` (`<fg>;<bg>` palette indices), which
+    /// some terminals set, tells.
+    pub fn from_env() -> Option<Self> {
+        let colors = env::var("COLORFGBG").ok()?;
+        let index: u8 = colors.rsplit(';').next()?.parse().ok()?;
+        Some(if index == 7 || index > 8 {
+            Self::Light
+        } else {
+            Self::Dark
+        })
+    }
+}
+
+static LIGHT: AtomicBool = AtomicBool::new(false);
+
+/// Picks the colors of the built-in theme, which also fill in what other
+/// themes leave out, for `background`.
+pub fn set_background(background: Background) {
+    LIGHT.store(background == Background::Light, Ordering::Relaxed);
+}
+
+fn background() -> Background {
+    if LIGHT.load(Ordering::Relaxed) {
+        Background::Light
+    } else {
+        Background::Dark
+    }
+}
+
+/// Which colors are drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Colors {
+    /// As many as the terminal announces, see [`detect_colors`].
+    #[default]
+    Auto,
+    Truecolor,
+    /// The nearest of the xterm palette: a 6×6×6 cube and a gray ramp.
+    #[serde(rename = "256")]
+    Palette256,
+    /// The nearest of the 16 ANSI colors, whatever the terminal makes of
+    /// them.
+    #[serde(rename = "16")]
+    Palette16,
+    /// None at all; text is told apart by attributes only.
+    Monochrome,
+}
+
+impl Colors {
+    /// How the config names them.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Truecolor => "truecolor",
+            Self::Palette256 => "256",
+            Self::Palette16 => "16",
+            Self::Monochrome => "monochrome",
+        }
+    }
+}
+
+/// The colors the terminal can draw: truecolor if `$COLORTERM` says so,
+/// else as many as the `colors` capability of its terminfo entry, else 256
+/// if `$TERM` names them and 16 otherwise.
+pub fn detect_colors() -> Colors {
+    if matches!(env::var("COLORTERM").as_deref(), Ok("truecolor" | "24bit")) {
+        return Colors::Truecolor;
+    }
+    let term = env::var("TERM").unwrap_or_default();
+    match terminfo_colors(&term) {
+        Some(colors) if colors >= 1 << 24 => Colors::Truecolor,
+        Some(colors) if colors >= 256 => Colors::Palette256,
+        Some(colors) if colors >= 8 => Colors::Palette16,
+        Some(_) => Colors::Monochrome,
+        None if term.ends_with("-direct") => Colors::Truecolor,
+        None if term.contains("256color") => Colors::Palette256,
+        None => Colors::Palette16,
+    }
+}
+
+/// The `colors` number of the compiled terminfo entry of `term`, negative
+/// if it has none.
+fn terminfo_colors(term: &str) -> Option<i32> {
+    // The index of `colors` among the numbers of term(5).
+    const COLORS: usize = 13;
+    let first = term.chars().next()?;
+    let mut dirs: Vec<PathBuf> = env::var_os("TERMINFO")
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    dirs.extend(env::var_os("HOME").map(|home| PathBuf::from(home).join(".terminfo")));
+    if let Some(terminfo_dirs) = env::var_os("TERMINFO_DIRS") {
+        dirs.extend(env::split_paths(&terminfo_dirs));
+    }
+    dirs.extend(
+        ["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"]
+            .into_iter()
+            .map(PathBuf::from),
+    );
+    let data = dirs.iter().find_map(|dir| {
+        // Directories are named by the first letter, or its hex code on
+        // macOS.
+        fs::read(dir.join(first.to_string()).join(term))
+            .or_else(|_| fs::read(dir.join(format!("{:x}", u32::from(first))).join(term)))
+            .ok()
+    })?;
+    let short = |at: usize| -> Option<usize> {
+        Some(usize::from(u16::from_le_bytes([
+            *data.get(at)?,
+            *data.get(at + 1)?,
+        ])))
+    };
+    let width = match short(0)? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+    let (names, booleans, numbers) = (short(2)?, short(4)?, short(6)?);
+    if numbers <= COLORS {
+        return Some(-1);
+    }
+    // The numbers start at an even offset.
+    let start = (12 + names + booleans).next_multiple_of(2) + COLORS * width;
+    let bytes = data.get(start..start + width)?;
+    Some(if width == 2 {
+        i32::from(i16::from_le_bytes([bytes[0], bytes[1]]))
+    } else {
+        i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    })
+}
+
+/// What `set_colors` was told to draw, `Colors` as a number.
+static COLORS: AtomicU8 = AtomicU8::new(Colors::Truecolor as u8);
+static NO_BACKGROUNDS: AtomicBool = AtomicBool::new(false);
+
+/// Draws with `colors`, detecting them if they are `Auto`, and without
+/// background colors unless `backgrounds`, for terminals and screen readers
+/// that do not get along with them.
+pub fn set_colors(colors: Colors, backgrounds: bool) {
+    let colors = match colors {
+        Colors::Auto => detect_colors(),
+        colors => colors,
+    };
+    COLORS.store(colors as u8, Ordering::Relaxed);
+    NO_BACKGROUNDS.store(!backgrounds, Ordering::Relaxed);
+}
+
+fn colors() -> Colors {
+    match COLORS.load(Ordering::Relaxed) {
+        value if value == Colors::Palette256 as u8 => Colors::Palette256,
+        value if value == Colors::Palette16 as u8 => Colors::Palette16,
+        value if value == Colors::Monochrome as u8 => Colors::Monochrome,
+        _ => Colors::Truecolor,
+    }
+}
+
+/// Whether background colors are drawn. Where they are not, what they
+/// mark is shown by attributes instead.
+pub fn backgrounds() -> bool {
+    colors() != Colors::Monochrome && !NO_BACKGROUNDS.load(Ordering::Relaxed)
+}
+
+/// How a piece of text is drawn. `None` colors are those of the terminal,
+/// or of the surrounding text.
+///
+/// In theme files a style is a color, `"#rrggbb"`, or a table:
+/// `{ fg = "#rrggbb", bg = "#rrggbb", bold = true, italic = true, underline = true,
+/// strikethrough = true }`.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    pub fg: Option<color::Rgb>,
+    pub bg: Option<color::Rgb>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+}
+
+impl Style {
+    const fn fg(color: color::Rgb) -> Self {
+        Self {
+            fg: Some(color),
+            bg: None,
+            bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+        }
+    }
+
+    const fn bg(color: color::Rgb) -> Self {
+        Self {
+            fg: None,
+            bg: Some(color),
+            bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+        }
+    }
+
+    const fn underlined() -> Self {
+        Self {
+            fg: None,
+            bg: None,
+            bold: false,
+            italic: false,
+            underline: true,
+            strikethrough: false,
+        }
+    }
+
+    const fn attributed(bold: bool, italic: bool, strikethrough: bool) -> Self {
+        Self {
+            fg: None,
+            bg: None,
+            bold,
+            italic,
+            underline: false,
+            strikethrough,
+        }
+    }
+
+    /// `self` with the colors `other` sets and its attributes added.
+    pub fn patch(self, other: Self) -> Self {
+        Self {
+            fg: other.fg.or(self.fg),
+            bg: other.bg.or(self.bg),
+            bold: self.bold || other.bold,
+            italic: self.italic || other.italic,
+            underline: self.underline || other.underline,
+            strikethrough: self.strikethrough || other.strikethrough,
+        }
+    }
+
+    /// The escape sequences switching on the attributes.
+    pub fn attributes(self) -> String {
+        let mut attributes = String::new();
+        if self.bold {
+            attributes.push_str(termion::style::Bold.as_ref());
+        }
+        if self.italic {
+            attributes.push_str(termion::style::Italic.as_ref());
+        }
+        if self.underline {
+            attributes.push_str(termion::style::Underline.as_ref());
+        }
+        if self.strikethrough {
+            attributes.push_str(termion::style::CrossedOut.as_ref());
+        }
+        attributes
+    }
+
+    /// The escape sequences switching from `self` to `other`, touching only
+    /// what differs so search marks and the like drawn on top are kept.
+    pub fn switch_to(self, other: Self) -> String {
+        let mut escape = String::new();
+        if self.fg != other.fg {
+            escape.push_str(&fg(other.fg));
+        }
+        if self.bg != other.bg {
+            escape.push_str(&bg(other.bg));
+        }
+        if self.bold != other.bold {
+            // 22 rather than 21, which some terminals take for a double
+            // underline.
+            escape.push_str(&if other.bold {
+                termion::style::Bold.to_string()
+            } else {
+                termion::style::NoFaint.to_string()
+            });
+        }
+        if self.italic != other.italic {
+            escape.push_str(&if other.italic {
+                termion::style::Italic.to_string()
+            } else {
+                termion::style::NoItalic.to_string()
+            });
+        }
+        if self.underline != other.underline {
+            escape.push_str(&if other.underline {
+                termion::style::Underline.to_string()
+            } else {
+                termion::style::NoUnderline.to_string()
+            });
+        }
+        if self.strikethrough != other.strikethrough {
+            escape.push_str(&if other.strikethrough {
+                termion::style::CrossedOut.to_string()
+            } else {
+                termion::style::NoCrossedOut.to_string()
+            });
+        }
+        escape
+    }
+
+    /// The escape sequences drawing in this style from scratch. Without
+    /// backgrounds, a style with one is drawn in reverse video.
+    pub fn escape(self) -> String {
+        let mut escape = format!("{}{}{}", termion::style::Reset, fg(self.fg), bg(self.bg));
+        if self.bg.is_some() && !backgrounds() {
+            escape.push_str(termion::style::Invert.as_ref());
+        }
+        escape + &self.attributes()
+    }
+
+    /// Parses the arguments of the `highlight` command: `fg=#rrggbb`,
+    /// `bg=#rrggbb` (`none` for the terminal's color), `bold`, `italic`,
+    /// `underline` and `strikethrough`.
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        let mut style = Self::default();
+        for word in words {
+            let color = |value: &str| match value {
+                "none" => Ok(None),
+                _ => parse_color(value).map(Some),
+            };
+            match word.split_once('=') {
+                Some(("fg", value)) => style.fg = color(value)?,
+                Some(("bg", value)) => style.bg = color(value)?,
+                None if *word == "bold" => style.bold = true,
+                None if *word == "italic" => style.italic = true,
+                None if *word == "underline" => style.underline = true,
+                None if *word == "strikethrough" => style.strikethrough = true,
+                _ => return Err(format!("Invalid style {word:?}")),
+            }
+        }
+        Ok(style)
+    }
+
+    /// The style as a value of a theme file.
+    fn to_toml(self) -> String {
+        if let (Some(color), None, false, false, false, false) = (
+            self.fg,
+            self.bg,
+            self.bold,
+            self.italic,
+            self.underline,
+            self.strikethrough,
+        ) {
+            return format!("\"{}\"", hex(color));
+        }
+        let mut entries = Vec::new();
+        if let Some(color) = self.fg {
+            entries.push(format!("fg = \"{}\"", hex(color)));
+        }
+        if let Some(color) = self.bg {
+            entries.push(format!("bg = \"{}\"", hex(color)));
+        }
+        for (name, set) in [
+            ("bold", self.bold),
+            ("italic", self.italic),
+            ("underline", self.underline),
+            ("strikethrough", self.strikethrough),
+        ] {
+            if set {
+                entries.push(format!("{name} = true"));
+            }
+        }
+        if entries.is_empty() {
+            return "{}".to_string();
+        }
+        format!("{{ {} }}", entries.join(", "))
+    }
+}
+
+/// In the syntax of the `highlight` command.
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut words = Vec::new();
+        if let Some(color) = self.fg {
+            words.push(format!("fg={}", hex(color)));
+        }
+        if let Some(color) = self.bg {
+            words.push(format!("bg={}", hex(color)));
+        }
+        for (name, set) in [
+            ("bold", self.bold),
+            ("italic", self.italic),
+            ("underline", self.underline),
+            ("strikethrough", self.strikethrough),
+        ] {
+            if set {
+                words.push(name.to_string());
+            }
+        }
+        if words.is_empty() {
+            return f.write_str("none");
+        }
+        f.write_str(&words.join(" "))
+    }
+}
+
+fn hex(color::Rgb(r, g, b): color::Rgb) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// The foreground escape of `color`, the terminal's for `None` and in
+/// monochrome, and the nearest the terminal has otherwise.
+pub fn fg(color: Option<color::Rgb>) -> String {
+    let Some(color) = color else {
+        return color::Fg(color::Reset).to_string();
+    };
+    match colors() {
+        Colors::Monochrome => color::Fg(color::Reset).to_string(),
+        Colors::Palette256 => color::Fg(color::AnsiValue(palette256(color))).to_string(),
+        Colors::Palette16 => match palette16(color) {
+            index @ 0..=7 => format!("\x1b[{}m", 30 + index),
+            index => format!("\x1b[{}m", 82 + index),
+        },
+        Colors::Auto | Colors::Truecolor => color::Fg(color).to_string(),
+    }
+}
+
+/// The background escape of `color`, the terminal's for `None` and
+/// without backgrounds, and the nearest the terminal has otherwise.
+pub fn bg(color: Option<color::Rgb>) -> String {
+    let Some(color) = color.filter(|_| backgrounds()) else {
+        return color::Bg(color::Reset).to_string();
+    };
+    match colors() {
+        Colors::Palette256 => color::Bg(color::AnsiValue(palette256(color))).to_string(),
+        Colors::Palette16 => match palette16(color) {
+            index @ 0..=7 => format!("\x1b[{}m", 40 + index),
+            index => format!("\x1b[{}m", 92 + index),
+        },
+        _ => color::Bg(color).to_string(),
+    }
+}
+
+/// How far apart two colors look, weighing green, to which the eye is
+/// most sensitive, the most.
+fn distance(color::Rgb(r1, g1, b1): color::Rgb, color::Rgb(r2, g2, b2): color::Rgb) -> u32 {
+    let square = |a: u8, b: u8| u32::from(a.abs_diff(b)).pow(2);
+    2 * square(r1, r2) + 4 * square(g1, g2) + 3 * square(b1, b2)
+}
+
+/// The index of the xterm 256 color palette nearest to `color`, from its
+/// color cube (16-231) or its gray ramp (232-255).
+fn palette256(color: color::Rgb) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |channel: u8| -> u8 {
+        (0..6u8)
+            .min_by_key(|&index| LEVELS[usize::from(index)].abs_diff(channel))
+            .unwrap_or(0)
+    };
+    let color::Rgb(r, g, b) = color;
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = color::Rgb(
+        LEVELS[usize::from(ri)],
+        LEVELS[usize::from(gi)],
+        LEVELS[usize::from(bi)],
+    );
+    let average = ((u16::from(r) + u16::from(g) + u16::from(b)) / 3) as u8;
+    let step = (average.saturating_sub(3) / 10).min(23);
+    let gray = 8 + 10 * step;
+    if distance(color, color::Rgb(gray, gray, gray)) < distance(color, cube) {
+        232 + step
+    } else {
+        16 + 36 * ri + 6 * gi + bi
+    }
+}
+
+/// The index of the ANSI color nearest to `color`, as xterm draws them by
+/// default: the 8 normal colors and then their bright variants.
+fn palette16(color: color::Rgb) -> u8 {
+    const ANSI: [color::Rgb; 16] = [
+        color::Rgb(0, 0, 0),
+        color::Rgb(205, 0, 0),
+        color::Rgb(0, 205, 0),
+        color::Rgb(205, 205, 0),
+        color::Rgb(0, 0, 238),
+        color::Rgb(205, 0, 205),
+        color::Rgb(0, 205, 205),
+        color::Rgb(229, 229, 229),
+        color::Rgb(127, 127, 127),
+        color::Rgb(255, 0, 0),
+        color::Rgb(0, 255, 0),
+        color::Rgb(255, 255, 0),
+        color::Rgb(92, 92, 255),
+        color::Rgb(255, 0, 255),
+        color::Rgb(0, 255, 255),
+        color::Rgb(255, 255, 255),
+    ];
+    (0..16u8)
+        .min_by_key(|&index| distance(color, ANSI[usize::from(index)]))
+        .unwrap_or(0)
+}
+
+impl<'de> Deserialize<'de> for Style {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StyleVisitor;
+
+        impl<'de> Visitor<'de> for StyleVisitor {
+            type Value = Style;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a color like \"#rrggbb\" or a table of fg, bg and attributes")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Style, E> {
+                Ok(Style::fg(parse_color(value).map_err(E::custom)?))
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Style, A::Error> {
+                let table = StyleTable::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(Style {
+                    fg: table.fg.map(|Hex(color)| color),
+                    bg: table.bg.map(|Hex(color)| color),
+                    bold: table.bold,
+                    italic: table.italic,
+                    underline: table.underline,
+                    strikethrough: table.strikethrough,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(StyleVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StyleTable {
+    fg: Option<Hex>,
+    bg: Option<Hex>,
+    #[serde(default)]
+    bold: bool,
+    #[serde(default)]
+    italic: bool,
+    #[serde(default)]
+    underline: bool,
+    #[serde(default)]
+    strikethrough: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+struct Hex(color::Rgb);
+
+impl TryFrom<String> for Hex {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        parse_color(&value).map(Self)
+    }
+}
+
+fn parse_color(value: &str) -> Result<color::Rgb, String> {
+    let invalid = || format!("Invalid color {value:?}, expected \"#rrggbb\"");
+    let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel =
+        |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).map_err(|_| invalid());
+    Ok(color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// The colors of the parts of the editor around the text.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ui {
+    pub status_bar: Style,
+    pub message_bar: Style,
+    /// Hovers, completion menus, diffs and other floating windows.
+    pub floating: Style,
+    /// The selected entry of a floating menu.
+    pub floating_selected: Style,
+    /// Search matches other than the one at the cursor, which is drawn in
+    /// reverse video.
+    pub search_match: Style,
+    /// The bracket at the cursor and the one it pairs with.
+    pub matching_bracket: Style,
+    pub selection: Style,
+    /// The summary of the lines a fold hides.
+    pub fold: Style,
+    /// The text language servers report problems for.
+    pub diagnostic: Style,
+    /// Words of comments and strings the spell checker does not know.
+    pub misspelled: Style,
+    /// Text past the maximum line length of the file type.
+    pub long_line: Style,
+    /// The sign in the gutter of annotated lines.
+    pub annotation: Style,
+    pub diff_added: Style,
+    pub diff_removed: Style,
+    pub diff_hunk: Style,
+    /// The words that changed within changed lines.
+    pub diff_added_word: Style,
+    pub diff_removed_word: Style,
+}
+
+impl Default for Ui {
+    fn default() -> Self {
+        match background() {
+            Background::Dark => Self::dark(),
+            Background::Light => Self::light(),
+        }
+    }
+}
+
+impl Ui {
+    fn dark() -> Self {
+        Self {
+            status_bar: Style {
+                fg: Some(color::Rgb(63, 63, 63)),
+                bg: Some(color::Rgb(239, 239, 239)),
+                ..Style::default()
+            },
+            message_bar: Style::default(),
+            floating: Style {
+                fg: Some(color::Rgb(76, 79, 105)),
+                bg: Some(color::Rgb(0, 0, 0)),
+                ..Style::default()
+            },
+            floating_selected: Style::bg(color::Rgb(48, 52, 70)),
+            search_match: Style::bg(color::Rgb(70, 70, 40)),
+            matching_bracket: Style::bg(color::Rgb(81, 87, 109)),
+            selection: Style::bg(color::Rgb(54, 58, 79)),
+            fold: Style::fg(color::Rgb(115, 121, 148)),
+            diagnostic: Style::underlined(),
+            misspelled: Style::underlined(),
+            long_line: Style::bg(color::Rgb(65, 45, 50)),
+            annotation: Style::fg(color::Rgb(229, 200, 144)),
+            diff_added: Style::fg(color::Rgb(64, 160, 43)),
+            diff_removed: Style::fg(color::Rgb(210, 15, 57)),
+            diff_hunk: Style::fg(color::Rgb(4, 165, 229)),
+            diff_added_word: Style::bg(color::Rgb(28, 68, 36)),
+            diff_removed_word: Style::bg(color::Rgb(88, 28, 40)),
+        }
+    }
+
+    fn light() -> Self {
+        Self {
+            status_bar: Style {
+                fg: Some(color::Rgb(239, 241, 245)),
+                bg: Some(color::Rgb(76, 79, 105)),
+                ..Style::default()
+            },
+            message_bar: Style::default(),
+            floating: Style {
+                fg: Some(color::Rgb(76, 79, 105)),
+                bg: Some(color::Rgb(230, 233, 239)),
+                ..Style::default()
+            },
+            floating_selected: Style::bg(color::Rgb(204, 208, 218)),
+            search_match: Style::bg(color::Rgb(249, 226, 175)),
+            matching_bracket: Style::bg(color::Rgb(188, 192, 204)),
+            selection: Style::bg(color::Rgb(220, 224, 232)),
+            fold: Style::fg(color::Rgb(140, 143, 161)),
+            diagnostic: Style::underlined(),
+            misspelled: Style::underlined(),
+            long_line: Style::bg(color::Rgb(245, 224, 220)),
+            annotation: Style::fg(color::Rgb(223, 142, 29)),
+            diff_added: Style::fg(color::Rgb(64, 160, 43)),
+            diff_removed: Style::fg(color::Rgb(210, 15, 57)),
+            diff_hunk: Style::fg(color::Rgb(4, 165, 229)),
+            diff_added_word: Style::bg(color::Rgb(204, 238, 204)),
+            diff_removed_word: Style::bg(color::Rgb(250, 210, 215)),
+        }
+    }
+
+    /// The styles by their names in theme files.
+    fn styles_mut(&mut self) -> [(&'static str, &mut Style); 17] {
+        [
+            ("status_bar", &mut self.status_bar),
+            ("message_bar", &mut self.message_bar),
+            ("floating", &mut self.floating),
+            ("floating_selected", &mut self.floating_selected),
+            ("search_match", &mut self.search_match),
+            ("matching_bracket", &mut self.matching_bracket),
+            ("selection", &mut self.selection),
+            ("fold", &mut self.fold),
+            ("diagnostic", &mut self.diagnostic),
+            ("misspelled", &mut self.misspelled),
+            ("long_line", &mut self.long_line),
+            ("annotation", &mut self.annotation),
+            ("diff_added", &mut self.diff_added),
+            ("diff_removed", &mut self.diff_removed),
+            ("diff_hunk", &mut self.diff_hunk),
+            ("diff_added_word", &mut self.diff_added_word),
+            ("diff_removed_word", &mut self.diff_removed_word),
+        ]
+    }
+}
+
+/// Colors of the highlighted captures and of the UI, read from
+/// `themes/<name>.toml` in the config directory:
+///
+/// ```toml
+/// [syntax]
+/// keyword = "#c678dd"
+/// comment = { fg = "#5c6370", italic = true }
+/// "function.builtin" = { fg = "#61afef", bold = true }
+///
+/// [ui]
+/// status_bar = { fg = "#282c34", bg = "#98c379" }
+/// floating = { fg = "#abb2bf", bg = "#21252b" }
+/// ```
+///
+/// Whatever a theme leaves out is taken from the built-in one.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// Styles by capture name, e.g. `function.builtin`.
+    syntax: HashMap<String, Style>,
+    pub ui: Ui,
+}
+
+impl Theme {
+    /// Loads the theme `name`; `default` and `high-contrast` are built in
+    /// unless there is a file of that name.
+    pub fn load(name: &str) -> Result<Self> {
+        let path = path(name).ok_or_else(|| anyhow!("Invalid theme name {name:?}"))?;
+        if !path.exists() {
+            match name {
+                DEFAULT_THEME => return Ok(Self::default()),
+                HIGH_CONTRAST_THEME => return Ok(Self::high_contrast()),
+                _ => (),
+            }
+        }
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Could not read {}: {e}", path.display()))?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Pure colors for text and bold, underline and reverse video for what
+    /// the other themes tell apart by backgrounds alone.
+    fn high_contrast() -> Self {
+        let light = background() == Background::Light;
+        let pick = |dark: (u8, u8, u8), bright: (u8, u8, u8)| {
+            let (r, g, b) = if light { dark } else { bright };
+            Some(color::Rgb(r, g, b))
+        };
+        let text = pick((0, 0, 0), (255, 255, 255));
+        let keyword = pick((0, 0, 192), (255, 255, 0));
+        let function = pick((0, 96, 128), (0, 255, 255));
+        let string = pick((0, 112, 0), (0, 255, 0));
+        let kind = pick((128, 0, 160), (255, 0, 255));
+        let constant = pick((176, 48, 0), (255, 176, 0));
+        let comment = pick((80, 80, 80), (200, 200, 200));
+        let back = pick((255, 255, 255), (0, 0, 0));
+        let styled = |fg, bold, italic| Style {
+            fg,
+            bold,
+            italic,
+            ..Style::default()
+        };
+        let syntax = [
+            ("attribute", styled(kind, false, false)),
+            ("boolean", styled(constant, false, false)),
+            ("carriage-return", styled(constant, false, false)),
+            ("comment", styled(comment, false, true)),
+            ("constant", styled(constant, false, false)),
+            ("constructor", styled(kind, false, false)),
+            ("embedded", styled(text, false, false)),
+            ("error", styled(constant, true, false)),
+            ("escape", styled(keyword, false, false)),
+            ("function", styled(function, false, false)),
+            ("keyword", styled(keyword, true, false)),
+            ("markup", styled(keyword, false, false)),
+            ("module", styled(kind, false, false)),
+            ("number", styled(constant, false, false)),
+            ("operator", styled(text, false, false)),
+            ("property", styled(text, false, false)),
+            ("punctuation", styled(text, false, false)),
+            ("string", styled(string, false, false)),
+            ("tag", styled(keyword, false, false)),
+            ("type", styled(kind, true, false)),
+            ("variable", styled(text, false, false)),
+        ]
+        .into_iter()
+        .map(|(name, style)| (name.to_string(), style))
+        .collect();
+        let style = |fg, bg, bold, underline| Style {
+            fg,
+            bg,
+            bold,
+            italic: false,
+            underline,
+            strikethrough: false,
+        };
+        Self {
+            syntax,
+            ui: Ui {
+                status_bar: style(back, text, true, false),
+                message_bar: style(text, None, false, false),
+                floating: style(text, back, false, false),
+                floating_selected: style(back, keyword, true, false),
+                search_match: style(None, pick((255, 224, 0), (96, 96, 0)), false, true),
+                matching_bracket: style(None, pick((160, 200, 255), (0, 64, 160)), true, false),
+                selection: style(None, pick((192, 192, 192), (64, 64, 64)), false, false),
+                fold: style(comment, None, true, false),
+                diagnostic: style(None, None, true, true),
+                misspelled: style(None, None, false, true),
+                long_line: style(None, pick((255, 192, 192), (96, 0, 0)), false, false),
+                annotation: style(keyword, None, true, false),
+                ..Ui::default()
+            },
+        }
+    }
+
+    /// The style of the capture `name`, or of the capture with most of its
+    /// leading parts, e.g. `function` for `function.builtin`. Entries of the
+    /// theme win over all built-in ones, but one for fewer parts than the
+    /// built-in attributes, e.g. `markup` for `markup.bold`, keeps them.
+    pub fn syntax(&self, name: &str) -> Style {
+        let lookup = |styles: &dyn Fn(&str) -> Option<Style>| {
+            let mut name = name;
+            loop {
+                if let Some(style) = styles(name) {
+                    return Some((name.len(), style));
+                }
+                name = &name[..name.rfind('.')?];
+            }
+        };
+        let find = |colors: &[(&str, color::Rgb)], name: &str| {
+            colors
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+                .map(|(_, color)| Style::fg(*color))
+        };
+        let light = background() == Background::Light;
+        let attributes = lookup(&|name| {
+            MARKUP_ATTRIBUTES
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+                .map(|(_, style)| *style)
+        });
+        let (specific, attributes) = attributes.unwrap_or_default();
+        match lookup(&|name| self.syntax.get(name).copied()) {
+            Some((matched, style)) if matched >= specific => style,
+            Some((_, style)) => style.patch(attributes),
+            None => lookup(&|name| {
+                light
+                    .then(|| find(&LIGHT_SYNTAX, name))
+                    .flatten()
+                    .or_else(|| find(&DEFAULT_SYNTAX, name))
+            })
+            .map(|(_, style)| style)
+            .unwrap_or_default()
+            .patch(attributes),
+        }
+    }
+
+    /// The style of `group`, the name of a `[ui]` entry or of a capture.
+    pub fn style(&self, group: &str) -> Style {
+        self.ui
+            .clone()
+            .styles_mut()
+            .into_iter()
+            .find(|(name, _)| *name == group)
+            .map_or_else(|| self.syntax(group), |(_, style)| *style)
+    }
+
+    /// Draws `group`, the name of a `[ui]` entry or of a capture, in `style`.
+    pub fn set_style(&mut self, group: &str, style: Style) {
+        match self
+            .ui
+            .styles_mut()
+            .into_iter()
+            .find(|(name, _)| *name == group)
+        {
+            Some((_, current)) => *current = style,
+            None => {
+                self.syntax.insert(group.to_string(), style);
+            }
+        }
+    }
+
+    /// The theme as a theme file, with every built-in color it does not
+    /// override spelled out.
+    pub fn to_toml(&self) -> String {
+        let mut names: Vec<&str> = DEFAULT_SYNTAX
+            .iter()
+            .map(|(name, _)| *name)
+            .chain(self.syntax.keys().map(String::as_str))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        let key = |name: &str| {
+            if name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                name.to_string()
+            } else {
+                format!("{name:?}")
+            }
+        };
+        let mut contents = "[syntax]\n".to_string();
+        for name in names {
+            contents.push_str(&format!(
+                "{} = {}\n",
+                key(name),
+                self.syntax(name).to_toml()
+            ));
+        }
+        contents.push_str("\n[ui]\n");
+        for (name, style) in self.ui.clone().styles_mut() {
+            contents.push_str(&format!("{name} = {}\n", style.to_toml()));
+        }
+        contents
+    }
+
+    /// Writes the theme to `themes/<name>.toml`, replacing what is there.
+    pub fn export(&self, name: &str) -> Result<PathBuf> {
+        let path = path(name).ok_or_else(|| anyhow!("Invalid theme name {name:?}"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, self.to_toml())
+            .map_err(|e| anyhow!("Could not write {}: {e}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// `themes/<name>.toml` of the config directory, `None` for names that are
+/// not plain words.
+fn path(name: &str) -> Option<PathBuf> {
+    let plain = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if name.is_empty() || !name.chars().all(plain) {
+        return None;
+    }
+    config_dir().map(|dir| dir.join(THEMES_DIR_NAME).join(format!("{name}.toml")))
+}
+
+/// The names of the built-in themes and of those in the `themes` directory.
+pub fn available() -> Vec<String> {
+    let mut names: Vec<String> = config_dir()
+        .and_then(|dir| fs::read_dir(dir.join(THEMES_DIR_NAME)).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "toml" {
+                return None;
+            }
+            Some(path.file_stem()?.to_str()?.to_string())
+        })
+        .chain([DEFAULT_THEME.to_string(), HIGH_CONTRAST_THEME.to_string()])
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn current_theme() -> &'static RwLock<Arc<Theme>> {
+    static CURRENT: OnceLock<RwLock<Arc<Theme>>> = OnceLock::new();
+    CURRENT.get_or_init(|| RwLock::new(Arc::new(Theme::default())))
+}
+
+/// The theme everything is drawn with.
+pub fn current() -> Arc<Theme> {
+    match current_theme().read() {
+        Ok(theme) => Arc::clone(&theme),
+        Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+    }
+}
+
+/// Draws everything with `theme` from now on.
+pub fn set(theme: Theme) {
+    match current_theme().write() {
+        Ok(mut current) => *current = Arc::new(theme),
+        Err(poisoned) => *poisoned.into_inner() = Arc::new(theme),
+    }
+}
+
+/// Changes the current theme with `change`.
+pub fn update(change: impl FnOnce(&mut Theme)) {
+    let mut theme = Theme::clone(&current());
+    change(&mut theme);
+    set(theme);
+}
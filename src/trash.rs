@@ -0,0 +1,142 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use lsp_types::Url;
+
+use crate::history;
+
+/// A file in the trash, as laid out by the freedesktop.org trash spec.
+struct Trashed {
+    /// Where the file was.
+    original: PathBuf,
+    /// The file in `Trash/files`.
+    file: PathBuf,
+    /// Its `.trashinfo` in `Trash/info`.
+    info: PathBuf,
+}
+
+impl Trashed {
+    /// Puts the file back where it was, over whatever is there now.
+    fn restore(&self) -> Result<()> {
+        if fs::rename(&self.file, &self.original).is_err() {
+            fs::copy(&self.file, &self.original)?;
+            fs::remove_file(&self.file)?;
+        }
+        let _ = fs::remove_file(&self.info);
+        Ok(())
+    }
+}
+
+/// The files a destructive file operation moved to the trash, so that
+/// undoing it can put them back.
+pub struct Operation {
+    /// What was done, e.g. `replace foo bar`.
+    pub name: String,
+    trashed: Vec<Trashed>,
+}
+
+impl Operation {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            trashed: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trashed.is_empty()
+    }
+
+    /// Copies `path` to the trash before it is overwritten.
+    pub fn keep(&mut self, path: &Path) -> Result<()> {
+        let trashed = reserve(path)?;
+        if let Err(e) = fs::copy(path, &trashed.file) {
+            let _ = fs::remove_file(&trashed.info);
+            return Err(e.into());
+        }
+        self.trashed.push(trashed);
+        Ok(())
+    }
+
+    /// Puts back every file, the latest first. Returns how many were
+    /// restored, or the paths of those that could not be.
+    pub fn undo(self) -> Result<usize, Vec<String>> {
+        let mut failed = Vec::new();
+        for trashed in self.trashed.iter().rev() {
+            if trashed.restore().is_err() {
+                failed.push(trashed.original.display().to_string());
+            }
+        }
+        if failed.is_empty() {
+            Ok(self.trashed.len())
+        } else {
+            Err(failed)
+        }
+    }
+}
+
+/// `$XDG_DATA_HOME/Trash`, falling back to `~/.local/share/Trash`.
+pub fn dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("Trash"));
+    }
+    env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(".local")
+            .join("share")
+            .join("Trash")
+    })
+}
+
+/// Claims a name in the trash for `path` by creating its `.trashinfo`,
+/// numbering the name when another file of that name is in the trash.
+fn reserve(path: &Path) -> Result<Trashed> {
+    let dir = dir().ok_or_else(|| anyhow!("No trash directory"))?;
+    let original = path.canonicalize()?;
+    let name = original
+        .file_name()
+        .ok_or_else(|| anyhow!("Cannot trash {}", original.display()))?
+        .to_string_lossy()
+        .into_owned();
+    fs::create_dir_all(dir.join("files"))?;
+    fs::create_dir_all(dir.join("info"))?;
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let location = Url::from_file_path(&original)
+        .map_err(|()| anyhow!("Cannot trash {}", original.display()))?;
+    for number in 1.. {
+        let name = if number == 1 {
+            name.clone()
+        } else {
+            format!("{name}.{number}")
+        };
+        let file = dir.join("files").join(&name);
+        let info = dir.join("info").join(format!("{name}.trashinfo"));
+        let mut info_file = match OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(info_file) => info_file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if file.exists() {
+            let _ = fs::remove_file(&info);
+            continue;
+        }
+        write!(
+            info_file,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            location.path(),
+            history::timestamp(time).replace(' ', "T")
+        )?;
+        return Ok(Trashed {
+            original,
+            file,
+            info,
+        });
+    }
+    unreachable!("the trash has a free name")
+}
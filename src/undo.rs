@@ -0,0 +1,225 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::Position;
+
+/// Steps kept for undoing; the oldest are forgotten first.
+const LIMIT: usize = 1000;
+
+/// A change of the text: `removed` replaced by `inserted` at `at`.
+pub struct Edit {
+    pub at: Position,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    /// Where the inserted text ends.
+    pub fn inserted_end(&self) -> Position {
+        end(&self.at, &self.inserted)
+    }
+
+    /// Where the removed text ended.
+    pub fn removed_end(&self) -> Position {
+        end(&self.at, &self.removed)
+    }
+
+    /// Whether the edit typed text into a word, which a following one may
+    /// continue in the same step.
+    fn is_typing(&self) -> bool {
+        self.removed.is_empty()
+            && !self.inserted.is_empty()
+            && !self.inserted.chars().any(char::is_whitespace)
+    }
+}
+
+/// The edits of one key press or command, undone and redone together.
+pub struct Step {
+    pub edits: Vec<Edit>,
+}
+
+impl Step {
+    /// Where the cursor goes when the step is undone: the first change.
+    pub fn start(&self) -> Position {
+        self.edits
+            .first()
+            .map(|edit| edit.at.clone())
+            .unwrap_or_default()
+    }
+
+    /// Where the cursor goes when the step is redone: after the last change.
+    pub fn end(&self) -> Position {
+        self.edits
+            .last()
+            .map(Edit::inserted_end)
+            .unwrap_or_default()
+    }
+}
+
+/// The steps of a document to undo and redo. Edits are collected until
+/// `end_step`; a step that only types on in the word of the one before is
+/// merged into it.
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    /// The edits since the last step ended.
+    pending: Vec<Edit>,
+    /// How many steps there were to undo when the document was last saved,
+    /// or `None` once that state cannot be got back to by undoing.
+    saved: Option<usize>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            pending: Vec::new(),
+            saved: Some(0),
+        }
+    }
+}
+
+impl History {
+    pub fn record(&mut self, edit: Edit) {
+        self.pending.push(edit);
+    }
+
+    /// Turns the edits since the last step into one.
+    pub fn end_step(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let edits = std::mem::take(&mut self.pending);
+        self.redo.clear();
+        if self.saved.is_some_and(|saved| saved > self.undo.len()) {
+            self.saved = None;
+        }
+        let at_saved = self.saved == Some(self.undo.len());
+        if let Some(last) = self.undo.last_mut().filter(|_| !at_saved) {
+            let continues = last.edits.iter().all(Edit::is_typing)
+                && edits.iter().all(Edit::is_typing)
+                && last.edits.last().map(Edit::inserted_end) == Some(edits[0].at.clone());
+            if continues {
+                last.edits.extend(edits);
+                return;
+            }
+        }
+        self.undo.push(Step { edits });
+        if self.undo.len() > LIMIT {
+            self.undo.remove(0);
+            self.saved = self.saved.and_then(|saved| saved.checked_sub(1));
+        }
+    }
+
+    /// The step to undo, if any, which `undone` takes back after it was.
+    pub fn undo(&mut self) -> Option<Step> {
+        self.end_step();
+        self.undo.pop()
+    }
+
+    pub fn undone(&mut self, step: Step) {
+        self.redo.push(step);
+    }
+
+    /// The step to redo, if any, which `redone` takes back after it was.
+    pub fn redo(&mut self) -> Option<Step> {
+        self.end_step();
+        self.redo.pop()
+    }
+
+    pub fn redone(&mut self, step: Step) {
+        self.undo.push(step);
+    }
+
+    /// Remembers the current state as the one on disk.
+    pub fn mark_saved(&mut self) {
+        self.end_step();
+        self.saved = Some(self.undo.len());
+    }
+
+    /// Whether the text is as it was when last saved.
+    pub fn is_saved(&self) -> bool {
+        self.pending.is_empty() && self.saved == Some(self.undo.len())
+    }
+}
+
+/// Where `text` ends when it starts at `at`.
+fn end(at: &Position, text: &str) -> Position {
+    match text.rsplit_once('\n') {
+        Some((before, last)) => Position {
+            x: last.graphemes(true).count(),
+            y: at.y + before.matches('\n').count() + 1,
+        },
+        None => Position {
+            x: at.x + text.graphemes(true).count(),
+            y: at.y,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(history: &mut History, x: usize, text: &str) {
+        history.record(Edit {
+            at: Position { x, y: 0 },
+            removed: String::new(),
+            inserted: String::from(text),
+        });
+        history.end_step();
+    }
+
+    #[test]
+    fn typing_merges() {
+        let mut history = History::default();
+        typed(&mut history, 0, "a");
+        history.mark_saved();
+        typed(&mut history, 1, "b");
+        typed(&mut history, 2, "c");
+        typed(&mut history, 3, " ");
+        typed(&mut history, 4, "d");
+        let step = history.undo().unwrap();
+        assert_eq!(step.edits.len(), 1);
+        history.undone(step);
+        history.undo().unwrap();
+        let step = history.undo().unwrap();
+        // Typing on after a save starts a step of its own.
+        assert_eq!(step.edits.len(), 2);
+        assert!(step.start() == Position { x: 1, y: 0 });
+        assert!(step.end() == Position { x: 3, y: 0 });
+    }
+
+    #[test]
+    fn saved_state() {
+        let mut history = History::default();
+        assert!(history.is_saved());
+        typed(&mut history, 0, "a b");
+        assert!(!history.is_saved());
+        let step = history.undo().unwrap();
+        history.undone(step);
+        assert!(history.is_saved());
+        let step = history.redo().unwrap();
+        history.redone(step);
+        history.mark_saved();
+        let step = history.undo().unwrap();
+        history.undone(step);
+        // A new edit drops the redo, and with it the saved state.
+        typed(&mut history, 0, "c d");
+        assert!(history.redo().is_none());
+        assert!(!history.is_saved());
+        history.undo().unwrap();
+        assert!(!history.is_saved());
+    }
+
+    #[test]
+    fn multiline_end() {
+        let edit = Edit {
+            at: Position { x: 4, y: 2 },
+            removed: String::from("é"),
+            inserted: String::from("a\nb\nçd"),
+        };
+        assert!(edit.inserted_end() == Position { x: 2, y: 4 });
+        assert!(edit.removed_end() == Position { x: 5, y: 2 });
+    }
+}
@@ -0,0 +1,149 @@
+use std::env::current_dir;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config::data_dir;
+
+const PROJECT_CONFIG_FILE_NAME: &str = ".neonano.toml";
+const TRUST_FILE_NAME: &str = "trust.toml";
+const ROOT_MARKERS: [&str; 2] = [PROJECT_CONFIG_FILE_NAME, ".git"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Trust {
+    Trusted,
+    Untrusted,
+    Unknown,
+}
+
+pub struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    /// The nearest ancestor of the opened file (or the current directory)
+    /// that contains a `.neonano.toml` or `.git`, falling back to the
+    /// directory itself.
+    pub fn discover(file_name: Option<&str>) -> Self {
+        let cwd = current_dir().unwrap_or_default();
+        let start = file_name
+            .map(|name| cwd.join(name))
+            .and_then(|path| path.canonicalize().ok())
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .unwrap_or(cwd);
+        let root = start
+            .ancestors()
+            .find(|dir| ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+            .map_or_else(|| start.clone(), Path::to_path_buf);
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn project_config(&self) -> Option<PathBuf> {
+        let path = self.root.join(PROJECT_CONFIG_FILE_NAME);
+        path.exists().then_some(path)
+    }
+
+    pub fn trust(&self) -> Trust {
+        let store = TrustStore::load();
+        let root = self.root.to_string_lossy();
+        if store.trusted.iter().any(|dir| *dir == root) {
+            Trust::Trusted
+        } else if store.untrusted.iter().any(|dir| *dir == root) {
+            Trust::Untrusted
+        } else {
+            Trust::Unknown
+        }
+    }
+
+    /// Remembers the decision for this workspace in the data directory.
+    pub fn remember(&self, trust: Trust) -> Result<()> {
+        let mut store = TrustStore::load();
+        let root = self.root.to_string_lossy().into_owned();
+        store.trusted.retain(|dir| *dir != root);
+        store.untrusted.retain(|dir| *dir != root);
+        match trust {
+            Trust::Trusted => store.trusted.push(root),
+            Trust::Untrusted => store.untrusted.push(root),
+            Trust::Unknown => (),
+        }
+        store.save()
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct TrustStore {
+    trusted: Vec<String>,
+    untrusted: Vec<String>,
+}
+
+impl TrustStore {
+    fn path() -> Option<PathBuf> {
+        data_dir().map(|dir| dir.join(TRUST_FILE_NAME))
+    }
+
+    fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = Self::path() {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(path, toml::to_string(self)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn discovered_at_project_config() {
+        let root = Path::new("target/snap/workspace");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join(PROJECT_CONFIG_FILE_NAME), "").unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        let workspace = Workspace::discover(Some("target/snap/workspace/src/main.rs"));
+        assert_eq!(workspace.root(), root.canonicalize().unwrap());
+        assert_eq!(
+            workspace.project_config(),
+            Some(workspace.root().join(PROJECT_CONFIG_FILE_NAME))
+        );
+    }
+
+    #[test]
+    fn trust_remembered() {
+        // As the editor tests do, so the store of the user is not touched.
+        env::set_var("XDG_DATA_HOME", "target/snap/data");
+        let workspace = Workspace {
+            root: PathBuf::from("/nonexistent/trust-remembered"),
+        };
+        workspace.remember(Trust::Unknown).unwrap();
+        assert!(workspace.trust() == Trust::Unknown);
+        workspace.remember(Trust::Trusted).unwrap();
+        assert!(workspace.trust() == Trust::Trusted);
+        workspace.remember(Trust::Untrusted).unwrap();
+        assert!(workspace.trust() == Trust::Untrusted);
+        let store = TrustStore::load();
+        assert!(!store
+            .trusted
+            .iter()
+            .any(|dir| dir.ends_with("trust-remembered")));
+        workspace.remember(Trust::Unknown).unwrap();
+        assert!(workspace.trust() == Trust::Unknown);
+    }
+}